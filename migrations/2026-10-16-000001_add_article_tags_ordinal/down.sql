@@ -0,0 +1 @@
+ALTER TABLE article_tags DROP COLUMN ordinal;
@@ -0,0 +1,2 @@
+-- authored order of an article's tags.
+ALTER TABLE article_tags ADD COLUMN ordinal INTEGER NOT NULL DEFAULT 0;
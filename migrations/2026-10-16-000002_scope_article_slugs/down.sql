@@ -0,0 +1,3 @@
+DROP INDEX articles_slug_idx;
+DROP INDEX articles_author_id_slug_idx;
+ALTER TABLE articles ADD CONSTRAINT articles_slug_key UNIQUE (slug);
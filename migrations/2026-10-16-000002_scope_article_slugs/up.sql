@@ -0,0 +1,5 @@
+-- Slugs can be unique per author (Article.slug_scope = "author"), global
+-- uniqueness is checked by the insert/update queries.
+ALTER TABLE articles DROP CONSTRAINT articles_slug_key;
+CREATE UNIQUE INDEX articles_author_id_slug_idx ON articles (author_id, slug);
+CREATE INDEX articles_slug_idx ON articles (slug);
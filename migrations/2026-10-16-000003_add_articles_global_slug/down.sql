@@ -0,0 +1,2 @@
+DROP INDEX articles_global_slug_idx;
+ALTER TABLE articles DROP COLUMN global_slug;
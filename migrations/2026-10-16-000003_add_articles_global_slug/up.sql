@@ -0,0 +1,4 @@
+-- Articles stored with `Article.slug_scope = "global"`.  Their slugs are
+-- unique, so concurrent inserts/updates can't both take the same slug.
+ALTER TABLE articles ADD COLUMN global_slug BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE UNIQUE INDEX articles_global_slug_idx ON articles (slug) WHERE global_slug;
@@ -0,0 +1,2 @@
+DROP INDEX users_canonical_email_idx;
+ALTER TABLE users DROP COLUMN canonical_email;
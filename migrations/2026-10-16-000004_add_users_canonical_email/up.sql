@@ -0,0 +1,29 @@
+-- canonical email, set on registration when `User.canonical_email` is enabled.
+ALTER TABLE users ADD COLUMN canonical_email VARCHAR(254);
+
+-- backfill existing accounts, same rules as `canonicalize_email`: lowercase,
+-- drop the `+tag` and for gmail the dots.  When multiple accounts already
+-- share a canonical email only the oldest one gets it.
+WITH parts AS (
+  SELECT id, e,
+    regexp_replace(e, '@[^@]*$', '') AS local,
+    regexp_replace(e, '^.*@', '') AS domain
+  FROM (SELECT id, lower(btrim(email)) AS e FROM users) u
+), untagged AS (
+  SELECT id, e, domain,
+    CASE WHEN strpos(local, '+') > 1 THEN left(local, strpos(local, '+') - 1) ELSE local END AS local
+  FROM parts
+), canonical AS (
+  SELECT DISTINCT ON (email) id, email FROM (
+    SELECT id, CASE
+      WHEN strpos(e, '@') = 0 THEN e
+      WHEN domain IN ('gmail.com', 'googlemail.com') THEN replace(local, '.', '') || '@gmail.com'
+      ELSE local || '@' || domain END AS email
+    FROM untagged
+  ) c
+  ORDER BY email, id
+)
+UPDATE users SET canonical_email = canonical.email
+FROM canonical WHERE users.id = canonical.id;
+
+CREATE UNIQUE INDEX users_canonical_email_idx ON users (canonical_email);
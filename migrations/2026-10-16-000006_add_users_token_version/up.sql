@@ -0,0 +1,2 @@
+-- tokens issued with an older version are rejected.
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- hash-chained log of admin actions.
+CREATE TABLE admin_audit (
+    id SERIAL PRIMARY KEY,
+    actor_id INTEGER NOT NULL REFERENCES users (id),
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    -- sha256 (hex) of the previous entry, unique so the chain can't fork.
+    prev_hash TEXT UNIQUE,
+    hash TEXT NOT NULL UNIQUE
+);
+
+-- only one entry can start the chain.
+CREATE UNIQUE INDEX admin_audit_first_idx ON admin_audit ((prev_hash IS NULL))
+  WHERE prev_hash IS NULL;
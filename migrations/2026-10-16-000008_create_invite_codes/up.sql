@@ -0,0 +1,8 @@
+-- registration invite codes, see `User.require_invite`.
+CREATE TABLE invite_codes (
+    code VARCHAR PRIMARY KEY,
+    uses_remaining INTEGER NOT NULL DEFAULT 1 CHECK (uses_remaining >= 0),
+    -- NULL = never expires.
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
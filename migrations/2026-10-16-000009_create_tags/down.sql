@@ -0,0 +1,3 @@
+DROP TRIGGER count_article_tags ON article_tags;
+DROP FUNCTION count_article_tags();
+DROP TABLE tags;
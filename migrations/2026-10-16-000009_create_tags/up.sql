@@ -0,0 +1,27 @@
+-- number of articles using each tag, kept up to date by a trigger on
+-- article_tags.
+CREATE TABLE tags (
+    name TEXT PRIMARY KEY,
+    article_count BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO tags(name, article_count)
+SELECT tag_name, COUNT(*) FROM article_tags GROUP BY tag_name;
+
+CREATE FUNCTION count_article_tags() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('DELETE', 'UPDATE') THEN
+        UPDATE tags SET article_count = article_count - 1 WHERE name = OLD.tag_name;
+        DELETE FROM tags WHERE name = OLD.tag_name AND article_count <= 0;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        INSERT INTO tags(name, article_count) VALUES (NEW.tag_name, 1)
+        ON CONFLICT (name) DO UPDATE SET article_count = tags.article_count + 1;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_article_tags
+AFTER INSERT OR DELETE OR UPDATE OF tag_name ON article_tags
+FOR EACH ROW EXECUTE FUNCTION count_article_tags();
@@ -0,0 +1,3 @@
+DROP INDEX users_email_idx;
+DROP INDEX users_username_idx;
+ALTER TABLE users ADD CONSTRAINT users_username_email_key UNIQUE (username, email);
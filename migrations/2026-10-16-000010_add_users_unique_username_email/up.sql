@@ -0,0 +1,5 @@
+-- Usernames and emails are each unique, `UNIQUE (username, email)` only
+-- rejected a duplicate pair.  Existing duplicates must be renamed first.
+ALTER TABLE users DROP CONSTRAINT users_username_email_key;
+CREATE UNIQUE INDEX users_username_idx ON users (username);
+CREATE UNIQUE INDEX users_email_idx ON users (email);
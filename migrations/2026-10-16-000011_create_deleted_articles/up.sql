@@ -0,0 +1,8 @@
+-- slugs of deleted articles, fetching them is a 410 instead of a 404.
+CREATE TABLE deleted_articles (
+    slug VARCHAR NOT NULL,
+    author_id INTEGER NOT NULL,
+    deleted_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX deleted_articles_slug_idx ON deleted_articles (slug);
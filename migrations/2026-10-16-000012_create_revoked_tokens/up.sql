@@ -0,0 +1,6 @@
+-- tokens revoked by logging out (`auth.enable_revocation`), by `jti` claim.
+-- Rows are removed once the token would have expired.
+CREATE TABLE revoked_tokens (
+    jti VARCHAR PRIMARY KEY,
+    expires_at TIMESTAMP NOT NULL
+);
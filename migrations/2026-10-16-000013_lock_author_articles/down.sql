@@ -0,0 +1 @@
+DROP FUNCTION lock_author_articles(INTEGER);
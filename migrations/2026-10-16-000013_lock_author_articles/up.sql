@@ -0,0 +1,12 @@
+-- Count an author's articles (all of them and those of the last hour) after
+-- taking a per-author lock until the end of the transaction.  The function is
+-- volatile, so the counts are read after the lock is held and include the
+-- articles of concurrent stores that held it first.
+CREATE FUNCTION lock_author_articles(author INTEGER, OUT total BIGINT, OUT last_hour BIGINT) AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock('articles'::regclass::oid::integer, author);
+    SELECT COUNT(*), COUNT(*) FILTER (WHERE created_at > LOCALTIMESTAMP - interval '1 hour')
+    INTO total, last_hour
+    FROM articles WHERE author_id = author;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
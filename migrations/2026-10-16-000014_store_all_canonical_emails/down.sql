@@ -0,0 +1,6 @@
+DROP FUNCTION canonical_email_used(VARCHAR, INTEGER);
+DROP INDEX users_canonical_email_idx;
+-- only the oldest account keeps a shared canonical email.
+UPDATE users SET canonical_email = NULL
+WHERE id NOT IN (SELECT DISTINCT ON (canonical_email) id FROM users ORDER BY canonical_email, id);
+CREATE UNIQUE INDEX users_canonical_email_idx ON users (canonical_email);
@@ -0,0 +1,34 @@
+-- The canonical email is stored for every account, also while
+-- `User.canonical_email` is off, so the index can't be unique.  The check is
+-- done by `canonical_email_used` when the option is on.
+DROP INDEX users_canonical_email_idx;
+CREATE INDEX users_canonical_email_idx ON users (canonical_email);
+
+-- backfill the accounts without one, same rules as `canonicalize_email`.
+WITH parts AS (
+  SELECT id, e,
+    regexp_replace(e, '@[^@]*$', '') AS local,
+    regexp_replace(e, '^.*@', '') AS domain
+  FROM (SELECT id, lower(btrim(email)) AS e FROM users WHERE canonical_email IS NULL) u
+), untagged AS (
+  SELECT id, e, domain,
+    CASE WHEN strpos(local, '+') > 1 THEN left(local, strpos(local, '+') - 1) ELSE local END AS local
+  FROM parts
+)
+UPDATE users SET canonical_email = CASE
+    WHEN strpos(e, '@') = 0 THEN e
+    WHEN domain IN ('gmail.com', 'googlemail.com') THEN replace(local, '.', '') || '@gmail.com'
+    ELSE local || '@' || domain END
+FROM untagged WHERE users.id = untagged.id;
+
+-- Check if another account (than `except_id`) uses a canonical email, after
+-- taking a lock on it until the end of the transaction.  The function is
+-- volatile, so the check sees the accounts of concurrent registrations that
+-- held the lock first.
+CREATE FUNCTION canonical_email_used(canonical VARCHAR, except_id INTEGER) RETURNS BOOLEAN AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock('users'::regclass::oid::integer, hashtext(canonical));
+    RETURN EXISTS (SELECT 1 FROM users
+        WHERE canonical_email = canonical AND id IS DISTINCT FROM except_id);
+END;
+$$ LANGUAGE plpgsql VOLATILE;
@@ -0,0 +1,2 @@
+-- `GET /stats/top-commenters?since=...` only scans the comments in the window.
+CREATE INDEX comments_created_at_idx ON comments (created_at);
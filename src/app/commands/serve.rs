@@ -1,6 +1,7 @@
 use log::*;
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::path::{Path, PathBuf};
 use std::thread;
 use futures::executor;
 
@@ -9,14 +10,20 @@ use crossbeam_channel::{
 };
 
 use actix_rt::System;
-use actix_web::{get, web, middleware, HttpResponse, App, HttpServer};
+use actix_web::{get, guard, web, middleware, HttpRequest, HttpResponse, App, HttpServer};
 use actix_cors::*;
+use actix_files::{Files, NamedFile};
 
 use crate::{
   error::*,
   app::*,
-  db::DbService,
-  services::config_services,
+  auth::pass::{HashAlgorithm, set_hash_algorithm, PWD_SCHEME_VERSION},
+  auth::jwt::JwtKeys,
+  db::{DbService, DbConfig, StatementKind},
+  middleware::{CacheControl, TrustedProxies, Https, RequestLimits, JsonContentType, Deprecations, RetryBudget, RateLimiter, Metrics, metrics, ErrorFormat},
+  models::{TimestampFormat, set_timestamp_format},
+  forms::set_rich_pagination,
+  services::{config_services, service_names},
 };
 
 #[derive(Debug)]
@@ -34,6 +41,51 @@ async fn stop_server(waiter: web::Data<ServerWaiter>) -> HttpResponse {
   HttpResponse::Ok().body("Shutting down.")
 }
 
+#[get("/version")]
+async fn version() -> HttpResponse {
+  HttpResponse::Ok().json(json!({
+    "version": env!("CARGO_PKG_VERSION"),
+    "gitHash": env!("GIT_HASH"),
+    "buildTimestamp": env!("BUILD_TIMESTAMP"),
+  }))
+}
+
+/// Liveness probe.
+#[get("/health")]
+async fn health() -> HttpResponse {
+  HttpResponse::Ok().json(json!({
+    "status": "ok",
+  }))
+}
+
+/// Readiness probe, ready once this worker's DB client is connected.
+#[get("/ready")]
+async fn ready(db: web::Data<DbService>) -> HttpResponse {
+  if db.shared_cl.is_connected() {
+    HttpResponse::Ok().json(json!({
+      "status": "ready",
+    }))
+  } else {
+    HttpResponse::ServiceUnavailable().json(json!({
+      "status": "unavailable",
+      "error": "Database not connected",
+    }))
+  }
+}
+
+#[get("/debug/db")]
+async fn debug_db(db: web::Data<DbService>) -> HttpResponse {
+  let status = db.prepared_status();
+  HttpResponse::Ok().json(json!({
+    "prepared": status.all_prepared(),
+    "services": status,
+    "statements": {
+      "read": db.statements_by_kind(StatementKind::Read),
+      "write": db.statements_by_kind(StatementKind::Write),
+    },
+  }))
+}
+
 #[derive(Clone)]
 struct ServerStopper {
   id: u32,
@@ -105,6 +157,18 @@ impl MainStopper {
     waiter
   }
 
+  /// Signal shutdown on SIGINT (ctrl-c) or SIGTERM.
+  pub fn spawn_signal_handler(&self) {
+    let tx = self.tx.clone();
+    thread::spawn(move || {
+      let mut sys = System::new("system.signals");
+      sys.block_on(wait_signal());
+      info!("Got shutdown signal from OS.");
+      // fails if the main thread has already stopped.
+      let _ = tx.send(StopEvent::Shutdown);
+    });
+  }
+
   pub fn wait_shutdown(&self) {
     // wait on main stopper
     debug!("Wait for shutdown signal");
@@ -163,11 +227,50 @@ impl MainStopper {
   }
 }
 
+/// Wait for SIGINT, or SIGTERM on unix.
+async fn wait_signal() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+      Ok(mut term) => {
+        futures::future::select(Box::pin(tokio::signal::ctrl_c()), Box::pin(term.recv())).await;
+        return;
+      },
+      Err(err) => {
+        error!("Failed to install SIGTERM handler: {:?}", err);
+      },
+    }
+  }
+  if let Err(err) = tokio::signal::ctrl_c().await {
+    error!("Failed to install ctrl-c handler: {:?}", err);
+    // never shutdown from a signal.
+    futures::future::pending::<()>().await;
+  }
+}
+
 pub fn execute(config: AppConfig) -> Result<()> {
+  // Password hashing for new hashes.
+  if let Some(algorithm) = config.get_str("auth.hash_algorithm")? {
+    let algorithm: HashAlgorithm = algorithm.parse()?;
+    let hash_version = config.get_int("auth.hash_version")?
+      .map(|v| v as usize).unwrap_or(PWD_SCHEME_VERSION);
+    info!("Password hash algorithm: {:?}, version={}", algorithm, hash_version);
+    set_hash_algorithm(algorithm, hash_version);
+  }
+
+  // Check the JWT secret before starting any servers, each server loads its
+  // own keys.
+  JwtKeys::from_config(&config)?;
+
   // Stopper for main thread.
   let mut main_stopper = MainStopper::new();
 
   let servers = config.get_array("servers")?.expect("Missing list of servers");
+  // Check the service lists before starting any servers.
+  for server in servers.iter() {
+    service_names(&config, &server.clone().into_str()?)?;
+  }
   for server in servers.iter() {
     let server = server.clone().into_str()?;
     let cfg = config.clone();
@@ -184,6 +287,9 @@ pub fn execute(config: AppConfig) -> Result<()> {
     });
   }
 
+  // OS signals stop all servers through the main stopper.
+  main_stopper.spawn_signal_handler();
+
   // wait on main stopper
   main_stopper.wait_shutdown();
 
@@ -191,38 +297,86 @@ pub fn execute(config: AppConfig) -> Result<()> {
   Ok(())
 }
 
-async fn test_db(url: String) -> Result<()> {
-  let db = DbService::new(&url)?;
+async fn test_db(config: DbConfig) -> Result<()> {
+  let db = DbService::new(&config)?;
   db.prepare().await
 }
 
-fn setup_cors(config: &Option<config::Table>) -> Result<Cors> {
+/// JSON timestamp format of a server, `<prefix>.timestamp_format` overrides
+/// `api.timestamp_format`.
+fn timestamp_format(config: &AppConfig, prefix: &str) -> Result<TimestampFormat> {
+  let format = match config.get_str(&format!("{}.timestamp_format", prefix))? {
+    Some(format) => Some(format),
+    None => config.get_str("api.timestamp_format")?,
+  };
+  format.map_or(Ok(TimestampFormat::default()), |format| format.parse())
+}
+
+/// List response shape of a server, `<prefix>.rich_pagination` overrides
+/// `api.rich_pagination`.
+fn rich_pagination(config: &AppConfig, prefix: &str) -> Result<bool> {
+  match config.get_bool(&format!("{}.rich_pagination", prefix))? {
+    Some(rich) => Ok(rich),
+    None => Ok(config.get_bool("api.rich_pagination")?.unwrap_or(false)),
+  }
+}
+
+/// Build the CORS layer from `<prefix>.cors`.  Without a config, debug
+/// builds allow any origin and release builds deny cross-origin requests.
+/// Preflight `OPTIONS` requests are answered by the layer.
+fn setup_cors(config: &Option<config::Table>, debug: bool) -> Result<Cors> {
   if let Some(config) = config {
     let mut cors = Cors::default();
 
-    // Origins
-    if let Some(origins) = config.get_str_array("origins")? {
+    // Origins.  The old names (`origins`, `methods`, `headers`) take
+    // precedence, so older configs still override the defaults.
+    let origins = match config.get_str_array("origins")? {
+      Some(origins) => Some(origins),
+      None => config.get_str_array("allowed_origins")?,
+    };
+    let mut any_origin = false;
+    if let Some(origins) = origins {
       debug!("Cors: origins = {:?}", origins);
       for origin in origins {
         cors = cors.allowed_origin(&origin);
         if origin == "*" {
+          any_origin = true;
           cors = cors.send_wildcard();
         }
       }
     }
 
     // Methods
-    if let Some(methods) = config.get_str_array("methods")? {
+    let methods = match config.get_str_array("methods")? {
+      Some(methods) => Some(methods),
+      None => config.get_str_array("allowed_methods")?,
+    };
+    if let Some(methods) = methods {
       debug!("Cors: methods = {:?}", methods);
       cors = cors.allowed_methods(methods.iter().map(|s| s.as_str()));
     }
 
     // Headers
-    if let Some(headers) = config.get_str_array("headers")? {
+    let headers = match config.get_str_array("headers")? {
+      Some(headers) => Some(headers),
+      None => config.get_str_array("allowed_headers")?,
+    };
+    if let Some(headers) = headers {
       debug!("Cors: headers = {:?}", headers);
       cors = cors.allowed_headers(headers.iter().map(|s| s.as_str()));
     }
 
+    // Credentials (cookies/Authorization from the browser)
+    if config.get_bool("allow_credentials")?.unwrap_or(false) {
+      if any_origin {
+        return Err(::config::ConfigError::Message(
+          "cors.allow_credentials can't be used with origin \"*\"".into()
+        ).into());
+      }
+      debug!("Cors: allow credentials");
+      cors = cors.supports_credentials();
+    }
+
     // max age
     if let Some(max_age) = config.get_int("max-age")? {
       let max_age: usize = max_age.try_into().expect("max-age must be positive.");
@@ -231,11 +385,39 @@ fn setup_cors(config: &Option<config::Table>) -> Result<Cors> {
     }
 
     Ok(cors)
+  } else if debug {
+    Ok(Cors::permissive())
   } else {
     Ok(Cors::default())
   }
 }
 
+/// Read an integer setting that must be `>= min` (and fit in `T`).
+fn get_limit<T: TryFrom<i64>>(config: &AppConfig, key: &str, min: i64) -> Result<Option<T>> {
+  match config.get_int(key)? {
+    Some(val) => match T::try_from(val) {
+      Ok(limit) if val >= min => Ok(Some(limit)),
+      _ => Err(::config::ConfigError::Message(
+        format!("{} must be >= {}, got {}", key, min, val)).into()),
+    },
+    None => Ok(None),
+  }
+}
+
+/// Serve a static front-end from `dir`, unknown paths get `index.html` so
+/// client-side routes work.  Only `GET` and `HEAD` are answered, any other
+/// method gets a 405 instead of the index.
+fn static_files(dir: &Path) -> Files {
+  let index = dir.join("index.html");
+  Files::new("/", dir)
+    .index_file("index.html")
+    .use_guards(guard::Any(guard::Get()).or(guard::Head()))
+    .default_handler(web::route().to(move |_req: HttpRequest| {
+      let index = index.clone();
+      async move { NamedFile::open(index) }
+    }))
+}
+
 fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<()> {
   let mut sys = System::new(format!("system.{}", prefix));
 
@@ -244,10 +426,10 @@ fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<
 
   if debug {
     // configure db service factory
-    let db_url = config.get_str("db.url")?.expect("db.url must be set");
+    let db_config = DbConfig::from_config(config, prefix)?;
 
     // Test db prepared statements.
-    sys.block_on(test_db(db_url.to_string()))?;
+    sys.block_on(test_db(db_config))?;
   }
 
   // configure services
@@ -261,23 +443,100 @@ fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<
     None
   };
 
+  // JSON timestamp format, set on each worker thread.
+  let timestamp_format = timestamp_format(config, prefix)?;
+  info!("API timestamp format: {:?}", timestamp_format);
+  // List response shape, also per worker thread.
+  let rich_pagination = rich_pagination(config, prefix)?;
+  if rich_pagination {
+    info!("API list responses: rich pagination");
+  }
+
+  // Cache-Control rules
+  let cache_control = CacheControl::from_config(config, prefix)?;
+
+  // HTTPS redirect / HSTS
+  let proxies = TrustedProxies::from_config(config, prefix)?;
+  let https = Https::from_config(config, prefix, proxies.clone())?;
+
+  // Per-IP rate limit, for routes wrapped with `RateLimit`
+  let rate_limiter = web::Data::new(RateLimiter::from_config(config, prefix, proxies.clone())?);
+  if rate_limiter.is_enabled() {
+    info!("Rate limit enabled for {}", prefix);
+  }
+
+  // URI/header size limits
+  let limits = RequestLimits::from_config(config, prefix)?;
+
+  // Deprecation/Sunset headers
+  let deprecations = Deprecations::from_config(config)?;
+
+  // Prometheus metrics
+  let metrics = Metrics::from_config(config, prefix, proxies.clone())?;
+
+  // DB retry time per request (and the client max wait)
+  let retry_budget = RetryBudget::from_config(config)?;
+
+  // Static front-end (SPA)
+  let static_dir = config.get_str(&format!("{}.http.static_dir", prefix))?
+    .map(PathBuf::from);
+  if let Some(ref dir) = static_dir {
+    info!("Serving static files from: {}", dir.display());
+    if !dir.join("index.html").is_file() {
+      warn!("Static dir {} has no index.html", dir.display());
+    }
+    // The `/api` scope is matched first, these files can't be reached.
+    if dir.join("api").exists() {
+      warn!("Static path {} is shadowed by the /api routes", dir.join("api").display());
+    }
+  }
+
   // CORS config
   let cors = config.get_table(&format!("{}.cors", prefix))?;
+  if cors.is_none() {
+    info!("No {}.cors config, cross-origin requests are {}", prefix,
+      if debug { "allowed (debug)" } else { "denied" });
+  }
   // Check for CORs config errors.
-  setup_cors(&cors)?;
+  setup_cors(&cors, debug)?;
 
   // Start http server
   let mut server = HttpServer::new(move || {
+    set_timestamp_format(timestamp_format);
+    set_rich_pagination(rich_pagination);
+
     // change default limits
     let form = web::FormConfig::default().limit(256 * 1024);
 
     let mut app = App::new()
       .app_data(form)
+      .app_data(rate_limiter.clone())
       // enable logger
-      .wrap(setup_cors(&cors).unwrap())
+      .wrap(setup_cors(&cors, debug).unwrap())
+      .wrap(cache_control.clone())
+      .wrap(middleware::Condition::new(deprecations.is_enabled(), deprecations.clone()))
+      .wrap(middleware::Condition::new(https.is_enabled(), https.clone()))
+      .wrap(middleware::Condition::new(limits.is_enabled(), limits))
+      .wrap(middleware::Condition::new(retry_budget.is_enabled(), retry_budget))
+      .wrap(JsonContentType)
+      .wrap(ErrorFormat)
       .wrap(middleware::Logger::default())
+      .wrap(middleware::Condition::new(metrics.is_enabled(), metrics.clone()))
       .wrap(middleware::Compress::default())
-      .configure(|web| services.web_config(web));
+      .configure(|web| services.web_config(web))
+      .service(version)
+      .service(health)
+      .service(ready);
+
+    if metrics.is_enabled() {
+      app = app.data(metrics.clone())
+        .service(metrics::metrics);
+    }
+
+    if debug {
+      // DB diagnostics
+      app = app.service(debug_db);
+    }
 
     if let Some(ref stopper) = stopper {
       // Server stopper
@@ -285,6 +544,11 @@ fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<
       .service(stop_server);
     }
 
+    // Must be last, so the service routes take precedence.
+    if let Some(ref dir) = static_dir {
+      app = app.service(static_files(dir));
+    }
+
     app
   });
 
@@ -300,6 +564,35 @@ fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<
     server = server.backlog(backlog as i32);
   }
 
+  // connection limits
+  if let Some(max_conn) = get_limit(config, &format!("{}.http.max_connections", prefix), 1)? {
+    info!("Max connections per worker: {}", max_conn);
+    server = server.max_connections(max_conn);
+  }
+  if let Some(rate) = get_limit(config, &format!("{}.http.max_connection_rate", prefix), 1)? {
+    info!("Max connection rate per worker: {}", rate);
+    server = server.max_connection_rate(rate);
+  }
+  if let Some(keep_alive) = get_limit::<usize>(config, &format!("{}.http.keep_alive", prefix), 0)? {
+    info!("Keep-alive: {}s", keep_alive);
+    server = server.keep_alive(keep_alive);
+  }
+  // time allowed to receive the request head.
+  if let Some(timeout) = get_limit(config, &format!("{}.http.client_timeout", prefix), 0)? {
+    info!("Client timeout: {}ms", timeout);
+    server = server.client_timeout(timeout);
+  }
+
+  // max time to wait for in-flight requests on graceful stop, then the
+  // workers are stopped.  actix defaults to 30 seconds.
+  if let Some(timeout) = get_limit(config, &format!("{}.http.shutdown_timeout_secs", prefix), 0)? {
+    info!("Shutdown timeout: {}s", timeout);
+    server = server.shutdown_timeout(timeout);
+  }
+
+  // Signals are handled by the main thread, which stops all servers.
+  server = server.disable_signals();
+
   // setup binds.
   let listen = config.get_str(&format!("{}.listen", prefix))?
     .expect(&format!("Missing {}.listen", prefix));
@@ -309,26 +602,24 @@ fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<
   // start server
   let server = server.run();
 
-  if debug {
-    let srv = server.clone();
-    let waiter = waiter.clone();
-    thread::spawn(move || {
-      debug!("Wait for shutdown signal");
-      // wait for shutdown signal.
-      match waiter.wait_shutdown() {
-        Err(_) => (),
-        Ok(StopEvent::StopServer) => {
-          debug!("Got shutdown signal.  Stop server: {}", waiter.id);
-          executor::block_on(srv.stop(true));
-          // notify main thread that we have stopped.
-          waiter.server_stopped();
-        },
-        Ok(ev) => {
-          error!("Server waiter received invalid event: {:?}", ev);
-        },
-      }
-    });
-  }
+  // Stop the server when the main thread shuts down (OS signal or `/stop`).
+  let srv = server.clone();
+  let stop_waiter = waiter.clone();
+  thread::spawn(move || {
+    debug!("Wait for shutdown signal");
+    // wait for shutdown signal.
+    match stop_waiter.wait_shutdown() {
+      Err(_) => (),
+      Ok(StopEvent::StopServer) => {
+        debug!("Got shutdown signal.  Stop server: {}", stop_waiter.id);
+        // graceful stop, the main thread is notified when `server` finishes.
+        executor::block_on(srv.stop(true));
+      },
+      Ok(ev) => {
+        error!("Server waiter received invalid event: {:?}", ev);
+      },
+    }
+  });
 
   // run server future
   let res = sys.block_on(server);
@@ -336,3 +627,239 @@ fn run_server(config: &AppConfig, prefix: &str, waiter: ServerWaiter) -> Result<
   Ok(res?)
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, http::{Method, StatusCode}};
+
+  #[cfg(unix)]
+  #[test]
+  fn sigterm_stops_all_servers() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    // keeps SIGTERM from killing the test process before the handler listens.
+    let mut sys = System::new("test.signals");
+    let _term = sys.block_on(async { signal(SignalKind::terminate()) }).unwrap();
+
+    let mut main_stopper = MainStopper::new();
+    let (stopped_tx, stopped_rx) = mpsc::channel();
+    for _ in 0..2 {
+      let waiter = main_stopper.new_server();
+      let stopped_tx = stopped_tx.clone();
+      thread::spawn(move || {
+        assert!(matches!(waiter.wait_shutdown(), Ok(StopEvent::StopServer)));
+        stopped_tx.send(waiter.id).unwrap();
+        waiter.server_stopped();
+      });
+    }
+    main_stopper.spawn_signal_handler();
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+      main_stopper.wait_shutdown();
+      done_tx.send(()).unwrap();
+    });
+
+    // signals sent before the handler listens are missed, repeat them.
+    let pid = std::process::id().to_string();
+    let mut tries = 0;
+    while done_rx.recv_timeout(Duration::from_millis(50)).is_err() {
+      tries += 1;
+      assert!(tries < 100, "SIGTERM didn't shut down");
+      std::process::Command::new("kill").args(["-TERM", &pid]).status().unwrap();
+    }
+    let mut stopped = stopped_rx.try_iter().collect::<Vec<_>>();
+    stopped.sort_unstable();
+    assert_eq!(stopped, vec![0, 1]);
+  }
+
+  #[actix_rt::test]
+  async fn version_reports_the_crate_version() {
+    let mut app = test::init_service(App::new().service(version)).await;
+    let req = test::TestRequest::get().uri("/version").to_request();
+    let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["gitHash"], env!("GIT_HASH"));
+  }
+
+  #[actix_rt::test]
+  async fn ready_once_the_db_is_connected() {
+    let db = DbService::new(&DbConfig::new("host=/nonexistent-rwtest port=1 user=test")).unwrap();
+    let mut app = test::init_service(App::new().data(db).service(health).service(ready)).await;
+    let req = test::TestRequest::get().uri("/health").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::get().uri("/ready").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+    assert_eq!(body["status"], "unavailable");
+
+    let url = match std::env::var("TEST_DATABASE_URL") {
+      Ok(url) => url,
+      Err(_) => return,
+    };
+    let db = DbService::new(&DbConfig::new(&url)).unwrap();
+    db.shared_cl.get_client().await.unwrap();
+    let mut app = test::init_service(App::new().data(db).service(ready)).await;
+    let req = test::TestRequest::get().uri("/ready").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+  }
+
+  #[test]
+  fn limits_must_be_in_range() {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("a.max_connections", 0).unwrap();
+    config.conf.set("a.keep_alive", -1).unwrap();
+    config.conf.set("a.client_timeout", 0).unwrap();
+    config.conf.set("a.max_connection_rate", 256).unwrap();
+    assert!(get_limit::<usize>(&config, "a.max_connections", 1).is_err());
+    assert!(get_limit::<usize>(&config, "a.keep_alive", 0).is_err());
+    assert_eq!(get_limit::<u64>(&config, "a.client_timeout", 0).unwrap(), Some(0));
+    assert_eq!(get_limit::<usize>(&config, "a.max_connection_rate", 1).unwrap(), Some(256));
+    assert_eq!(get_limit::<usize>(&config, "a.missing", 1).unwrap(), None);
+  }
+
+  #[actix_rt::test]
+  async fn shutdown_stops_slow_requests_after_the_timeout() {
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("a.http.shutdown_timeout_secs", 1).unwrap();
+    let timeout = get_limit(&config, "a.http.shutdown_timeout_secs", 0).unwrap().unwrap();
+    let server = HttpServer::new(|| App::new().route("/slow", web::get().to(|| async {
+        actix_rt::time::delay_for(Duration::from_secs(60)).await;
+        HttpResponse::Ok().finish()
+      })))
+      .workers(1)
+      .disable_signals()
+      .shutdown_timeout(timeout)
+      .bind("127.0.0.1:0").unwrap();
+    let addr = server.addrs()[0];
+    let srv = server.run();
+
+    // an in-flight request that never finishes by itself.
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+      let mut stream = std::net::TcpStream::connect(addr).unwrap();
+      stream.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+      started_tx.send(()).unwrap();
+      let _ = stream.read(&mut [0; 64]);
+    });
+    started_rx.recv().unwrap();
+    actix_rt::time::delay_for(Duration::from_millis(200)).await;
+
+    let start = Instant::now();
+    srv.stop(true).await;
+    assert!(start.elapsed() < Duration::from_secs(10), "stop took {:?}", start.elapsed());
+  }
+
+  #[actix_rt::test]
+  async fn static_dir_falls_back_to_the_index() {
+    let dir = std::env::temp_dir().join(format!("rw-static-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.html"), "<html>app</html>").unwrap();
+    std::fs::write(dir.join("app.js"), "run();").unwrap();
+
+    let mut app = test::init_service(App::new().service(static_files(&dir))).await;
+    for (uri, body) in &[("/app.js", "run();"), ("/", "<html>app</html>"),
+                         ("/article/some-slug", "<html>app</html>")] {
+      let req = test::TestRequest::get().uri(uri).to_request();
+      let resp = test::call_service(&mut app, req).await;
+      assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+      assert_eq!(test::read_body(resp).await, body.as_bytes(), "{}", uri);
+    }
+    for method in &[Method::POST, Method::PUT, Method::DELETE] {
+      let req = test::TestRequest::with_uri("/article/some-slug")
+        .method(method.clone())
+        .to_request();
+      let resp = test::call_service(&mut app, req).await;
+      assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", method);
+      assert_ne!(test::read_body(resp).await, "<html>app</html>".as_bytes(), "{}", method);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[actix_rt::test]
+  async fn static_dir_does_not_shadow_the_api() {
+    let dir = std::env::temp_dir().join(format!("rw-static-api-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("api")).unwrap();
+    std::fs::write(dir.join("index.html"), "<html>app</html>").unwrap();
+    std::fs::write(dir.join("api").join("tags"), "static tags").unwrap();
+
+    // same layout as the server: the `/api` scope with its own default,
+    // then the static files last.
+    let mut app = test::init_service(App::new()
+      .service(web::scope("/api")
+        .route("/tags", web::get().to(|| HttpResponse::Ok().body("api tags")))
+        .default_service(web::route().to(|| HttpResponse::NotFound().finish())))
+      .service(static_files(&dir))).await;
+    for (method, uri, status, body) in &[
+      (Method::GET, "/api/tags", StatusCode::OK, "api tags"),
+      (Method::GET, "/api/unknown", StatusCode::NOT_FOUND, ""),
+      (Method::POST, "/api/unknown", StatusCode::NOT_FOUND, ""),
+      (Method::GET, "/tags", StatusCode::OK, "<html>app</html>"),
+    ] {
+      let req = test::TestRequest::with_uri(uri).method(method.clone()).to_request();
+      let resp = test::call_service(&mut app, req).await;
+      assert_eq!(resp.status(), *status, "{} {}", method, uri);
+      assert_eq!(test::read_body(resp).await, body.as_bytes(), "{} {}", method, uri);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn rich_pagination_per_server() {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    assert!(!rich_pagination(&config, "a").unwrap());
+    config.conf.set("api.rich_pagination", true).unwrap();
+    config.conf.set("b.rich_pagination", false).unwrap();
+    assert!(rich_pagination(&config, "a").unwrap());
+    assert!(!rich_pagination(&config, "b").unwrap());
+  }
+
+  #[actix_rt::test]
+  async fn cors_from_config() {
+    // the `Access-Control-Allow-Origin` of a preflight request to an api route.
+    async fn preflight(cors: Cors, origin: &str) -> Option<String> {
+      let mut app = test::init_service(App::new().wrap(cors)
+        .route("/api/articles", web::get().to(|| HttpResponse::Ok()))).await;
+      let req = test::TestRequest::with_uri("/api/articles")
+        .method(actix_web::http::Method::OPTIONS)
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "GET")
+        .to_request();
+      let resp = test::call_service(&mut app, req).await;
+      resp.headers().get("Access-Control-Allow-Origin")
+        .map(|value| value.to_str().unwrap().to_string())
+    }
+    let table = |values: &[(&str, ::config::Value)]| {
+      let mut config = AppConfig { conf: ::config::Config::default() };
+      for (key, value) in values {
+        config.conf.set(&format!("test.cors.{}", key), value.clone()).unwrap();
+      }
+      config.get_table("test.cors").unwrap()
+    };
+    let spa = "http://localhost:4100";
+
+    let cors = table(&[("allowed_origins", vec![spa].into()), ("allowed_methods", vec!["GET"].into())]);
+    assert_eq!(preflight(setup_cors(&cors, false).unwrap(), spa).await.as_deref(), Some(spa));
+    assert_eq!(preflight(setup_cors(&cors, false).unwrap(), "http://other.example").await, None);
+
+    // the old names take precedence.
+    let cors = table(&[("origins", vec!["http://old.example"].into()), ("allowed_origins", vec![spa].into())]);
+    assert_eq!(preflight(setup_cors(&cors, false).unwrap(), spa).await, None);
+
+    // credentials can't be allowed for any origin.
+    let cors = table(&[("allowed_origins", "*".into()), ("allow_credentials", true.into())]);
+    assert!(setup_cors(&cors, false).is_err());
+    let cors = table(&[("allowed_origins", vec![spa].into()), ("allow_credentials", true.into())]);
+    assert!(setup_cors(&cors, false).is_ok());
+
+    // without a config only debug builds allow other origins.
+    assert_eq!(preflight(setup_cors(&None, true).unwrap(), spa).await.as_deref(), Some(spa));
+    assert_eq!(preflight(setup_cors(&None, false).unwrap(), spa).await, None);
+  }
+}
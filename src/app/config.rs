@@ -128,11 +128,29 @@ impl AppConfig {
     };
     Ok(val)
   }
+
+  /// Read a secret from the file named by `key` (e.g. a mounted Docker/k8s
+  /// secret).  Trailing newlines are removed.
+  pub fn get_secret_file(&self, key: &str) -> Result<Option<String>> {
+    let val = if let Some(path) = self.get_str(key)? {
+      let secret = std::fs::read_to_string(&path)?;
+      Some(secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+    } else {
+      None
+    };
+    Ok(val)
+  }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct Table(HashMap<String, Value>);
 
+impl From<HashMap<String, Value>> for Table {
+  fn from(table: HashMap<String, Value>) -> Self {
+    Table(table)
+  }
+}
+
 impl Table {
   pub fn new() -> Self {
     Default::default()
@@ -232,3 +250,30 @@ impl Table {
     Ok(val)
   }
 }
+
+/// Write a secret file in the temp dir, returns its path.
+#[cfg(test)]
+pub(crate) fn test_secret_file(name: &str, contents: &str) -> String {
+  let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+  std::fs::write(&path, contents).unwrap();
+  path.to_str().unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn secret_files_are_trimmed() {
+    let mut config = AppConfig { conf: Config::default() };
+    assert_eq!(config.get_secret_file("db.password_file").unwrap(), None);
+
+    config.conf.set("db.password_file", test_secret_file("unix-secret", "s3cret \n")).unwrap();
+    assert_eq!(config.get_secret_file("db.password_file").unwrap().as_deref(), Some("s3cret "));
+    config.conf.set("db.password_file", test_secret_file("dos-secret", "s3cret\r\n\r\n")).unwrap();
+    assert_eq!(config.get_secret_file("db.password_file").unwrap().as_deref(), Some("s3cret"));
+
+    config.conf.set("db.password_file", "/nonexistent/secret").unwrap();
+    assert!(config.get_secret_file("db.password_file").is_err());
+  }
+}
@@ -1,3 +1,7 @@
+use log::*;
+
+use rand::Rng;
+
 use serde::{Deserialize, Serialize};
 
 use chrono::{Duration, Utc};
@@ -5,61 +9,298 @@ use chrono::{Duration, Utc};
 use jsonwebtoken::{
   encode, Header, EncodingKey,
   decode, DecodingKey,
-  Validation
+  Algorithm, Validation,
+  errors::{Error as JwtError, ErrorKind},
 };
 
 use crate::error::*;
+use crate::app::AppConfig;
 use crate::models::User;
 
+/// Default `auth.min_secret_len`.
+pub const DEFAULT_MIN_SECRET_LEN: usize = 32;
+
+/// Default `auth.jwt_expiry_secs` (21 days).
+pub const DEFAULT_EXPIRY_SECS: i64 = 21 * 24 * 60 * 60;
+
+/// Default `auth.refresh_window_secs` (1 day).
+pub const DEFAULT_REFRESH_WINDOW_SECS: i64 = 24 * 60 * 60;
+
 #[derive(Debug, Default, Clone)]
 pub struct AuthData {
   pub user_id: i32,
   pub token: String,
+  pub token_version: i32,
+  /// Token id, for revoking the token (missing in older tokens).
+  pub jti: Option<String>,
+  /// Expiry of the token (unix timestamp).
+  pub exp: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
   pub id: i32,
   pub exp: i64,
+  /// User's token version when issued (missing in older tokens).
+  #[serde(default)]
+  pub ver: i32,
+  /// Random token id.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub jti: Option<String>,
+}
+
+/// Keys for signing and checking tokens, loaded once at startup.
+#[derive(Clone)]
+pub struct JwtKeys {
+  encoding: EncodingKey,
+  decoding: DecodingKey<'static>,
+  /// Lifetime of new tokens.
+  expiry: Duration,
+  /// Check tokens against `revoked_tokens` (`auth.enable_revocation`).
+  revocation: bool,
+  /// Suggest refreshing tokens this close to expiry (0 = never).
+  refresh_window: Duration,
+}
+
+impl JwtKeys {
+  pub fn from_secret(secret: &str) -> Self {
+    Self {
+      encoding: EncodingKey::from_secret(secret.as_ref()),
+      decoding: DecodingKey::from_secret(secret.as_ref()).into_static(),
+      expiry: Duration::seconds(DEFAULT_EXPIRY_SECS),
+      revocation: false,
+      refresh_window: Duration::seconds(DEFAULT_REFRESH_WINDOW_SECS),
+    }
+  }
+
+  /// Window before expiry to suggest a refresh (`auth.refresh_window_secs`).
+  pub fn refresh_window(&self) -> Duration {
+    self.refresh_window
+  }
+
+  /// Check if single tokens can be revoked (`POST /users/logout`).
+  pub fn revocation_enabled(&self) -> bool {
+    self.revocation
+  }
+
+  /// Set the lifetime of new tokens.
+  pub fn with_expiry(mut self, expiry: Duration) -> Self {
+    self.expiry = expiry;
+    self
+  }
+
+  /// Load the secret from `auth.jwt_secret_file`, `auth.jwt_secret` or the
+  /// `JWT_SECRET` environment variable (in that order).
+  ///
+  /// A secret shorter than `auth.min_secret_len` is an error, only a warning
+  /// with `debug = true`.  HS256 tokens signed with a short secret can be
+  /// brute-forced offline.
+  pub fn from_config(config: &AppConfig) -> Result<Self> {
+    let secret = match config.get_secret_file("auth.jwt_secret_file")? {
+      Some(secret) => Some(secret),
+      None => match config.get_str("auth.jwt_secret")? {
+        Some(secret) => Some(secret),
+        None => dotenv::var("JWT_SECRET").ok(),
+      },
+    };
+    let min_len = config.get_int("auth.min_secret_len")?
+      .map(|len| len.max(0) as usize).unwrap_or(DEFAULT_MIN_SECRET_LEN);
+    if let Err(err) = check_secret(secret.as_deref(), min_len) {
+      // only refuse to start a short secret in production.
+      if secret.is_some() && config.get_bool("debug")?.unwrap_or(false) {
+        warn!("Insecure JWT secret: {:?}", err);
+      } else {
+        return Err(err);
+      }
+    }
+    let expiry = config.get_int("auth.jwt_expiry_secs")?.unwrap_or(DEFAULT_EXPIRY_SECS);
+    if expiry <= 0 {
+      return Err(config::ConfigError::Message(
+        format!("auth.jwt_expiry_secs must be > 0, got {}", expiry)).into());
+    }
+    let mut keys = Self::from_secret(&secret.unwrap_or_default()).with_expiry(Duration::seconds(expiry));
+    keys.revocation = config.get_bool("auth.enable_revocation")?.unwrap_or(false);
+    let refresh_window = config.get_int("auth.refresh_window_secs")?
+      .unwrap_or(DEFAULT_REFRESH_WINDOW_SECS).max(0);
+    keys.refresh_window = Duration::seconds(refresh_window);
+    Ok(keys)
+  }
 }
 
 pub trait GenerateJwt {
-  fn generate_jwt(&self) -> Result<String>;
+  fn generate_jwt(&self, keys: &JwtKeys) -> Result<String>;
 }
 
 pub trait DecodeJwt {
-  fn decode_jwt(&self) -> Result<AuthData>;
+  fn decode_jwt(&self, keys: &JwtKeys) -> Result<AuthData>;
 }
 
 impl GenerateJwt for User {
-  fn generate_jwt(&self) -> Result<String> {
+  fn generate_jwt(&self, keys: &JwtKeys) -> Result<String> {
     let claims = Claims{
       id: self.id,
-      exp: (Utc::now() + Duration::days(21)).timestamp(),
+      exp: (Utc::now() + keys.expiry).timestamp(),
+      ver: self.token_version,
+      jti: Some(format!("{:032x}", rand::thread_rng().gen::<u128>())),
     };
 
     let header = Header::default();
-    let secret = &EncodingKey::from_secret(get_secret().as_ref());
-    let token = encode(&header, &claims, secret)?;
+    let token = encode(&header, &claims, &keys.encoding)?;
 
     Ok(token)
   }
 }
 
+/// Tokens are HS256 signed and checked for expiry without leeway.
+fn validation() -> Validation {
+  Validation {
+    leeway: 0,
+    validate_exp: true,
+    algorithms: vec![Algorithm::HS256],
+    ..Validation::default()
+  }
+}
+
 impl DecodeJwt for String {
-  fn decode_jwt(&self) -> Result<AuthData> {
-    let secret = get_secret();
-    let secret_key = DecodingKey::from_secret(secret.as_ref());
-    let token = decode::<Claims>(&self, &secret_key, &Validation::default())?;
+  fn decode_jwt(&self, keys: &JwtKeys) -> Result<AuthData> {
+    let token = decode::<Claims>(self, &keys.decoding, &validation())?;
+    // `Validation` only rejects `exp < now`, the token already expired at `exp`.
+    if token.claims.exp <= Utc::now().timestamp() {
+      return Err(JwtError::from(ErrorKind::ExpiredSignature).into());
+    }
     Ok(AuthData{
       user_id: token.claims.id,
       token: self.to_string(),
+      token_version: token.claims.ver,
+      jti: token.claims.jti,
+      exp: token.claims.exp,
     })
   }
 }
 
-fn get_secret() -> String {
-  dotenv::var("JWT_SECRET")
-    .expect("Missing JWT_SECRET environment variable.")
+/// Check that the JWT secret is set and has at least `min_len` bytes.
+fn check_secret(secret: Option<&str>, min_len: usize) -> Result<()> {
+  let secret = secret.ok_or_else(|| {
+    config::ConfigError::Message("Missing JWT secret, set auth.jwt_secret_file, auth.jwt_secret or JWT_SECRET".to_string())
+  })?;
+  if secret.len() < min_len {
+    return Err(config::ConfigError::Message(format!(
+      "JWT secret is too short ({} bytes), auth.min_secret_len requires at least {}",
+      secret.len(), min_len)).into());
+  }
+  Ok(())
+}
+
+/// A user to sign test tokens for (not stored).
+#[cfg(test)]
+pub(crate) fn test_jwt_user() -> User {
+  User {
+    id: 1,
+    username: "jwt".to_string(),
+    email: "jwt@example.com".to_string(),
+    password: String::new(),
+    bio: None,
+    image: None,
+    created_at: Utc::now().naive_utc(),
+    updated_at: Utc::now().naive_utc(),
+    token_version: 3,
+  }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn secret_error(secret: Option<&str>, min_len: usize) -> Option<String> {
+    match check_secret(secret, min_len) {
+      Ok(()) => None,
+      Err(Error::ConfigError { source }) => Some(source.to_string()),
+      Err(err) => panic!("expected a config error: {:?}", err),
+    }
+  }
+
+  #[test]
+  fn short_secrets_are_rejected() {
+    let strong = "0123456789abcdef0123456789abcdef";
+    assert_eq!(secret_error(Some(strong), DEFAULT_MIN_SECRET_LEN), None);
+    assert_eq!(secret_error(Some(&strong[1..]), DEFAULT_MIN_SECRET_LEN).as_deref(),
+      Some("JWT secret is too short (31 bytes), auth.min_secret_len requires at least 32"));
+    assert_eq!(secret_error(Some("short"), 0), None);
+    assert!(secret_error(None, 0).unwrap().contains("Missing JWT secret"));
+  }
+
+  fn keys_error(config: &AppConfig) -> String {
+    match JwtKeys::from_config(config) {
+      Ok(_) => panic!("expected a config error"),
+      Err(Error::ConfigError { source }) => source.to_string(),
+      Err(err) => panic!("expected a config error: {:?}", err),
+    }
+  }
+
+  #[test]
+  fn keys_from_config() {
+    let user = test_jwt_user();
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("auth.jwt_secret", "0123456789abcdef0123456789abcdef").unwrap();
+    let keys = JwtKeys::from_config(&config).unwrap();
+    let token = user.generate_jwt(&keys).unwrap();
+    let auth = token.decode_jwt(&keys).unwrap();
+    assert_eq!((auth.user_id, auth.token_version), (1, 3));
+    // a token signed with another secret is rejected.
+    let other = JwtKeys::from_secret("another secret, also 32 bytes long");
+    assert!(token.decode_jwt(&other).is_err());
+
+    // the file takes precedence.
+    let file = crate::app::test_secret_file("jwt_keys", "from a file, at least 32 bytes long\n");
+    config.conf.set("auth.jwt_secret_file", file).unwrap();
+    let keys = JwtKeys::from_config(&config).unwrap();
+    assert!(token.decode_jwt(&keys).is_err());
+    let from_file = JwtKeys::from_secret("from a file, at least 32 bytes long");
+    assert!(user.generate_jwt(&keys).unwrap().decode_jwt(&from_file).is_ok());
+
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("auth.jwt_secret", "short").unwrap();
+    assert!(keys_error(&config).contains("JWT secret is too short (5 bytes)"));
+    // only a warning in debug mode.
+    config.conf.set("debug", true).unwrap();
+    assert!(JwtKeys::from_config(&config).is_ok());
+    config.conf.set("auth.min_secret_len", 0).unwrap();
+    config.conf.set("debug", false).unwrap();
+    assert!(JwtKeys::from_config(&config).is_ok());
+
+    config.conf.set("auth.jwt_expiry_secs", 0).unwrap();
+    assert_eq!(keys_error(&config), "auth.jwt_expiry_secs must be > 0, got 0");
+  }
+
+  #[test]
+  fn zero_expiry_is_expired() {
+    let keys = JwtKeys::from_secret("secret").with_expiry(Duration::zero());
+    let token = test_jwt_user().generate_jwt(&keys).unwrap();
+    match token.decode_jwt(&keys) {
+      Err(Error::JwtError { source }) => assert!(matches!(source.kind(), ErrorKind::ExpiredSignature)),
+      res => panic!("expected an expired token: {:?}", res),
+    }
+    let keys = keys.with_expiry(Duration::seconds(60));
+    assert!(test_jwt_user().generate_jwt(&keys).unwrap().decode_jwt(&keys).is_ok());
+  }
+
+  #[test]
+  fn expiry_from_config() {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("auth.jwt_secret", "0123456789abcdef0123456789abcdef").unwrap();
+    config.conf.set("auth.jwt_expiry_secs", 60).unwrap();
+    let keys = JwtKeys::from_config(&config).unwrap();
+    assert_eq!(keys.expiry, Duration::seconds(60));
+    let token = test_jwt_user().generate_jwt(&keys).unwrap();
+    let claims = decode::<Claims>(&token, &keys.decoding, &validation()).unwrap().claims;
+    let left = claims.exp - Utc::now().timestamp();
+    assert!(left > 55 && left <= 60, "{}", left);
+
+    assert_eq!(JwtKeys::from_secret("secret").expiry, Duration::seconds(DEFAULT_EXPIRY_SECS));
+
+    // the same refresh window without the key, like conf/default.toml.
+    assert_eq!(keys.refresh_window(), Duration::seconds(DEFAULT_REFRESH_WINDOW_SECS));
+    config.conf.set("auth.refresh_window_secs", 0).unwrap();
+    assert_eq!(JwtKeys::from_config(&config).unwrap().refresh_window(), Duration::zero());
+  }
+}
@@ -1,19 +1,78 @@
-use libreauth::pass::{Algorithm, HashBuilder, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use libreauth::pass::{Algorithm, HashBuilder};
 
 use crate::error::*;
 
 pub const PWD_ALGORITHM: Algorithm = Algorithm::Argon2;
 pub const PWD_SCHEME_VERSION: usize = 1;
 
-// If the Hasher changes, make sure to increment PWD_SCHEME_VERSION
-lazy_static! {
-  pub static ref HASHER: Hasher = {
-    HashBuilder::new()
-      .algorithm(PWD_ALGORITHM)
-      .version(PWD_SCHEME_VERSION)
-      .finalize()
-      .unwrap()
+/// Supported password hashing algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+  Argon2,
+  Pbkdf2,
+}
+
+impl HashAlgorithm {
+  fn algorithm(&self) -> Algorithm {
+    match self {
+      HashAlgorithm::Argon2 => Algorithm::Argon2,
+      HashAlgorithm::Pbkdf2 => Algorithm::Pbkdf2,
+    }
+  }
+
+  /// PHC string identifier prefix ("$argon2", "$pbkdf2-sha512", etc.)
+  fn phc_id(&self) -> &'static str {
+    match self {
+      HashAlgorithm::Argon2 => "argon2",
+      HashAlgorithm::Pbkdf2 => "pbkdf2",
+    }
+  }
+}
+
+impl FromStr for HashAlgorithm {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "argon2" => Ok(HashAlgorithm::Argon2),
+      "pbkdf2" => Ok(HashAlgorithm::Pbkdf2),
+      _ => Err(config::ConfigError::Message(
+        format!("Invalid hash algorithm '{}', expected 'argon2' or 'pbkdf2'", s)
+      ).into()),
+    }
+  }
+}
+
+// Selected once at startup, defaults to `PWD_ALGORITHM`/`PWD_SCHEME_VERSION`.
+static ALGORITHM: AtomicU8 = AtomicU8::new(0);
+static SCHEME_VERSION: AtomicUsize = AtomicUsize::new(PWD_SCHEME_VERSION);
+
+/// Set the algorithm and scheme version used for new password hashes.
+///
+/// Stored hashes using a different algorithm or version are still checked
+/// and will be flagged for rehashing.
+/// If the hasher settings change, make sure to increment the version.
+pub fn set_hash_algorithm(algorithm: HashAlgorithm, version: usize) {
+  let val = match algorithm {
+    HashAlgorithm::Argon2 => 0,
+    HashAlgorithm::Pbkdf2 => 1,
   };
+  ALGORITHM.store(val, Ordering::Relaxed);
+  SCHEME_VERSION.store(version, Ordering::Relaxed);
+}
+
+pub fn get_hash_algorithm() -> HashAlgorithm {
+  match ALGORITHM.load(Ordering::Relaxed) {
+    1 => HashAlgorithm::Pbkdf2,
+    _ => HashAlgorithm::Argon2,
+  }
+}
+
+fn get_scheme_version() -> usize {
+  SCHEME_VERSION.load(Ordering::Relaxed)
 }
 
 #[derive(Debug)]
@@ -30,10 +89,27 @@ impl CheckedPass {
   }
 }
 
+/// Check if a stored PHC hash uses a different algorithm than the configured one.
+fn is_other_algorithm(stored: &str) -> bool {
+  let id = stored.split('$').nth(1).unwrap_or("");
+  !id.starts_with(get_hash_algorithm().phc_id())
+}
+
+/// Legacy bcrypt hashes (`$2a$`, `$2b$`, `$2y$`) aren't PHC strings.
+fn is_bcrypt(stored: &str) -> bool {
+  ["$2a$", "$2b$", "$2y$"].iter().any(|id| stored.starts_with(id))
+}
+
 pub fn check_password(stored: &str, password: &str) -> Result<CheckedPass> {
+  if is_bcrypt(stored) {
+    // only checked, new hashes never use bcrypt.
+    let is_valid = bcrypt::verify(password, stored)
+      .map_err(|e| Error::PasswordError(e.to_string()))?;
+    return Ok(CheckedPass::new(is_valid, is_valid));
+  }
   let checker = HashBuilder::from_phc(stored)?;
   if checker.is_valid(password) {
-    if checker.needs_update(Some(PWD_SCHEME_VERSION)) {
+    if is_other_algorithm(stored) || checker.needs_update(Some(get_scheme_version())) {
       Ok(CheckedPass::new(true, true))
     } else {
       Ok(CheckedPass::new(true, false))
@@ -44,6 +120,32 @@ pub fn check_password(stored: &str, password: &str) -> Result<CheckedPass> {
 }
 
 pub fn hash_password(password: &str) -> Result<String> {
-  Ok(HASHER.hash(password)?)
+  let hasher = HashBuilder::new()
+    .algorithm(get_hash_algorithm().algorithm())
+    .version(get_scheme_version())
+    .finalize()?;
+  Ok(hasher.hash(password)?)
 }
 
+/// bcrypt("password", cost 4)
+#[cfg(test)]
+pub(crate) const BCRYPT_PASSWORD: &str =
+  "$2b$04$HpVZHpn31bYTi3S/ZX.CQeET3PHmd/XzGzEi5pJxIOzGeEdUKvRZq";
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bcrypt_hashes_are_upgraded() {
+    let res = check_password(BCRYPT_PASSWORD, "password").unwrap();
+    assert!(res.is_valid && res.needs_update);
+    let res = check_password(BCRYPT_PASSWORD, "wrong").unwrap();
+    assert!(!res.is_valid && !res.needs_update);
+
+    let hash = hash_password("password").unwrap();
+    assert!(!is_bcrypt(&hash));
+    let res = check_password(&hash, "password").unwrap();
+    assert!(res.is_valid && !res.needs_update);
+  }
+}
@@ -1,50 +1,72 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
 use slug::slugify;
 
-use tokio_postgres::Row;
+use tokio_postgres::{Row, error::SqlState, types::ToSql};
 
 use crate::error::*;
 
 use crate::auth::*;
 use crate::models::*;
 use crate::forms::article::*;
+use crate::forms::page::DEFAULT_PAGE_LIMIT;
 
 use crate::db::*;
 use crate::db::util::*;
 
 #[derive(Clone)]
 pub struct ArticleService {
+  // load favorited/following flags of lists with `viewer_flags`.
+  bulk_flags: bool,
+  viewer_flags: VersionedStatement,
+
   // get one article
   article_by_id: VersionedStatement,
   article_by_slug: VersionedStatement,
+  article_by_author_slug: VersionedStatement,
+  article_by_scoped_slug: VersionedStatement,
 
   // store article
   store_article: VersionedStatement,
-  add_tag: VersionedStatement,
-  delete_tag: VersionedStatement,
 
   // update article
   update_article: VersionedStatement,
+  get_revisions: VersionedStatement,
 
   // delete article
   delete_article: VersionedStatement,
-  delete_article_tags: VersionedStatement,
-  delete_article_favs: VersionedStatement,
-  delete_article_comments: VersionedStatement,
+  count_article_dependents: VersionedStatement,
+  is_deleted_slug: VersionedStatement,
+  is_deleted_author_slug: VersionedStatement,
 
   // get multiple articles
   get_articles: VersionedStatement,
   get_articles_by_author: VersionedStatement,
   get_articles_by_tag: VersionedStatement,
   get_articles_by_favorite: VersionedStatement,
+  get_articles_filtered: VersionedStatement,
+  get_articles_oldest: VersionedStatement,
+  get_articles_most_favorited: VersionedStatement,
+  count_articles: VersionedStatement,
+  count_articles_by_author: VersionedStatement,
+  count_articles_by_tag: VersionedStatement,
+  count_articles_by_favorite: VersionedStatement,
+  count_articles_filtered: VersionedStatement,
+  get_user_favorites: VersionedStatement,
+  count_user_favorites: VersionedStatement,
+  get_user_articles: VersionedStatement,
+  get_articles_before: VersionedStatement,
 
   // get user's feed
   get_feed: VersionedStatement,
+  count_feed: VersionedStatement,
+  count_feed_since: VersionedStatement,
 
   // (un)favorite article
   favorite_article: VersionedStatement,
+  favorite_follow_article: VersionedStatement,
   unfavorite_article: VersionedStatement,
+  favorite_slugs_by_user: VersionedStatement,
 }
 
 lazy_static! {
@@ -75,6 +97,45 @@ lazy_static! {
   };
 }
 
+/// Slug for an article title.
+///
+/// `slugify` transliterates non-ASCII characters (so Cyrillic/CJK titles keep
+/// their words), a title without any usable characters gets a slug made from
+/// a hash of the title instead of an empty one, so previews match the stored
+/// slug.  Slugs taken by fixed `/articles/...` routes get an `-article`
+/// suffix, so those articles can still be read.
+pub fn title_slug(title: &str) -> String {
+  let slug = slugify(title);
+  if slug.is_empty() {
+    format!("article-{:016x}", title_hash(title))
+  } else if RESERVED_SLUGS.contains(&slug.as_str()) {
+    format!("{}-article", slug)
+  } else {
+    slug
+  }
+}
+
+/// Slugs shadowed by the fixed `/articles/<name>` routes.
+const RESERVED_SLUGS: &[&str] = &["feed", "slug-preview"];
+
+/// FNV-1a hash, stable between builds (unlike `DefaultHasher`).
+fn title_hash(title: &str) -> u64 {
+  title.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+    (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+  })
+}
+
+/// Check if `slug` could have been made by `title_slug`: lowercase ASCII
+/// letters and digits, with single dashes between words.
+pub fn is_valid_slug(slug: &str, max_len: usize) -> bool {
+  if slug.is_empty() || (max_len > 0 && slug.len() > max_len) {
+    return false;
+  }
+  slug.split('-').all(|word| {
+    !word.is_empty() && word.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+  })
+}
+
 fn article_details_from_row(row: &Row) -> ArticleDetails {
   let id: i32 = row.get(0);
   let slug: String = row.get(1);
@@ -110,12 +171,16 @@ fn article_details_from_row(row: &Row) -> ArticleDetails {
     tag_list: tags,
     favorited: favorited == 1,
     favorites_count: favorites_count.into(),
+    url: None,
     author: Profile {
       user_id,
       username,
       bio,
       image,
       following: following == 1,
+      created_at: None,
+      articles_count: None,
+      profile_url: None,
     },
   }
 }
@@ -128,16 +193,56 @@ fn article_details_from_opt_row(row: &Option<Row>) -> Option<ArticleDetails> {
   }
 }
 
-#[derive(Debug)]
-enum TagChange {
-  Add,
-  Remove,
-  Keep,
+/// Result of storing an article.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreArticle {
+  Stored(i32),
+  /// The slug is already used (within the slug scope).
+  SlugUsed,
+  /// The author reached `Article.max_per_author`.
+  MaxPerAuthor,
+  /// The author's account is new and reached its hourly limit.
+  NewbieLimited,
+  /// The author has another article with the title
+  /// (`Article.unique_title_per_author`).
+  TitleUsed,
+}
+
+/// Checks of a new article, done by the insert.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArticleLimits {
+  /// Maximum articles per author (0 = unlimited).
+  pub max_per_author: i64,
+  pub newbie: NewbieLimit,
+  /// Titles are unique per author.
+  pub unique_title: bool,
+}
+
+/// 422 for a title used by another article of the author.
+pub fn title_used_error() -> Error {
+  Error::UnprocessableEntity(json!({
+    "errors": {
+      "title": ["already used by another of your articles"],
+    },
+  }))
+}
+
+/// Trim tags and drop empty or duplicate tags, keeping the authored order.
+pub fn clean_tag_list(tag_list: &[String]) -> Vec<String> {
+  let mut tags: Vec<String> = Vec::with_capacity(tag_list.len());
+  for tag in tag_list {
+    let tag = tag.trim();
+    if tag.is_empty() || tags.iter().any(|t| t == tag) {
+      continue;
+    }
+    tags.push(tag.to_string());
+  }
+  tags
 }
 
 static ARTICLE_DETAILS_SELECT: &'static str = r#"
 SELECT a.id, slug, title, description, body, a.created_at, a.updated_at,
-  (SELECT STRING_AGG(tag_name, ',') FROM article_tags WHERE article_id = a.id) AS TagList,
+  (SELECT STRING_AGG(tag_name, ',' ORDER BY ordinal, tag_name) FROM article_tags WHERE article_id = a.id) AS TagList,
   (SELECT COUNT(*)::integer FROM favorite_articles WHERE article_id = a.id AND user_id = $1) AS Favorited,
   (SELECT COUNT(*)::integer FROM favorite_articles WHERE article_id = a.id) AS FavoritesCount,
   u.id, u.username, u.bio, u.image,
@@ -145,12 +250,90 @@ SELECT a.id, slug, title, description, body, a.created_at, a.updated_at,
 FROM articles a INNER JOIN users u ON a.author_id = u.id
 "#;
 
+// Same columns as `ARTICLE_DETAILS_SELECT`, without the per-row viewer
+// subqueries (and the viewer's $1).  Favorited/Following are loaded by
+// `viewer_flags`.
+static ARTICLE_LIST_SELECT: &str = r#"
+SELECT a.id, slug, title, description, body, a.created_at, a.updated_at,
+  (SELECT STRING_AGG(tag_name, ',' ORDER BY ordinal, tag_name) FROM article_tags WHERE article_id = a.id) AS TagList,
+  0 AS Favorited,
+  (SELECT COUNT(*)::integer FROM favorite_articles WHERE article_id = a.id) AS FavoritesCount,
+  u.id, u.username, u.bio, u.image,
+  0 AS Following
+FROM articles a INNER JOIN users u ON a.author_id = u.id
+"#;
+
+static ARTICLES_FROM: &str = "FROM articles a INNER JOIN users u ON a.author_id = u.id";
+
+/// Filter of an article list, selected by the set `ArticleRequest` filters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArticleFilter {
+  None,
+  Author,
+  Tag,
+  Favorited,
+  /// More than one filter, unused filters are NULL.
+  Combined,
+}
+
+impl ArticleFilter {
+  fn from_request(req: &ArticleRequest) -> Self {
+    let filters = [&req.author, &req.tag, &req.favorited].iter()
+      .filter(|filter| filter.is_some()).count();
+    if filters > 1 {
+      ArticleFilter::Combined
+    } else if req.author.is_some() {
+      ArticleFilter::Author
+    } else if req.tag.is_some() {
+      ArticleFilter::Tag
+    } else if req.favorited.is_some() {
+      ArticleFilter::Favorited
+    } else {
+      ArticleFilter::None
+    }
+  }
+
+  /// Joins and WHERE clause, after `FROM articles a INNER JOIN users u`.
+  /// The filter parameters start at `$<first>`.
+  fn clause(self, first: usize) -> String {
+    match self {
+      ArticleFilter::None => String::new(),
+      ArticleFilter::Author => format!("WHERE u.username = ${}", first),
+      ArticleFilter::Tag => format!(r#"INNER JOIN article_tags t ON a.id = t.article_id
+          WHERE t.tag_name = ${}"#, first),
+      ArticleFilter::Favorited => format!(r#"INNER JOIN favorite_articles fav_art ON a.id = fav_art.article_id
+          INNER JOIN users fav_u ON fav_art.user_id = fav_u.id
+          WHERE fav_u.username = ${}"#, first),
+      // author ($first), tag ($first + 1) and favorited ($first + 2).
+      ArticleFilter::Combined => format!(r#"WHERE (${a}::text IS NULL OR u.username = ${a})
+          AND (${t}::text IS NULL OR EXISTS (
+            SELECT 1 FROM article_tags t WHERE t.article_id = a.id AND t.tag_name = ${t}))
+          AND (${f}::text IS NULL OR EXISTS (
+            SELECT 1 FROM favorite_articles fav_art
+              INNER JOIN users fav_u ON fav_art.user_id = fav_u.id
+            WHERE fav_art.article_id = a.id AND fav_u.username = ${f}))"#,
+        a = first, t = first + 1, f = first + 2),
+    }
+  }
+
+  /// Filter parameters, in the order used by `clause`.
+  fn params(self, req: &ArticleRequest) -> Vec<&(dyn ToSql + Sync)> {
+    match self {
+      ArticleFilter::None => vec![],
+      ArticleFilter::Author => vec![&req.author],
+      ArticleFilter::Tag => vec![&req.tag],
+      ArticleFilter::Favorited => vec![&req.favorited],
+      ArticleFilter::Combined => vec![&req.author, &req.tag, &req.favorited],
+    }
+  }
+}
+
 static FEED_DETAILS_SELECT: &'static str = r#"
 WITH following(author_id) AS (
   SELECT user_id FROM followers WHERE follower_id = $1
 )
 SELECT a.id, slug, title, description, body, a.created_at, a.updated_at,
-  (SELECT STRING_AGG(tag_name, ',') FROM article_tags WHERE article_id = a.id) AS TagList,
+  (SELECT STRING_AGG(tag_name, ',' ORDER BY ordinal, tag_name) FROM article_tags WHERE article_id = a.id) AS TagList,
   (SELECT COUNT(*)::integer FROM favorite_articles WHERE article_id = a.id AND user_id = $1) AS Favorited,
   (SELECT COUNT(*)::integer FROM favorite_articles WHERE article_id = a.id) AS FavoritesCount,
   u.id, u.username, u.bio, u.image,
@@ -159,6 +342,21 @@ FROM following f INNER JOIN articles a ON a.author_id = f.author_id
   INNER JOIN users u ON a.author_id = u.id
 "#;
 
+// `FEED_DETAILS_SELECT` without the Favorited subquery, loaded by `viewer_flags`.
+static FEED_LIST_SELECT: &str = r#"
+WITH following(author_id) AS (
+  SELECT user_id FROM followers WHERE follower_id = $1
+)
+SELECT a.id, slug, title, description, body, a.created_at, a.updated_at,
+  (SELECT STRING_AGG(tag_name, ',' ORDER BY ordinal, tag_name) FROM article_tags WHERE article_id = a.id) AS TagList,
+  0 AS Favorited,
+  (SELECT COUNT(*)::integer FROM favorite_articles WHERE article_id = a.id) AS FavoritesCount,
+  u.id, u.username, u.bio, u.image,
+  1::integer AS Following
+FROM following f INNER JOIN articles a ON a.author_id = f.author_id
+  INNER JOIN users u ON a.author_id = u.id
+"#;
+
 impl ArticleService {
   pub fn new(cl: SharedClient) -> Result<ArticleService> {
     // Build article_by_* queries
@@ -166,109 +364,318 @@ impl ArticleService {
         &format!(r#"{} WHERE a.id = $2"#, ARTICLE_DETAILS_SELECT))?;
     let article_by_slug = VersionedStatement::new(cl.clone(),
         &format!(r#"{} WHERE a.slug = $2"#, ARTICLE_DETAILS_SELECT))?;
+    let article_by_author_slug = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE u.username = $2 AND a.slug = $3"#, ARTICLE_DETAILS_SELECT))?;
+    // author scoped slugs: prefer the current user's article, at most 2 rows
+    // are needed to detect an ambiguous slug.
+    let article_by_scoped_slug = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE a.slug = $2
+          ORDER BY (a.author_id = $1) DESC, a.id LIMIT 2"#, ARTICLE_DETAILS_SELECT))?;
 
     // store article query
+    // $6 = slugs are globally unique, otherwise unique per author (the unique
+    // indexes catch concurrent inserts).
+    // $7 = maximum articles per author (0 = unlimited), $8/$9 = the newbie
+    // limit (`NewbieLimit`), $10 = titles are unique per author, checked by
+    // the insert.  The tags ($11, ordered by `ordinal`) are added by the same
+    // statement, so the article is never stored with only part of its tags.
+    // With a limit, the author's articles are counted under a per-author lock
+    // (`lock_author_articles`), so concurrent stores can't both pass it.
     let store_article = VersionedStatement::new(cl.clone(),
-        r#"INSERT INTO articles(author_id, slug, title, description, body)
-        VALUES($1, $2, $3, $4, $5) RETURNING id"#)?;
-    let add_tag = VersionedStatement::new(cl.clone(),
-        r#"INSERT INTO article_tags(article_id, tag_name)
-        VALUES($1, $2)"#)?;
-    let delete_tag = VersionedStatement::new(cl.clone(),
-        r#"DELETE FROM article_tags WHERE article_id = $1 AND tag_name = $2"#)?;
-
-    // update article query
+        r#"WITH counts AS (
+          SELECT total, last_hour FROM lock_author_articles($1) WHERE $7::bigint > 0 OR $9::bigint > 0
+        ), limits AS (
+          SELECT $7::bigint > 0 AND (SELECT total FROM counts) >= $7 AS max_reached,
+            $9::bigint > 0
+              AND EXISTS (SELECT 1 FROM users
+                WHERE id = $1 AND created_at > LOCALTIMESTAMP - make_interval(hours => $8::integer))
+              AND (SELECT last_hour FROM counts) >= $9
+              AS newbie_limited,
+            $10::boolean AND EXISTS (SELECT 1 FROM articles WHERE author_id = $1 AND title = $3)
+              AS title_used
+        ), new_article AS (
+          INSERT INTO articles(author_id, slug, title, description, body, global_slug)
+          SELECT $1, $2, $3, $4, $5, $6 FROM limits
+          WHERE NOT limits.max_reached AND NOT limits.newbie_limited AND NOT limits.title_used
+            AND NOT EXISTS (SELECT 1 FROM articles WHERE slug = $2 AND ($6 OR author_id = $1))
+          RETURNING id
+        ), tags AS (
+          INSERT INTO article_tags(article_id, tag_name, ordinal)
+          SELECT a.id, tag, (ord - 1)::integer
+          FROM new_article a, UNNEST($11::text[]) WITH ORDINALITY AS t(tag, ord)
+        )
+        SELECT (SELECT id FROM new_article), max_reached, newbie_limited, title_used FROM limits"#)?.write().non_idempotent();
+    // update article query, the old version is saved as a revision when $7 > 0
+    // and an article field changes, only the newest $7 revisions are kept.  $8 = the title must be unique
+    // per author.  The tags are replaced by $9 (NULL = unchanged) in the same
+    // statement, only when the article is updated.
     let update_article = VersionedStatement::new(cl.clone(),
-        r#"UPDATE articles SET slug = $2, title = $3, description = $4, body = $5
-        WHERE id = $1"#)?;
+        r#"WITH checks AS (
+          SELECT $8::boolean AND EXISTS (SELECT 1 FROM articles o INNER JOIN articles a
+            ON a.id = $1 AND o.author_id = a.author_id
+            WHERE o.title = $3 AND o.id <> $1) AS title_used
+        ), revision AS (
+          INSERT INTO article_revisions(article_id, slug, title, description, body, created_at)
+          SELECT id, slug, title, description, body, updated_at FROM articles, checks
+          WHERE id = $1 AND $7::bigint > 0 AND NOT checks.title_used AND NOT EXISTS (SELECT 1 FROM articles o
+            WHERE o.slug = $2 AND o.id <> $1 AND ($6 OR o.author_id = articles.author_id))
+            AND (slug, title, description, body) IS DISTINCT FROM ($2, $3, $4, $5)
+          RETURNING id
+        ), updated AS (
+          UPDATE articles SET slug = $2, title = $3, description = $4, body = $5, global_slug = $6
+          FROM checks
+          WHERE id = $1 AND NOT checks.title_used AND NOT EXISTS (SELECT 1 FROM articles o
+            WHERE o.slug = $2 AND o.id <> $1 AND ($6 OR o.author_id = articles.author_id))
+          RETURNING id
+        ), pruned AS (
+          DELETE FROM article_revisions WHERE EXISTS (SELECT 1 FROM revision)
+            AND article_id = $1 AND id NOT IN (
+              SELECT id FROM article_revisions WHERE article_id = $1 ORDER BY id DESC LIMIT $7 - 1)
+        ), removed_tags AS (
+          DELETE FROM article_tags WHERE $9::text[] IS NOT NULL
+            AND article_id IN (SELECT id FROM updated) AND tag_name <> ALL($9::text[])
+        ), tags AS (
+          INSERT INTO article_tags(article_id, tag_name, ordinal)
+          SELECT u.id, tag, (ord - 1)::integer
+          FROM updated u, UNNEST($9::text[]) WITH ORDINALITY AS t(tag, ord)
+            ON CONFLICT (article_id, tag_name)
+          DO UPDATE SET ordinal = EXCLUDED.ordinal
+        )
+        SELECT (SELECT id FROM updated), title_used FROM checks"#)?.write().non_idempotent();
+    let get_revisions = VersionedStatement::new(cl.clone(),
+        r#"SELECT slug, title, description, body, created_at FROM article_revisions
+        WHERE article_id = $1 ORDER BY id DESC"#)?;
 
     // delete article query
+    // keep the slug, so fetching it can be told apart from a missing slug.
+    // delete an article and its dependents with one statement, only when
+    // its comments + favorites match $2 (NULL = any).  A comment/favorite
+    // added concurrently fails the statement (foreign keys) instead of being
+    // removed unconfirmed.
     let delete_article = VersionedStatement::new(cl.clone(),
-        r#"DELETE FROM articles WHERE id = $1"#)?;
-    let delete_article_tags = VersionedStatement::new(cl.clone(),
-        r#"DELETE FROM article_tags WHERE article_id = $1"#)?;
-    let delete_article_favs = VersionedStatement::new(cl.clone(),
-        r#"DELETE FROM favorite_articles WHERE article_id = $1"#)?;
-    let delete_article_comments = VersionedStatement::new(cl.clone(),
-        r#"DELETE FROM comments WHERE article_id = $1"#)?;
+        r#"WITH target AS (
+          SELECT id FROM articles WHERE id = $1
+            AND ($2::bigint IS NULL OR $2 =
+              (SELECT COUNT(*) FROM comments WHERE article_id = $1)
+              + (SELECT COUNT(*) FROM favorite_articles WHERE article_id = $1))
+        ), tags AS (
+          DELETE FROM article_tags WHERE article_id IN (SELECT id FROM target)
+        ), favorites AS (
+          DELETE FROM favorite_articles WHERE article_id IN (SELECT id FROM target)
+        ), comments AS (
+          DELETE FROM comments WHERE article_id IN (SELECT id FROM target)
+        ), revisions AS (
+          DELETE FROM article_revisions WHERE article_id IN (SELECT id FROM target)
+        ), deleted AS (
+          DELETE FROM articles WHERE id IN (SELECT id FROM target) RETURNING slug, author_id
+        )
+        INSERT INTO deleted_articles(slug, author_id)
+        SELECT slug, author_id FROM deleted"#)?.write();
+    let is_deleted_slug = VersionedStatement::new(cl.clone(),
+        r#"SELECT EXISTS (SELECT 1 FROM deleted_articles WHERE slug = $1)"#)?;
+    let is_deleted_author_slug = VersionedStatement::new(cl.clone(),
+        r#"SELECT EXISTS (SELECT 1 FROM deleted_articles d INNER JOIN users u ON u.id = d.author_id
+          WHERE u.username = $1 AND d.slug = $2)"#)?;
+    let count_article_dependents = VersionedStatement::new(cl.clone(),
+        r#"SELECT
+          (SELECT COUNT(*) FROM comments WHERE article_id = $1) AS CommentsCount,
+          (SELECT COUNT(*) FROM favorite_articles WHERE article_id = $1) AS FavoritesCount"#)?;
 
     // Build get_articles queries
-    let get_articles = VersionedStatement::new(cl.clone(),
-        &format!(r#"{} ORDER BY a.id DESC LIMIT $2 OFFSET $3 "#, ARTICLE_DETAILS_SELECT))?;
-    let get_articles_by_author = VersionedStatement::new(cl.clone(),
-        &format!(r#"{} WHERE u.username = $4
-          ORDER BY a.id DESC LIMIT $2 OFFSET $3 "#, ARTICLE_DETAILS_SELECT))?;
-    let get_articles_by_tag = VersionedStatement::new(cl.clone(),
-        &format!(r#"{} INNER JOIN article_tags t ON a.id = t.article_id
-          WHERE t.tag_name = $4
-          ORDER BY a.id DESC LIMIT $2 OFFSET $3 "#, ARTICLE_DETAILS_SELECT))?;
-    let get_articles_by_favorite = VersionedStatement::new(cl.clone(),
+    let order_by = build_order_by("a", "id", true);
+    let bulk_flags = cl.config().bulk_article_flags;
+    let (list_select, feed_select) = if bulk_flags {
+      (ARTICLE_LIST_SELECT, FEED_LIST_SELECT)
+    } else {
+      (ARTICLE_DETAILS_SELECT, FEED_DETAILS_SELECT)
+    };
+    // 1 = favorited article ids, 2 = followed author ids.
+    let viewer_flags = VersionedStatement::new(cl.clone(),
+        r#"SELECT 1, article_id FROM favorite_articles WHERE user_id = $1 AND article_id = ANY($2)
+        UNION ALL
+        SELECT 2, user_id FROM followers WHERE follower_id = $1 AND user_id = ANY($3)"#)?;
+    // the lists use $1-$3 (user, limit, offset) and the filters from $4.  With
+    // `bulk_flags` there is no user parameter, the others start at $1.  The
+    // counts only have the filter parameters.
+    let first = if bulk_flags { 1 } else { 2 };
+    let list = |filter: ArticleFilter| {
+      VersionedStatement::new(cl.clone(), &format!(r#"{} {} {} LIMIT ${} OFFSET ${} "#,
+        list_select, filter.clause(first + 2), order_by, first, first + 1))
+    };
+    let count = |filter: ArticleFilter| {
+      VersionedStatement::new(cl.clone(),
+        &format!(r#"SELECT COUNT(*) {} {}"#, ARTICLES_FROM, filter.clause(1)))
+    };
+    let get_articles = list(ArticleFilter::None)?;
+    let get_articles_by_author = list(ArticleFilter::Author)?;
+    let get_articles_by_tag = list(ArticleFilter::Tag)?;
+    let get_articles_by_favorite = list(ArticleFilter::Favorited)?;
+    let get_articles_filtered = list(ArticleFilter::Combined)?;
+    // other orders use the combined filter, any filter param can be NULL.
+    let sorted_list = |order_by: &str| {
+      VersionedStatement::new(cl.clone(), &format!(r#"{} {} {} LIMIT ${} OFFSET ${} "#,
+        list_select, ArticleFilter::Combined.clause(first + 2), order_by, first, first + 1))
+    };
+    let get_articles_oldest = sorted_list(&build_order_by("a", "id", false))?;
+    let get_articles_most_favorited = sorted_list(
+      &format!("ORDER BY FavoritesCount DESC, {}", order_by.trim_start_matches("ORDER BY ")))?;
+    let count_articles = count(ArticleFilter::None)?;
+    let count_articles_by_author = count(ArticleFilter::Author)?;
+    let count_articles_by_tag = count(ArticleFilter::Tag)?;
+    let count_articles_by_favorite = count(ArticleFilter::Favorited)?;
+    let count_articles_filtered = count(ArticleFilter::Combined)?;
+    // current user's favorites, keyed on the user id.
+    let get_user_favorites = VersionedStatement::new(cl.clone(),
         &format!(r#"{} INNER JOIN favorite_articles fav_art ON a.id = fav_art.article_id
-          INNER JOIN users fav_u ON fav_art.user_id = fav_u.id
-          WHERE fav_u.username = $4
-          ORDER BY a.id DESC LIMIT $2 OFFSET $3 "#, ARTICLE_DETAILS_SELECT))?;
+          WHERE fav_art.user_id = $1
+          {} LIMIT $2 OFFSET $3 "#, list_select, order_by))?;
+    let count_user_favorites = VersionedStatement::new(cl.clone(),
+        "SELECT COUNT(*) FROM favorite_articles WHERE user_id = $1")?;
+    // current user's own articles, keyed on the author id.
+    let get_user_articles = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE a.author_id = $1
+          {} LIMIT $2 "#, ARTICLE_DETAILS_SELECT, order_by))?;
+
+    // keyset paging, used for exporting all articles.
+    let get_articles_before = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE a.id < $2 ORDER BY a.id DESC LIMIT $3 "#, ARTICLE_DETAILS_SELECT))?;
 
     // Build get_feed queries
     let get_feed = VersionedStatement::new(cl.clone(),
-        &format!(r#"{} ORDER BY a.id DESC LIMIT $2 OFFSET $3 "#,
-        FEED_DETAILS_SELECT))?;
+        &format!(r#"{} {} LIMIT $2 OFFSET $3 "#,
+        feed_select, order_by))?;
+    let count_feed = VersionedStatement::new(cl.clone(),
+        r#"SELECT COUNT(*) FROM followers f INNER JOIN articles a ON a.author_id = f.user_id
+        WHERE f.follower_id = $1"#)?;
+    // count feed articles newer than an article id ($2) or time ($3).
+    let count_feed_since = VersionedStatement::new(cl.clone(),
+        r#"WITH following(author_id) AS (
+          SELECT user_id FROM followers WHERE follower_id = $1
+        )
+        SELECT COUNT(*) FROM following f INNER JOIN articles a ON a.author_id = f.author_id
+        WHERE ($2::integer IS NULL OR a.id > $2)
+          AND ($3::timestamp IS NULL OR a.created_at > $3)"#)?;
 
     // (un)favorite
     let favorite_article = VersionedStatement::new(cl.clone(),
-        &FAVORITE_COLUMNS.build_upsert("(user_id, article_id)", true))?;
+        &FAVORITE_COLUMNS.build_insert_ignore("(user_id, article_id)", true))?.write();
+    // favorite article and follow its author in one statement.
+    let favorite_follow_article = VersionedStatement::new(cl.clone(),
+        &format!(r#"WITH follow AS (
+          INSERT INTO followers(user_id, follower_id)
+          SELECT author_id, $1 FROM articles WHERE id = $2 AND author_id <> $1
+          ON CONFLICT (user_id, follower_id) DO NOTHING
+        ) {}"#, FAVORITE_COLUMNS.build_insert_ignore("(user_id, article_id)", true)))?.write();
     let unfavorite_article = VersionedStatement::new(cl.clone(),
-        "DELETE FROM favorite_articles WHERE user_id = $1 AND article_id = $2")?;
+        "DELETE FROM favorite_articles WHERE user_id = $1 AND article_id = $2")?.write();
+    let favorite_slugs_by_user = VersionedStatement::new(cl.clone(),
+        r#"SELECT a.slug FROM favorite_articles fav_art INNER JOIN articles a ON fav_art.article_id = a.id
+        WHERE fav_art.user_id = $1
+        ORDER BY a.id LIMIT $2"#)?;
 
     Ok(ArticleService {
+      bulk_flags,
+      viewer_flags,
+
       article_by_id,
       article_by_slug,
+      article_by_author_slug,
+      article_by_scoped_slug,
 
       store_article,
-      add_tag,
-      delete_tag,
 
       update_article,
+      get_revisions,
       delete_article,
-      delete_article_tags,
-      delete_article_favs,
-      delete_article_comments,
+      count_article_dependents,
+      is_deleted_slug,
+      is_deleted_author_slug,
 
       get_articles,
       get_articles_by_author,
       get_articles_by_tag,
       get_articles_by_favorite,
+      get_articles_filtered,
+      get_articles_oldest,
+      get_articles_most_favorited,
+      count_articles,
+      count_articles_by_author,
+      count_articles_by_tag,
+      count_articles_by_favorite,
+      count_articles_filtered,
+      get_user_favorites,
+      count_user_favorites,
+      get_user_articles,
+      get_articles_before,
       get_feed,
+      count_feed,
+      count_feed_since,
 
       favorite_article,
+      favorite_follow_article,
       unfavorite_article,
+      favorite_slugs_by_user,
     })
   }
 
+  /// All statements, by name.
+  pub fn statements(&self) -> Vec<(&'static str, &VersionedStatement)> {
+    vec![
+      ("viewer_flags", &self.viewer_flags),
+
+      ("article_by_id", &self.article_by_id),
+      ("article_by_slug", &self.article_by_slug),
+      ("article_by_author_slug", &self.article_by_author_slug),
+      ("article_by_scoped_slug", &self.article_by_scoped_slug),
+
+      ("store_article", &self.store_article),
+
+      ("update_article", &self.update_article),
+      ("get_revisions", &self.get_revisions),
+      ("delete_article", &self.delete_article),
+      ("count_article_dependents", &self.count_article_dependents),
+      ("is_deleted_slug", &self.is_deleted_slug),
+      ("is_deleted_author_slug", &self.is_deleted_author_slug),
+
+      ("get_articles", &self.get_articles),
+      ("get_articles_by_author", &self.get_articles_by_author),
+      ("get_articles_by_tag", &self.get_articles_by_tag),
+      ("get_articles_by_favorite", &self.get_articles_by_favorite),
+      ("get_articles_filtered", &self.get_articles_filtered),
+      ("get_articles_oldest", &self.get_articles_oldest),
+      ("get_articles_most_favorited", &self.get_articles_most_favorited),
+      ("count_articles", &self.count_articles),
+      ("count_articles_by_author", &self.count_articles_by_author),
+      ("count_articles_by_tag", &self.count_articles_by_tag),
+      ("count_articles_by_favorite", &self.count_articles_by_favorite),
+      ("count_articles_filtered", &self.count_articles_filtered),
+      ("get_user_favorites", &self.get_user_favorites),
+      ("count_user_favorites", &self.count_user_favorites),
+      ("get_user_articles", &self.get_user_articles),
+      ("get_articles_before", &self.get_articles_before),
+      ("get_feed", &self.get_feed),
+      ("count_feed", &self.count_feed),
+      ("count_feed_since", &self.count_feed_since),
+
+      ("favorite_article", &self.favorite_article),
+      ("favorite_follow_article", &self.favorite_follow_article),
+      ("unfavorite_article", &self.unfavorite_article),
+      ("favorite_slugs_by_user", &self.favorite_slugs_by_user),
+    ]
+  }
+
   pub async fn prepare(&self) -> Result<()> {
-    self.article_by_id.prepare().await?;
-    self.article_by_slug.prepare().await?;
-
-    self.store_article.prepare().await?;
-    self.add_tag.prepare().await?;
-    self.delete_tag.prepare().await?;
-
-    self.update_article.prepare().await?;
-    self.delete_article.prepare().await?;
-    self.delete_article_tags.prepare().await?;
-    self.delete_article_favs.prepare().await?;
-    self.delete_article_comments.prepare().await?;
-
-    self.get_articles.prepare().await?;
-    self.get_articles_by_author.prepare().await?;
-    self.get_articles_by_tag.prepare().await?;
-    self.get_articles_by_favorite.prepare().await?;
-    self.get_feed.prepare().await?;
-
-    self.favorite_article.prepare().await?;
-    self.unfavorite_article.prepare().await?;
+    for (_, statement) in self.statements() {
+      statement.prepare().await?;
+    }
     Ok(())
   }
 
+  /// Check if all statements are prepared for the current connection.
+  pub fn is_prepared(&self) -> bool {
+    self.statements().iter().all(|(_, statement)| statement.is_prepared())
+  }
+
   pub async fn get_by_id(&self, auth: &AuthData, article_id: i32) -> Result<Option<ArticleDetails>> {
     let row = self.article_by_id.query_opt(&[&auth.user_id, &article_id]).await?;
     Ok(article_details_from_opt_row(&row))
@@ -279,30 +686,66 @@ impl ArticleService {
     Ok(article_details_from_opt_row(&row))
   }
 
-  pub async fn store(&self, auth: &AuthData, article: &CreateArticle) -> Result<Option<i32>> {
-    let slug = slugify(&article.title);
-    match self.store_article.query_opt(&[
-        &auth.user_id, &slug, &article.title, &article.description, &article.body
-      ]).await? {
-      Some(row) => {
-        let article_id: i32 = row.get(0);
-        // add tags to new article.
-        for tag in &article.tag_list {
-          self.add_tag.execute(&[&article_id, &tag]).await?;
-        }
-        Ok(Some(article_id))
+  pub async fn get_by_author_slug(&self, auth: &AuthData, author: &str, slug: &str) -> Result<Option<ArticleDetails>> {
+    let row = self.article_by_author_slug.query_opt(&[&auth.user_id, &author, &slug]).await?;
+    Ok(article_details_from_opt_row(&row))
+  }
+
+  /// Lookup a slug without the author, for author scoped slugs.
+  ///
+  /// The current user's own article is used first, otherwise the slug must
+  /// only be used by one author.
+  pub async fn get_by_scoped_slug(&self, auth: &AuthData, slug: &str) -> Result<Option<ArticleDetails>> {
+    let rows = self.article_by_scoped_slug.query(&[&auth.user_id, &slug]).await?;
+    match rows.first().map(article_details_from_row) {
+      Some(article) if article.author.user_id == auth.user_id || rows.len() == 1 => {
+        Ok(Some(article))
       },
-      None => {
-        Ok(None)
-      }
+      _ => Ok(None),
     }
   }
 
-  pub async fn update(&self, article: &mut ArticleDetails, req: &UpdateArticle) -> Result<u64> {
+  /// Store a new article, unless the slug is already used (within `scope`)
+  /// or it fails one of the `limits`.
+  pub async fn store(&self, auth: &AuthData, article: &CreateArticle, scope: SlugScope, limits: ArticleLimits) -> Result<StoreArticle> {
+    let slug = title_slug(&article.title);
+    let global = scope == SlugScope::Global;
+    let tags = clean_tag_list(&article.tag_list);
+    let row = match self.store_article.query_one(&[
+        &auth.user_id, &slug, &article.title, &article.description, &article.body, &global,
+        &limits.max_per_author, &limits.newbie.period_hours, &limits.newbie.per_hour, &limits.unique_title,
+        &tags
+      ]).await {
+      Ok(row) => row,
+      // Stored by a concurrent request.
+      Err(Error::PgError { source }) if source.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+        return Ok(StoreArticle::SlugUsed);
+      },
+      Err(err) => return Err(err),
+    };
+    let article_id: Option<i32> = row.get(0);
+    let max_reached: bool = row.get(1);
+    let newbie_limited: bool = row.get(2);
+    let title_used: bool = row.get(3);
+    Ok(match article_id {
+      Some(id) => StoreArticle::Stored(id),
+      None if max_reached => StoreArticle::MaxPerAuthor,
+      None if newbie_limited => StoreArticle::NewbieLimited,
+      None if title_used => StoreArticle::TitleUsed,
+      None => StoreArticle::SlugUsed,
+    })
+  }
+
+  /// Returns `0` if the new slug is already used (within `scope`).  With
+  /// `unique_title`, a new title used by another article of the author is an
+  /// `UnprocessableEntity` error.
+  ///
+  /// The previous version is kept as a revision, up to `max_revisions` (0 = disabled).
+  pub async fn update(&self, article: &mut ArticleDetails, req: &UpdateArticle, scope: SlugScope, max_revisions: i64, unique_title: bool) -> Result<u64> {
     // Update article fields
     if let Some(title) = &req.title {
       article.title = title.clone();
-      article.slug = slugify(&title);
+      article.slug = title_slug(title);
     }
     if let Some(desc) = &req.description {
       article.description = desc.clone();
@@ -311,74 +754,880 @@ impl ArticleService {
       article.body = body.clone();
     }
     // store article changes.
-    self.update_article.execute(&[
-        &article.id, &article.slug, &article.title, &article.description, &article.body
-    ]).await?;
-
-    // update list of tags.
-    let mut tags = HashMap::new();
-    for tag in &article.tag_list {
-      // mark all old tags as remove.
-      tags.insert(tag, TagChange::Remove);
-    }
-    for tag in &req.tag_list {
-      tags.entry(&tag)
-        .and_modify(|e| *e = TagChange::Keep)
-        .or_insert(TagChange::Add);
+    let global = scope == SlugScope::Global;
+    // only a new title is checked.
+    let unique_title = unique_title && req.title.is_some();
+    let new_tags = req.tag_list.as_ref().map(|tag_list| clean_tag_list(tag_list));
+    match self.update_article.query_one(&[
+        &article.id, &article.slug, &article.title, &article.description, &article.body, &global,
+        &max_revisions, &unique_title, &new_tags
+    ]).await {
+      Ok(row) => {
+        let updated: Option<i32> = row.get(0);
+        let title_used: bool = row.get(1);
+        if title_used {
+          return Err(title_used_error());
+        }
+        if updated.is_none() {
+          return Ok(0);
+        }
+      },
+      // The slug was taken by a concurrent request.
+      Err(Error::PgError { source }) if source.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+        return Ok(0);
+      },
+      Err(err) => return Err(err),
     }
-
-    // apply tag changes
-    for (tag, change) in tags.iter() {
-      match change {
-        TagChange::Add => {
-          self.add_tag.execute(&[&article.id, &tag]).await?;
-        },
-        TagChange::Remove => {
-          self.delete_tag.execute(&[&article.id, &tag]).await?;
-        },
-        TagChange::Keep => (),
-      }
+    if let Some(new_tags) = new_tags {
+      article.tag_list = new_tags;
     }
 
     Ok(1)
   }
 
-  pub async fn delete(&self, article_id: i32) -> Result<u64> {
-    self.delete_article_tags.execute(&[&article_id]).await?;
-    self.delete_article_favs.execute(&[&article_id]).await?;
-    self.delete_article_comments.execute(&[&article_id]).await?;
-    Ok(self.delete_article.execute(&[&article_id]).await?)
+  /// Delete the article with its tags, favorites, comments and revisions.
+  ///
+  /// With `confirm`, nothing is deleted (`0`) unless the article has exactly
+  /// that many comments + favorites.
+  pub async fn delete(&self, article_id: i32, confirm: Option<i64>) -> Result<u64> {
+    self.delete_article.execute(&[&article_id, &confirm]).await
+  }
+
+  /// Check if an article with the slug was deleted.
+  pub async fn is_deleted_slug(&self, slug: &str) -> Result<bool> {
+    let row = self.is_deleted_slug.query_one(&[&slug]).await?;
+    Ok(row.get(0))
+  }
+
+  /// Check if an article by `author` with the slug was deleted.
+  pub async fn is_deleted_author_slug(&self, author: &str, slug: &str) -> Result<bool> {
+    let row = self.is_deleted_author_slug.query_one(&[&author, &slug]).await?;
+    Ok(row.get(0))
+  }
+
+  /// Previous versions of the article, newest first.
+  pub async fn get_revisions(&self, article_id: i32) -> Result<Vec<ArticleRevision>> {
+    let rows = self.get_revisions.query(&[&article_id]).await?;
+    Ok(rows.iter().map(|row| ArticleRevision {
+      slug: row.get(0),
+      title: row.get(1),
+      description: row.get(2),
+      body: row.get(3),
+      created_at: row.get(4),
+    }).collect())
+  }
+
+  /// Count the rows that would be removed along with the article.
+  pub async fn count_dependents(&self, article_id: i32) -> Result<ArticleDependents> {
+    let row = self.count_article_dependents.query_one(&[&article_id]).await?;
+    Ok(ArticleDependents {
+      comments_count: row.get(0),
+      favorites_count: row.get(1),
+    })
   }
 
   pub async fn favorite(&self, auth: &AuthData, article_id: i32) -> Result<u64> {
     Ok(self.favorite_article.execute(&[&auth.user_id, &article_id]).await?)
   }
 
+  /// Favorite the article and follow its author (unless it is the current user).
+  pub async fn favorite_and_follow(&self, auth: &AuthData, article_id: i32) -> Result<u64> {
+    self.favorite_follow_article.execute(&[&auth.user_id, &article_id]).await
+  }
+
   pub async fn unfavorite(&self, auth: &AuthData, article_id: i32) -> Result<u64> {
     Ok(self.unfavorite_article.execute(&[&auth.user_id, &article_id]).await?)
   }
 
+  /// Get a page of articles.
   pub async fn get_articles(&self, auth: &AuthData, req: ArticleRequest) -> Result<Vec<ArticleDetails>> {
-    let limit = req.limit.unwrap_or(20);
+    let limit = req.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
     let offset = req.offset.unwrap_or(0);
-    let rows = if let Some(author) = &req.author {
-      self.get_articles_by_author.query(&[&auth.user_id, &limit, &offset,
-        &author]).await?
-    } else if let Some(tag) = &req.tag {
-      self.get_articles_by_tag.query(&[&auth.user_id, &limit, &offset, &tag]).await?
-    } else if let Some(favorited) = &req.favorited {
-      self.get_articles_by_favorite.query(&[&auth.user_id, &limit, &offset, &favorited]).await?
+    // the single filter statements have simpler query plans.
+    let filter = ArticleFilter::from_request(&req);
+    let list = match filter {
+      ArticleFilter::None => &self.get_articles,
+      ArticleFilter::Author => &self.get_articles_by_author,
+      ArticleFilter::Tag => &self.get_articles_by_tag,
+      ArticleFilter::Favorited => &self.get_articles_by_favorite,
+      ArticleFilter::Combined => &self.get_articles_filtered,
+    };
+    let (list, list_filter) = match req.sort_order().unwrap_or(ArticleSort::Newest) {
+      ArticleSort::Newest => (list, filter),
+      ArticleSort::Oldest => (&self.get_articles_oldest, ArticleFilter::Combined),
+      ArticleSort::MostFavorited => (&self.get_articles_most_favorited, ArticleFilter::Combined),
+    };
+    let mut params: Vec<&(dyn ToSql + Sync)> = if self.bulk_flags {
+      vec![&limit, &offset]
     } else {
-      self.get_articles.query(&[&auth.user_id, &limit, &offset]).await?
+      vec![&auth.user_id, &limit, &offset]
     };
+    params.extend(list_filter.params(&req));
+    let rows = list.query(&params).await?;
+    self.list_from_rows(auth, &rows).await
+  }
+
+  /// Total number of articles matching the request's filters (all pages).
+  pub async fn count_articles(&self, req: &ArticleRequest) -> Result<i64> {
+    let filter = ArticleFilter::from_request(req);
+    let count = match filter {
+      ArticleFilter::None => &self.count_articles,
+      ArticleFilter::Author => &self.count_articles_by_author,
+      ArticleFilter::Tag => &self.count_articles_by_tag,
+      ArticleFilter::Favorited => &self.count_articles_by_favorite,
+      ArticleFilter::Combined => &self.count_articles_filtered,
+    };
+    Ok(count.query_one(&filter.params(req)).await?.get(0))
+  }
+
+  /// Articles of a list, with the viewer flags loaded by `viewer_flags` when
+  /// the list doesn't select them.
+  async fn list_from_rows(&self, auth: &AuthData, rows: &[Row]) -> Result<Vec<ArticleDetails>> {
+    let mut articles: Vec<ArticleDetails> = rows.iter().map(article_details_from_row).collect();
+    if self.bulk_flags {
+      self.load_viewer_flags(auth, &mut articles).await?;
+    }
+    Ok(articles)
+  }
+
+  /// Set the favorited/following flags of a page of articles with one query.
+  async fn load_viewer_flags(&self, auth: &AuthData, articles: &mut [ArticleDetails]) -> Result<()> {
+    if articles.is_empty() || auth.user_id == 0 {
+      // anonymous users don't favorite/follow.
+      return Ok(());
+    }
+    let article_ids: Vec<i32> = articles.iter().map(|a| a.id).collect();
+    let author_ids: Vec<i32> = articles.iter().map(|a| a.author.user_id).collect();
+    let rows = self.viewer_flags.query(&[&auth.user_id, &article_ids, &author_ids]).await?;
+    let mut favorited = HashSet::new();
+    let mut following = HashSet::new();
+    for row in rows.iter() {
+      let kind: i32 = row.get(0);
+      let id: i32 = row.get(1);
+      if kind == 1 {
+        favorited.insert(id);
+      } else {
+        following.insert(id);
+      }
+    }
+    for article in articles.iter_mut() {
+      article.favorited = favorited.contains(&article.id);
+      article.author.following = following.contains(&article.author.user_id);
+    }
+    Ok(())
+  }
+
+  /// Get a page of the current user's favorited articles and the total
+  /// number of favorited articles.
+  pub async fn get_user_favorites(&self, auth: &AuthData, req: FeedRequest) -> Result<(Vec<ArticleDetails>, i64)> {
+    let limit = req.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = req.offset.unwrap_or(0);
+    let rows = self.get_user_favorites.query(&[&auth.user_id, &limit, &offset]).await?;
+    let total: i64 = self.count_user_favorites.query_one(&[&auth.user_id]).await?.get(0);
+    Ok((self.list_from_rows(auth, &rows).await?, total))
+  }
+
+  /// Get the current user's own articles, newest first.
+  pub async fn get_user_articles(&self, auth: &AuthData, limit: i64) -> Result<Vec<ArticleDetails>> {
+    let rows = self.get_user_articles.query(&[&auth.user_id, &limit]).await?;
     Ok(rows.iter().map(article_details_from_row).collect())
   }
 
+  /// Get a page of articles older than `before_id`, newest first.
+  pub async fn get_articles_before(&self, auth: &AuthData, before_id: i32, limit: i64) -> Result<Vec<ArticleDetails>> {
+    let rows = self.get_articles_before.query(&[&auth.user_id, &before_id, &limit]).await?;
+    Ok(rows.iter().map(article_details_from_row).collect())
+  }
+
+  /// Get a page of the feed.
   pub async fn get_feed(&self, auth: &AuthData, req: FeedRequest) -> Result<Vec<ArticleDetails>> {
-    let user_id = auth.user_id;
-    let limit = req.limit.unwrap_or(20);
+    let limit = req.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
     let offset = req.offset.unwrap_or(0);
-    let rows = self.get_feed.query(&[&user_id, &limit, &offset]).await?;
-    Ok(rows.iter().map(article_details_from_row).collect())
+    let rows = self.get_feed.query(&[&auth.user_id, &limit, &offset]).await?;
+    self.list_from_rows(auth, &rows).await
+  }
+
+  /// Total number of feed articles (all pages).
+  pub async fn count_feed(&self, auth: &AuthData) -> Result<i64> {
+    Ok(self.count_feed.query_one(&[&auth.user_id]).await?.get(0))
+  }
+
+  /// Slugs of up to `limit` articles favorited by a user.
+  pub async fn get_favorite_slugs(&self, user_id: i32, limit: i64) -> Result<Vec<String>> {
+    let rows = self.favorite_slugs_by_user.query(&[&user_id, &limit]).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+  }
+
+  /// Count feed articles newer than the marker.
+  pub async fn count_feed_since(&self, auth: &AuthData, since: &FeedMarker) -> Result<i64> {
+    let (since_id, since_time) = match since {
+      FeedMarker::Id(id) => (Some(*id), None),
+      FeedMarker::Time(ts) => (None, Some(*ts)),
+    };
+    let row = self.count_feed_since.query_one(&[&auth.user_id, &since_id, &since_time]).await?;
+    Ok(row.get(0))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::db::{test_db, test_suffix, test_user, test_article};
+
+  #[test]
+  fn transliterated_slugs() {
+    assert_eq!(title_slug("Привет, мир"), "privet-mir");
+    assert_eq!(title_slug("北京欢迎你"), "bei-jing-huan-ying-ni");
+    assert_eq!(title_slug("Café Ünïcode"), "cafe-unicode");
+    assert_eq!(title_slug("Hello, World!"), "hello-world");
+  }
+
+  #[test]
+  fn reserved_slugs_get_a_suffix() {
+    assert_eq!(title_slug("Feed"), "feed-article");
+    assert_eq!(title_slug("Slug preview!"), "slug-preview-article");
+    assert_eq!(title_slug("Feed me"), "feed-me");
+    assert!(is_valid_slug(&title_slug("Feed"), 0));
+  }
+
+  #[test]
+  fn generated_slugs_are_stable() {
+    // nothing to transliterate.
+    let slug = title_slug("\u{e000}\u{e001}");
+    assert!(slug.starts_with("article-"));
+    assert_eq!(slug.len(), "article-".len() + 16);
+    assert_eq!(slug, title_slug("\u{e000}\u{e001}"));
+    assert_ne!(slug, title_slug("\u{e000}"));
+  }
+
+  #[test]
+  fn clean_tags() {
+    let tags = vec!["".to_string(), "  ".to_string(), " rust ".to_string(), "rust".to_string()];
+    assert_eq!(clean_tag_list(&tags), vec!["rust".to_string()]);
+  }
+
+  #[actix_rt::test]
+  async fn blank_tags_are_not_stored() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("tags{}", suffix)).await;
+    let req = CreateArticle {
+      title: format!("Blank tags {}", suffix),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: vec!["".to_string(), "  ".to_string(), "rust".to_string()],
+    };
+    let id = match db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap() {
+      StoreArticle::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+    let mut article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.tag_list, vec!["rust".to_string()]);
+
+    let update = UpdateArticle {
+      title: None,
+      description: None,
+      body: None,
+      tag_list: Some(vec![" ".to_string(), "rust".to_string(), "".to_string()]),
+    };
+    db.article.update(&mut article, &update, SlugScope::Global, 0, false).await.unwrap();
+    let article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.tag_list, vec!["rust".to_string()]);
+  }
+
+  #[actix_rt::test]
+  async fn delete_checks_the_confirm_count() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("delete{}", suffix)).await;
+    let reader = test_user(&db, &format!("reader{}", suffix)).await;
+    let id = test_article(&db, &author, &format!("Delete {}", suffix)).await;
+    let comment = crate::forms::comment::CreateComment { body: "comment".to_string() };
+    assert!(matches!(db.comment.store(&reader, id, &comment, 0, NewbieLimit::default()).await.unwrap(), StoreComment::Stored(_)));
+    assert!(matches!(db.comment.store(&author, id, &comment, 0, NewbieLimit::default()).await.unwrap(), StoreComment::Stored(_)));
+    db.article.favorite(&reader, id).await.unwrap();
+
+    let dependents = db.article.count_dependents(id).await.unwrap();
+    assert_eq!(dependents, ArticleDependents { comments_count: 2, favorites_count: 1 });
+    assert_eq!(dependents.total(), 3);
+
+    // a wrong count deletes nothing.
+    assert_eq!(db.article.delete(id, Some(2)).await.unwrap(), 0);
+    assert!(db.article.get_by_id(&author, id).await.unwrap().is_some());
+    assert_eq!(db.article.delete(id, Some(3)).await.unwrap(), 1);
+    assert!(db.article.get_by_id(&author, id).await.unwrap().is_none());
+    assert_eq!(db.article.count_dependents(id).await.unwrap().total(), 0);
+
+    // without a confirm count.
+    let id = test_article(&db, &author, &format!("Delete again {}", suffix)).await;
+    db.article.favorite(&reader, id).await.unwrap();
+    assert_eq!(db.article.delete(id, None).await.unwrap(), 1);
+  }
+
+  #[actix_rt::test]
+  async fn max_articles_per_author() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("prolific{}", suffix)).await;
+    let req = |n| CreateArticle {
+      title: format!("Article {} {}", n, suffix),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: vec![],
+    };
+    let max = |max_per_author| ArticleLimits { max_per_author, ..Default::default() };
+    for n in 0..2 {
+      assert!(matches!(db.article.store(&author, &req(n), SlugScope::Global, max(2)).await.unwrap(), StoreArticle::Stored(_)));
+    }
+    assert_eq!(db.article.store(&author, &req(2), SlugScope::Global, max(2)).await.unwrap(), StoreArticle::MaxPerAuthor);
+    // a higher cap or none.
+    assert!(matches!(db.article.store(&author, &req(3), SlugScope::Global, max(3)).await.unwrap(), StoreArticle::Stored(_)));
+    assert!(matches!(db.article.store(&author, &req(4), SlugScope::Global, ArticleLimits::default()).await.unwrap(), StoreArticle::Stored(_)));
+
+    // concurrent stores (on separate connections) at `max - 1`, only one passes.
+    let other = test_db().await.unwrap();
+    let author = test_user(&db, &format!("racing{}", suffix)).await;
+    let (first, second) = (req(5), req(6));
+    let (a, b) = futures::join!(
+      db.article.store(&author, &first, SlugScope::Global, max(1)),
+      other.article.store(&author, &second, SlugScope::Global, max(1)));
+    let mut res = vec![a.unwrap(), b.unwrap()];
+    res.sort_by_key(|res| matches!(res, StoreArticle::Stored(_)));
+    assert_eq!(res[0], StoreArticle::MaxPerAuthor);
+    assert!(matches!(res[1], StoreArticle::Stored(_)));
+  }
+
+  #[actix_rt::test]
+  async fn tags_keep_the_authored_order() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("ordered{}", suffix)).await;
+    let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+    let req = CreateArticle {
+      title: format!("Ordered tags {}", suffix),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: tags(&["zeta", "alpha", "mid"]),
+    };
+    let id = match db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap() {
+      StoreArticle::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+    let mut article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.tag_list, tags(&["zeta", "alpha", "mid"]));
+
+    // kept tags are reordered.
+    let update = UpdateArticle {
+      title: None,
+      description: None,
+      body: None,
+      tag_list: Some(tags(&["mid", "new", "zeta"])),
+    };
+    db.article.update(&mut article, &update, SlugScope::Global, 0, false).await.unwrap();
+    let mut article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.tag_list, tags(&["mid", "new", "zeta"]));
+
+    // a failed tag change leaves all the tags as they were.  The last tag
+    // is too large for the tag index, so adding it fails.
+    let huge: String = (0..1000u32).map(|n| format!("{:08x}", n.wrapping_mul(2_654_435_761))).collect();
+    let update = UpdateArticle {
+      title: None,
+      description: None,
+      body: None,
+      tag_list: Some(vec!["other".to_string(), huge]),
+    };
+    assert!(db.article.update(&mut article, &update, SlugScope::Global, 0, false).await.is_err());
+    let article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.tag_list, tags(&["mid", "new", "zeta"]));
+  }
+
+  #[actix_rt::test]
+  async fn edits_are_kept_as_revisions() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("revised{}", suffix)).await;
+    let id = test_article(&db, &author, &format!("Revised {}", suffix)).await;
+    let mut article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    let edit = |body: &str| UpdateArticle {
+      title: None,
+      description: None,
+      body: Some(body.to_string()),
+      tag_list: None,
+    };
+    // a blind rerun would save another revision.
+    assert!(!db.article.update_article.is_idempotent());
+
+    // two edits, two revisions, newest first.
+    db.article.update(&mut article, &edit("first edit"), SlugScope::Global, 2, false).await.unwrap();
+    db.article.update(&mut article, &edit("second edit"), SlugScope::Global, 2, false).await.unwrap();
+    let revisions = db.article.get_revisions(id).await.unwrap();
+    let bodies: Vec<_> = revisions.iter().map(|rev| rev.body.as_str()).collect();
+    assert_eq!(bodies, vec!["first edit", "body"]);
+
+    // only the newest `max_revisions` are kept.
+    db.article.update(&mut article, &edit("third edit"), SlugScope::Global, 2, false).await.unwrap();
+    let revisions = db.article.get_revisions(id).await.unwrap();
+    let bodies: Vec<_> = revisions.iter().map(|rev| rev.body.as_str()).collect();
+    assert_eq!(bodies, vec!["second edit", "first edit"]);
+
+    // tags only (or unchanged fields) don't add a revision.
+    let tags_only = UpdateArticle { tag_list: Some(vec!["revised".to_string()]), ..edit("third edit") };
+    db.article.update(&mut article, &tags_only, SlugScope::Global, 2, false).await.unwrap();
+    assert_eq!(db.article.get_by_id(&author, id).await.unwrap().unwrap().tag_list, vec!["revised".to_string()]);
+    let revisions = db.article.get_revisions(id).await.unwrap();
+    let bodies: Vec<_> = revisions.iter().map(|rev| rev.body.as_str()).collect();
+    assert_eq!(bodies, vec!["second edit", "first edit"]);
+  }
+
+  /// A `DbService` loading the list flags with `viewer_flags`.
+  async fn bulk_flags_db() -> DbService {
+    let url = std::env::var("TEST_DATABASE_URL").unwrap();
+    let config = DbConfig { bulk_article_flags: true, ..DbConfig::new(&url) };
+    let db = DbService::new(&config).unwrap();
+    db.prepare().await.unwrap();
+    db
+  }
+
+  /// (id, favorited, following) of a list.
+  fn flags(articles: &[ArticleDetails]) -> Vec<(i32, bool, bool)> {
+    articles.iter().map(|article| (article.id, article.favorited, article.author.following)).collect()
+  }
+
+  #[actix_rt::test]
+  async fn bulk_flags_match_subqueries() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let bulk = bulk_flags_db().await;
+    let suffix = test_suffix();
+    let reader = test_user(&db, &format!("flags{}", suffix)).await;
+    let author = test_user(&db, &format!("flagsauthor{}", suffix)).await;
+    let other = test_user(&db, &format!("flagsother{}", suffix)).await;
+    db.user.follow(&reader, author.user_id).await.unwrap();
+    let liked = test_article(&db, &author, &format!("Liked {}", suffix)).await;
+    let followed = test_article(&db, &author, &format!("Followed {}", suffix)).await;
+    let unfollowed = test_article(&db, &other, &format!("Unfollowed {}", suffix)).await;
+    db.article.favorite(&reader, liked).await.unwrap();
+    db.article.favorite(&reader, unfollowed).await.unwrap();
+
+    let by = |name: &str| ArticleRequest {
+      author: Some(format!("{}{}", name, suffix)),
+      ..Default::default()
+    };
+    // both paths load the same articles, all columns included.
+    for name in &["flagsauthor", "flagsother"] {
+      let expected = db.article.get_articles(&reader, by(name)).await.unwrap();
+      let articles = bulk.article.get_articles(&reader, by(name)).await.unwrap();
+      assert_eq!(articles, expected);
+    }
+    let articles = bulk.article.get_articles(&reader, by("flagsauthor")).await.unwrap();
+    assert_eq!(flags(&articles), vec![(followed, false, true), (liked, true, true)]);
+    // anonymous viewers get no flags.
+    let anonymous = AuthData::default();
+    let expected = db.article.get_articles(&anonymous, by("flagsauthor")).await.unwrap();
+    let articles = bulk.article.get_articles(&anonymous, by("flagsauthor")).await.unwrap();
+    assert_eq!(articles, expected);
+    assert_eq!(flags(&articles), vec![(followed, false, false), (liked, false, false)]);
+
+    let expected = db.article.get_feed(&reader, FeedRequest::default()).await.unwrap();
+    let articles = bulk.article.get_feed(&reader, FeedRequest::default()).await.unwrap();
+    assert_eq!(articles, expected);
+    assert_eq!(bulk.article.count_feed(&reader).await.unwrap(), 2);
+    assert_eq!(flags(&articles), vec![(followed, false, true), (liked, true, true)]);
+
+    let (expected, _) = db.article.get_user_favorites(&reader, FeedRequest::default()).await.unwrap();
+    let (articles, _) = bulk.article.get_user_favorites(&reader, FeedRequest::default()).await.unwrap();
+    assert_eq!(articles, expected);
+    assert_eq!(flags(&articles), vec![(unfollowed, true, false), (liked, true, true)]);
+  }
+
+  #[actix_rt::test]
+  async fn stored_tags_keep_the_authored_order() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("tagorder{}", suffix)).await;
+    let tag = |name: &str| format!("{}{}", name, suffix);
+    let req = CreateArticle {
+      title: format!("Tag order {}", suffix),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: vec![tag("b"), " ".to_string(), tag("a"), tag("c"), format!(" {} ", tag("b"))],
+    };
+    let id = match db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap() {
+      StoreArticle::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+    let article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.tag_list, vec![tag("b"), tag("a"), tag("c")]);
+    let counts = db.tag.get_tag_counts().await.unwrap();
+    assert!(article.tag_list.iter().all(|name| counts.get(name) == Some(&1)), "{:?}", counts);
+
+    // without tags.
+    let req = CreateArticle { title: format!("No tags {}", suffix), tag_list: vec![], ..req };
+    let id = match db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap() {
+      StoreArticle::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+    assert!(db.article.get_by_id(&author, id).await.unwrap().unwrap().tag_list.is_empty());
+  }
+
+  #[actix_rt::test]
+  async fn sorted_lists() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let bulk = bulk_flags_db().await;
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("sorter{}", suffix)).await;
+    let fan = test_user(&db, &format!("sorterfan{}", suffix)).await;
+    let other = test_user(&db, &format!("sorterother{}", suffix)).await;
+    let a = test_article(&db, &author, &format!("Sort a {}", suffix)).await;
+    let b = test_article(&db, &author, &format!("Sort b {}", suffix)).await;
+    let c = test_article(&db, &author, &format!("Sort c {}", suffix)).await;
+    db.article.favorite(&fan, a).await.unwrap();
+    db.article.favorite(&other, a).await.unwrap();
+    db.article.favorite(&fan, b).await.unwrap();
+
+    let sorted = |sort: Option<&str>| ArticleRequest {
+      author: Some(format!("sorter{}", suffix)),
+      sort: sort.map(str::to_string),
+      ..Default::default()
+    };
+    let ids = |articles: Vec<ArticleDetails>| articles.iter().map(|a| a.id).collect::<Vec<_>>();
+    for db in &[&db, &bulk] {
+      let cases = vec![
+        (None, vec![c, b, a]),
+        (Some("newest"), vec![c, b, a]),
+        (Some("oldest"), vec![a, b, c]),
+        (Some("most_favorited"), vec![a, b, c]),
+      ];
+      for (sort, expected) in cases {
+        let articles = db.article.get_articles(&fan, sorted(sort)).await.unwrap();
+        assert_eq!(ids(articles), expected, "{:?}", sort);
+        assert_eq!(db.article.count_articles(&sorted(sort)).await.unwrap(), 3);
+      }
+      // the other orders page too.
+      let req = ArticleRequest { limit: Some(1), offset: Some(1), ..sorted(Some("oldest")) };
+      let articles = db.article.get_articles(&fan, req).await.unwrap();
+      assert_eq!(ids(articles), vec![b]);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn tied_sort_keys_page_stably() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("tied{}", suffix)).await;
+    let fan = test_user(&db, &format!("tiedfan{}", suffix)).await;
+    let mut articles = vec![];
+    for n in 0..5 {
+      articles.push(test_article(&db, &author, &format!("Tied {} {}", n, suffix)).await);
+    }
+    // two favorites tie at the top, the others tie with none.
+    db.article.favorite(&fan, articles[1]).await.unwrap();
+    db.article.favorite(&fan, articles[3]).await.unwrap();
+
+    // one article per page, ties are broken by the newest id.
+    let mut paged = vec![];
+    for offset in 0..5 {
+      let req = ArticleRequest {
+        author: Some(format!("tied{}", suffix)),
+        sort: Some("most_favorited".to_string()),
+        limit: Some(1),
+        offset: Some(offset),
+        ..Default::default()
+      };
+      let page = db.article.get_articles(&fan, req).await.unwrap();
+      paged.extend(page.iter().map(|a| a.id));
+    }
+    assert_eq!(paged, vec![articles[3], articles[1], articles[4], articles[2], articles[0]]);
+  }
+
+  #[actix_rt::test]
+  async fn combined_list_filters() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let bulk = bulk_flags_db().await;
+    let suffix = test_suffix();
+    let first = test_user(&db, &format!("filtera{}", suffix)).await;
+    let second = test_user(&db, &format!("filterb{}", suffix)).await;
+    let fan = test_user(&db, &format!("filterfan{}", suffix)).await;
+    let (x, y) = (format!("x{}", suffix), format!("y{}", suffix));
+    let store = |auth: AuthData, title: &str, tag: &str| {
+      let db = db.clone();
+      let req = CreateArticle {
+        title: format!("{} {}", title, suffix),
+        description: "description".to_string(),
+        body: "body".to_string(),
+        tag_list: vec![tag.to_string()],
+      };
+      async move {
+        match db.article.store(&auth, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap() {
+          StoreArticle::Stored(id) => id,
+          res => panic!("store failed: {:?}", res),
+        }
+      }
+    };
+    let first_x = store(first.clone(), "First x", &x).await;
+    let first_y = store(first.clone(), "First y", &y).await;
+    let second_x = store(second.clone(), "Second x", &x).await;
+    db.article.favorite(&fan, first_x).await.unwrap();
+    db.article.favorite(&fan, first_y).await.unwrap();
+    db.article.favorite(&fan, second_x).await.unwrap();
+
+    let name = |prefix: &str| Some(format!("{}{}", prefix, suffix));
+    let cases = vec![
+      ((name("filtera"), Some(x.clone()), None), vec![first_x]),
+      ((None, Some(x.clone()), name("filterfan")), vec![second_x, first_x]),
+      ((name("filtera"), None, name("filterfan")), vec![first_y, first_x]),
+      ((name("filterb"), Some(y.clone()), name("filterfan")), vec![]),
+      ((name("filtera"), Some(x.clone()), name("filterfan")), vec![first_x]),
+    ];
+    for ((author, tag, favorited), expected) in cases {
+      let req = || ArticleRequest {
+        author: author.clone(),
+        tag: tag.clone(),
+        favorited: favorited.clone(),
+        ..Default::default()
+      };
+      for db in &[&db, &bulk] {
+        let articles = db.article.get_articles(&fan, req()).await.unwrap();
+        let ids: Vec<i32> = articles.iter().map(|article| article.id).collect();
+        assert_eq!(ids, expected, "{:?}", req());
+        // the total counts all pages.
+        let paged = || ArticleRequest { limit: Some(1), ..req() };
+        let articles = db.article.get_articles(&fan, paged()).await.unwrap();
+        assert_eq!(articles.len(), expected.len().min(1));
+        assert_eq!(db.article.count_articles(&paged()).await.unwrap(), expected.len() as i64, "{:?}", req());
+      }
+    }
+  }
+
+  /// Time both flag strategies on a page of the global list:
+  /// `cargo test --lib bulk_flags_benchmark -- --ignored --nocapture`
+  #[actix_rt::test]
+  #[ignore]
+  async fn bulk_flags_benchmark() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let bulk = bulk_flags_db().await;
+    let suffix = test_suffix();
+    let reader = test_user(&db, &format!("bench{}", suffix)).await;
+    let author = test_user(&db, &format!("benchauthor{}", suffix)).await;
+    db.user.follow(&reader, author.user_id).await.unwrap();
+    for n in 0..100 {
+      let id = test_article(&db, &author, &format!("Bench {} {}", n, suffix)).await;
+      if n % 3 == 0 {
+        db.article.favorite(&reader, id).await.unwrap();
+      }
+    }
+    let page = || ArticleRequest { limit: Some(100), ..Default::default() };
+    for (name, db) in &[("subquery", &db), ("bulk", &bulk)] {
+      // warm up the plans.
+      db.article.get_articles(&reader, page()).await.unwrap();
+      let start = std::time::Instant::now();
+      for _ in 0..50 {
+        db.article.get_articles(&reader, page()).await.unwrap();
+        db.article.get_feed(&reader, FeedRequest { limit: Some(100), offset: None }).await.unwrap();
+      }
+      println!("{}: {:?} per list + feed", name, start.elapsed() / 50);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn tags_are_written_with_the_article() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("tagged{}", suffix)).await;
+    let req = |title: &str, tags: &[&str]| CreateArticle {
+      title: title.to_string(),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: tags.iter().map(|tag| tag.to_string()).collect(),
+    };
+    let store = |req: CreateArticle| {
+      let db = db.clone();
+      let author = author.clone();
+      async move { db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await }
+    };
+    let tags = |id: i32| {
+      let db = db.clone();
+      let author = author.clone();
+      async move { db.article.get_by_id(&author, id).await.unwrap().unwrap().tag_list }
+    };
+
+    let title = format!("Tagged {}", suffix);
+    let id = match store(req(&title, &[" b ", "a", "b", ""])).await.unwrap() {
+      StoreArticle::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+    assert_eq!(tags(id).await, vec!["b", "a"]);
+
+    // a tag Postgres can't store (NUL) fails the whole article.
+    let failed = format!("Failed {}", suffix);
+    assert!(store(req(&failed, &["fine", "nul\0"])).await.is_err());
+    assert!(db.article.get_by_slug(&author, &title_slug(&failed)).await.unwrap().is_none());
+
+    let update = |title: Option<String>, tag_list: Option<Vec<&str>>| UpdateArticle {
+      title,
+      description: None,
+      body: None,
+      tag_list: tag_list.map(|tags| tags.iter().map(|tag| tag.to_string()).collect()),
+    };
+    let mut article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    let req = update(None, Some(vec!["c", "b"]));
+    assert_eq!(db.article.update(&mut article, &req, SlugScope::Global, 0, false).await.unwrap(), 1);
+    assert_eq!(tags(id).await, vec!["c", "b"]);
+    // nor are the article's other changes kept when its tags fail.
+    let mut article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    let req = update(Some(format!("Renamed {}", suffix)), Some(vec!["d", "nul\0"]));
+    assert!(db.article.update(&mut article, &req, SlugScope::Global, 0, false).await.is_err());
+    let article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    assert_eq!(article.title, title);
+    assert_eq!(article.tag_list, vec!["c", "b"]);
+    // no tag list keeps the tags.
+    let mut article = article;
+    let req = update(Some(format!("Renamed {}", suffix)), None);
+    assert_eq!(db.article.update(&mut article, &req, SlugScope::Global, 0, false).await.unwrap(), 1);
+    assert_eq!(tags(id).await, vec!["c", "b"]);
+  }
+
+  #[actix_rt::test]
+  async fn titles_unique_per_author() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("titles{}", suffix)).await;
+    let title = format!("Same title {}", suffix);
+    test_article(&db, &author, &title).await;
+    let other = test_article(&db, &author, &format!("Other title {}", suffix)).await;
+
+    let req = CreateArticle {
+      title: title.clone(),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: vec![],
+    };
+    let unique = ArticleLimits { unique_title: true, ..Default::default() };
+    assert_eq!(db.article.store(&author, &req, SlugScope::Global, unique).await.unwrap(), StoreArticle::TitleUsed);
+    // without the check, the same title is still refused by its slug.
+    assert_eq!(db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap(),
+      StoreArticle::SlugUsed);
+
+    let update = UpdateArticle {
+      title: Some(title.clone()),
+      description: None,
+      body: None,
+      tag_list: None,
+    };
+    let mut article = db.article.get_by_id(&author, other).await.unwrap().unwrap();
+    match db.article.update(&mut article, &update, SlugScope::Global, 0, true).await {
+      Err(Error::UnprocessableEntity(errors)) => assert!(errors["errors"]["title"].is_array()),
+      res => panic!("duplicate title updated: {:?}", res),
+    }
+    let mut article = db.article.get_by_id(&author, other).await.unwrap().unwrap();
+    assert_eq!(db.article.update(&mut article, &update, SlugScope::Global, 0, false).await.unwrap(), 0);
+
+    // keeping its own title isn't a duplicate.
+    let update = UpdateArticle {
+      title: Some(format!("Other title {}", suffix)),
+      body: Some("new body".to_string()),
+      ..update
+    };
+    let mut article = db.article.get_by_id(&author, other).await.unwrap().unwrap();
+    assert_eq!(db.article.update(&mut article, &update, SlugScope::Global, 0, true).await.unwrap(), 1);
+  }
+
+  #[actix_rt::test]
+  async fn unread_feed_count() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let reader = test_user(&db, &format!("reader{}", suffix)).await;
+    let author = test_user(&db, &format!("author{}", suffix)).await;
+    let other = test_user(&db, &format!("other{}", suffix)).await;
+    db.user.follow(&reader, author.user_id).await.unwrap();
+    let seen = test_article(&db, &author, &format!("Seen {}", suffix)).await;
+    test_article(&db, &other, &format!("Not followed {}", suffix)).await;
+    let since = FeedMarker::Id(seen);
+    assert_eq!(db.article.count_feed_since(&reader, &since).await.unwrap(), 0);
+
+    test_article(&db, &author, &format!("New {}", suffix)).await;
+    test_article(&db, &author, &format!("Newer {}", suffix)).await;
+    test_article(&db, &other, &format!("Other {}", suffix)).await;
+    assert_eq!(db.article.count_feed_since(&reader, &since).await.unwrap(), 2);
+    let before = FeedMarker::Time(chrono::NaiveDateTime::from_timestamp(0, 0));
+    assert_eq!(db.article.count_feed_since(&reader, &before).await.unwrap(), 3);
+    // the author doesn't follow anyone.
+    assert_eq!(db.article.count_feed_since(&author, &before).await.unwrap(), 0);
+  }
+
+  #[actix_rt::test]
+  async fn user_articles_are_only_their_own() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let alice = test_user(&db, &format!("alice{}", suffix)).await;
+    let bob = test_user(&db, &format!("bob{}", suffix)).await;
+    let own = test_article(&db, &alice, &format!("Alice {}", suffix)).await;
+    let other = test_article(&db, &bob, &format!("Bob {}", suffix)).await;
+
+    let articles = db.article.get_user_articles(&alice, 100).await.unwrap();
+    let ids = articles.iter().map(|article| article.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![own]);
+    assert!(articles.iter().all(|article| article.author.user_id == alice.user_id));
+    assert!(!ids.contains(&other));
+  }
+
+  #[test]
+  fn valid_slugs() {
+    assert!(is_valid_slug("hello-world-2", 0));
+    assert!(is_valid_slug(&title_slug("How to train your dragon"), 0));
+    assert!(is_valid_slug("abc", 3));
+    assert!(!is_valid_slug("abcd", 3));
+    assert!(!is_valid_slug("", 0));
+    assert!(!is_valid_slug("Hello", 0));
+    assert!(!is_valid_slug("hello--world", 0));
+    assert!(!is_valid_slug("-hello", 0));
+    assert!(!is_valid_slug("hello-", 0));
+    assert!(!is_valid_slug("hello_world", 0));
+    assert!(!is_valid_slug("caf\u{e9}", 0));
   }
 }
@@ -0,0 +1,192 @@
+use tokio_postgres::{Row, error::SqlState};
+
+use crate::error::*;
+
+use crate::models::*;
+use crate::forms::*;
+
+use crate::db::*;
+
+/// Attempts to append an entry when a concurrent write used the same
+/// previous entry.
+const MAX_APPEND_ATTEMPTS: usize = 3;
+
+/// Append an entry for actor `$1`, action `$2` and target `$3`, chained to
+/// the previous entry.  `action` is an optional CTE (with a trailing comma)
+/// run in the same statement, then the entry is only added for its rows
+/// (`from`).
+fn append_entry_sql(action: &str, from: &str) -> String {
+  // The hash covers the previous entry's hash and this entry's fields.
+  // `prev_hash` is unique, so concurrent appends can't fork the chain.
+  format!(r#"WITH {}
+    entry AS (
+      SELECT $1::integer AS actor_id, $2::text AS action, $3::text AS target,
+        date_trunc('microseconds', LOCALTIMESTAMP) AS created_at,
+        (SELECT hash FROM admin_audit ORDER BY id DESC LIMIT 1) AS prev_hash
+      {}
+    )
+    INSERT INTO admin_audit(actor_id, action, target, created_at, prev_hash, hash)
+    SELECT actor_id, action, target, created_at, prev_hash,
+      encode(sha256(convert_to(concat_ws('|', COALESCE(prev_hash, ''),
+        actor_id, action, target,
+        to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS.US')), 'UTF8')), 'hex')
+    FROM entry
+    RETURNING id"#, action, from)
+}
+
+#[derive(Clone)]
+pub struct AuditService {
+  // append entry
+  append_entry: VersionedStatement,
+
+  // bump a user's token version (logout-all) and append the entry.
+  bump_token_version: VersionedStatement,
+
+  // get entries
+  get_entries: VersionedStatement,
+}
+
+fn audit_entry_from_row(row: &Row) -> AuditEntry {
+  AuditEntry {
+    id: row.get(0),
+    actor: row.get(1),
+    action: row.get(2),
+    target: row.get(3),
+    created_at: row.get(4),
+    prev_hash: row.get(5),
+    hash: row.get(6),
+  }
+}
+
+impl AuditService {
+  pub fn new(cl: SharedClient) -> Result<AuditService> {
+    let append_entry = VersionedStatement::new(cl.clone(),
+        &append_entry_sql("", ""))?.write().non_idempotent();
+
+    // the target ($3) is the username.
+    let bump_token_version = VersionedStatement::new(cl.clone(),
+        &append_entry_sql(r#"bumped AS (
+          UPDATE users SET token_version = token_version + 1 WHERE username = $3::text
+          RETURNING id
+        ),"#, "FROM bumped"))?.write().non_idempotent();
+
+    let get_entries = VersionedStatement::new(cl.clone(),
+        r#"SELECT a.id, u.username, a.action, a.target, a.created_at, a.prev_hash, a.hash
+        FROM admin_audit a INNER JOIN users u ON a.actor_id = u.id
+        ORDER BY a.id DESC
+        LIMIT $1 OFFSET $2"#)?;
+
+    Ok(AuditService {
+      append_entry,
+      bump_token_version,
+      get_entries,
+    })
+  }
+
+  /// All statements, by name.
+  pub fn statements(&self) -> Vec<(&'static str, &VersionedStatement)> {
+    vec![
+      ("append_entry", &self.append_entry),
+      ("bump_token_version", &self.bump_token_version),
+      ("get_entries", &self.get_entries),
+    ]
+  }
+
+  pub async fn prepare(&self) -> Result<()> {
+    for (_, statement) in self.statements() {
+      statement.prepare().await?;
+    }
+    Ok(())
+  }
+
+  /// Check if all statements are prepared for the current connection.
+  pub fn is_prepared(&self) -> bool {
+    self.statements().iter().all(|(_, statement)| statement.is_prepared())
+  }
+
+  /// Run an appending statement, retrying when another entry was appended
+  /// after the one it chained to.
+  async fn append_with(&self, statement: &VersionedStatement, actor_id: i32, action: &str, target: &str) -> Result<Option<i32>> {
+    let mut attempts = 0;
+    loop {
+      attempts += 1;
+      match statement.query_opt(&[&actor_id, &action, &target]).await {
+        Ok(row) => return Ok(row.map(|row| row.get(0))),
+        Err(Error::PgError { source })
+          if attempts < MAX_APPEND_ATTEMPTS && source.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+          continue;
+        },
+        Err(err) => return Err(err),
+      }
+    }
+  }
+
+  /// Append an entry to the audit log, returns the new entry's id.
+  pub async fn append(&self, actor_id: i32, action: &str, target: &str) -> Result<i32> {
+    let id = self.append_with(&self.append_entry, actor_id, action, target).await?;
+    id.ok_or(Error::InternalServerError)
+  }
+
+  /// Bump a user's token version and append the entry in one statement,
+  /// returns the entry's id (`None` if there is no such user).
+  pub async fn bump_token_version(&self, actor_id: i32, action: &str, username: &str) -> Result<Option<i32>> {
+    self.append_with(&self.bump_token_version, actor_id, action, username).await
+  }
+
+  /// Newest entries first.
+  pub async fn get_entries(&self, req: AuditRequest) -> Result<Vec<AuditEntry>> {
+    let limit = req.limit.unwrap_or(DEFAULT_PAGE_LIMIT).max(0);
+    let offset = req.offset.unwrap_or(0).max(0);
+    let rows = self.get_entries.query(&[&limit, &offset]).await?;
+    Ok(rows.iter().map(audit_entry_from_row).collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[actix_rt::test]
+  async fn entries_are_hash_chained() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let admin = test_user(&db, &format!("admin{}", suffix)).await;
+    let target = format!("target{}", suffix);
+    let target_id = test_user(&db, &target).await.user_id;
+
+    let first = db.audit.append(admin.user_id, "test", "first").await.unwrap();
+    let bumped = db.audit.bump_token_version(admin.user_id, "logout-all", &target).await.unwrap()
+      .expect("bumped");
+    assert!(bumped > first);
+    let user = db.user.get_by_id(target_id).await.unwrap().unwrap();
+    assert_eq!(user.token_version, 1);
+    // no entry (or bump) for a missing user.
+    let missing = format!("missing{}", suffix);
+    assert_eq!(db.audit.bump_token_version(admin.user_id, "logout-all", &missing).await.unwrap(), None);
+
+    // each entry links to the previous one, and its hash matches its fields.
+    let verify = VersionedStatement::new(db.shared_cl.clone(),
+      r#"SELECT id, prev_hash IS NOT DISTINCT FROM prev, hash = encode(sha256(convert_to(
+          concat_ws('|', COALESCE(prev_hash, ''), actor_id, action, target,
+            to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS.US')), 'UTF8')), 'hex')
+        FROM (SELECT *, lag(hash) OVER (ORDER BY id) AS prev FROM admin_audit) a
+        WHERE id >= $1 ORDER BY id"#).unwrap();
+    let rows = verify.query(&[&first]).await.unwrap();
+    assert!(rows.len() >= 2);
+    for row in rows {
+      let (id, linked, valid): (i32, bool, bool) = (row.get(0), row.get(1), row.get(2));
+      assert!(linked && valid, "entry {} linked={} valid={}", id, linked, valid);
+    }
+
+    // a negative limit or offset is clamped.
+    let req = AuditRequest { limit: Some(-1), offset: Some(-5) };
+    assert!(db.audit.get_entries(req).await.unwrap().is_empty());
+    let req = AuditRequest { limit: Some(2), offset: Some(-5) };
+    let entries = db.audit.get_entries(req).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].prev_hash.as_ref(), Some(&entries[1].hash));
+  }
+}
@@ -0,0 +1,102 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+thread_local! {
+  // Deadline of the request future being polled on this worker thread.
+  static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+  // Client's max. wait (`X-Max-Wait-Ms`) of the request future.
+  static WAIT_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Runs a future with a retry deadline.  The DB retry loops (reconnecting,
+/// re-preparing and re-running queries) give up once it has passed.
+///
+/// The client's wait deadline (if any) also abandons reads still running.
+///
+/// Workers poll many requests on one thread, so the deadlines are only set
+/// while this future is polled.
+pub struct RetryBudget<F> {
+  deadline: Instant,
+  wait_deadline: Option<Instant>,
+  inner: Pin<Box<F>>,
+}
+
+impl<F: Future> RetryBudget<F> {
+  pub fn new(deadline: Instant, wait_deadline: Option<Instant>, inner: F) -> Self {
+    Self {
+      deadline,
+      wait_deadline,
+      inner: Box::pin(inner),
+    }
+  }
+}
+
+impl<F: Future> Future for RetryBudget<F> {
+  type Output = F::Output;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    let prev = DEADLINE.with(|deadline| deadline.replace(Some(self.deadline)));
+    let prev_wait = WAIT_DEADLINE.with(|deadline| deadline.replace(self.wait_deadline));
+    let res = self.inner.as_mut().poll(cx);
+    DEADLINE.with(|deadline| deadline.set(prev));
+    WAIT_DEADLINE.with(|deadline| deadline.set(prev_wait));
+    res
+  }
+}
+
+/// The current request's client wait deadline (`X-Max-Wait-Ms`), if any.
+pub fn wait_deadline() -> Option<Instant> {
+  WAIT_DEADLINE.with(|deadline| deadline.get())
+}
+
+/// Check if the current request's retry deadline has passed.  Always false
+/// outside of a `RetryBudget`.
+pub fn retry_budget_exhausted() -> bool {
+  DEADLINE.with(|deadline| match deadline.get() {
+    Some(deadline) => Instant::now() >= deadline,
+    None => false,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::time::Duration;
+
+  use futures::future::{poll_fn, FutureExt};
+
+  #[actix_rt::test]
+  async fn deadline_only_while_polled() {
+    let passed = Instant::now() - Duration::from_millis(1);
+    let future = Instant::now() + Duration::from_secs(60);
+    assert!(!retry_budget_exhausted());
+    assert!(RetryBudget::new(passed, None, async { retry_budget_exhausted() }).await);
+    assert!(!RetryBudget::new(future, None, async { retry_budget_exhausted() }).await);
+    // the deadline is reset after each poll.
+    assert!(!retry_budget_exhausted());
+
+    // nested budgets restore the outer deadline.
+    let outer = RetryBudget::new(passed, None, async {
+      let inner = RetryBudget::new(future, None, async { retry_budget_exhausted() }).await;
+      (inner, retry_budget_exhausted())
+    });
+    assert_eq!(outer.await, (false, true));
+
+    // a pending request doesn't leak its deadline into others.
+    let mut polled = false;
+    let mut pending = RetryBudget::new(passed, None, poll_fn(|_| {
+      if polled {
+        Poll::Ready(())
+      } else {
+        polled = true;
+        Poll::Pending
+      }
+    }));
+    assert!((&mut pending).now_or_never().is_none());
+    assert!(!retry_budget_exhausted());
+  }
+}
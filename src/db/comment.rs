@@ -7,12 +7,25 @@ use crate::forms::*;
 use crate::db::*;
 use crate::db::util::*;
 
+use chrono::NaiveDateTime;
+
 use tokio_postgres::Row;
 
+/// Result of storing a comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreComment {
+  Stored(i32),
+  /// The user commented on the article too recently, seconds to wait.
+  TooSoon(i64),
+  /// The user's account is new and reached its hourly limit.
+  NewbieLimited,
+}
+
 #[derive(Clone)]
 pub struct CommentService {
   // get comment
   comment_by_id: VersionedStatement,
+  comment_by_id_anonymous: VersionedStatement,
 
   // store comment
   store_comment: VersionedStatement,
@@ -22,6 +35,11 @@ pub struct CommentService {
 
   // get multiple comments
   comments_by_slug: VersionedStatement,
+  comments_by_article: VersionedStatement,
+  comments_by_user: VersionedStatement,
+
+  // stats
+  top_commenters: VersionedStatement,
 }
 
 lazy_static! {
@@ -56,16 +74,31 @@ fn comment_details_from_row(row: &Row) -> CommentDetails {
     created_at,
     updated_at,
     body,
+    edited: updated_at > created_at,
     author: Profile {
       user_id,
       username,
       bio,
       image,
       following: following == 1,
+      created_at: None,
+      articles_count: None,
+      profile_url: None,
     },
   }
 }
 
+fn comment_from_row(row: &Row) -> Comment {
+  Comment {
+    id: row.get(0),
+    article_id: row.get(1),
+    user_id: row.get(2),
+    body: row.get(3),
+    created_at: row.get(4),
+    updated_at: row.get(5),
+  }
+}
+
 fn comment_details_from_opt_row(row: &Option<Row>) -> Option<CommentDetails> {
   if let Some(ref row) = row {
     Some(comment_details_from_row(row))
@@ -81,65 +114,374 @@ SELECT c.id, c.body, c.created_at, c.updated_at,
 FROM comments c INNER JOIN users u ON c.user_id = u.id
 "#;
 
+// Same columns as `COMMENT_DETAILS_SELECT`, for anonymous viewers (who
+// don't follow anyone), without the `Following` subquery.
+static COMMENT_DETAILS_ANONYMOUS_SELECT: &str = r#"
+SELECT c.id, c.body, c.created_at, c.updated_at,
+  u.id, u.username, u.bio, u.image,
+  0::integer AS Following
+FROM comments c INNER JOIN users u ON c.user_id = u.id
+"#;
+
+// Comment lists load the author profiles separately, see `cached_profiles`.
+static COMMENT_SELECT: &str = r#"
+SELECT c.id, c.article_id, c.user_id, c.body, c.created_at, c.updated_at
+FROM comments c
+"#;
+
 impl CommentService {
   pub fn new(cl: SharedClient) -> Result<CommentService> {
     // Build get_comment_* queries
     let comment_by_id = VersionedStatement::new(cl.clone(),
         &format!(r#"{} WHERE c.id = $2"#, COMMENT_DETAILS_SELECT))?;
+    let comment_by_id_anonymous = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE c.id = $1"#, COMMENT_DETAILS_ANONYMOUS_SELECT))?;
 
-    // insert comment query
+    // insert comment query, unless the user's last comment on the article
+    // is less than $4 seconds old or the user reached the newbie limit
+    // ($5/$6, `NewbieLimit`).
     let store_comment = VersionedStatement::new(cl.clone(),
-        r#"INSERT INTO comments(article_id, user_id, body)
-        VALUES($1, $2, $3) RETURNING id"#)?;
+        r#"WITH last AS (
+          SELECT MAX(created_at) AS created_at FROM comments
+          WHERE article_id = $1 AND user_id = $2
+        ), newbie AS (
+          SELECT $6::bigint > 0
+            AND EXISTS (SELECT 1 FROM users
+              WHERE id = $2 AND created_at > LOCALTIMESTAMP - make_interval(hours => $5::integer))
+            AND (SELECT COUNT(*) FROM comments
+              WHERE user_id = $2 AND created_at > LOCALTIMESTAMP - interval '1 hour') >= $6
+            AS limited
+        ), ins AS (
+          INSERT INTO comments(article_id, user_id, body)
+          SELECT $1, $2, $3 FROM last, newbie
+          WHERE NOT newbie.limited AND ($4::float8 <= 0 OR last.created_at IS NULL
+            OR last.created_at <= LOCALTIMESTAMP - make_interval(secs => $4))
+          RETURNING id
+        )
+        SELECT (SELECT id FROM ins),
+          CEIL(EXTRACT(EPOCH FROM
+            last.created_at + make_interval(secs => $4) - LOCALTIMESTAMP))::bigint,
+          newbie.limited
+        FROM last, newbie"#)?.write().non_idempotent();
 
     // delete comment query
     let delete_comment = VersionedStatement::new(cl.clone(),
-        r#"DELETE FROM comments WHERE id = $1"#)?;
+        r#"DELETE FROM comments WHERE id = $1"#)?.write();
 
     // Build get_comments_* queries
+    let order_by = build_order_by("c", "id", true);
     let comments_by_slug = VersionedStatement::new(cl.clone(),
         &format!(r#"{} INNER JOIN articles a ON c.article_id = a.id
-          WHERE a.slug = $2
-          ORDER BY c.id DESC"#, COMMENT_DETAILS_SELECT))?;
+          WHERE a.slug = $1
+          {}"#, COMMENT_SELECT, order_by))?;
+    let comments_by_article = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE c.article_id = $1
+          {}"#, COMMENT_SELECT, order_by))?;
+    // oldest first, for exports.
+    let comments_by_user = VersionedStatement::new(cl.clone(),
+        &format!(r#"{} WHERE c.user_id = $1
+          ORDER BY c.id LIMIT $2"#, COMMENT_SELECT))?;
+
+    // users with the most comments since $2 (all time when NULL).
+    let top_commenters = VersionedStatement::new(cl.clone(),
+        r#"SELECT u.id, u.username, u.bio, u.image, COUNT(*) AS CommentsCount
+        FROM comments c INNER JOIN users u ON c.user_id = u.id
+        WHERE ($2::timestamp IS NULL OR c.created_at >= $2)
+        GROUP BY u.id
+        ORDER BY CommentsCount DESC, u.id
+        LIMIT $1"#)?;
 
     Ok(CommentService {
       comment_by_id,
+      comment_by_id_anonymous,
 
       store_comment,
       delete_comment,
 
       comments_by_slug,
+      comments_by_article,
+      comments_by_user,
+
+      top_commenters,
     })
   }
 
-  pub async fn prepare(&self) -> Result<()> {
-    self.comment_by_id.prepare().await?;
+  /// All statements, by name.
+  pub fn statements(&self) -> Vec<(&'static str, &VersionedStatement)> {
+    vec![
+      ("comment_by_id", &self.comment_by_id),
+      ("comment_by_id_anonymous", &self.comment_by_id_anonymous),
 
-    self.store_comment.prepare().await?;
-    self.delete_comment.prepare().await?;
+      ("store_comment", &self.store_comment),
+      ("delete_comment", &self.delete_comment),
 
-    self.comments_by_slug.prepare().await?;
+      ("comments_by_slug", &self.comments_by_slug),
+      ("comments_by_article", &self.comments_by_article),
+      ("comments_by_user", &self.comments_by_user),
 
+      ("top_commenters", &self.top_commenters),
+    ]
+  }
+
+  pub async fn prepare(&self) -> Result<()> {
+    for (_, statement) in self.statements() {
+      statement.prepare().await?;
+    }
     Ok(())
   }
 
+  /// Check if all statements are prepared for the current connection.
+  pub fn is_prepared(&self) -> bool {
+    self.statements().iter().all(|(_, statement)| statement.is_prepared())
+  }
+
   pub async fn get_comment_by_id(&self, auth: &AuthData, comment_id: i32) -> Result<Option<CommentDetails>> {
-    let row = self.comment_by_id.query_opt(&[&auth.user_id, &comment_id]).await?;
+    let row = if auth.user_id == 0 {
+      // anonymous users don't follow anyone.
+      self.comment_by_id_anonymous.query_opt(&[&comment_id]).await?
+    } else {
+      self.comment_by_id.query_opt(&[&auth.user_id, &comment_id]).await?
+    };
     Ok(comment_details_from_opt_row(&row))
   }
 
-  pub async fn store(&self, auth: &AuthData, article_id: i32, comment: &CreateComment) -> Result<Option<i32>> {
-    Ok(self.store_comment.query_opt(&[&article_id, &auth.user_id, &comment.body])
-      .await?.map(|row| row.get(0))
-    )
+  /// `min_interval` is the minimum number of seconds between the user's
+  /// comments on the same article (0 = no limit).
+  pub async fn store(&self, auth: &AuthData, article_id: i32, comment: &CreateComment, min_interval: i64, newbie: NewbieLimit) -> Result<StoreComment> {
+    let min_interval = min_interval as f64;
+    let row = self.store_comment.query_one(&[
+        &article_id, &auth.user_id, &comment.body, &min_interval, &newbie.period_hours, &newbie.per_hour
+      ]).await?;
+    let comment_id: Option<i32> = row.get(0);
+    let newbie_limited: bool = row.get(2);
+    Ok(match comment_id {
+      Some(id) => StoreComment::Stored(id),
+      None if newbie_limited => StoreComment::NewbieLimited,
+      None => {
+        let wait: Option<i64> = row.get(1);
+        StoreComment::TooSoon(wait.unwrap_or(1).max(1))
+      },
+    })
   }
 
   pub async fn delete(&self, comment_id: i32) -> Result<u64> {
     Ok(self.delete_comment.execute(&[&comment_id]).await?)
   }
 
-  pub async fn get_comments_by_slug(&self, auth: &AuthData, slug: &str) -> Result<Vec<CommentDetails>> {
-    let rows = self.comments_by_slug.query(&[&auth.user_id, &slug]).await?;
-    Ok(rows.iter().map(comment_details_from_row).collect())
+  pub async fn get_comments_by_article(&self, article_id: i32) -> Result<Vec<Comment>> {
+    let rows = self.comments_by_article.query(&[&article_id]).await?;
+    Ok(rows.iter().map(comment_from_row).collect())
+  }
+
+  pub async fn get_comments_by_slug(&self, slug: &str) -> Result<Vec<Comment>> {
+    let rows = self.comments_by_slug.query(&[&slug]).await?;
+    Ok(rows.iter().map(comment_from_row).collect())
+  }
+
+  /// Get up to `limit` of a user's comments, oldest first.
+  pub async fn get_comments_by_user(&self, user_id: i32, limit: i64) -> Result<Vec<Comment>> {
+    let rows = self.comments_by_user.query(&[&user_id, &limit]).await?;
+    Ok(rows.iter().map(comment_from_row).collect())
+  }
+
+  /// Users ranked by their number of comments since `since`, as seen by an
+  /// anonymous user.
+  pub async fn get_top_commenters(&self, limit: i64, since: Option<NaiveDateTime>) -> Result<Vec<TopCommenter>> {
+    let rows = self.top_commenters.query(&[&limit, &since]).await?;
+    Ok(rows.iter().map(|row| {
+      TopCommenter {
+        profile: Profile {
+          user_id: row.get(0),
+          username: row.get(1),
+          bio: row.get(2),
+          image: row.get(3),
+          following: false,
+          created_at: None,
+          articles_count: None,
+          profile_url: None,
+        },
+        comments_count: row.get(4),
+      }
+    }).collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::db::{test_db, test_suffix, test_user, test_article};
+
+  #[actix_rt::test]
+  async fn anonymous_comments_skip_following() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("anonauthor{}", suffix)).await;
+    let article_id = test_article(&db, &author, &format!("Anonymous {}", suffix)).await;
+    let comment = CreateComment {
+      body: "hello".to_string(),
+    };
+    let id = match db.comment.store(&author, article_id, &comment, 0, NewbieLimit::default()).await.unwrap() {
+      StoreComment::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+
+    // the same comment as the full statement returns for user 0.
+    let anonymous = AuthData::default();
+    let row = db.comment.comment_by_id.query_opt(&[&0i32, &id]).await.unwrap();
+    let full = comment_details_from_opt_row(&row);
+    assert!(full.is_some());
+    assert_eq!(db.comment.get_comment_by_id(&anonymous, id).await.unwrap(), full);
+    let profiles = db.user.get_profiles_by_ids(&anonymous, &[author.user_id]).await.unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert!(!profiles[0].following);
+
+    // without reading the followers.
+    let plan = |query: String, params: Vec<i32>| {
+      let shared_cl = db.shared_cl.clone();
+      async move {
+        let explain = VersionedStatement::new(shared_cl, &format!("EXPLAIN {}", query)).unwrap();
+        let params = params.iter().map(|p| p as &(dyn tokio_postgres::types::ToSql + Sync)).collect::<Vec<_>>();
+        explain.query(&params).await.unwrap().iter()
+          .map(|row| row.get::<_, String>(0))
+          .collect::<Vec<_>>()
+          .join("\n")
+      }
+    };
+    let full = plan(format!("{} WHERE c.id = $2", COMMENT_DETAILS_SELECT), vec![0, id]).await;
+    assert!(full.contains("followers"), "{}", full);
+    let anonymous = plan(format!("{} WHERE c.id = $1", COMMENT_DETAILS_ANONYMOUS_SELECT), vec![id]).await;
+    assert!(!anonymous.contains("followers"), "{}", anonymous);
+  }
+
+  #[actix_rt::test]
+  async fn edited_comments() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let auth = test_user(&db, &format!("edited{}", suffix)).await;
+    let article_id = test_article(&db, &auth, &format!("Edited comments {}", suffix)).await;
+    let req = CreateComment { body: "comment".to_string() };
+    let id = match db.comment.store(&auth, article_id, &req, 0, NewbieLimit::default()).await.unwrap() {
+      StoreComment::Stored(id) => id,
+      res => panic!("store failed: {:?}", res),
+    };
+    assert!(!db.comment.get_comment_by_id(&auth, id).await.unwrap().unwrap().edited);
+
+    // the `updated_at` trigger marks the change.
+    let edit = VersionedStatement::new(db.shared_cl.clone(), "UPDATE comments SET body = 'edited' WHERE id = $1")
+      .unwrap();
+    edit.execute(&[&id]).await.unwrap();
+    assert!(db.comment.get_comment_by_id(&auth, id).await.unwrap().unwrap().edited);
+  }
+
+  #[actix_rt::test]
+  async fn store_comment_min_interval() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let auth = test_user(&db, &format!("commenter{}", suffix)).await;
+    let article_id = test_article(&db, &auth, &format!("Comments {}", suffix)).await;
+    let comment = CreateComment {
+      body: "first".to_string(),
+    };
+    assert!(matches!(db.comment.store(&auth, article_id, &comment, 60, NewbieLimit::default()).await.unwrap(),
+      StoreComment::Stored(_)));
+    match db.comment.store(&auth, article_id, &comment, 60, NewbieLimit::default()).await.unwrap() {
+      StoreComment::TooSoon(secs) => assert!(secs > 0 && secs <= 60, "secs = {}", secs),
+      res => panic!("expected TooSoon: {:?}", res),
+    }
+    // 0 = no slow mode.
+    assert!(matches!(db.comment.store(&auth, article_id, &comment, 0, NewbieLimit::default()).await.unwrap(),
+      StoreComment::Stored(_)));
+  }
+
+  #[actix_rt::test]
+  async fn top_commenters_since() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let users = ["early", "most", "second", "third"];
+    let mut auths = Vec::new();
+    for name in users.iter() {
+      auths.push(test_user(&db, &format!("{}{}", name, suffix)).await);
+    }
+    let article_id = test_article(&db, &auths[0], &format!("Top {}", suffix)).await;
+    let comment = |body: &str| CreateComment {
+      body: body.to_string(),
+    };
+    let store = |auth, body| {
+      let req = comment(body);
+      let db = &db;
+      async move {
+        match db.comment.store(auth, article_id, &req, 0, NewbieLimit::default()).await.unwrap() {
+          StoreComment::Stored(id) => id,
+          res => panic!("store failed: {:?}", res),
+        }
+      }
+    };
+    // comments before `since` aren't counted, even in the same hour.
+    for _ in 0..10 {
+      store(&auths[0], "early").await;
+    }
+    let first = store(&auths[1], "first").await;
+    let since = db.comment.get_comment_by_id(&auths[1], first).await.unwrap().unwrap().created_at;
+    for (auth, count) in auths[1..].iter().zip(&[5, 4, 3]) {
+      let start = if auth.user_id == auths[1].user_id { 1 } else { 0 };
+      for _ in start..*count {
+        store(auth, "comment").await;
+      }
+    }
+
+    // other tests add comments at the same time, only rank our users.
+    let top = db.comment.get_top_commenters(i64::MAX, Some(since)).await.unwrap();
+    let ours = top.iter()
+      .filter(|c| auths.iter().any(|a| a.user_id == c.profile.user_id))
+      .map(|c| (c.profile.user_id, c.comments_count))
+      .collect::<Vec<_>>();
+    let expected = auths[1..].iter().map(|a| a.user_id).zip(vec![5, 4, 3]).collect::<Vec<_>>();
+    assert_eq!(ours, expected);
+    let top = db.comment.get_top_commenters(1, Some(since)).await.unwrap();
+    assert_eq!(top.len(), 1);
+  }
+
+  #[actix_rt::test]
+  async fn store_comment_newbie_limit() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let auth = test_user(&db, &format!("newbie{}", suffix)).await;
+    let article_id = test_article(&db, &auth, &format!("Newbie {}", suffix)).await;
+    let comment = CreateComment {
+      body: "hello".to_string(),
+    };
+    let newbie = NewbieLimit {
+      period_hours: 24,
+      per_hour: 2,
+    };
+    for _ in 0..2 {
+      assert!(matches!(db.comment.store(&auth, article_id, &comment, 0, newbie).await.unwrap(),
+        StoreComment::Stored(_)));
+    }
+    assert!(matches!(db.comment.store(&auth, article_id, &comment, 0, newbie).await.unwrap(),
+      StoreComment::NewbieLimited));
+    // older accounts aren't limited.
+    let old = NewbieLimit {
+      period_hours: 0,
+      ..newbie
+    };
+    assert!(matches!(db.comment.store(&auth, article_id, &comment, 0, old).await.unwrap(),
+      StoreComment::Stored(_)));
   }
 }
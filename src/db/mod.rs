@@ -4,12 +4,20 @@ mod user;
 mod article;
 mod comment;
 mod tag;
+mod audit;
 pub use self::{
   user::*,
   article::*,
   comment::*,
   tag::*,
+  audit::*,
 };
 
 mod service;
 pub use service::*;
+
+mod tls;
+pub use tls::DbTls;
+
+mod budget;
+pub use budget::*;
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use crate::error::*;
 
 use crate::models::*;
@@ -10,6 +12,12 @@ use crate::db::util::*;
 pub struct TagService {
   // get multiple tags
   get_tags: VersionedStatement,
+  get_popular_tags: VersionedStatement,
+  get_tag_counts: VersionedStatement,
+
+  // tags without articles (the counts drifted from article_tags)
+  get_orphan_tags: VersionedStatement,
+  delete_orphan_tags: VersionedStatement,
 }
 
 lazy_static! {
@@ -29,23 +37,131 @@ lazy_static! {
 impl TagService {
   pub fn new(cl: SharedClient) -> Result<TagService> {
     // Build get_tags queries
+    // a NULL limit returns all tags.
     let get_tags = VersionedStatement::new(cl.clone(),
-        r#"SELECT tag_name FROM article_tags GROUP BY tag_name ORDER BY tag_name"#)?;
+        r#"SELECT tag_name FROM article_tags GROUP BY tag_name ORDER BY tag_name LIMIT $1"#)?;
+    let get_popular_tags = VersionedStatement::new(cl.clone(),
+        r#"SELECT tag_name FROM article_tags GROUP BY tag_name
+        ORDER BY COUNT(*) DESC, tag_name LIMIT $1"#)?;
+    // `tags` has the counts, kept up to date by a trigger on article_tags.
+    let get_tag_counts = VersionedStatement::new(cl.clone(),
+        r#"SELECT name, article_count FROM tags"#)?;
+
+    let get_orphan_tags = VersionedStatement::new(cl.clone(),
+        r#"SELECT name FROM tags t
+        WHERE NOT EXISTS (SELECT 1 FROM article_tags WHERE tag_name = t.name)
+        ORDER BY name"#)?;
+    let delete_orphan_tags = VersionedStatement::new(cl.clone(),
+        r#"DELETE FROM tags t
+        WHERE NOT EXISTS (SELECT 1 FROM article_tags WHERE tag_name = t.name)
+        RETURNING name"#)?.write();
 
     Ok(TagService {
       get_tags,
+      get_popular_tags,
+      get_tag_counts,
+      get_orphan_tags,
+      delete_orphan_tags,
     })
   }
 
+  /// All statements, by name.
+  pub fn statements(&self) -> Vec<(&'static str, &VersionedStatement)> {
+    vec![
+      ("get_tags", &self.get_tags),
+      ("get_popular_tags", &self.get_popular_tags),
+      ("get_tag_counts", &self.get_tag_counts),
+      ("get_orphan_tags", &self.get_orphan_tags),
+      ("delete_orphan_tags", &self.delete_orphan_tags),
+    ]
+  }
+
   pub async fn prepare(&self) -> Result<()> {
-    self.get_tags.prepare().await?;
+    for (_, statement) in self.statements() {
+      statement.prepare().await?;
+    }
     Ok(())
   }
 
-  pub async fn get_tags(&self) -> Result<TagList> {
-    let rows = self.get_tags.query(&[]).await?;
+  /// Check if all statements are prepared for the current connection.
+  pub fn is_prepared(&self) -> bool {
+    self.statements().iter().all(|(_, statement)| statement.is_prepared())
+  }
+
+  /// Get up to `limit` tags, by name or the most used first.
+  pub async fn get_tags(&self, limit: Option<i64>, popular: bool) -> Result<TagList> {
+    let rows = if popular {
+      self.get_popular_tags.query(&[&limit]).await?
+    } else {
+      self.get_tags.query(&[&limit]).await?
+    };
     Ok(TagList{
       tags: rows.iter().map(|r| TagName(r.get(0))).collect(),
     })
   }
+
+  /// Number of articles using each tag.
+  pub async fn get_tag_counts(&self) -> Result<BTreeMap<String, i64>> {
+    let rows = self.get_tag_counts.query(&[]).await?;
+    Ok(rows.iter().map(|r| (r.get(0), r.get(1))).collect())
+  }
+
+  /// Counted tags that no article uses.
+  pub async fn get_orphan_tags(&self) -> Result<Vec<String>> {
+    let rows = self.get_orphan_tags.query(&[]).await?;
+    Ok(rows.iter().map(|r| r.get(0)).collect())
+  }
+
+  /// Remove the counts of tags that no article uses, returns the removed tags.
+  pub async fn delete_orphan_tags(&self) -> Result<Vec<String>> {
+    let rows = self.delete_orphan_tags.query(&[]).await?;
+    let mut tags: Vec<String> = rows.iter().map(|r| r.get(0)).collect();
+    tags.sort();
+    Ok(tags)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[actix_rt::test]
+  async fn orphan_tags() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("orphans{}", suffix)).await;
+    let id = test_article(&db, &author, &format!("Orphans {}", suffix)).await;
+    let used = format!("used{}", suffix);
+    let tagged = format!("tagged{}", suffix);
+    let orphan_count = format!("drifted{}", suffix);
+    let tag = VersionedStatement::new(db.shared_cl.clone(),
+      r#"INSERT INTO article_tags(article_id, tag_name) VALUES ($1, $2)"#).unwrap();
+    tag.execute(&[&id, &used]).await.unwrap();
+    tag.execute(&[&id, &tagged]).await.unwrap();
+    let counts = db.tag.get_tag_counts().await.unwrap();
+    assert_eq!((counts.get(&used), counts.get(&tagged)), (Some(&1), Some(&1)));
+
+    // the trigger keeps the counts in sync.
+    VersionedStatement::new(db.shared_cl.clone(),
+      r#"DELETE FROM article_tags WHERE tag_name = $1"#).unwrap()
+      .execute(&[&used]).await.unwrap();
+    assert_eq!(db.tag.get_tag_counts().await.unwrap().get(&used), None);
+
+    // a count without any article tags (drift).
+    VersionedStatement::new(db.shared_cl.clone(),
+      r#"INSERT INTO tags(name, article_count) VALUES ($1, 2)"#).unwrap()
+      .execute(&[&orphan_count]).await.unwrap();
+    let orphans = db.tag.get_orphan_tags().await.unwrap();
+    assert!(orphans.contains(&orphan_count));
+    assert!(!orphans.contains(&tagged));
+
+    // (other tests can remove it too)
+    db.tag.delete_orphan_tags().await.unwrap();
+    assert!(!db.tag.get_orphan_tags().await.unwrap().contains(&orphan_count));
+    let counts = db.tag.get_tag_counts().await.unwrap();
+    assert_eq!((counts.get(&tagged), counts.get(&orphan_count)), (Some(&1), None));
+  }
 }
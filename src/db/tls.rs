@@ -0,0 +1,120 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+
+use rustls::{ClientConfig, internal::pemfile};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::error::*;
+use crate::app::AppConfig;
+
+fn config_error(msg: String) -> Error {
+  config::ConfigError::Message(msg).into()
+}
+
+fn open_pem(path: &str) -> Result<BufReader<File>> {
+  let file = File::open(path)
+    .map_err(|err| config_error(format!("Failed to open '{}': {}", path, err)))?;
+  Ok(BufReader::new(file))
+}
+
+/// TLS connector for Postgres connections (`db.tls`).
+#[derive(Clone)]
+pub struct DbTls(pub MakeRustlsConnect);
+
+impl fmt::Debug for DbTls {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("DbTls")
+  }
+}
+
+impl DbTls {
+  /// Load `db.tls.*`, `None` when TLS isn't enabled.
+  ///
+  /// Without `ca_cert` the server certificate is checked against the
+  /// Mozilla root certificates (webpki-roots).
+  pub fn from_config(config: &AppConfig) -> Result<Option<Self>> {
+    if !config.get_bool("db.tls.enabled")?.unwrap_or(false) {
+      return Ok(None);
+    }
+    let mut tls = ClientConfig::new();
+
+    // Server certificate
+    match config.get_str("db.tls.ca_cert")? {
+      Some(path) => {
+        let (valid, _) = tls.root_store.add_pem_file(&mut open_pem(&path)?)
+          .map_err(|_| config_error(format!("Invalid CA certificate '{}'", path)))?;
+        if valid == 0 {
+          return Err(config_error(format!("No CA certificates in '{}'", path)));
+        }
+      },
+      None => {
+        tls.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+      },
+    }
+
+    // Client certificate
+    let client_cert = config.get_str("db.tls.client_cert")?;
+    let client_key = config.get_str("db.tls.client_key")?;
+    match (client_cert, client_key) {
+      (Some(cert_path), Some(key_path)) => {
+        let certs = pemfile::certs(&mut open_pem(&cert_path)?)
+          .map_err(|_| config_error(format!("Invalid client certificate '{}'", cert_path)))?;
+        // PKCS#8 or RSA key.
+        let mut keys = pemfile::pkcs8_private_keys(&mut open_pem(&key_path)?)
+          .unwrap_or_default();
+        if keys.is_empty() {
+          keys = pemfile::rsa_private_keys(&mut open_pem(&key_path)?)
+            .unwrap_or_default();
+        }
+        let key = keys.into_iter().next()
+          .ok_or_else(|| config_error(format!("No private key in '{}'", key_path)))?;
+        tls.set_single_client_cert(certs, key)
+          .map_err(|err| config_error(format!("Invalid client certificate/key: {}", err)))?;
+      },
+      (None, None) => (),
+      _ => {
+        return Err(config_error(
+          "db.tls.client_cert and db.tls.client_key must be set together".to_string()));
+      },
+    }
+
+    Ok(Some(DbTls(MakeRustlsConnect::new(tls))))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::app::test_secret_file;
+
+  fn db_tls(toml: &str) -> Result<Option<DbTls>> {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.merge(::config::File::from_str(toml, ::config::FileFormat::Toml)).unwrap();
+    DbTls::from_config(&config)
+  }
+
+  #[test]
+  fn tls_config() {
+    assert!(db_tls("").unwrap().is_none());
+    assert!(db_tls("[db.tls]\nenabled = false\nca_cert = \"/nonexistent.pem\"").unwrap().is_none());
+    // the Mozilla roots without a `ca_cert`.
+    assert!(db_tls("[db.tls]\nenabled = true").unwrap().is_some());
+
+    let err = |toml: String| match db_tls(&toml) {
+      Err(Error::ConfigError { source }) => source.to_string(),
+      res => panic!("expected a config error: {:?}", res),
+    };
+    assert!(err("[db.tls]\nenabled = true\nca_cert = \"/nonexistent.pem\"".to_string())
+      .contains("Failed to open '/nonexistent.pem'"));
+    let empty = test_secret_file("db-tls-empty.pem", "not a certificate\n");
+    assert!(err(format!("[db.tls]\nenabled = true\nca_cert = \"{}\"", empty))
+      .contains("No CA certificates"));
+    assert!(err(format!("[db.tls]\nenabled = true\nclient_cert = \"{}\"", empty))
+      .contains("must be set together"));
+    assert!(err(format!("[db.tls]\nenabled = true\nclient_cert = \"{0}\"\nclient_key = \"{0}\"", empty))
+      .contains("No private key"));
+    std::fs::remove_file(empty).unwrap();
+  }
+}
@@ -7,7 +7,9 @@ use crate::forms::*;
 use crate::db::*;
 use crate::db::util::*;
 
-use tokio_postgres::Row;
+use chrono::NaiveDateTime;
+
+use tokio_postgres::{Row, error::{DbError, SqlState}};
 
 #[derive(Clone)]
 pub struct UserService {
@@ -16,6 +18,14 @@ pub struct UserService {
   user_by_email: VersionedStatement,
   user_by_username: VersionedStatement,
 
+  // token version
+  get_token_version: VersionedStatement,
+  bump_token_version: VersionedStatement,
+
+  // revoked tokens
+  get_token_state: VersionedStatement,
+  revoke_token: VersionedStatement,
+
   // register user
   insert_user: VersionedStatement,
 
@@ -27,10 +37,14 @@ pub struct UserService {
 
   // get profile
   get_profile: VersionedStatement,
+  get_profiles_by_ids: VersionedStatement,
+  get_profiles_by_ids_anonymous: VersionedStatement,
 
   // (un)follow
   follow_user: VersionedStatement,
+  follow_users: VersionedStatement,
   unfollow_user: VersionedStatement,
+  following_by_user: VersionedStatement,
 }
 
 lazy_static! {
@@ -46,6 +60,7 @@ lazy_static! {
         column("image"),
         column("created_at"),
         column("updated_at"),
+        column("token_version"),
       ],
     }
   };
@@ -71,6 +86,7 @@ fn user_from_row(row: &Row) -> User {
     image: row.get(5),
     created_at: row.get(6),
     updated_at: row.get(7),
+    token_version: row.get(8),
   }
 }
 
@@ -90,6 +106,9 @@ fn profile_from_row(row: &Row) -> Profile {
     bio: row.get(2),
     image: row.get(3),
     following: (following > 0),
+    created_at: row.get(5),
+    articles_count: row.get(6),
+    profile_url: None,
   }
 }
 
@@ -101,6 +120,52 @@ fn profile_from_opt_row(row: &Option<Row>) -> Option<Profile> {
   }
 }
 
+/// Result of registering a user.
+#[derive(Debug)]
+pub enum Registration {
+  Registered(User),
+  /// The (canonical) email is already used.
+  EmailUsed,
+  /// The username is already used.
+  UsernameUsed,
+  /// The invite code doesn't exist, is used up or has expired.
+  InvalidInvite,
+}
+
+/// Hourly limit of new accounts (`User.newbie_*`), checked by the article
+/// and comment inserts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NewbieLimit {
+  /// Accounts younger than this many hours are limited (0 = disabled).
+  pub period_hours: i32,
+  /// Articles or comments per hour (0 = unlimited).
+  pub per_hour: i64,
+}
+
+/// Canonical form of an email address, only used to detect duplicate accounts.
+///
+/// The address is lowercased and `+tag`s are removed from the local part.  For
+/// gmail the dots are also removed (and `googlemail.com` is `gmail.com`).
+/// Other providers have their own rules (some treat `-` as a tag separator or
+/// have case-sensitive local parts), those aren't handled.
+pub fn canonicalize_email(email: &str) -> String {
+  let email = email.trim().to_lowercase();
+  let (local, domain) = match email.rfind('@') {
+    Some(idx) => (&email[..idx], &email[idx + 1..]),
+    None => return email,
+  };
+  let local = match local.find('+') {
+    Some(idx) if idx > 0 => &local[..idx],
+    _ => local,
+  };
+  match domain {
+    "gmail.com" | "googlemail.com" => {
+      format!("{}@gmail.com", local.replace('.', ""))
+    },
+    _ => format!("{}@{}", local, domain),
+  }
+}
+
 impl UserService {
   pub fn new(cl: SharedClient) -> Result<UserService> {
     let select = USER_COLUMNS.build_select_query(false);
@@ -112,41 +177,134 @@ impl UserService {
     let user_by_username = VersionedStatement::new(cl.clone(),
         &format!(r#"{} WHERE username = $1"#, select))?;
 
-    // register user
+    // token version
+    let get_token_version = VersionedStatement::new(cl.clone(),
+        r#"SELECT token_version FROM users WHERE id = $1"#)?;
+    let bump_token_version = VersionedStatement::new(cl.clone(),
+        r#"UPDATE users SET token_version = token_version + 1 WHERE username = $1"#)?.write().non_idempotent();
+
+    // token version and if the token id ($2) was revoked.
+    let get_token_state = VersionedStatement::new(cl.clone(),
+        r#"SELECT token_version,
+          EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $2) AS revoked
+        FROM users WHERE id = $1"#)?;
+    // revoke a token, removing revoked tokens that have expired (the
+    // expiry is the token's `exp`, in UTC).
+    let revoke_token = VersionedStatement::new(cl.clone(),
+        r#"WITH expired AS (
+          DELETE FROM revoked_tokens WHERE expires_at < (NOW() AT TIME ZONE 'UTC')
+        )
+        INSERT INTO revoked_tokens(jti, expires_at) VALUES ($1, $2)
+        ON CONFLICT (jti) DO NOTHING"#)?.write();
+
+    // register user, $4 = canonical email, $5 = invite code (NULL when not
+    // required), $6 = the canonical email must be unused.  The invite's
+    // remaining uses are only decremented when the user is inserted.
     let insert_user = VersionedStatement::new(cl.clone(),
-        r#"INSERT INTO users(username, email, password)
-        VALUES($1, $2, $3)"#)?;
+        r#"WITH email_ok AS (
+          SELECT CASE WHEN $6::boolean THEN NOT canonical_email_used($4, NULL) ELSE true END AS ok
+        ), invite AS (
+          UPDATE invite_codes SET uses_remaining = uses_remaining - 1
+          WHERE $5::varchar IS NOT NULL AND code = $5 AND uses_remaining > 0
+            AND (expires_at IS NULL OR expires_at > LOCALTIMESTAMP)
+            AND (SELECT ok FROM email_ok)
+          RETURNING code
+        ), ins AS (
+          INSERT INTO users(username, email, password, canonical_email)
+          SELECT $1, $2, $3, $4
+          WHERE (SELECT ok FROM email_ok) AND ($5 IS NULL OR EXISTS (SELECT 1 FROM invite))
+          RETURNING id
+        )
+        SELECT (SELECT id FROM ins), (SELECT ok FROM email_ok)"#)?.write().non_idempotent();
 
     // update user password
     let update_user_password = VersionedStatement::new(cl.clone(),
-        r#"UPDATE users SET password = $1 WHERE id = $2"#)?;
+        r#"UPDATE users SET password = $1 WHERE id = $2"#)?.write();
 
-    // update user
+    // update user, only provided fields are changed.
+    // A new email also replaces the canonical email ($9), which must be
+    // unused when $10 is set (`ok` is false when it is used).
     let update_user = VersionedStatement::new(cl.clone(),
-        r#"UPDATE users
-        SET username = $2, email = $3, password = $4, bio = $5, image = $6
-        WHERE id = $1"#)?;
+        &format!(r#"WITH email_ok AS (
+          SELECT CASE WHEN $3::varchar IS NOT NULL AND $10::boolean
+            THEN NOT canonical_email_used($9, $1) ELSE true END AS ok
+        ), updated AS (
+          UPDATE users
+          SET username = COALESCE($2, username),
+            email = COALESCE($3, email),
+            canonical_email = CASE WHEN $3::varchar IS NULL THEN canonical_email ELSE $9::varchar END,
+            password = COALESCE($4, password),
+            bio = CASE WHEN $5 THEN $6 ELSE bio END,
+            image = CASE WHEN $7 THEN $8 ELSE image END
+          FROM email_ok
+          WHERE id = $1 AND email_ok.ok
+          RETURNING {}
+        )
+        SELECT updated.*, email_ok.ok FROM email_ok LEFT JOIN updated ON true"#,
+        USER_COLUMNS.get_columns(false)))?.write();
 
     // get profile
     let get_profile = VersionedStatement::new(cl.clone(),
         r#"SELECT u.id, u.username, u.bio, u.image,
           (CASE WHEN f.user_id IS NOT NULL THEN
-            1 ELSE 0 END)::integer AS Following
+            1 ELSE 0 END)::integer AS Following,
+          u.created_at,
+          (CASE WHEN $3 THEN
+            (SELECT COUNT(*) FROM articles WHERE author_id = u.id) END) AS ArticlesCount
         FROM users u LEFT JOIN followers f
           ON f.user_id = u.id AND follower_id = $1
         WHERE username = $2"#)?;
+    let get_profiles_by_ids = VersionedStatement::new(cl.clone(),
+        r#"SELECT u.id, u.username, u.bio, u.image,
+          (CASE WHEN f.user_id IS NOT NULL THEN
+            1 ELSE 0 END)::integer AS Following,
+          u.created_at, NULL::bigint AS ArticlesCount
+        FROM users u LEFT JOIN followers f
+          ON f.user_id = u.id AND follower_id = $1
+        WHERE u.id = ANY($2)"#)?;
+    // comment authors of anonymous viewers, without the followers join.
+    let get_profiles_by_ids_anonymous = VersionedStatement::new(cl.clone(),
+        r#"SELECT u.id, u.username, u.bio, u.image, 0::integer AS Following,
+          u.created_at, NULL::bigint AS ArticlesCount
+        FROM users u
+        WHERE u.id = ANY($1)"#)?;
 
     // (un)follow
     let follow_user = VersionedStatement::new(cl.clone(),
-        &FOLLOWER_COLUMNS.build_upsert("(user_id, follower_id)", true))?;
+        &FOLLOWER_COLUMNS.build_insert_ignore("(user_id, follower_id)", true))?.write();
+    // follow multiple users by username, in one statement.
+    let follow_users = VersionedStatement::new(cl.clone(),
+        r#"WITH names AS (
+          SELECT DISTINCT name FROM UNNEST($2::text[]) AS name
+        ), targets AS (
+          SELECT n.name, u.id FROM names n LEFT JOIN users u ON u.username = n.name
+        ), inserted AS (
+          INSERT INTO followers(user_id, follower_id)
+          SELECT id, $1 FROM targets WHERE id IS NOT NULL AND id <> $1
+          ON CONFLICT (user_id, follower_id) DO NOTHING
+          RETURNING user_id
+        )
+        SELECT t.name, t.id,
+          EXISTS (SELECT 1 FROM inserted i WHERE i.user_id = t.id) AS Followed
+        FROM targets t"#)?.write();
     let unfollow_user = VersionedStatement::new(cl.clone(),
-        "DELETE FROM followers WHERE user_id = $1 AND follower_id = $2")?;
+        "DELETE FROM followers WHERE user_id = $1 AND follower_id = $2")?.write();
+    let following_by_user = VersionedStatement::new(cl.clone(),
+        r#"SELECT u.username FROM followers f INNER JOIN users u ON f.user_id = u.id
+        WHERE f.follower_id = $1
+        ORDER BY u.username LIMIT $2"#)?;
 
     Ok(UserService {
       user_by_id,
       user_by_email,
       user_by_username,
 
+      get_token_version,
+      bump_token_version,
+
+      get_token_state,
+      revoke_token,
+
       insert_user,
 
       update_user_password,
@@ -154,30 +312,58 @@ impl UserService {
       update_user,
 
       get_profile,
+      get_profiles_by_ids,
+      get_profiles_by_ids_anonymous,
 
       follow_user,
+      follow_users,
       unfollow_user,
+      following_by_user,
     })
   }
 
-  pub async fn prepare(&self) -> Result<()> {
-    self.user_by_id.prepare().await?;
-    self.user_by_email.prepare().await?;
-    self.user_by_username.prepare().await?;
+  /// All statements, by name.
+  pub fn statements(&self) -> Vec<(&'static str, &VersionedStatement)> {
+    vec![
+      ("user_by_id", &self.user_by_id),
+      ("user_by_email", &self.user_by_email),
+      ("user_by_username", &self.user_by_username),
+
+      ("get_token_version", &self.get_token_version),
+      ("bump_token_version", &self.bump_token_version),
+
+      ("get_token_state", &self.get_token_state),
+      ("revoke_token", &self.revoke_token),
 
-    self.insert_user.prepare().await?;
+      ("insert_user", &self.insert_user),
 
-    self.update_user_password.prepare().await?;
+      ("update_user_password", &self.update_user_password),
 
-    self.update_user.prepare().await?;
+      ("update_user", &self.update_user),
 
-    self.get_profile.prepare().await?;
+      ("get_profile", &self.get_profile),
+      ("get_profiles_by_ids", &self.get_profiles_by_ids),
+      ("get_profiles_by_ids_anonymous", &self.get_profiles_by_ids_anonymous),
 
-    self.follow_user.prepare().await?;
-    self.unfollow_user.prepare().await?;
+      ("follow_user", &self.follow_user),
+      ("follow_users", &self.follow_users),
+      ("unfollow_user", &self.unfollow_user),
+      ("following_by_user", &self.following_by_user),
+    ]
+  }
+
+  pub async fn prepare(&self) -> Result<()> {
+    for (_, statement) in self.statements() {
+      statement.prepare().await?;
+    }
     Ok(())
   }
 
+  /// Check if all statements are prepared for the current connection.
+  pub fn is_prepared(&self) -> bool {
+    self.statements().iter().all(|(_, statement)| statement.is_prepared())
+  }
+
   pub async fn get_by_id(&self, id: i32) -> Result<Option<User>> {
     let row = self.user_by_id.query_opt(&[&id]).await?;
     Ok(user_from_opt_row(&row))
@@ -193,16 +379,62 @@ impl UserService {
     Ok(user_from_opt_row(&row))
   }
 
-  pub async fn register_user(&self, user: &RegisterUser) -> Result<Option<User>> {
+  /// Current token version of the user, `None` if the user doesn't exist.
+  pub async fn get_token_version(&self, user_id: i32) -> Result<Option<i32>> {
+    let row = self.get_token_version.query_opt(&[&user_id]).await?;
+    Ok(row.map(|row| row.get(0)))
+  }
+
+  /// Invalidate all tokens issued to the user.
+  pub async fn bump_token_version(&self, username: &str) -> Result<u64> {
+    self.bump_token_version.execute(&[&username]).await
+  }
+
+  /// Current token version of the user and if the token id was revoked,
+  /// `None` if the user doesn't exist.
+  pub async fn get_token_state(&self, user_id: i32, jti: &Option<String>) -> Result<Option<(i32, bool)>> {
+    let row = self.get_token_state.query_opt(&[&user_id, jti]).await?;
+    Ok(row.map(|row| (row.get(0), row.get(1))))
+  }
+
+  /// Revoke a token until it expires (`expires_at`).
+  pub async fn revoke_token(&self, jti: &str, expires_at: NaiveDateTime) -> Result<u64> {
+    self.revoke_token.execute(&[&jti, &expires_at]).await
+  }
+
+  /// Register a new user.  With `canonical` the email must also be unique
+  /// after canonicalization (see `canonicalize_email`).  With an `invite`
+  /// code, one use of the code is redeemed by the registration.
+  pub async fn register_user(&self, user: &RegisterUser, canonical: bool, invite: Option<&str>) -> Result<Registration> {
     let hash = pass::hash_password(&user.password)?;
-    match self.insert_user.execute(&[&user.username, &user.email, &hash]).await? {
-      0 => {
-        // Insert user failed.
-        Ok(None)
+    let canonical_email = canonicalize_email(&user.email);
+    let row = match self.insert_user.query_one(&[
+        &user.username, &user.email, &hash, &canonical_email, &invite, &canonical
+      ]).await {
+      Ok(row) => row,
+      // registered by a concurrent request, or the username is taken.
+      Err(Error::PgError { source }) if source.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+        let constraint = std::error::Error::source(&source)
+          .and_then(|err| err.downcast_ref::<DbError>())
+          .and_then(|err| err.constraint());
+        return Ok(match constraint {
+          Some("users_username_idx") => Registration::UsernameUsed,
+          _ => Registration::EmailUsed,
+        });
       },
-      _ => {
-        self.get_by_email(&user.email).await
-      }
+      Err(err) => return Err(err),
+    };
+    let user_id: Option<i32> = row.get(0);
+    let email_ok: bool = row.get(1);
+    match user_id {
+      Some(_) => {
+        match self.get_by_email(&user.email).await? {
+          Some(user) => Ok(Registration::Registered(user)),
+          None => Err(Error::InternalServerError),
+        }
+      },
+      None if !email_ok => Ok(Registration::EmailUsed),
+      None => Ok(Registration::InvalidInvite),
     }
   }
 
@@ -211,49 +443,352 @@ impl UserService {
     Ok(self.update_user_password.execute(&[&hash, &user_id]).await?)
   }
 
-  pub async fn update(&self, user: &mut User, req: &UpdateUser) -> Result<u64> {
-    // Update user fields
-    if let Some(username) = &req.username {
-      user.username = username.clone();
-    }
-    if let Some(email) = &req.email {
-      user.email = email.clone();
-    }
-    if let Some(password) = &req.password {
-      let hash = pass::hash_password(&password)?;
-      user.password = hash;
-    }
-    if let Some(bio) = &req.bio {
-      if bio == "" {
-        user.bio = None;
-      } else {
-        user.bio = Some(bio.clone());
-      }
-    }
-    if let Some(image) = &req.image {
-      if image == "" {
-        user.image = None;
-      } else {
-        user.image = Some(image.clone());
-      }
-    }
+  /// Update the provided fields of a user, returns the updated user.
+  /// A username or email used by another user (or with the same canonical
+  /// email, when `canonical` is set) is an `UnprocessableEntity`.
+  pub async fn update_user(&self, user_id: i32, req: &UpdateUser, canonical: bool) -> Result<Option<User>> {
+    let password = match &req.password {
+      Some(password) => Some(pass::hash_password(password)?),
+      None => None,
+    };
+    let (set_bio, bio) = match &req.bio {
+      Some(bio) => (true, bio.clone()),
+      None => (false, None),
+    };
+    let (set_image, image) = match &req.image {
+      Some(image) => (true, image.clone()),
+      None => (false, None),
+    };
+    let canonical_email = req.email.as_deref().map(canonicalize_email);
+    let used = || Error::UnprocessableEntity(json!({
+      "error": "Username or email already registered.",
+    }));
     // store user changes.
-    Ok(self.update_user.execute(&[
-      &user.id, &user.username, &user.email, &user.password, &user.bio, &user.image
-    ]).await?)
+    match self.update_user.query_one(&[
+      &user_id, &req.username, &req.email, &password, &set_bio, &bio, &set_image, &image,
+      &canonical_email, &canonical
+    ]).await {
+      Ok(ref row) if !row.get::<_, bool>("ok") => Err(used()),
+      Ok(ref row) if row.get::<_, Option<i32>>(0).is_none() => Ok(None),
+      Ok(ref row) => Ok(Some(user_from_row(row))),
+      Err(Error::PgError { source }) if source.code() == Some(&SqlState::UNIQUE_VIOLATION) => Err(used()),
+      Err(err) => Err(err),
+    }
   }
 
-  pub async fn get_profile(&self, auth: &AuthData, username: &str) -> Result<Option<Profile>> {
-    let row = self.get_profile.query_opt(&[&auth.user_id, &username]).await?;
+  /// Get a profile by username, `articles_count` is only loaded with `with_count`.
+  pub async fn get_profile(&self, auth: &AuthData, username: &str, with_count: bool) -> Result<Option<Profile>> {
+    let row = self.get_profile.query_opt(&[&auth.user_id, &username, &with_count]).await?;
     Ok(profile_from_opt_row(&row))
   }
 
+  /// Get the profiles of multiple users with one query, unknown ids are skipped.
+  pub async fn get_profiles_by_ids(&self, auth: &AuthData, user_ids: &[i32]) -> Result<Vec<Profile>> {
+    let rows = if auth.user_id == 0 {
+      self.get_profiles_by_ids_anonymous.query(&[&user_ids]).await?
+    } else {
+      self.get_profiles_by_ids.query(&[&auth.user_id, &user_ids]).await?
+    };
+    Ok(rows.iter().map(profile_from_row).collect())
+  }
+
   pub async fn follow(&self, auth: &AuthData, user_id: i32) -> Result<u64> {
     Ok(self.follow_user.execute(&[&user_id, &auth.user_id]).await?)
   }
 
+  /// Follow multiple users by username, with the result for each username.
+  pub async fn follow_many(&self, auth: &AuthData, usernames: &[String]) -> Result<Vec<FollowResult>> {
+    let rows = self.follow_users.query(&[&auth.user_id, &usernames]).await?;
+    let mut results: Vec<FollowResult> = rows.iter().map(|row| {
+      let user_id: Option<i32> = row.get(1);
+      let followed: bool = row.get(2);
+      let status = match user_id {
+        None => FollowStatus::NotFound,
+        Some(id) if id == auth.user_id => FollowStatus::IsSelf,
+        Some(_) if followed => FollowStatus::Followed,
+        Some(_) => FollowStatus::AlreadyFollowing,
+      };
+      FollowResult {
+        username: row.get(0),
+        status,
+      }
+    }).collect();
+    // keep the requested order.
+    results.sort_by_key(|res| usernames.iter().position(|name| *name == res.username));
+    Ok(results)
+  }
+
   pub async fn unfollow(&self, auth: &AuthData, user_id: i32) -> Result<u64> {
     Ok(self.unfollow_user.execute(&[&user_id, &auth.user_id]).await?)
   }
 
+  /// Usernames of up to `limit` users followed by a user.
+  pub async fn get_following(&self, user_id: i32, limit: i64) -> Result<Vec<String>> {
+    let rows = self.following_by_user.query(&[&user_id, &limit]).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::db::{test_db, test_suffix, test_user};
+
+  fn register(name: &str) -> RegisterUser {
+    RegisterUser {
+      username: name.to_string(),
+      email: format!("{}@example.com", name),
+      password: "password".to_string(),
+      code: None,
+    }
+  }
+
+  async fn registered(db: &DbService, name: &str, canonical: bool) -> User {
+    match db.user.register_user(&register(name), canonical, None).await.unwrap() {
+      Registration::Registered(user) => user,
+      res => panic!("register failed: {:?}", res),
+    }
+  }
+
+  #[actix_rt::test]
+  async fn partial_updates() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let auth = test_user(&db, &format!("partial{}", test_suffix())).await;
+
+    let req: UpdateUser = serde_json::from_value(json!({ "bio": "bio", "image": "image.png" })).unwrap();
+    let user = db.user.update_user(auth.user_id, &req, false).await.unwrap().unwrap();
+    assert_eq!((user.bio.as_deref(), user.image.as_deref()), (Some("bio"), Some("image.png")));
+
+    // only the bio is changed.
+    let req: UpdateUser = serde_json::from_value(json!({ "bio": "new bio" })).unwrap();
+    db.user.update_user(auth.user_id, &req, false).await.unwrap();
+    let user = db.user.get_by_id(auth.user_id).await.unwrap().unwrap();
+    assert_eq!((user.bio.as_deref(), user.image.as_deref()), (Some("new bio"), Some("image.png")));
+
+    // an explicit null clears the bio, an empty string the image.
+    let req: UpdateUser = serde_json::from_value(json!({ "bio": null, "image": "" })).unwrap();
+    db.user.update_user(auth.user_id, &req, false).await.unwrap();
+    let user = db.user.get_by_id(auth.user_id).await.unwrap().unwrap();
+    assert_eq!((user.bio, user.image), (None, None));
+  }
+
+  #[actix_rt::test]
+  async fn articles_count_on_profiles() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = crate::db::test_user(&db, &format!("counted{}", suffix)).await;
+    let other = crate::db::test_user(&db, &format!("other{}", suffix)).await;
+    let username = format!("counted{}", suffix);
+    let count = |with_count: bool| {
+      let db = db.clone();
+      let username = username.clone();
+      async move {
+        let anonymous = AuthData::default();
+        db.user.get_profile(&anonymous, &username, with_count).await.unwrap().unwrap().articles_count
+      }
+    };
+    assert_eq!(count(true).await, Some(0));
+
+    for n in 0..3 {
+      crate::db::test_article(&db, &author, &format!("Counted {} {}", n, suffix)).await;
+    }
+    crate::db::test_article(&db, &other, &format!("Not counted {}", suffix)).await;
+    assert_eq!(count(true).await, Some(3));
+    // the subquery only runs when asked for.
+    assert_eq!(count(false).await, None);
+  }
+
+  #[actix_rt::test]
+  async fn invite_codes() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let insert = VersionedStatement::new(db.shared_cl.clone(),
+      r#"INSERT INTO invite_codes(code, uses_remaining, expires_at)
+      VALUES ($1, $2, LOCALTIMESTAMP + make_interval(days => $3))"#).unwrap();
+    let valid = format!("valid{}", suffix);
+    let expired = format!("expired{}", suffix);
+    insert.execute(&[&valid, &1i32, &1i32]).await.unwrap();
+    insert.execute(&[&expired, &5i32, &-1i32]).await.unwrap();
+    let invited = |name: &str, code: &str| {
+      let db = db.clone();
+      let user = register(&format!("{}{}", name, suffix));
+      let code = code.to_string();
+      async move { db.user.register_user(&user, false, Some(&code)).await.unwrap() }
+    };
+
+    assert!(matches!(invited("invited", &valid).await, Registration::Registered(_)));
+    // the only use was redeemed.
+    assert!(matches!(invited("exhausted", &valid).await, Registration::InvalidInvite));
+    assert!(matches!(invited("expired", &expired).await, Registration::InvalidInvite));
+    assert!(matches!(invited("unknown", &format!("unknown{}", suffix)).await, Registration::InvalidInvite));
+    // a refused registration doesn't create the user.
+    assert!(db.user.get_by_email(&format!("exhausted{}@example.com", suffix)).await.unwrap().is_none());
+
+    // a failed registration doesn't use up the code.
+    let retry = format!("retry{}", suffix);
+    insert.execute(&[&retry, &1i32, &1i32]).await.unwrap();
+    registered(&db, &format!("owner{}", suffix), true).await;
+    let mut taken = register(&format!("taken{}", suffix));
+    taken.email = format!("owner{}+x@example.com", suffix);
+    assert!(matches!(db.user.register_user(&taken, true, Some(&retry)).await.unwrap(),
+      Registration::EmailUsed));
+    assert!(matches!(invited("retried", &retry).await, Registration::Registered(_)));
+  }
+
+  #[actix_rt::test]
+  async fn expired_revocations_are_pruned() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    // far from UTC, the expiries are UTC whatever the session time zone.
+    VersionedStatement::new(db.shared_cl.clone(), "SET TIME ZONE 'Etc/GMT-14'").unwrap()
+      .execute(&[]).await.unwrap();
+    let suffix = test_suffix();
+    let user = registered(&db, &format!("revoked{}", suffix), false).await;
+    let now = chrono::Utc::now().naive_utc();
+    let valid = Some(format!("valid{}", suffix));
+    let expired = Some(format!("expired{}", suffix));
+    db.user.revoke_token(valid.as_deref().unwrap(), now + chrono::Duration::hours(1)).await.unwrap();
+    db.user.revoke_token(expired.as_deref().unwrap(), now - chrono::Duration::minutes(1)).await.unwrap();
+    assert_eq!(db.user.get_token_state(user.id, &expired).await.unwrap(), Some((0, true)));
+
+    // the next revocation removes the expired one.
+    db.user.revoke_token(&format!("next{}", suffix), now + chrono::Duration::hours(1)).await.unwrap();
+    assert_eq!(db.user.get_token_state(user.id, &valid).await.unwrap(), Some((0, true)));
+    assert_eq!(db.user.get_token_state(user.id, &expired).await.unwrap(), Some((0, false)));
+  }
+
+  #[actix_rt::test]
+  async fn revoking_twice_is_a_no_op() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    // safe to retry after a lost connection, the second insert is skipped.
+    assert!(db.user.revoke_token.is_idempotent());
+    let suffix = test_suffix();
+    let user = registered(&db, &format!("revoked{}", suffix), false).await;
+    let jti = Some(format!("twice{}", suffix));
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::hours(1);
+    assert_eq!(db.user.revoke_token(jti.as_deref().unwrap(), expires_at).await.unwrap(), 1);
+    assert_eq!(db.user.revoke_token(jti.as_deref().unwrap(), expires_at).await.unwrap(), 0);
+    assert_eq!(db.user.get_token_state(user.id, &jti).await.unwrap(), Some((0, true)));
+  }
+
+  #[test]
+  fn canonical_emails() {
+    assert_eq!(canonicalize_email("User@Example.com"), "user@example.com");
+    assert_eq!(canonicalize_email(" user+news@example.com "), "user@example.com");
+    // dots only matter outside of gmail.
+    assert_eq!(canonicalize_email("first.last@example.com"), "first.last@example.com");
+    assert_eq!(canonicalize_email("First.Last+x@googlemail.com"), "firstlast@gmail.com");
+    // a leading `+` isn't a tag.
+    assert_eq!(canonicalize_email("+tag@example.com"), "+tag@example.com");
+    assert_eq!(canonicalize_email("not-an-email"), "not-an-email");
+  }
+
+  #[actix_rt::test]
+  async fn update_checks_canonical_email() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let a = registered(&db, &format!("ca{}", suffix), true).await;
+    let b = registered(&db, &format!("cb{}", suffix), true).await;
+
+    // a `+tag` variant of another account's email.
+    let req: UpdateUser = serde_json::from_value(json!({ "email": format!("ca{}+x@example.com", suffix) })).unwrap();
+    assert!(matches!(db.user.update_user(b.id, &req, true).await, Err(Error::UnprocessableEntity(_))));
+
+    // the old canonical email is released.
+    let req: UpdateUser = serde_json::from_value(json!({ "email": format!("moved{}@example.com", suffix) })).unwrap();
+    db.user.update_user(a.id, &req, true).await.unwrap();
+    let mut reuse = register(&format!("cc{}", suffix));
+    reuse.email = format!("ca{}+y@example.com", suffix);
+    assert!(matches!(db.user.register_user(&reuse, true, None).await.unwrap(), Registration::Registered(_)));
+  }
+
+  #[actix_rt::test]
+  async fn canonical_email_is_stored_while_unchecked() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let variant = |name: &str, tag: &str| {
+      let mut user = register(&format!("{}{}", name, suffix));
+      user.email = format!("off{}+{}@example.com", suffix, tag);
+      user
+    };
+    // while unchecked, variants of the same email can register.
+    let a = registered(&db, &format!("off{}", suffix), false).await;
+    assert!(matches!(db.user.register_user(&variant("offb", "b"), false, None).await.unwrap(),
+      Registration::Registered(_)));
+    // and are found once checked.
+    assert!(matches!(db.user.register_user(&variant("offc", "c"), true, None).await.unwrap(),
+      Registration::EmailUsed));
+
+    // an email changed while unchecked too.
+    let moved = format!("moved{}@example.com", suffix);
+    let req: UpdateUser = serde_json::from_value(json!({ "email": format!("moved{}+x@example.com", suffix) })).unwrap();
+    db.user.update_user(a.id, &req, false).await.unwrap().unwrap();
+    let mut reuse = register(&format!("offd{}", suffix));
+    reuse.email = moved.clone();
+    assert!(matches!(db.user.register_user(&reuse, true, None).await.unwrap(), Registration::EmailUsed));
+    let b = registered(&db, &format!("offe{}", suffix), false).await;
+    let req: UpdateUser = serde_json::from_value(json!({ "email": moved })).unwrap();
+    assert!(matches!(db.user.update_user(b.id, &req, true).await, Err(Error::UnprocessableEntity(_))));
+    // the user's own canonical email isn't a conflict.
+    let req: UpdateUser = serde_json::from_value(json!({ "email": format!("moved{}+y@example.com", suffix) })).unwrap();
+    assert!(db.user.update_user(a.id, &req, true).await.unwrap().is_some());
+  }
+
+  #[actix_rt::test]
+  async fn duplicate_username_or_email() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let a = registered(&db, &format!("a{}", suffix), false).await;
+    let b = registered(&db, &format!("b{}", suffix), false).await;
+
+    // only one column collides.
+    let mut dup = register(&a.username);
+    dup.email = format!("c{}@example.com", suffix);
+    assert!(matches!(db.user.register_user(&dup, false, None).await.unwrap(),
+      Registration::UsernameUsed));
+    let mut dup = register(&format!("c{}", suffix));
+    dup.email = a.email.clone();
+    assert!(matches!(db.user.register_user(&dup, false, None).await.unwrap(),
+      Registration::EmailUsed));
+
+    let req = UpdateUser {
+      username: Some(a.username.clone()),
+      ..Default::default()
+    };
+    assert!(matches!(db.user.update_user(b.id, &req, false).await,
+      Err(Error::UnprocessableEntity(_))));
+    let req = UpdateUser {
+      email: Some(a.email.clone()),
+      ..Default::default()
+    };
+    assert!(matches!(db.user.update_user(b.id, &req, false).await,
+      Err(Error::UnprocessableEntity(_))));
+    // an unknown user.
+    assert!(db.user.update_user(-1, &UpdateUser::default(), false).await.unwrap().is_none());
+  }
 }
@@ -101,6 +101,11 @@ impl ColumnMappers {
     String::from_utf8_lossy(&buf).to_string()
   }
 
+  pub fn build_insert_ignore(&self, on_conflict: &str, all_columns: bool) -> String {
+    format!(r#"{}
+      ON CONFLICT {} DO NOTHING"#, self.build_insert_query(all_columns), on_conflict)
+  }
+
   pub fn build_upsert(&self, on_conflict: &str, all_columns: bool) -> String {
     let mut buf = Vec::new();
     let mut idx = 0;
@@ -189,6 +194,18 @@ impl ColumnMappers {
   }
 }
 
+/// Build an `ORDER BY` clause for `alias.column`, with the unique `alias.id`
+/// as the secondary sort key (in the same direction), so paging is stable
+/// when the column has ties.
+pub fn build_order_by(alias: &str, column: &str, desc: bool) -> String {
+  let dir = if desc { "DESC" } else { "ASC" };
+  if column == "id" {
+    format!("ORDER BY {}.id {}", alias, dir)
+  } else {
+    format!("ORDER BY {0}.{1} {2}, {0}.id {2}", alias, column, dir)
+  }
+}
+
 fn row_value_to_string(row: &Row, idx: usize, col_type: &Type) -> Result<Option<String>> {
   match *col_type {
     Type::VARCHAR => {
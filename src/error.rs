@@ -19,6 +19,14 @@ pub enum Error {
   #[error("not found: {0}")]
   NotFound(JsonValue),
 
+  // 410
+  #[error("gone: {0}")]
+  Gone(JsonValue),
+
+  // 415
+  #[error("unsupported media type: {0}")]
+  UnsupportedMediaType(JsonValue),
+
   // 422
   #[error("unprocessable entity: {0}")]
   UnprocessableEntity(JsonValue),
@@ -51,6 +59,10 @@ pub enum Error {
   #[error("disconnected: {0}")]
   DisconnectedError(String),
 
+  // 503
+  #[error("database retry budget exhausted")]
+  RetryBudgetExhausted,
+
   #[error("postgres error")]
   PgError {
     #[from]
@@ -96,18 +108,37 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 // the ResponseError trait lets us convert errors to http responses with appropriate data
 // https://actix.rs/docs/errors/
 impl ResponseError for Error {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+      Error::NotFound(_) => StatusCode::NOT_FOUND,
+      Error::Gone(_) => StatusCode::GONE,
+      Error::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+      Error::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+      Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+      Error::DisconnectedError(_) => StatusCode::BAD_GATEWAY,
+      Error::RetryBudgetExhausted => StatusCode::SERVICE_UNAVAILABLE,
+      _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
   fn error_response(&self) -> HttpResponse {
     match self {
-      Error::Unauthorized(ref message) => HttpResponse::Unauthorized().json(message),
-      Error::NotFound(ref message) => HttpResponse::NotFound().json(message),
+      Error::Unauthorized(ref message) |
+      Error::NotFound(ref message) |
+      Error::Gone(ref message) |
+      Error::UnsupportedMediaType(ref message) |
       Error::UnprocessableEntity(ref message) => {
-        HttpResponse::build(StatusCode::UNPROCESSABLE_ENTITY).json(message)
-      },
-      Error::BadRequest(ref message) => {
-        HttpResponse::build(StatusCode::BAD_REQUEST).json(message)
+        HttpResponse::build(self.status_code()).json(message)
       },
+      Error::BadRequest(ref message) |
       Error::DisconnectedError(ref message) => {
-        HttpResponse::build(StatusCode::BAD_GATEWAY).json(message)
+        HttpResponse::build(self.status_code()).json(message)
+      },
+      Error::RetryBudgetExhausted => {
+        HttpResponse::build(self.status_code()).json(json!({
+          "error": "Database unavailable, try again later.",
+        }))
       },
       ref err => {
         error!("InternalServerError: {:?}", err);
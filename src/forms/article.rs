@@ -1,10 +1,26 @@
+use chrono::NaiveDateTime;
+
 use serde::{Deserialize, Serialize};
 
+use validator::Validate;
+
+use crate::models::{ArticleDetails, ArticleRevision, TimestampFormat};
+use crate::forms::not_blank;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ArticleOut<T> {
   pub article: T,
 }
 
+/// `ArticleOut` plus the non-fatal content warnings (`Article.emit_warnings`),
+/// `warnings` is left out when empty.
+#[derive(Debug, Serialize)]
+pub struct ArticleSavedOut<T> {
+  pub article: T,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub warnings: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ArticleList<T> {
@@ -12,36 +28,209 @@ pub struct ArticleList<T> {
   pub articles_count: usize,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArticleRevisionList {
+  pub revisions: Vec<ArticleRevision>,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct ArticleRequest {
   pub tag: Option<String>,
   pub author: Option<String>,
   pub favorited: Option<String>,
+  /// "newest" (default), "oldest" or "most_favorited".
+  pub sort: Option<String>,
   pub limit: Option<i64>,
   pub offset: Option<i64>,
 }
 
+/// Order of an article list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArticleSort {
+  Newest,
+  Oldest,
+  MostFavorited,
+}
+
+impl ArticleSort {
+  /// Accepted `sort` values.
+  pub const NAMES: &'static [&'static str] = &["newest", "oldest", "most_favorited"];
+}
+
+impl ArticleRequest {
+  /// Requested order, newest first by default.  `None` for an unknown `sort`.
+  pub fn sort_order(&self) -> Option<ArticleSort> {
+    match self.sort.as_deref() {
+      None | Some("newest") => Some(ArticleSort::Newest),
+      Some("oldest") => Some(ArticleSort::Oldest),
+      Some("most_favorited") => Some(ArticleSort::MostFavorited),
+      Some(_) => None,
+    }
+  }
+
+  /// Check if the list is filtered by tag, author or favorited.
+  pub fn has_filter(&self) -> bool {
+    self.tag.is_some() || self.author.is_some() || self.favorited.is_some()
+  }
+
+  /// Check that the tag filter is at most `max_len` characters (0 = unlimited).
+  pub fn tag_within(&self, max_len: usize) -> bool {
+    match &self.tag {
+      Some(tag) if max_len > 0 => tag.chars().count() <= max_len,
+      _ => true,
+    }
+  }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct FeedRequest {
   pub limit: Option<i64>,
   pub offset: Option<i64>,
 }
 
+/// Desired favorite state of `PUT /articles/<slug>/favorite`.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct SetFavorite {
+  pub favorited: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct SlugPreviewRequest {
+  pub title: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct UnreadCountRequest {
+  /// Last seen timestamp, in the API's timestamp format, or an article id
+  /// (only with the `rfc3339` format).
+  pub since: Option<String>,
+  /// Last seen article id, with any timestamp format.
+  pub since_id: Option<i32>,
+}
+
+/// Last seen position in the feed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedMarker {
+  Id(i32),
+  Time(NaiveDateTime),
+}
+
+impl FeedMarker {
+  /// Parse `since` by the API's timestamp format.  With `unix` an integer is
+  /// a timestamp, not an article id.
+  pub fn parse(since: &str, format: TimestampFormat) -> Option<Self> {
+    match format {
+      TimestampFormat::Unix => {
+        since.parse::<i64>().ok()
+          .and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, 0))
+          .map(FeedMarker::Time)
+      },
+      TimestampFormat::Rfc3339 => {
+        if let Ok(id) = since.parse::<i32>() {
+          return Some(FeedMarker::Id(id));
+        }
+        chrono::DateTime::parse_from_rfc3339(since).ok()
+          .map(|ts| FeedMarker::Time(ts.naive_utc()))
+      },
+    }
+  }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct DeleteArticleRequest {
+  /// Only report the comments/favorites that would be removed.
+  pub dry_run: Option<bool>,
+  /// Number of comments + favorites the caller expects to remove.
+  pub confirm: Option<i64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateArticle {
+  #[validate(custom = "not_blank")]
   pub title: String,
+  /// Checked by `Article.require_description`/`max_description_len`.
   pub description: String,
+  #[validate(custom = "not_blank")]
   pub body: String,
   pub tag_list: Vec<String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateArticle {
+  #[validate(custom = "not_blank")]
   pub title: Option<String>,
   pub description: Option<String>,
+  #[validate(custom = "not_blank")]
   pub body: Option<String>,
-  pub tag_list: Vec<String>,
+  /// Tags are left unchanged when omitted.
+  #[serde(default)]
+  pub tag_list: Option<Vec<String>>,
 }
 
+/// Changed fields of a partially updated article.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArticleChanges {
+  pub slug: String,
+  #[serde(with = "crate::models::timestamp")]
+  pub updated_at: NaiveDateTime,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub body: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tag_list: Option<Vec<String>>,
+}
+
+impl ArticleChanges {
+  pub fn diff(old: &ArticleDetails, new: &ArticleDetails) -> Self {
+    fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+      if old != new { Some(new.clone()) } else { None }
+    }
+    Self {
+      slug: new.slug.clone(),
+      updated_at: new.updated_at,
+      title: changed(&old.title, &new.title),
+      description: changed(&old.description, &new.description),
+      body: changed(&old.body, &new.body),
+      tag_list: changed(&old.tag_list, &new.tag_list),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn feed_marker_by_timestamp_format() {
+    let time = |secs| Some(FeedMarker::Time(NaiveDateTime::from_timestamp(secs, 0)));
+    assert_eq!(FeedMarker::parse("42", TimestampFormat::Rfc3339), Some(FeedMarker::Id(42)));
+    assert_eq!(FeedMarker::parse("2016-02-18T03:22:56Z", TimestampFormat::Rfc3339), time(1455765776));
+    // unix seconds are a timestamp, not an article id.
+    assert_eq!(FeedMarker::parse("1455765776", TimestampFormat::Unix), time(1455765776));
+    assert_eq!(FeedMarker::parse("42", TimestampFormat::Unix), time(42));
+    assert_eq!(FeedMarker::parse("2016-02-18T03:22:56Z", TimestampFormat::Unix), None);
+    assert_eq!(FeedMarker::parse("new", TimestampFormat::Rfc3339), None);
+  }
+
+  #[test]
+  fn sort_order_names() {
+    let sort = |sort: Option<&str>| ArticleRequest {
+      sort: sort.map(str::to_string),
+      ..Default::default()
+    }.sort_order();
+    assert_eq!(sort(None), Some(ArticleSort::Newest));
+    assert_eq!(sort(Some("newest")), Some(ArticleSort::Newest));
+    assert_eq!(sort(Some("oldest")), Some(ArticleSort::Oldest));
+    assert_eq!(sort(Some("most_favorited")), Some(ArticleSort::MostFavorited));
+    assert_eq!(sort(Some("Oldest")), None);
+    assert_eq!(sort(Some("")), None);
+    // every documented name is accepted.
+    assert!(ArticleSort::NAMES.iter().all(|name| sort(Some(name)).is_some()));
+  }
+}
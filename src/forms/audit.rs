@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::audit::*;
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuditRequest {
+  pub limit: Option<i64>,
+  pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditList {
+  pub entries: Vec<AuditEntry>,
+  pub entries_count: usize,
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use validator::Validate;
+
 use crate::models::comment::*;
+use crate::forms::not_blank;
+use crate::models::Profile;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CommentOut<T> {
@@ -14,6 +18,22 @@ pub struct CommentList {
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TopCommentersRequest {
+  pub limit: Option<i64>,
+  /// Only count comments since this RFC3339 time.
+  pub since: Option<String>,
+}
+
+/// A user and their number of comments, for `GET /stats/top-commenters`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopCommenter {
+  pub profile: Profile,
+  pub comments_count: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Validate)]
 pub struct CreateComment {
+  #[validate(custom = "not_blank")]
   pub body: String,
 }
@@ -9,3 +9,12 @@ pub use user::*;
 
 pub mod tag;
 pub use tag::*;
+
+pub mod audit;
+pub use audit::*;
+
+pub mod page;
+pub use page::*;
+
+pub mod validate;
+pub use validate::*;
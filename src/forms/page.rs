@@ -0,0 +1,147 @@
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+/// Page size when a list request doesn't have a `limit`.
+pub const DEFAULT_PAGE_LIMIT: i64 = 20;
+
+thread_local! {
+  // Set by each server's workers, so servers can use different shapes.
+  static RICH_PAGINATION: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set the list shape for this thread (a server worker).
+pub fn set_rich_pagination(enabled: bool) {
+  RICH_PAGINATION.with(|rich| rich.set(enabled));
+}
+
+pub fn rich_pagination() -> bool {
+  RICH_PAGINATION.with(|rich| rich.get())
+}
+
+/// Paging metadata of `api.rich_pagination` list responses.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PageMeta {
+  /// Total number of items, when known.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub total: Option<i64>,
+  pub limit: Option<i64>,
+  pub offset: i64,
+  pub has_more: bool,
+}
+
+impl PageMeta {
+  /// A page of `len` items, a full page might have more items after it.
+  /// Without a total, an empty page (`limit=0`) doesn't tell.
+  pub fn page(len: usize, limit: Option<i64>, offset: Option<i64>) -> Self {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    Self {
+      total: None,
+      limit: Some(limit),
+      offset: offset.unwrap_or(0),
+      has_more: limit > 0 && len as i64 >= limit,
+    }
+  }
+
+  /// Set the total number of items, `has_more` is exact with a total.
+  pub fn with_total(mut self, total: i64) -> Self {
+    self.total = Some(total);
+    if let Some(limit) = self.limit {
+      self.has_more = self.offset + limit < total;
+    }
+    self
+  }
+
+  /// All `len` items of the list.
+  pub fn complete(len: usize) -> Self {
+    Self {
+      total: Some(len as i64),
+      limit: None,
+      offset: 0,
+      has_more: false,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+  pub data: Vec<T>,
+  pub meta: PageMeta,
+}
+
+/// List response, the RealWorld shape (`L`) or `{data, meta}` with
+/// `api.rich_pagination`.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ListOut<L, T> {
+  Legacy(L),
+  Rich(Page<T>),
+}
+
+impl<L, T> ListOut<L, T> {
+  pub fn new<F: FnOnce(Vec<T>) -> L>(items: Vec<T>, meta: PageMeta, legacy: F) -> Self {
+    if rich_pagination() {
+      ListOut::Rich(Page {
+        data: items,
+        meta,
+      })
+    } else {
+      ListOut::Legacy(legacy(items))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde_json::json;
+
+  #[derive(Serialize)]
+  #[serde(rename_all = "camelCase")]
+  struct Names {
+    names: Vec<&'static str>,
+    names_count: usize,
+  }
+
+  fn names_out(meta: PageMeta) -> serde_json::Value {
+    let out = ListOut::new(vec!["a", "b"], meta, |names| Names {
+      names_count: names.len(),
+      names,
+    });
+    serde_json::to_value(&out).unwrap()
+  }
+
+  #[test]
+  fn legacy_and_rich_shapes() {
+    assert!(!rich_pagination());
+    let meta = PageMeta::page(2, Some(2), Some(4)).with_total(7);
+    assert_eq!(names_out(meta.clone()), json!({
+      "names": ["a", "b"],
+      "namesCount": 2,
+    }));
+
+    set_rich_pagination(true);
+    let rich = names_out(meta);
+    // other threads (servers) keep their own shape.
+    assert!(!std::thread::spawn(rich_pagination).join().unwrap());
+    set_rich_pagination(false);
+    assert_eq!(rich, json!({
+      "data": ["a", "b"],
+      "meta": { "total": 7, "limit": 2, "offset": 4, "hasMore": true },
+    }));
+  }
+
+  #[test]
+  fn has_more() {
+    assert!(PageMeta::page(20, None, None).has_more);
+    assert!(!PageMeta::page(19, None, None).has_more);
+    // an empty page doesn't tell without a total.
+    assert!(!PageMeta::page(0, Some(0), None).has_more);
+    assert!(PageMeta::page(0, Some(0), None).with_total(1).has_more);
+    assert!(!PageMeta::page(0, Some(0), Some(1)).with_total(1).has_more);
+    assert!(!PageMeta::page(2, Some(2), Some(5)).with_total(7).has_more);
+    assert!(!PageMeta::complete(3).has_more);
+  }
+}
@@ -2,6 +2,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::models::tag::*;
 
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TagRequest {
+  pub limit: Option<i64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TagList {
   pub tags: Vec<TagName>,
@@ -1,36 +1,104 @@
-use std::convert::TryFrom;
+use chrono::NaiveDateTime;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use validator::Validate;
 
 use crate::error::*;
 use crate::auth::jwt::*;
-use crate::models::{User, Profile};
+use crate::models::{User, Profile, ArticleDetails, Comment};
+use crate::forms::{not_blank, check_max_len};
 
 #[derive(Debug, Deserialize)]
 pub struct UserOut<T> {
   pub user: T,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Validate)]
 pub struct LoginUser {
+  #[validate(custom = "not_blank")]
   pub email: String,
+  #[validate(length(min = 1, message = "can't be blank"))]
   pub password: String,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Validate)]
 pub struct RegisterUser {
+  #[validate(custom = "not_blank")]
   pub username: String,
+  #[validate(email(message = "is invalid"))]
   pub email: String,
+  #[validate(length(min = 8, message = "is too short (minimum is 8 characters)"))]
   pub password: String,
+  /// Invite code, required with `User.require_invite`.
+  #[serde(default)]
+  pub code: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AvailableRequest {
+  pub username: Option<String>,
+  pub email: Option<String>,
+}
+
+/// Partial user update.
+///
+/// Omitted fields are left unchanged.  `bio` and `image` can be cleared
+/// by sending `null` or an empty string.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Validate)]
 pub struct UpdateUser {
+  #[validate(custom = "not_blank")]
   pub username: Option<String>,
+  #[validate(email(message = "is invalid"))]
   pub email: Option<String>,
+  #[validate(length(min = 8, message = "is too short (minimum is 8 characters)"))]
   pub password: Option<String>,
-  pub bio: Option<String>,
-  pub image: Option<String>,
+  #[serde(default, deserialize_with = "nullable_field")]
+  pub bio: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable_field")]
+  pub image: Option<Option<String>>,
+}
+
+/// Maximum lengths (characters) of user fields, 0 = unlimited.
+#[derive(Debug, Clone, Copy)]
+pub struct UserLimits {
+  pub username: usize,
+  pub email: usize,
+  pub bio: usize,
+}
+
+impl Default for UserLimits {
+  fn default() -> Self {
+    Self {
+      username: 64,
+      email: 254,
+      bio: 0,
+    }
+  }
+}
+
+impl UserLimits {
+  pub fn check_register(&self, user: &RegisterUser) -> Result<()> {
+    check_max_len(&[
+      ("username", Some(user.username.as_str()), self.username),
+      ("email", Some(user.email.as_str()), self.email),
+    ])
+  }
+
+  pub fn check_update(&self, user: &UpdateUser) -> Result<()> {
+    check_max_len(&[
+      ("username", user.username.as_deref(), self.username),
+      ("email", user.email.as_deref(), self.email),
+      ("bio", user.bio.as_ref().and_then(|bio| bio.as_deref()), self.bio),
+    ])
+  }
+}
+
+/// Distinguish a missing field (`None`) from an explicit `null` (`Some(None)`).
+/// An empty string is treated as `null`.
+fn nullable_field<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+  let val: Option<String> = Option::deserialize(deserializer)?;
+  Ok(Some(val.filter(|v| !v.is_empty())))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +106,72 @@ pub struct ProfileOut {
   pub profile: Profile,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct FollowBatchRequest {
+  pub usernames: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum FollowStatus {
+  Followed,
+  AlreadyFollowing,
+  #[serde(rename = "self")]
+  IsSelf,
+  NotFound,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FollowResult {
+  pub username: String,
+  pub status: FollowStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FollowBatchResponse {
+  pub results: Vec<FollowResult>,
+}
+
+/// The user's own account data in `GET /user/export` (no password hash).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedUser {
+  pub username: String,
+  pub email: String,
+  pub bio: Option<String>,
+  pub image: Option<String>,
+  #[serde(with = "crate::models::timestamp")]
+  pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
+  pub updated_at: NaiveDateTime,
+}
+
+impl From<User> for ExportedUser {
+  fn from(user: User) -> Self {
+    Self {
+      username: user.username,
+      email: user.email,
+      bio: user.bio,
+      image: user.image,
+      created_at: user.created_at,
+      updated_at: user.updated_at,
+    }
+  }
+}
+
+/// Everything stored for a user (`GET /user/export`).  Others' content is
+/// only referenced: followed users by username, favorites by slug.
+#[derive(Debug, Serialize)]
+pub struct UserExport {
+  pub user: ExportedUser,
+  pub articles: Vec<ArticleDetails>,
+  pub comments: Vec<Comment>,
+  pub following: Vec<String>,
+  pub favorites: Vec<String>,
+  /// A list was cut at `User.export_max_items`.
+  pub truncated: bool,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct UserResponseInner {
   pub username: String,
@@ -52,11 +186,10 @@ pub struct UserResponse {
   pub user: UserResponseInner,
 }
 
-impl TryFrom<User> for UserResponse {
-  type Error = Error;
-
-  fn try_from(user: User) -> Result<Self> {
-    let token = user.generate_jwt()?;
+impl UserResponse {
+  /// Response for `user`, with a new token.
+  pub fn new(user: User, keys: &JwtKeys) -> Result<Self> {
+    let token = user.generate_jwt(keys)?;
     Ok(UserResponse {
       user: UserResponseInner {
         username: user.username,
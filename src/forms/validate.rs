@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::error::*;
+
+/// Validate a request form.  Failures are an `UnprocessableEntity` with the
+/// RealWorld error body: `{"errors": {"<field>": ["<message>", ..]}}`.
+pub fn validate_form<T: Validate>(form: &T) -> Result<()> {
+  form.validate().map_err(validation_error)
+}
+
+/// Validator for required text fields, whitespace alone is blank:
+/// `#[validate(custom = "not_blank")]`.
+pub fn not_blank(val: &str) -> std::result::Result<(), ValidationError> {
+  if val.trim().is_empty() {
+    let mut err = ValidationError::new("blank");
+    err.message = Some("can't be blank".into());
+    return Err(err);
+  }
+  Ok(())
+}
+
+/// Check that each `(field, value, max)` is at most `max` characters
+/// (0 = unlimited), with the same error body as `validate_form`.
+pub fn check_max_len(fields: &[(&'static str, Option<&str>, usize)]) -> Result<()> {
+  let mut errors = BTreeMap::new();
+  for &(field, val, max) in fields {
+    match val {
+      Some(val) if max > 0 && val.chars().count() > max => {
+        errors.insert(field, vec![format!("is too long (maximum is {} characters)", max)]);
+      },
+      _ => (),
+    }
+  }
+  if errors.is_empty() {
+    return Ok(());
+  }
+  Err(Error::UnprocessableEntity(json!({
+    "errors": errors,
+  })))
+}
+
+fn validation_error(errors: ValidationErrors) -> Error {
+  let mut fields = BTreeMap::new();
+  for (field, errors) in errors.field_errors() {
+    let messages: Vec<String> = errors.iter().map(|err| {
+      match &err.message {
+        Some(msg) => msg.to_string(),
+        None => err.code.to_string(),
+      }
+    }).collect();
+    fields.insert(field, messages);
+  }
+  Error::UnprocessableEntity(json!({
+    "errors": fields,
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn blank_fields() {
+    assert!(not_blank("title").is_ok());
+    assert!(not_blank(" a ").is_ok());
+    for blank in &["", " ", "\t\n", "\u{3000}"] {
+      let err = not_blank(blank).unwrap_err();
+      assert_eq!(err.message.as_deref(), Some("can't be blank"));
+    }
+  }
+
+  /// Fields with errors of a failed `validate_form`.
+  fn invalid_fields<T: Validate>(form: &T) -> Vec<String> {
+    match validate_form(form) {
+      Err(Error::UnprocessableEntity(body)) => {
+        body["errors"].as_object().unwrap().keys().cloned().collect()
+      },
+      res => panic!("expected UnprocessableEntity: {:?}", res),
+    }
+  }
+
+  #[test]
+  fn invalid_forms() {
+    use crate::forms::{RegisterUser, LoginUser, UpdateUser, CreateArticle, UpdateArticle, CreateComment};
+
+    let register = RegisterUser {
+      username: "jake".to_string(),
+      email: "jake@example.com".to_string(),
+      password: "password".to_string(),
+      code: None,
+    };
+    assert!(validate_form(&register).is_ok());
+    let register = RegisterUser {
+      username: " \t".to_string(),
+      email: "jake".to_string(),
+      password: "short".to_string(),
+      code: None,
+    };
+    assert_eq!(invalid_fields(&register), vec!["email", "password", "username"]);
+    assert_eq!(invalid_fields(&LoginUser::default()), vec!["email", "password"]);
+
+    let update = UpdateUser { username: Some("  ".to_string()), ..Default::default() };
+    assert_eq!(invalid_fields(&update), vec!["username"]);
+    let update = UpdateUser { email: Some("jake".to_string()), ..Default::default() };
+    assert_eq!(invalid_fields(&update), vec!["email"]);
+    assert!(validate_form(&UpdateUser::default()).is_ok());
+
+    let article = CreateArticle {
+      title: "  ".to_string(),
+      body: "\n".to_string(),
+      description: "description".to_string(),
+      ..Default::default()
+    };
+    assert_eq!(invalid_fields(&article), vec!["body", "title"]);
+    assert_eq!(invalid_fields(&CreateArticle::default()), vec!["body", "title"]);
+    let update = UpdateArticle { title: Some(" ".to_string()), ..Default::default() };
+    assert_eq!(invalid_fields(&update), vec!["title"]);
+    assert!(validate_form(&UpdateArticle::default()).is_ok());
+
+    let comment = CreateComment { body: "   ".to_string() };
+    assert_eq!(invalid_fields(&comment), vec!["body"]);
+    let comment = CreateComment { body: " ok ".to_string() };
+    assert!(validate_form(&comment).is_ok());
+  }
+
+  #[test]
+  fn max_len_in_characters() {
+    assert!(check_max_len(&[]).is_ok());
+    assert!(check_max_len(&[("bio", Some("caf\u{e9}"), 4)]).is_ok());
+    // 0 = unlimited, missing values aren't checked.
+    assert!(check_max_len(&[("bio", Some("long bio"), 0), ("email", None, 1)]).is_ok());
+    match check_max_len(&[("username", Some("abcd"), 3), ("email", Some("a@b"), 3), ("bio", Some("xy"), 1)]) {
+      Err(Error::UnprocessableEntity(body)) => assert_eq!(body, json!({
+        "errors": {
+          "bio": ["is too long (maximum is 1 characters)"],
+          "username": ["is too long (maximum is 3 characters)"],
+        },
+      })),
+      res => panic!("expected UnprocessableEntity: {:?}", res),
+    }
+  }
+}
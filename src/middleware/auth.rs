@@ -1,17 +1,22 @@
 use log::*;
 
+use std::rc::Rc;
+use std::cell::RefCell;
 use std::task::{Context, Poll};
 
-use futures::future::{ok, err, Either, Ready};
+use futures::future::{ok, err, Either, LocalBoxFuture, Ready};
+
+use chrono::{Duration, Utc};
 
 use actix_web::{
   http::header::{
-    HeaderMap, AUTHORIZATION
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION
   },
   error::ErrorNotFound,
   Error, HttpMessage,
   HttpResponse, ResponseError,
-  HttpRequest, FromRequest
+  HttpRequest, FromRequest,
+  web,
 };
 use actix_web::dev::{
   Service, Transform,
@@ -21,10 +26,33 @@ use actix_web::dev::{
 
 use crate::error::Result;
 use crate::auth::jwt::*;
+use crate::db::DbService;
+
+/// Seconds until the request's token expires.
+const TOKEN_EXPIRES_IN: &str = "x-token-expires-in";
+/// Set when the token expires within `auth.refresh_window_secs`.
+const TOKEN_REFRESH_SUGGESTED: &str = "x-token-refresh-suggested";
+
+/// Accepted `Authorization` schemes (case-insensitive).
+const TOKEN_SCHEMES: &[&str] = &["Token", "Bearer"];
+
+/// Remove the scheme from an `Authorization` header value.
+fn strip_scheme(header: &str) -> Option<&str> {
+  let (scheme, token) = header.split_once(' ')?;
+  if TOKEN_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme)) {
+    Some(token.trim_start())
+  } else {
+    None
+  }
+}
 
-const TOKEN_PREFIX: &str = "Token ";
+/// Check tokens against the user's current token version
+/// (`auth.check_token_version`), registered as app data.  Costs an extra
+/// lookup per authenticated request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenVersionCheck(pub bool);
 
-pub fn decode_jwt_claims(headers: &HeaderMap) -> Result<Option<AuthData>> {
+pub fn decode_jwt_claims(headers: &HeaderMap, keys: &JwtKeys) -> Result<Option<AuthData>> {
   let token = match headers.get(AUTHORIZATION) {
     Some(token) => {
       let token = token.to_str().map_err(|_| {
@@ -32,13 +60,14 @@ pub fn decode_jwt_claims(headers: &HeaderMap) -> Result<Option<AuthData>> {
           "error": "Invalid authorization token",
         }))
       })?;
-      if !token.starts_with(TOKEN_PREFIX) {
-        return Err(crate::error::Error::Unauthorized(json!({
-          "error": "Invalid authorization method",
-        })));
+      match strip_scheme(token) {
+        Some(token) => token.to_string(),
+        None => {
+          return Err(crate::error::Error::Unauthorized(json!({
+            "error": "Invalid authorization method",
+          })));
+        },
       }
-      // remove prefix
-      token.replacen(TOKEN_PREFIX, "", 1)
     },
     None => {
       // No authorization provided.  Allow caller to decide if this is an error.
@@ -46,11 +75,22 @@ pub fn decode_jwt_claims(headers: &HeaderMap) -> Result<Option<AuthData>> {
     },
   };
 
-  let auth_data = token.decode_jwt()?;
+  let auth_data = token.decode_jwt(keys)?;
 
   Ok(Some(auth_data))
 }
 
+/// Seconds until the token expires (`X-Token-Expires-In`), and
+/// `X-Token-Refresh-Suggested` once inside the refresh window.
+fn set_token_headers<B>(res: &mut ServiceResponse<B>, exp: i64, refresh_window: Duration) {
+  let expires_in = (exp - Utc::now().timestamp()).max(0);
+  let headers = res.headers_mut();
+  headers.insert(HeaderName::from_static(TOKEN_EXPIRES_IN), HeaderValue::from(expires_in));
+  if refresh_window > Duration::zero() && expires_in <= refresh_window.num_seconds() {
+    headers.insert(HeaderName::from_static(TOKEN_REFRESH_SUGGESTED), HeaderValue::from_static("true"));
+  }
+}
+
 impl FromRequest for AuthData {
   type Error = Error;
   type Future = Ready<Result<Self, Self::Error>>;
@@ -88,8 +128,9 @@ impl Auth {
 
 impl<S, B> Transform<S> for Auth
 where
-  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
   S::Future: 'static,
+  B: 'static,
 {
   type Request = ServiceRequest;
   type Response = ServiceResponse<B>;
@@ -101,41 +142,90 @@ where
   fn new_transform(&self, service: S) -> Self::Future {
     ok(AuthMiddleware {
       is_optional: self.is_optional,
-      service
+      service: Rc::new(RefCell::new(service)),
     })
   }
 }
 
 pub struct AuthMiddleware<S> {
   is_optional: bool,
-  service: S,
+  service: Rc<RefCell<S>>,
 }
 
 impl<S, B> Service for AuthMiddleware<S>
 where
-  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
   S::Future: 'static,
+  B: 'static,
 {
   type Request = ServiceRequest;
   type Response = ServiceResponse<B>;
   type Error = Error;
-  type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+  type Future = Either<
+    Either<S::Future, LocalBoxFuture<'static, Result<Self::Response, Self::Error>>>,
+    Ready<Result<Self::Response, Self::Error>>
+  >;
 
   fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-    self.service.poll_ready(cx)
+    self.service.borrow_mut().poll_ready(cx)
   }
 
   fn call(&mut self, req: ServiceRequest) -> Self::Future {
-    let has_auth = match decode_jwt_claims(req.headers()) {
+    let claims = match req.app_data::<web::Data<JwtKeys>>() {
+      Some(keys) => decode_jwt_claims(req.headers(), keys),
+      None => Err(crate::error::Error::InternalServerError),
+    };
+    match claims {
       Ok(Some(auth_data)) => {
         debug!("Has authorization token: {:?}", auth_data);
+        let exp = auth_data.exp;
+        let refresh_window = req.app_data::<web::Data<JwtKeys>>()
+          .map(|keys| keys.refresh_window())
+          .unwrap_or_else(Duration::zero);
+        // Check the token version against the user's current version and the
+        // token id against revoked tokens, if enabled.
+        let version_check = req.app_data::<web::Data<TokenVersionCheck>>().is_some_and(|check| check.0);
+        let jti = match req.app_data::<web::Data<JwtKeys>>() {
+          Some(keys) if keys.revocation_enabled() => auth_data.jti.clone(),
+          _ => None,
+        };
+        let db = req.app_data::<web::Data<DbService>>().filter(|_| version_check || jti.is_some());
+        if let Some(db) = db {
+          let db = db.clone();
+          let user_id = auth_data.user_id;
+          // without the version check any version is accepted.
+          let token_version = if version_check { auth_data.token_version } else { i32::MAX };
+          req.extensions_mut().insert(auth_data);
+          let service = self.service.clone();
+          return Either::Left(Either::Right(Box::pin(async move {
+            let state = if jti.is_some() {
+              db.user.get_token_state(user_id, &jti).await?
+            } else {
+              db.user.get_token_version(user_id).await?.map(|v| (v, false))
+            };
+            match state {
+              Some((version, false)) if token_version >= version => {
+                let fut = service.borrow_mut().call(req);
+                let mut res = fut.await?;
+                set_token_headers(&mut res, exp, refresh_window);
+                Ok(res)
+              },
+              _ => Err(crate::error::Error::Unauthorized(json!({
+                "error": "Token revoked",
+              })).into()),
+            }
+          })));
+        }
         req.extensions_mut().insert(auth_data);
-
-        true
+        let fut = self.service.borrow_mut().call(req);
+        return Either::Left(Either::Right(Box::pin(async move {
+          let mut res = fut.await?;
+          set_token_headers(&mut res, exp, refresh_window);
+          Ok(res)
+        })));
       },
       Ok(None) => {
         debug!("No authorization token");
-        false
       },
       Err(err) => {
         error!("Error getting JWT claims: {:?}", err);
@@ -143,11 +233,11 @@ where
           err.error_response().into_body()
         )));
       },
-    };
+    }
 
-    debug!("Auth check: has_auth={}, optional={}", has_auth, self.is_optional);
-    if has_auth || self.is_optional {
-      Either::Left(self.service.call(req))
+    debug!("Auth check: optional={}", self.is_optional);
+    if self.is_optional {
+      Either::Left(Either::Left(self.service.borrow_mut().call(req)))
     } else {
       Either::Right(ok(req.into_response(
         HttpResponse::Unauthorized().json(json!({
@@ -158,3 +248,144 @@ where
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, App, http::StatusCode};
+
+  #[actix_rt::test]
+  async fn token_expiry_headers() {
+    use crate::app::AppConfig;
+
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("auth.jwt_secret", "a test secret that is long enough").unwrap();
+    config.conf.set("auth.refresh_window_secs", 600).unwrap();
+    let keys = JwtKeys::from_config(&config).unwrap();
+    let user = crate::auth::jwt::test_jwt_user();
+    let mut app = test::init_service(App::new()
+      .data(keys.clone())
+      .service(web::resource("/user")
+        .wrap(Auth::optional())
+        .to(HttpResponse::Ok)))
+      .await;
+    let mut headers = |token: Option<String>| {
+      let mut req = test::TestRequest::get().uri("/user");
+      if let Some(token) = token {
+        req = req.header(AUTHORIZATION, format!("Token {}", token));
+      }
+      let res = app.call(req.to_request());
+      async move {
+        let res = res.await.unwrap();
+        let header = |name: &str| res.headers().get(name).map(|value| value.to_str().unwrap().to_string());
+        (header(TOKEN_EXPIRES_IN).map(|secs| secs.parse::<i64>().unwrap()), header(TOKEN_REFRESH_SUGGESTED))
+      }
+    };
+
+    // (token lifetime, refresh suggested)
+    for &(lifetime, suggested) in &[(3600, false), (300, true)] {
+      let token = user.generate_jwt(&keys.clone().with_expiry(Duration::seconds(lifetime))).unwrap();
+      let (expires_in, refresh) = headers(Some(token)).await;
+      let expires_in = expires_in.expect("X-Token-Expires-In");
+      assert!(expires_in <= lifetime && expires_in >= lifetime - 5, "{} of {}", expires_in, lifetime);
+      assert_eq!(refresh.as_deref(), if suggested { Some("true") } else { None });
+    }
+    // anonymous requests have no token headers.
+    assert_eq!(headers(None).await, (None, None));
+  }
+
+  async fn status(db: &DbService, version_check: bool, token: &str) -> StatusCode {
+    let mut app = test::init_service(App::new()
+      .data(db.clone())
+      .data(TokenVersionCheck(version_check))
+      .data(crate::services::test_jwt_keys())
+      .service(web::resource("/user")
+        .wrap(Auth::required())
+        .to(HttpResponse::Ok)))
+      .await;
+    let req = test::TestRequest::get().uri("/user")
+      .header(AUTHORIZATION, format!("Token {}", token))
+      .to_request();
+    // a rejected token is an `Err` from the middleware.
+    match app.call(req).await {
+      Ok(res) => res.status(),
+      Err(err) => err.as_response_error().error_response().status(),
+    }
+  }
+
+  #[actix_rt::test]
+  async fn bump_invalidates_old_tokens() {
+    let db = match crate::db::test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let name = format!("bump{}", crate::db::test_suffix());
+    let (auth, token) = crate::services::test_login(&db, &name).await;
+    assert_eq!(status(&db, true, &token).await, 200);
+
+    assert_eq!(db.user.bump_token_version(&name).await.unwrap(), 1);
+    assert_eq!(status(&db, true, &token).await, 401);
+    // without the check the old token is still accepted (no lookup).
+    assert_eq!(status(&db, false, &token).await, 200);
+
+    // a new token has the current version.
+    let user = db.user.get_by_id(auth.user_id).await.unwrap().expect("test user");
+    let token = user.generate_jwt(&crate::services::test_jwt_keys()).unwrap();
+    assert_eq!(status(&db, true, &token).await, 200);
+  }
+
+  #[actix_rt::test]
+  async fn tokens_are_checked_with_the_app_keys() {
+    let db = match crate::db::test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let name = format!("keys{}", crate::db::test_suffix());
+    let (auth, token) = crate::services::test_login(&db, &name).await;
+    assert_eq!(status(&db, false, &token).await, 200);
+
+    // signed with another secret.
+    let user = db.user.get_by_id(auth.user_id).await.unwrap().expect("test user");
+    let other = JwtKeys::from_secret("another secret, also 32 bytes long");
+    let token = user.generate_jwt(&other).unwrap();
+    assert_ne!(status(&db, false, &token).await, StatusCode::OK);
+  }
+
+  #[test]
+  fn strip_token_schemes() {
+    assert_eq!(strip_scheme("Token abc"), Some("abc"));
+    assert_eq!(strip_scheme("Bearer abc"), Some("abc"));
+    assert_eq!(strip_scheme("bearer   abc"), Some("abc"));
+    assert_eq!(strip_scheme("TOKEN abc"), Some("abc"));
+    assert_eq!(strip_scheme("Basic abc"), None);
+    assert_eq!(strip_scheme("abc"), None);
+    assert_eq!(strip_scheme(""), None);
+  }
+
+  #[test]
+  fn decode_either_scheme() {
+    use actix_web::http::HeaderValue;
+
+    let keys = crate::services::test_jwt_keys();
+    let user = crate::auth::jwt::test_jwt_user();
+    let token = user.generate_jwt(&keys).unwrap();
+    let decode = |value: String| {
+      let mut headers = HeaderMap::new();
+      headers.insert(AUTHORIZATION, HeaderValue::from_str(&value).unwrap());
+      decode_jwt_claims(&headers, &keys)
+    };
+    for scheme in &["Token", "Bearer", "bearer"] {
+      let auth = decode(format!("{} {}", scheme, token)).unwrap().expect("claims");
+      assert_eq!(auth.user_id, user.id, "{}", scheme);
+      assert_eq!(auth.token, token);
+    }
+    match decode(format!("Basic {}", token)) {
+      Err(crate::error::Error::Unauthorized(body)) => {
+        assert_eq!(body["error"], "Invalid authorization method");
+      },
+      res => panic!("expected Unauthorized: {:?}", res),
+    }
+    assert!(decode_jwt_claims(&HeaderMap::new(), &keys).unwrap().is_none());
+  }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use actix_web::{
+  http::{
+    header::{HeaderValue, AUTHORIZATION, CACHE_CONTROL, VARY},
+    Method, StatusCode,
+  },
+  Error,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+
+const NO_STORE: &str = "no-store";
+
+/// A `Cache-Control` value for a path.  A path ending with `*` matches as a prefix.
+#[derive(Debug, Clone)]
+pub struct CacheRule {
+  pub path: String,
+  pub value: HeaderValue,
+}
+
+fn config_error(msg: String) -> crate::error::Error {
+  config::ConfigError::Message(msg).into()
+}
+
+impl CacheRule {
+  fn matches(&self, path: &str) -> bool {
+    if self.path.ends_with('*') {
+      path.starts_with(&self.path[..self.path.len() - 1])
+    } else {
+      path == self.path
+    }
+  }
+}
+
+/// Sets `Cache-Control` headers on GET/HEAD responses.
+///
+/// Only successful (2xx/304) responses of paths with a configured rule are
+/// cacheable.  Authenticated requests always get `no-store`, so per-user data
+/// is never cached.  Responses with a policy also get `Vary: Authorization`,
+/// since they differ by token.
+#[derive(Debug, Clone, Default)]
+pub struct CacheControl {
+  rules: Arc<Vec<CacheRule>>,
+}
+
+impl CacheControl {
+  /// Load rules from `<prefix>.cache.rules`:
+  ///
+  /// ```toml
+  /// [[public.cache.rules]]
+  /// path = "/api/tags"
+  /// value = "public, max-age=300"
+  /// ```
+  pub fn from_config(config: &AppConfig, prefix: &str) -> Result<Self> {
+    let mut rules = Vec::new();
+    if let Some(list) = config.get_array(&format!("{}.cache.rules", prefix))? {
+      for rule in list.into_iter() {
+        let rule = crate::app::Table::from(rule.into_table()?);
+        let path = rule.get_str("path")?
+          .ok_or_else(|| config_error("Cache rule missing 'path'".to_string()))?;
+        let value = rule.get_str("value")?
+          .ok_or_else(|| config_error(format!("Cache rule '{}' missing 'value'", path)))?;
+        let value = HeaderValue::from_str(&value)
+          .map_err(|_| config_error(format!("Invalid cache rule value: {}", value)))?;
+        rules.push(CacheRule { path, value });
+      }
+    }
+    Ok(Self {
+      rules: Arc::new(rules),
+    })
+  }
+
+  fn get_value(&self, req: &ServiceRequest) -> Option<HeaderValue> {
+    match *req.method() {
+      Method::GET | Method::HEAD => (),
+      _ => return None,
+    }
+    if req.headers().contains_key(AUTHORIZATION) {
+      return Some(HeaderValue::from_static(NO_STORE));
+    }
+    let path = req.path();
+    self.rules.iter()
+      .find(|rule| rule.matches(path))
+      .map(|rule| rule.value.clone())
+  }
+}
+
+impl<S, B> Transform<S> for CacheControl
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = CacheControlMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(CacheControlMiddleware {
+      cache: self.clone(),
+      service
+    })
+  }
+}
+
+pub struct CacheControlMiddleware<S> {
+  cache: CacheControl,
+  service: S,
+}
+
+impl<S, B> Service for CacheControlMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let value = self.cache.get_value(&req);
+    let fut = self.service.call(req);
+
+    Box::pin(async move {
+      let mut res = fut.await?;
+      // error responses of cached paths aren't cacheable.
+      let status = res.status();
+      let cacheable = status.is_success() || status == StatusCode::NOT_MODIFIED;
+      if let Some(value) = value.filter(|value| cacheable || value == NO_STORE) {
+        let headers = res.headers_mut();
+        let varies = headers.get_all(VARY)
+          .filter_map(|value| value.to_str().ok())
+          .any(|value| value.split(',').any(|name| name.trim().eq_ignore_ascii_case("authorization")));
+        if !varies {
+          headers.append(VARY, HeaderValue::from_static("Authorization"));
+        }
+        // Handlers can set their own policy.
+        if !headers.contains_key(CACHE_CONTROL) {
+          headers.insert(CACHE_CONTROL, value);
+        }
+      }
+      Ok(res)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, web, App, HttpResponse};
+
+  #[actix_rt::test]
+  async fn cache_headers() {
+    let cache = CacheControl {
+      rules: Arc::new(vec![CacheRule {
+        path: "/api/tags*".to_string(),
+        value: HeaderValue::from_static("public, max-age=300"),
+      }]),
+    };
+    let mut app = test::init_service(
+      App::new()
+        .wrap(cache)
+        .route("/api/tags", web::get().to(HttpResponse::Ok))
+        .route("/api/tags/missing", web::get().to(HttpResponse::NotFound))
+        .route("/api/user", web::get().to(HttpResponse::Ok))
+    ).await;
+    let cache_control = |res: &ServiceResponse| {
+      res.headers().get(CACHE_CONTROL).map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let vary = |res: &ServiceResponse| {
+      res.headers().get(VARY).map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let res = test::call_service(&mut app, test::TestRequest::get().uri("/api/tags").to_request()).await;
+    assert_eq!(cache_control(&res).as_deref(), Some("public, max-age=300"));
+    assert_eq!(vary(&res).as_deref(), Some("Authorization"));
+    // errors aren't cached.
+    let res = test::call_service(&mut app, test::TestRequest::get().uri("/api/tags/missing").to_request()).await;
+    assert_eq!(cache_control(&res), None);
+    // paths without a rule, and authenticated requests.
+    let res = test::call_service(&mut app, test::TestRequest::get().uri("/api/user").to_request()).await;
+    assert_eq!(cache_control(&res), None);
+    let req = test::TestRequest::get().uri("/api/user")
+      .header(AUTHORIZATION, "Token abc")
+      .to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(cache_control(&res).as_deref(), Some(NO_STORE));
+    let req = test::TestRequest::get().uri("/api/tags")
+      .header(AUTHORIZATION, "Token abc")
+      .to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(cache_control(&res).as_deref(), Some(NO_STORE));
+    assert_eq!(vary(&res).as_deref(), Some("Authorization"));
+  }
+}
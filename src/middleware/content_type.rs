@@ -0,0 +1,142 @@
+use std::task::{Context, Poll};
+
+use futures::future::{ok, err, Either, Ready};
+
+use actix_web::{
+  http::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING},
+    Method,
+  },
+  Error,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+
+/// Reject request bodies that aren't JSON with `415 Unsupported Media Type`.
+///
+/// Only POST/PUT/PATCH requests with a body are checked, parameters like
+/// `charset=utf-8` are allowed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonContentType;
+
+/// Check if the request has a body that isn't JSON.
+fn is_unsupported(req: &ServiceRequest) -> bool {
+  match *req.method() {
+    Method::POST | Method::PUT | Method::PATCH => (),
+    _ => return false,
+  }
+  let headers = req.headers();
+  let has_body = headers.contains_key(TRANSFER_ENCODING) || headers.get(CONTENT_LENGTH)
+    .and_then(|len| len.to_str().ok())
+    .and_then(|len| len.parse::<u64>().ok())
+    .map(|len| len > 0)
+    .unwrap_or(false);
+  if !has_body {
+    return false;
+  }
+  let mime = headers.get(CONTENT_TYPE)
+    .and_then(|val| val.to_str().ok())
+    .and_then(|val| val.split(';').next())
+    .map(|mime| mime.trim().to_lowercase());
+  match mime {
+    Some(mime) => !(mime == "application/json" || mime.ends_with("+json")),
+    None => true,
+  }
+}
+
+impl<S, B> Transform<S> for JsonContentType
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = JsonContentTypeMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(JsonContentTypeMiddleware {
+      service
+    })
+  }
+}
+
+pub struct JsonContentTypeMiddleware<S> {
+  service: S,
+}
+
+impl<S, B> Service for JsonContentTypeMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    if is_unsupported(&req) {
+      Either::Right(err(crate::error::Error::UnsupportedMediaType(json!({
+        "error": "Content-Type must be application/json",
+      })).into()))
+    } else {
+      Either::Left(self.service.call(req))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, web, App, HttpResponse, http::StatusCode};
+
+  #[actix_rt::test]
+  async fn bodies_must_be_json() {
+    let mut app = test::init_service(App::new()
+      .wrap(JsonContentType)
+      .route("/articles", web::post().to(HttpResponse::Ok))
+      .route("/articles", web::get().to(HttpResponse::Ok))).await;
+    let mut status = |method: Method, content_type: Option<&str>, body: &'static str| {
+      let mut req = test::TestRequest::default().method(method).uri("/articles");
+      if let Some(content_type) = content_type {
+        req = req.header(CONTENT_TYPE, content_type);
+      }
+      // test requests don't set `Content-Length` like a client does.
+      let req = req.header(CONTENT_LENGTH, body.len()).set_payload(body).to_request();
+      // a rejected request is an `Err` from the middleware.
+      let res = app.call(req);
+      async move {
+        match res.await {
+          Ok(res) => res.status(),
+          Err(err) => err.as_response_error().error_response().status(),
+        }
+      }
+    };
+    let json = Some("application/json");
+    assert_eq!(status(Method::POST, json, "{}").await, StatusCode::OK);
+    assert_eq!(status(Method::POST, Some("application/json; charset=utf-8"), "{}").await, StatusCode::OK);
+    assert_eq!(status(Method::POST, Some("Application/JSON;charset=UTF-8"), "{}").await, StatusCode::OK);
+    assert_eq!(status(Method::POST, Some("application/vnd.api+json"), "{}").await, StatusCode::OK);
+
+    let unsupported = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    assert_eq!(status(Method::POST, Some("text/plain"), "{}").await, unsupported);
+    assert_eq!(status(Method::POST, Some("application/x-www-form-urlencoded"), "a=1").await, unsupported);
+    assert_eq!(status(Method::POST, None, "{}").await, unsupported);
+
+    // requests without a body aren't checked.
+    assert_eq!(status(Method::POST, Some("text/plain"), "").await, StatusCode::OK);
+    assert_eq!(status(Method::GET, Some("text/plain"), "{}").await, StatusCode::OK);
+  }
+}
@@ -0,0 +1,230 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use actix_web::{
+  http::header::{HeaderName, HeaderValue, LINK},
+  Error,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+
+const DEPRECATION: &str = "deprecation";
+const SUNSET: &str = "sunset";
+
+/// Headers for a deprecated path.  A path ending with `*` matches as a prefix.
+#[derive(Debug, Clone)]
+pub struct DeprecatedRoute {
+  pub path: String,
+  pub deprecation: HeaderValue,
+  pub sunset: Option<HeaderValue>,
+  pub link: Option<HeaderValue>,
+}
+
+impl DeprecatedRoute {
+  fn matches(&self, path: &str) -> bool {
+    if self.path.ends_with('*') {
+      path.starts_with(&self.path[..self.path.len() - 1])
+    } else {
+      path == self.path
+    }
+  }
+}
+
+fn config_error(msg: String) -> crate::error::Error {
+  config::ConfigError::Message(msg).into()
+}
+
+fn header_value(key: &str, value: &str) -> Result<HeaderValue> {
+  HeaderValue::from_str(value)
+    .map_err(|_| config_error(format!("Invalid deprecated route {}: {}", key, value)))
+}
+
+/// Adds `Deprecation` and `Sunset` (RFC 8594) headers to responses of
+/// deprecated routes.
+#[derive(Debug, Clone, Default)]
+pub struct Deprecations {
+  routes: Arc<Vec<DeprecatedRoute>>,
+}
+
+impl Deprecations {
+  /// Load routes from `api.deprecated_routes`:
+  ///
+  /// ```toml
+  /// [[api.deprecated_routes]]
+  /// path = "/api/articles/feed"
+  /// # HTTP-date the route will be removed (optional).
+  /// sunset = "Thu, 31 Dec 2026 23:59:59 GMT"
+  /// # Migration docs (optional).
+  /// link = "https://example.com/docs/feed-v2"
+  /// ```
+  pub fn from_config(config: &AppConfig) -> Result<Self> {
+    let mut routes = Vec::new();
+    if let Some(list) = config.get_array("api.deprecated_routes")? {
+      for route in list.into_iter() {
+        let route = crate::app::Table::from(route.into_table()?);
+        let path = route.get_str("path")?
+          .ok_or_else(|| config_error("Deprecated route missing 'path'".to_string()))?;
+        let deprecation = route.get_str("deprecation")?.unwrap_or_else(|| "true".to_string());
+        let sunset = match route.get_str("sunset")? {
+          Some(sunset) => {
+            if chrono::DateTime::parse_from_rfc2822(&sunset).is_err() {
+              return Err(config_error(
+                format!("Invalid deprecated route sunset (expected an HTTP-date): {}", sunset)));
+            }
+            Some(header_value("sunset", &sunset)?)
+          },
+          None => None,
+        };
+        let link = match route.get_str("link")? {
+          Some(link) => Some(header_value("link", &format!(r#"<{}>; rel="deprecation""#, link))?),
+          None => None,
+        };
+        routes.push(DeprecatedRoute {
+          path,
+          deprecation: header_value("deprecation", &deprecation)?,
+          sunset,
+          link,
+        });
+      }
+    }
+    Ok(Self {
+      routes: Arc::new(routes),
+    })
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    !self.routes.is_empty()
+  }
+
+  fn get_route(&self, req: &ServiceRequest) -> Option<DeprecatedRoute> {
+    let path = req.path();
+    self.routes.iter()
+      .find(|route| route.matches(path))
+      .cloned()
+  }
+}
+
+impl<S, B> Transform<S> for Deprecations
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = DeprecationsMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(DeprecationsMiddleware {
+      deprecations: self.clone(),
+      service
+    })
+  }
+}
+
+pub struct DeprecationsMiddleware<S> {
+  deprecations: Deprecations,
+  service: S,
+}
+
+impl<S, B> Service for DeprecationsMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let route = self.deprecations.get_route(&req);
+    let fut = self.service.call(req);
+
+    Box::pin(async move {
+      let mut res = fut.await?;
+      if let Some(route) = route {
+        let headers = res.headers_mut();
+        headers.insert(HeaderName::from_static(DEPRECATION), route.deprecation);
+        if let Some(sunset) = route.sunset {
+          headers.insert(HeaderName::from_static(SUNSET), sunset);
+        }
+        if let Some(link) = route.link {
+          headers.append(LINK, link);
+        }
+      }
+      Ok(res)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, web, App, HttpResponse};
+
+  fn deprecations(toml: &str) -> Result<Deprecations> {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.merge(::config::File::from_str(toml, ::config::FileFormat::Toml)).unwrap();
+    Deprecations::from_config(&config)
+  }
+
+  #[actix_rt::test]
+  async fn deprecated_routes_get_headers() {
+    let deprecations = deprecations(r#"
+      [[api.deprecated_routes]]
+      path = "/api/articles/feed"
+      sunset = "Thu, 31 Dec 2026 23:59:59 GMT"
+      link = "https://example.com/docs/feed-v2"
+
+      [[api.deprecated_routes]]
+      path = "/api/v1/*"
+    "#).unwrap();
+    assert!(deprecations.is_enabled());
+    let mut app = test::init_service(
+      App::new()
+        .wrap(deprecations)
+        .route("/api/articles/feed", web::get().to(HttpResponse::Ok))
+        .route("/api/articles", web::get().to(HttpResponse::Ok))
+        .route("/api/v1/tags", web::get().to(HttpResponse::Ok))
+    ).await;
+    let mut headers = |uri: &'static str| {
+      let req = test::TestRequest::get().uri(uri).to_request();
+      let res = app.call(req);
+      async move {
+        let res = res.await.unwrap();
+        let header = |name: &str| res.headers().get(name).map(|value| value.to_str().unwrap().to_string());
+        (header(DEPRECATION), header(SUNSET), header("link"))
+      }
+    };
+
+    let (deprecation, sunset, link) = headers("/api/articles/feed").await;
+    assert_eq!(deprecation.as_deref(), Some("true"));
+    assert_eq!(sunset.as_deref(), Some("Thu, 31 Dec 2026 23:59:59 GMT"));
+    assert_eq!(link.as_deref(), Some(r#"<https://example.com/docs/feed-v2>; rel="deprecation""#));
+    // prefix routes, sunset and link are optional.
+    assert_eq!(headers("/api/v1/tags").await, (Some("true".to_string()), None, None));
+    assert_eq!(headers("/api/articles").await, (None, None, None));
+  }
+
+  #[test]
+  fn invalid_routes_are_config_errors() {
+    assert!(!deprecations("").unwrap().is_enabled());
+    assert!(deprecations("[[api.deprecated_routes]]\nsunset = \"Thu, 31 Dec 2026 23:59:59 GMT\"").is_err());
+    assert!(deprecations("[[api.deprecated_routes]]\npath = \"/api/tags\"\nsunset = \"2026-12-31\"").is_err());
+  }
+}
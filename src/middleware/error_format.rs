@@ -0,0 +1,267 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+
+use actix_web::{
+  http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+  http::HeaderValue,
+  body::{Body, BodySize, MessageBody, ResponseBody},
+  error::InternalError,
+  web, Error, HttpResponse,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use serde_json::Value as JsonValue;
+
+/// Render JSON error bodies as plain text when the `Accept` header prefers
+/// `text/plain` over `application/json`.  JSON stays the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ErrorFormat;
+
+/// Check if `Accept` ranks `text/plain` above `application/json`.
+fn prefers_text(accept: &str) -> bool {
+  let (mut json, mut text, mut any) = (None, None, None);
+  for range in accept.split(',') {
+    let mut parts = range.split(';');
+    let media = parts.next().unwrap_or("").trim().to_lowercase();
+    let q = parts.filter_map(|p| p.trim().strip_prefix("q="))
+      .next()
+      .and_then(|q| q.parse::<f32>().ok())
+      .unwrap_or(1.0);
+    let slot = match media.as_str() {
+      "application/json" => &mut json,
+      "text/plain" => &mut text,
+      "*/*" => &mut any,
+      _ => continue,
+    };
+    *slot = Some(slot.unwrap_or(0.0f32).max(q));
+  }
+  let json = json.or(any).unwrap_or(0.0);
+  let text = text.or(any).unwrap_or(0.0);
+  text > json
+}
+
+/// Plain message of a JSON error body: `{"error": ".."}`, the RealWorld
+/// `{"errors": {"<field>": [".."]}}` or a bare string.
+fn error_text(body: &[u8]) -> String {
+  let value: JsonValue = match serde_json::from_slice(body) {
+    Ok(value) => value,
+    Err(_) => return String::from_utf8_lossy(body).into_owned(),
+  };
+  match value {
+    JsonValue::String(msg) => msg,
+    JsonValue::Object(ref obj) => {
+      if let Some(JsonValue::String(msg)) = obj.get("error") {
+        return msg.clone();
+      }
+      if let Some(JsonValue::Object(fields)) = obj.get("errors") {
+        let mut lines = Vec::new();
+        for (field, messages) in fields {
+          let messages = match messages {
+            JsonValue::Array(messages) => messages.iter().collect(),
+            msg => vec![msg],
+          };
+          for msg in messages {
+            match msg.as_str() {
+              Some(msg) => lines.push(format!("{} {}", field, msg)),
+              None => lines.push(format!("{} {}", field, msg)),
+            }
+          }
+        }
+        return lines.join("\n");
+      }
+      value.to_string()
+    },
+    value => value.to_string(),
+  }
+}
+
+/// Boxed (`Unpin`) response body, to pass on bodies that aren't converted.
+struct BoxedBody<B>(Pin<Box<ResponseBody<B>>>);
+
+impl<B: MessageBody> MessageBody for BoxedBody<B> {
+  fn size(&self) -> BodySize {
+    self.0.size()
+  }
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<web::Bytes, Error>>> {
+    self.0.as_mut().poll_next(cx)
+  }
+}
+
+/// Plain text version of an error from the inner services, actix renders it
+/// after this middleware.
+fn text_error(err: Error) -> Error {
+  let res = err.as_response_error().error_response();
+  let msg = match res.body().as_ref() {
+    Some(Body::Bytes(bytes)) => error_text(bytes),
+    _ => err.to_string(),
+  };
+  let res = HttpResponse::build(res.status())
+    .content_type("text/plain; charset=utf-8")
+    .body(msg);
+  InternalError::from_response(err, res).into()
+}
+
+impl<S, B> Transform<S> for ErrorFormat
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: MessageBody + 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<Body>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = ErrorFormatMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(ErrorFormatMiddleware {
+      service
+    })
+  }
+}
+
+pub struct ErrorFormatMiddleware<S> {
+  service: S,
+}
+
+impl<S, B> Service for ErrorFormatMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: MessageBody + 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<Body>;
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let text = req.headers().get(ACCEPT)
+      .and_then(|accept| accept.to_str().ok())
+      .map(prefers_text)
+      .unwrap_or(false);
+    let fut = self.service.call(req);
+    Box::pin(async move {
+      // errors from inner middleware are negotiated too.
+      let mut res = match fut.await {
+        Ok(res) => res,
+        Err(err) if text => return Err(text_error(err)),
+        Err(err) => return Err(err),
+      };
+      let is_json_error = res.status().as_u16() >= 400 && res.headers().get(CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.starts_with("application/json"))
+        .unwrap_or(false);
+      if !text || !is_json_error {
+        return Ok(res.map_body(|_, body| {
+          ResponseBody::Other(Body::from_message(BoxedBody(Box::pin(body))))
+        }));
+      }
+
+      // Collect the (small) JSON error body.
+      let mut body = Box::pin(res.take_body());
+      let mut buf = Vec::new();
+      while let Some(chunk) = body.next().await {
+        buf.extend_from_slice(&chunk?);
+      }
+      let msg = error_text(&buf);
+      let headers = res.headers_mut();
+      headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+      headers.remove(CONTENT_LENGTH);
+      Ok(res.map_body(|_, _| ResponseBody::Other(Body::from(msg))))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn prefers_text_by_quality() {
+    assert!(prefers_text("text/plain"));
+    assert!(prefers_text("text/plain, application/json;q=0.5"));
+    assert!(prefers_text("TEXT/PLAIN;q=0.9, */*;q=0.1"));
+    assert!(!prefers_text(""));
+    assert!(!prefers_text("*/*"));
+    assert!(!prefers_text("application/json"));
+    assert!(!prefers_text("text/plain, application/json"));
+    assert!(!prefers_text("text/plain;q=0.5, application/json"));
+    assert!(!prefers_text("text/html"));
+  }
+
+  #[test]
+  fn error_text_of_json_bodies() {
+    assert_eq!(error_text(br#"{"error": "Not found"}"#), "Not found");
+    assert_eq!(error_text(br#""Bad request""#), "Bad request");
+    assert_eq!(error_text(br#"{"errors": {"email": ["is invalid", "is taken"], "username": "is taken"}}"#),
+      "email is invalid\nemail is taken\nusername is taken");
+    assert_eq!(error_text(br#"{"errors": {"limit": [10]}}"#), "limit 10");
+    assert_eq!(error_text(br#"{"other": 1}"#), r#"{"other":1}"#);
+    assert_eq!(error_text(b"not json"), "not json");
+  }
+
+  #[actix_rt::test]
+  async fn error_bodies_are_negotiated() {
+    use actix_web::{test, App, http::StatusCode};
+
+    let mut app = test::init_service(
+      App::new()
+        .wrap(ErrorFormat)
+        .route("/missing", web::get().to(|| async {
+          HttpResponse::NotFound().json(json!({"error": "Article not found"}))
+        }))
+        .route("/invalid", web::get().to(|| async {
+          Err::<HttpResponse, _>(crate::error::Error::UnprocessableEntity(json!({
+            "errors": {"title": ["can't be blank"]},
+          })))
+        }))
+        .route("/ok", web::get().to(|| async {
+          HttpResponse::Ok().json(json!({"ok": true}))
+        }))
+    ).await;
+    let get = |uri: &str, accept: &str| {
+      let req = test::TestRequest::get().uri(uri);
+      if accept.is_empty() { req } else { req.header(ACCEPT, accept) }.to_request()
+    };
+    let content_type = |res: &ServiceResponse<Body>| {
+      res.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap().to_string()
+    };
+
+    // JSON by default, and when it is preferred.
+    for accept in &["", "application/json", "text/plain;q=0.5, application/json"] {
+      let res = test::call_service(&mut app, get("/missing", accept)).await;
+      assert_eq!(res.status(), StatusCode::NOT_FOUND);
+      assert!(content_type(&res).starts_with("application/json"), "{}", accept);
+      assert_eq!(test::read_body(res).await, r#"{"error":"Article not found"}"#);
+    }
+
+    let res = test::call_service(&mut app, get("/missing", "text/plain")).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(content_type(&res), "text/plain; charset=utf-8");
+    assert_eq!(test::read_body(res).await, "Article not found");
+
+    // errors returned by the handlers.
+    let res = test::call_service(&mut app, get("/invalid", "text/plain")).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(content_type(&res), "text/plain; charset=utf-8");
+    assert_eq!(test::read_body(res).await, "title can't be blank");
+
+    // successful responses are left alone.
+    let res = test::call_service(&mut app, get("/ok", "text/plain")).await;
+    assert!(content_type(&res).starts_with("application/json"));
+    assert_eq!(test::read_body(res).await, r#"{"ok":true}"#);
+  }
+}
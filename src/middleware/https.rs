@@ -0,0 +1,218 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+
+use actix_web::{
+  http::header::{HeaderValue, HOST, LOCATION, STRICT_TRANSPORT_SECURITY},
+  Error, HttpResponse,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+
+use super::TrustedProxies;
+
+/// Redirect insecure requests to HTTPS and add the HSTS header.
+///
+/// Requests are only known to be insecure when a trusted proxy sends
+/// `X-Forwarded-Proto: http`.  Direct requests (health checks from a load
+/// balancer) and exempt paths are never redirected.  The redirect goes to
+/// `canonical_host`, else the host forwarded by the trusted proxy, else the
+/// `Host` header (never a forwarded host from an untrusted peer).
+#[derive(Debug, Clone, Default)]
+pub struct Https {
+  force_https: bool,
+  hsts: Option<HeaderValue>,
+  canonical_host: Option<String>,
+  exempt: Arc<Vec<String>>,
+  proxies: TrustedProxies,
+}
+
+impl Https {
+  /// Load `<prefix>.http.force_https`, `hsts_max_age`, `canonical_host` and
+  /// `https_exempt`.
+  pub fn from_config(config: &AppConfig, prefix: &str, proxies: TrustedProxies) -> Result<Self> {
+    let force_https = config.get_bool(&format!("{}.http.force_https", prefix))?.unwrap_or(false);
+    let hsts = match config.get_int(&format!("{}.http.hsts_max_age", prefix))? {
+      Some(max_age) if max_age < 0 => {
+        return Err(config::ConfigError::Message(
+          format!("{}.http.hsts_max_age must be >= 0: {}", prefix, max_age)).into());
+      },
+      Some(max_age) if max_age > 0 => {
+        Some(HeaderValue::from_str(&format!("max-age={}", max_age)).unwrap())
+      },
+      _ => None,
+    };
+    let canonical_host = config.get_str(&format!("{}.http.canonical_host", prefix))?;
+    let exempt = config.get_str_array(&format!("{}.http.https_exempt", prefix))?
+      .unwrap_or_else(|| vec!["/health".to_string(), "/ready".to_string()]);
+    Ok(Self {
+      force_https,
+      hsts,
+      canonical_host,
+      exempt: Arc::new(exempt),
+      proxies,
+    })
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.force_https || self.hsts.is_some()
+  }
+
+  fn redirect_host(&self, req: &ServiceRequest) -> String {
+    if let Some(host) = &self.canonical_host {
+      return host.clone();
+    }
+    self.proxies.forwarded_host(req.head())
+      .or_else(|| req.headers().get(HOST).and_then(|host| host.to_str().ok()).map(str::to_string))
+      .or_else(|| req.uri().authority().map(|authority| authority.to_string()))
+      .unwrap_or_default()
+  }
+}
+
+impl<S, B> Transform<S> for Https
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = HttpsMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(HttpsMiddleware {
+      https: self.clone(),
+      service
+    })
+  }
+}
+
+pub struct HttpsMiddleware<S> {
+  https: Https,
+  service: S,
+}
+
+impl<S, B> Service for HttpsMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Either<LocalBoxFuture<'static, Result<Self::Response, Self::Error>>,
+    Ready<Result<Self::Response, Self::Error>>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let proto = self.https.proxies.forwarded_proto(req.head());
+    let is_secure = proto.as_deref() == Some("https");
+
+    if self.https.force_https && proto.as_deref() == Some("http")
+        && !self.https.exempt.iter().any(|path| path == req.path()) {
+      let location = format!("https://{}{}", self.https.redirect_host(&req), req.uri());
+      return Either::Right(ok(req.into_response(
+        HttpResponse::PermanentRedirect()
+          .header(LOCATION, location)
+          .finish()
+          .into_body()
+      )));
+    }
+
+    let hsts = if is_secure { self.https.hsts.clone() } else { None };
+    let fut = self.service.call(req);
+    Either::Left(Box::pin(async move {
+      let mut res = fut.await?;
+      if let Some(hsts) = hsts {
+        res.headers_mut().insert(STRICT_TRANSPORT_SECURITY, hsts);
+      }
+      Ok(res)
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, web, App, http::Method};
+
+  #[actix_rt::test]
+  async fn redirect_keeps_the_method() {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("a.http.force_https", true).unwrap();
+    config.conf.set("a.http.hsts_max_age", 60).unwrap();
+    config.conf.set("a.http.trusted_proxies", vec!["127.0.0.1"]).unwrap();
+    let proxies = TrustedProxies::from_config(&config, "a").unwrap();
+    let https = Https::from_config(&config, "a", proxies).unwrap();
+    let mut app = test::init_service(App::new()
+      .wrap(https)
+      .route("/articles", web::post().to(HttpResponse::Ok))
+      .route("/health", web::get().to(HttpResponse::Ok))).await;
+    let req = |method, uri, proto| test::TestRequest::default()
+      .method(method)
+      .uri(uri)
+      .header("host", "example.com")
+      .header("x-forwarded-proto", proto)
+      .peer_addr("127.0.0.1:1234".parse().unwrap())
+      .to_request();
+
+    // 308, so a POST isn't turned into a GET.
+    let res = test::call_service(&mut app, req(Method::POST, "/articles?a=1", "http")).await;
+    assert_eq!(res.status(), 308);
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "https://example.com/articles?a=1");
+    let res = test::call_service(&mut app, req(Method::GET, "/health", "http")).await;
+    assert_eq!(res.status(), 200);
+    let res = test::call_service(&mut app, req(Method::POST, "/articles", "https")).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get(STRICT_TRANSPORT_SECURITY).unwrap(), "max-age=60");
+    // only a trusted proxy picks the redirect host.
+    let forwarded = |peer: &str| test::TestRequest::get()
+      .uri("/articles")
+      .header("host", "example.com")
+      .header("x-forwarded-proto", "http")
+      .header("x-forwarded-host", "proxied.example.com")
+      .header("forwarded", "host=evil.example.com")
+      .peer_addr(peer.parse().unwrap())
+      .to_request();
+    let mut get_app = test::init_service(App::new()
+      .wrap(Https::from_config(&config, "a", TrustedProxies::from_config(&config, "a").unwrap()).unwrap())
+      .route("/articles", web::get().to(HttpResponse::Ok))).await;
+    let res = test::call_service(&mut get_app, forwarded("127.0.0.1:1234")).await;
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "https://proxied.example.com/articles");
+    let req = test::TestRequest::get()
+      .uri("/articles")
+      .header("host", "example.com")
+      .header("x-forwarded-proto", "http")
+      .header("forwarded", "host=evil.example.com")
+      .peer_addr("127.0.0.1:1234".parse().unwrap())
+      .to_request();
+    let res = test::call_service(&mut get_app, req).await;
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "https://example.com/articles");
+    config.conf.set("a.http.canonical_host", "www.example.com").unwrap();
+    let mut get_app = test::init_service(App::new()
+      .wrap(Https::from_config(&config, "a", TrustedProxies::from_config(&config, "a").unwrap()).unwrap())
+      .route("/articles", web::get().to(HttpResponse::Ok))).await;
+    let res = test::call_service(&mut get_app, forwarded("127.0.0.1:1234")).await;
+    assert_eq!(res.headers().get(LOCATION).unwrap(), "https://www.example.com/articles");
+    // X-Forwarded-Proto is ignored from untrusted peers.
+    let req = test::TestRequest::post()
+      .uri("/articles")
+      .header("x-forwarded-proto", "http")
+      .peer_addr("10.0.0.1:1234".parse().unwrap())
+      .to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), 200);
+  }
+}
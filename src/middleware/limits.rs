@@ -0,0 +1,150 @@
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Either, Ready};
+
+use actix_web::{
+  http::StatusCode,
+  Error, HttpResponse,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+
+/// Reject requests with an oversized URI or headers.
+///
+/// actix itself closes the connection when the request head is larger than
+/// 128KB or has more than 96 headers, these limits can only be lower.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestLimits {
+  /// Maximum URI (path + query) length, 0 = unlimited.
+  max_uri_len: usize,
+  /// Maximum total size of header names and values, 0 = unlimited.
+  max_header_size: usize,
+}
+
+impl RequestLimits {
+  /// Load `<prefix>.http.max_uri_len` and `<prefix>.http.max_header_size`.
+  pub fn from_config(config: &AppConfig, prefix: &str) -> Result<Self> {
+    let max_uri_len = config.get_int(&format!("{}.http.max_uri_len", prefix))?.unwrap_or(0);
+    let max_header_size = config.get_int(&format!("{}.http.max_header_size", prefix))?.unwrap_or(0);
+    Ok(Self {
+      max_uri_len: max_uri_len as usize,
+      max_header_size: max_header_size as usize,
+    })
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.max_uri_len > 0 || self.max_header_size > 0
+  }
+
+  fn check(&self, req: &ServiceRequest) -> Option<(StatusCode, &'static str)> {
+    if self.max_uri_len > 0 {
+      let uri = req.uri();
+      let len = uri.path_and_query().map(|p| p.as_str().len()).unwrap_or(0);
+      if len > self.max_uri_len {
+        return Some((StatusCode::URI_TOO_LONG, "Request URI too long"));
+      }
+    }
+    if self.max_header_size > 0 {
+      let size: usize = req.headers().iter()
+        .map(|(name, val)| name.as_str().len() + val.len())
+        .sum();
+      if size > self.max_header_size {
+        return Some((StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, "Request headers too large"));
+      }
+    }
+    None
+  }
+}
+
+impl<S, B> Transform<S> for RequestLimits
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = RequestLimitsMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(RequestLimitsMiddleware {
+      limits: *self,
+      service
+    })
+  }
+}
+
+pub struct RequestLimitsMiddleware<S> {
+  limits: RequestLimits,
+  service: S,
+}
+
+impl<S, B> Service for RequestLimitsMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    match self.limits.check(&req) {
+      Some((status, msg)) => {
+        Either::Right(ok(req.into_response(
+          HttpResponse::build(status).json(json!({
+            "error": msg,
+          }))
+          .into_body()
+        )))
+      },
+      None => Either::Left(self.service.call(req)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, web, App};
+
+  #[actix_rt::test]
+  async fn oversized_requests_are_rejected() {
+    let limits = RequestLimits {
+      max_uri_len: 32,
+      max_header_size: 64,
+    };
+    let mut app = test::init_service(App::new()
+      .wrap(limits)
+      .route("/articles", web::get().to(HttpResponse::Ok))).await;
+
+    let req = test::TestRequest::get().uri("/articles?tag=a").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri("/articles?tag=a&tag=b&tag=c&tag=d&tag=e").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+
+    let req = test::TestRequest::get().uri("/articles")
+      .header("x-padding", "x".repeat(64))
+      .to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "Request headers too large");
+  }
+}
@@ -0,0 +1,250 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
+
+use prometheus::{
+  Encoder, TextEncoder,
+  HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
+};
+
+use actix_web::{
+  get, web, Error, HttpRequest, HttpResponse,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+
+use super::TrustedProxies;
+
+/// Set once a server has metrics enabled, DB query durations are only
+/// recorded when set.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+  static ref REGISTRY: Registry = Registry::new();
+
+  static ref HTTP_REQUESTS: IntCounterVec = {
+    let counter = IntCounterVec::new(
+      Opts::new("http_requests_total", "HTTP requests by route and status."),
+      &["method", "route", "status"],
+    ).expect("valid metric");
+    REGISTRY.register(Box::new(counter.clone())).expect("metric registered once");
+    counter
+  };
+
+  static ref HTTP_DURATION: HistogramVec = {
+    let histogram = HistogramVec::new(
+      HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route."),
+      &["method", "route"],
+    ).expect("valid metric");
+    REGISTRY.register(Box::new(histogram.clone())).expect("metric registered once");
+    histogram
+  };
+
+  static ref DB_QUERY_DURATION: HistogramVec = {
+    let histogram = HistogramVec::new(
+      HistogramOpts::new("db_query_duration_seconds", "DB query latency by prepared statement."),
+      &["statement"],
+    ).expect("valid metric");
+    REGISTRY.register(Box::new(histogram.clone())).expect("metric registered once");
+    histogram
+  };
+}
+
+/// Record the duration of a successful DB query.
+pub fn observe_db_query(statement: &str, elapsed: Duration) {
+  if ENABLED.load(Ordering::Relaxed) {
+    DB_QUERY_DURATION.with_label_values(&[statement]).observe(elapsed.as_secs_f64());
+  }
+}
+
+/// Prometheus metrics endpoint, only for clients in `<prefix>.metrics.allow_ips`.
+#[get("/metrics")]
+pub async fn metrics(req: HttpRequest, cfg: web::Data<Metrics>) -> HttpResponse {
+  if !cfg.is_allowed(&req) {
+    return HttpResponse::Forbidden().json(json!({
+      "error": "Metrics access denied.",
+    }));
+  }
+  let encoder = TextEncoder::new();
+  let mut buf = Vec::new();
+  if let Err(err) = encoder.encode(&REGISTRY.gather(), &mut buf) {
+    return HttpResponse::InternalServerError().body(err.to_string());
+  }
+  HttpResponse::Ok()
+    .content_type(encoder.format_type())
+    .body(buf)
+}
+
+fn config_error(msg: String) -> crate::error::Error {
+  config::ConfigError::Message(msg).into()
+}
+
+/// Request count, latency and status codes per route (`<prefix>.metrics`).
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+  enabled: bool,
+  allow_ips: Arc<Vec<IpAddr>>,
+  proxies: TrustedProxies,
+}
+
+impl Metrics {
+  /// Load `<prefix>.metrics.enabled` and `<prefix>.metrics.allow_ips` (only
+  /// loopback by default).
+  pub fn from_config(config: &AppConfig, prefix: &str, proxies: TrustedProxies) -> Result<Self> {
+    let enabled = config.get_bool(&format!("{}.metrics.enabled", prefix))?.unwrap_or(false);
+    if enabled {
+      ENABLED.store(true, Ordering::Relaxed);
+    }
+    let allow_ips = match config.get_str_array(&format!("{}.metrics.allow_ips", prefix))? {
+      Some(list) => {
+        list.iter().map(|ip| {
+          ip.parse().map_err(|_| config_error(format!("Invalid metrics allowed IP: {}", ip)))
+        }).collect::<Result<Vec<_>>>()?
+      },
+      None => vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)],
+    };
+    Ok(Self {
+      enabled,
+      allow_ips: Arc::new(allow_ips),
+      proxies,
+    })
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.enabled
+  }
+
+  /// Check if the client (behind trusted proxies) can read the metrics.
+  pub fn is_allowed(&self, req: &HttpRequest) -> bool {
+    match self.proxies.client_ip(req.head()) {
+      Some(ip) => self.allow_ips.contains(&ip),
+      None => false,
+    }
+  }
+}
+
+impl<S, B> Transform<S> for Metrics
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = MetricsMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(MetricsMiddleware {
+      enabled: self.enabled,
+      service
+    })
+  }
+}
+
+pub struct MetricsMiddleware<S> {
+  enabled: bool,
+  service: S,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Either<S::Future, LocalBoxFuture<'static, Result<Self::Response, Self::Error>>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    if !self.enabled {
+      return Either::Left(self.service.call(req));
+    }
+    let start = Instant::now();
+    let method = req.method().to_string();
+    // route pattern, so paths with ids/slugs share one series.
+    let route = req.match_pattern()
+      .unwrap_or_else(|| "unmatched".to_string());
+    let fut = self.service.call(req);
+    Either::Right(Box::pin(async move {
+      let res = fut.await;
+      // errors are turned into responses further out, count their status.
+      let status = match res {
+        Ok(ref res) => res.status(),
+        Err(ref err) => err.as_response_error().status_code(),
+      };
+      HTTP_REQUESTS.with_label_values(&[&method, &route, status.as_str()]).inc();
+      HTTP_DURATION.with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+      res
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, App};
+  use actix_web::error::ErrorForbidden;
+
+  #[actix_rt::test]
+  async fn counts_errors_and_limits_access() {
+    let cfg = Metrics {
+      enabled: true,
+      allow_ips: Arc::new(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]),
+      proxies: TrustedProxies::default(),
+    };
+    let mut app = test::init_service(
+      App::new()
+        .wrap(cfg.clone())
+        .data(cfg)
+        .service(metrics)
+        .service(web::resource("/denied/{id}")
+          .wrap_fn(|_req, _srv| futures::future::err(ErrorForbidden("denied")))
+          .to(HttpResponse::Ok))
+        .service(web::resource("/gone")
+          .wrap_fn(|_req, _srv| futures::future::err(crate::error::Error::Gone(json!({})).into()))
+          .to(HttpResponse::Ok))
+    ).await;
+
+    let denied = || HTTP_REQUESTS.with_label_values(&["GET", "/denied/{id}", "403"]).get();
+    let before = denied();
+    let req = test::TestRequest::get().uri("/denied/1").to_request();
+    assert!(app.call(req).await.is_err());
+    assert_eq!(denied(), before + 1);
+
+    // errors from this crate are counted with their status, not as 500s.
+    let gone = || HTTP_REQUESTS.with_label_values(&["GET", "/gone", "410"]).get();
+    let before = gone();
+    let req = test::TestRequest::get().uri("/gone").to_request();
+    assert!(app.call(req).await.is_err());
+    assert_eq!(gone(), before + 1);
+
+    let req = test::TestRequest::get().uri("/metrics")
+      .peer_addr("10.0.0.1:1234".parse().unwrap())
+      .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 403);
+    let req = test::TestRequest::get().uri("/metrics")
+      .peer_addr("127.0.0.1:1234".parse().unwrap())
+      .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+  }
+}
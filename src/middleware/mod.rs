@@ -1,2 +1,32 @@
 pub mod auth;
 pub use auth::*;
+
+pub mod cache_control;
+pub use cache_control::*;
+
+pub mod proxy;
+pub use proxy::*;
+
+pub mod https;
+pub use https::*;
+
+pub mod limits;
+pub use limits::*;
+
+pub mod content_type;
+pub use content_type::*;
+
+pub mod deprecation;
+pub use deprecation::*;
+
+pub mod retry_budget;
+pub use retry_budget::*;
+
+pub mod rate_limit;
+pub use rate_limit::*;
+
+pub mod metrics;
+pub use metrics::Metrics;
+
+pub mod error_format;
+pub use error_format::*;
@@ -0,0 +1,127 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use actix_web::dev::RequestHead;
+
+use crate::error::Result;
+use crate::app::AppConfig;
+
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Proxies allowed to set `X-Forwarded-*` headers.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+  ips: Arc<Vec<IpAddr>>,
+}
+
+impl TrustedProxies {
+  /// Load `<prefix>.http.trusted_proxies` (list of IP addresses).
+  pub fn from_config(config: &AppConfig, prefix: &str) -> Result<Self> {
+    let mut ips = Vec::new();
+    if let Some(list) = config.get_str_array(&format!("{}.http.trusted_proxies", prefix))? {
+      for ip in list {
+        let ip = ip.parse().map_err(|_| -> crate::error::Error {
+          config::ConfigError::Message(format!("Invalid trusted proxy IP: {}", ip)).into()
+        })?;
+        ips.push(ip);
+      }
+    }
+    Ok(Self {
+      ips: Arc::new(ips),
+    })
+  }
+
+  /// Check if the request came directly from a trusted proxy.
+  pub fn is_trusted(&self, req: &RequestHead) -> bool {
+    match req.peer_addr {
+      Some(addr) => self.ips.contains(&addr.ip()),
+      None => false,
+    }
+  }
+
+  /// `X-Forwarded-Proto` from a trusted proxy.
+  pub fn forwarded_proto(&self, req: &RequestHead) -> Option<String> {
+    if !self.is_trusted(req) {
+      return None;
+    }
+    req.headers.get(X_FORWARDED_PROTO)
+      .and_then(|val| val.to_str().ok())
+      .map(|val| val.trim().to_lowercase())
+  }
+
+  /// `X-Forwarded-Host` from a trusted proxy.
+  pub fn forwarded_host(&self, req: &RequestHead) -> Option<String> {
+    if !self.is_trusted(req) {
+      return None;
+    }
+    req.headers.get(X_FORWARDED_HOST)
+      .and_then(|val| val.to_str().ok())
+      .map(|val| val.trim().to_string())
+      .filter(|val| !val.is_empty())
+  }
+
+  /// Client IP: the last `X-Forwarded-For` address that isn't a trusted
+  /// proxy (only when the request came from a trusted proxy), else the peer
+  /// address.
+  pub fn client_ip(&self, req: &RequestHead) -> Option<IpAddr> {
+    let peer = req.peer_addr.map(|addr| addr.ip());
+    if !self.is_trusted(req) {
+      return peer;
+    }
+    let mut lines = req.headers.get_all(X_FORWARDED_FOR).collect::<Vec<_>>();
+    if lines.len() > 1 {
+      // actix-http 2 keeps the first two lines of a repeated header swapped,
+      // the line a proxy appended must come after the client's own.
+      lines.swap(0, 1);
+    }
+    let forwarded = lines.into_iter()
+      .filter_map(|val| val.to_str().ok())
+      .flat_map(|val| val.split(','))
+      .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+      .collect::<Vec<_>>();
+    forwarded.into_iter().rev()
+      .find(|ip| !self.ips.contains(ip))
+      .or(peer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::test::TestRequest;
+
+  fn proxies(ips: &[&str]) -> TrustedProxies {
+    TrustedProxies {
+      ips: Arc::new(ips.iter().map(|ip| ip.parse().unwrap()).collect()),
+    }
+  }
+
+  fn client_ip(proxies: &TrustedProxies, peer: &str, forwarded: &[&str]) -> Option<IpAddr> {
+    let mut req = TestRequest::default().peer_addr(format!("{}:1234", peer).parse().unwrap());
+    for value in forwarded {
+      req = req.header(X_FORWARDED_FOR, *value);
+    }
+    proxies.client_ip(req.to_http_request().head())
+  }
+
+  #[test]
+  fn client_ip_from_trusted_proxies_only() {
+    let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+    let proxies = proxies(&["10.0.0.1", "10.0.0.2"]);
+    // an untrusted peer is the client, whatever it forwards.
+    assert_eq!(client_ip(&proxies, "192.0.2.1", &["198.51.100.1"]), ip("192.0.2.1"));
+    // a trusted peer forwards the client.
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &["198.51.100.1"]), ip("198.51.100.1"));
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &[]), ip("10.0.0.1"));
+    // the client can send its own header, only the address the proxies
+    // appended counts.
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &["203.0.113.7, 198.51.100.1"]), ip("198.51.100.1"));
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &["203.0.113.7", "198.51.100.1, 10.0.0.2"]), ip("198.51.100.1"));
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &["203.0.113.7", "203.0.113.8", "198.51.100.1"]), ip("198.51.100.1"));
+    // invalid entries are skipped.
+    assert_eq!(client_ip(&proxies, "10.0.0.1", &["198.51.100.1, junk"]), ip("198.51.100.1"));
+  }
+}
@@ -0,0 +1,413 @@
+use log::*;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::task::{Context, Poll};
+
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use actix_web::{
+  http::header::{HeaderMap, HeaderName},
+  Error, HttpResponse,
+  web,
+};
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+use crate::middleware::TrustedProxies;
+
+const X_RATELIMIT_LIMIT: &str = "x-ratelimit-limit";
+const X_RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+const X_RATELIMIT_RESET: &str = "x-ratelimit-reset";
+
+/// Default for `<prefix>.rate_limit.max_clients`.
+const DEFAULT_MAX_CLIENTS: i64 = 100_000;
+
+/// Least time between two scans for refilled buckets, while `max_clients`
+/// are tracked.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+}
+
+impl Bucket {
+  /// Tokens at `now`, refilled at `rate` tokens per second.
+  fn tokens_at(&self, now: Instant, capacity: f64, rate: f64) -> f64 {
+    (self.tokens + now.duration_since(self.updated).as_secs_f64() * rate).min(capacity)
+  }
+}
+
+/// Bucket key of a client.  IPv6 clients are keyed by their /64, a single
+/// host usually has a whole /64 to pick addresses from.
+fn client_key(ip: IpAddr) -> IpAddr {
+  match ip {
+    IpAddr::V4(_) => ip,
+    IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+      Some(ip) => IpAddr::V4(ip),
+      None => {
+        let mut segments = ip.segments();
+        segments[4..].iter_mut().for_each(|segment| *segment = 0);
+        IpAddr::V6(Ipv6Addr::from(segments))
+      },
+    },
+  }
+}
+
+/// Buckets of up to `max_clients` clients.
+///
+/// Once `max_clients` are tracked, only full buckets (idle clients) are
+/// dropped to make room, so a flood of new clients can't reset the throttled
+/// ones.  While none can be dropped, new clients share the `overflow` bucket.
+#[derive(Debug)]
+struct Buckets {
+  clients: HashMap<IpAddr, Bucket>,
+  overflow: Bucket,
+  pruned: Option<Instant>,
+}
+
+impl Buckets {
+  fn new(now: Instant, tokens: f64) -> Self {
+    Self {
+      clients: HashMap::new(),
+      overflow: Bucket { tokens, updated: now },
+      pruned: None,
+    }
+  }
+
+  /// The client's bucket, a new bucket starts full.
+  fn get(&mut self, key: IpAddr, now: Instant, max_clients: usize, capacity: f64, rate: f64) -> &mut Bucket {
+    if self.clients.len() >= max_clients && !self.clients.contains_key(&key) {
+      if self.pruned.is_none_or(|pruned| now.duration_since(pruned) >= PRUNE_INTERVAL) {
+        self.clients.retain(|_, bucket| bucket.tokens_at(now, capacity, rate) < capacity);
+        self.pruned = Some(now);
+      }
+      if self.clients.len() >= max_clients {
+        return &mut self.overflow;
+      }
+    }
+    self.clients.entry(key).or_insert(Bucket { tokens: capacity, updated: now })
+  }
+}
+
+/// Per-IP token buckets, shared by all workers of a server.
+///
+/// Registered as app data by the server, the `RateLimit` middleware is added
+/// to the routes that should be throttled.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+  /// Bucket size, 0 = disabled.
+  requests_per_minute: u32,
+  /// Max. tracked clients.
+  max_clients: usize,
+  proxies: TrustedProxies,
+  buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+  /// Load `<prefix>.rate_limit.requests_per_minute` and `<prefix>.rate_limit.max_clients`.
+  pub fn from_config(config: &AppConfig, prefix: &str, proxies: TrustedProxies) -> Result<Self> {
+    let requests_per_minute = config.get_int(&format!("{}.rate_limit.requests_per_minute", prefix))?
+      .unwrap_or(0).max(0);
+    let max_clients = config.get_int(&format!("{}.rate_limit.max_clients", prefix))?
+      .unwrap_or(DEFAULT_MAX_CLIENTS).max(1);
+    Ok(Self::new(requests_per_minute as u32, max_clients as usize, proxies))
+  }
+
+  fn new(requests_per_minute: u32, max_clients: usize, proxies: TrustedProxies) -> Self {
+    Self {
+      requests_per_minute,
+      max_clients,
+      proxies,
+      buckets: Arc::new(Mutex::new(Buckets::new(Instant::now(), requests_per_minute as f64))),
+    }
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.requests_per_minute > 0
+  }
+
+  /// Take a token for the client.
+  fn check(&self, ip: IpAddr) -> Quota {
+    self.check_at(ip, Instant::now())
+  }
+
+  fn check_at(&self, ip: IpAddr, now: Instant) -> Quota {
+    let capacity = self.requests_per_minute as f64;
+    let rate = capacity / 60.0;
+    let mut buckets = self.buckets.lock().unwrap();
+    let bucket = buckets.get(client_key(ip), now, self.max_clients, capacity, rate);
+    bucket.tokens = bucket.tokens_at(now, capacity, rate);
+    bucket.updated = now;
+    let retry_after = if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      None
+    } else {
+      Some(((1.0 - bucket.tokens) / rate).ceil() as u64)
+    };
+    Quota {
+      limit: self.requests_per_minute,
+      remaining: bucket.tokens.floor() as u32,
+      reset: ((capacity - bucket.tokens) / rate).ceil() as u64,
+      retry_after,
+    }
+  }
+}
+
+/// A client's rate limit state after a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quota {
+  limit: u32,
+  remaining: u32,
+  /// Seconds until the bucket is full again.
+  reset: u64,
+  /// Seconds until a token is available, if the request was rejected.
+  retry_after: Option<u64>,
+}
+
+impl Quota {
+  /// Add `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset`.
+  fn add_headers(&self, headers: &mut HeaderMap) {
+    headers.insert(HeaderName::from_static(X_RATELIMIT_LIMIT), self.limit.into());
+    headers.insert(HeaderName::from_static(X_RATELIMIT_REMAINING), self.remaining.into());
+    headers.insert(HeaderName::from_static(X_RATELIMIT_RESET), self.reset.into());
+  }
+}
+
+/// Throttle a route by client IP, using the server's `RateLimiter`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimit;
+
+impl<S, B> Transform<S> for RateLimit
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = RateLimitMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(RateLimitMiddleware {
+      service
+    })
+  }
+}
+
+pub struct RateLimitMiddleware<S> {
+  service: S,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let quota = match req.app_data::<web::Data<RateLimiter>>() {
+      Some(limiter) if limiter.is_enabled() => {
+        limiter.proxies.client_ip(req.head()).map(|ip| (ip, limiter.check(ip)))
+      },
+      _ => None,
+    };
+    match quota {
+      Some((ip, quota)) => {
+        if let Some(retry_after) = quota.retry_after {
+          debug!("Rate limit reached: ip={}, retry_after={}s", ip, retry_after);
+          let mut res = HttpResponse::TooManyRequests()
+            .header("Retry-After", retry_after.to_string())
+            .json(json!({
+              "error": "Too many requests, please try again later.",
+            }));
+          quota.add_headers(res.headers_mut());
+          return Box::pin(ok(req.into_response(res.into_body())));
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move {
+          let mut res = fut.await?;
+          quota.add_headers(res.headers_mut());
+          Ok(res)
+        })
+      },
+      None => Box::pin(self.service.call(req)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limiter(requests_per_minute: u32, max_clients: usize) -> RateLimiter {
+    RateLimiter::new(requests_per_minute, max_clients, TrustedProxies::default())
+  }
+
+  fn ip(n: u8) -> IpAddr {
+    IpAddr::from([10, 0, 0, n])
+  }
+
+  #[test]
+  fn check_limits_per_client() {
+    let limiter = limiter(2, 100);
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(ip(1), now).retry_after, None);
+    assert_eq!(limiter.check_at(ip(1), now).retry_after, None);
+    assert_eq!(limiter.check_at(ip(1), now).retry_after, Some(30));
+    // Other clients have their own bucket.
+    assert_eq!(limiter.check_at(ip(2), now).retry_after, None);
+  }
+
+  #[test]
+  fn check_refills() {
+    let limiter = limiter(60, 100);
+    let now = Instant::now();
+    for _ in 0..60 {
+      assert_eq!(limiter.check_at(ip(1), now).retry_after, None);
+    }
+    assert_eq!(limiter.check_at(ip(1), now).retry_after, Some(1));
+    assert_eq!(limiter.check_at(ip(1), now + Duration::from_secs(1)).retry_after, None);
+  }
+
+  #[test]
+  fn check_keeps_active_clients() {
+    let limiter = limiter(2, 100);
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(ip(1), now).retry_after, None);
+    assert_eq!(limiter.check_at(ip(1), now).retry_after, None);
+    assert_eq!(limiter.check_at(ip(1), now + Duration::from_secs(50)).retry_after, None);
+    // The partly used bucket is kept.
+    let later = now + Duration::from_secs(61);
+    assert_eq!(limiter.check_at(ip(1), later).retry_after, None);
+    assert!(limiter.check_at(ip(1), later).retry_after.is_some());
+  }
+
+  #[test]
+  fn max_clients_bounds_memory() {
+    let limiter = limiter(1, 10);
+    let now = Instant::now();
+    for n in 0..10 {
+      assert_eq!(limiter.check_at(ip(n), now).retry_after, None);
+    }
+    // the throttled clients are kept, new clients share one bucket.
+    assert_eq!(limiter.check_at(ip(10), now).retry_after, None);
+    assert!(limiter.check_at(ip(11), now).retry_after.is_some());
+    for n in 0..10 {
+      assert!(limiter.check_at(ip(n), now).retry_after.is_some(), "{}", n);
+    }
+    assert_eq!(limiter.buckets.lock().unwrap().clients.len(), 10);
+
+    // full buckets make room.
+    let later = now + Duration::from_secs(61);
+    assert_eq!(limiter.check_at(ip(12), later).retry_after, None);
+    assert!(limiter.check_at(ip(12), later).retry_after.is_some());
+    let buckets = limiter.buckets.lock().unwrap();
+    assert_eq!(buckets.clients.len(), 1);
+    assert!(buckets.clients.contains_key(&ip(12)));
+  }
+
+  #[test]
+  fn ipv6_clients_share_their_64() {
+    let limiter = limiter(1, 100);
+    let now = Instant::now();
+    let v6 = |addr: &str| addr.parse::<IpAddr>().unwrap();
+    assert_eq!(limiter.check_at(v6("2001:db8:1:2::1"), now).retry_after, None);
+    assert!(limiter.check_at(v6("2001:db8:1:2:ffff::9"), now).retry_after.is_some());
+    assert_eq!(limiter.check_at(v6("2001:db8:1:3::1"), now).retry_after, None);
+    // IPv4-mapped addresses are the IPv4 client.
+    assert_eq!(limiter.check_at(v6("::ffff:10.0.0.1"), now).retry_after, None);
+    assert!(limiter.check_at(ip(1), now).retry_after.is_some());
+    assert_eq!(limiter.check_at(v6("::ffff:10.0.0.2"), now).retry_after, None);
+  }
+
+  #[actix_rt::test]
+  async fn headers_count_down() {
+    use actix_web::{test, App};
+
+    let limiter = web::Data::new(limiter(3, 100));
+    let mut app = test::init_service(
+      App::new()
+        .app_data(limiter)
+        .service(web::resource("/").wrap(RateLimit).to(HttpResponse::Ok))
+    ).await;
+    let mut remaining = Vec::new();
+    for _ in 0..4 {
+      let req = test::TestRequest::get().uri("/")
+        .peer_addr("10.0.0.1:1234".parse().unwrap())
+        .to_request();
+      let res = test::call_service(&mut app, req).await;
+      let headers = res.headers();
+      assert_eq!(headers.get(X_RATELIMIT_LIMIT).unwrap(), "3");
+      assert!(headers.contains_key(X_RATELIMIT_RESET));
+      let value = headers.get(X_RATELIMIT_REMAINING).unwrap().to_str().unwrap().to_string();
+      remaining.push((res.status().as_u16(), value));
+    }
+    assert_eq!(remaining, vec![
+      (200, "2".to_string()),
+      (200, "1".to_string()),
+      (200, "0".to_string()),
+      (429, "0".to_string()),
+    ]);
+  }
+
+  #[actix_rt::test]
+  async fn rejects_with_retry_after() {
+    use actix_web::{test, App, http::StatusCode};
+
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("test.http.trusted_proxies", vec!["10.0.0.9"]).unwrap();
+    let proxies = TrustedProxies::from_config(&config, "test").unwrap();
+    let limiter = web::Data::new(RateLimiter::new(1, 100, proxies));
+    let mut app = test::init_service(
+      App::new()
+        .app_data(limiter)
+        .service(web::resource("/").wrap(RateLimit).to(HttpResponse::Ok))
+    ).await;
+    let request = |peer: &str, forwarded: &str| {
+      let mut req = test::TestRequest::get().uri("/").peer_addr(peer.parse().unwrap());
+      if !forwarded.is_empty() {
+        req = req.header("X-Forwarded-For", forwarded);
+      }
+      req.to_request()
+    };
+
+    let res = test::call_service(&mut app, request("10.0.0.1:1234", "")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&mut app, request("10.0.0.1:1234", "")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers().get("Retry-After").unwrap(), "60");
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "Too many requests, please try again later.");
+
+    // behind the trusted proxy the forwarded client is throttled, a spoofed
+    // address in front of it doesn't get a new bucket.
+    let res = test::call_service(&mut app, request("10.0.0.9:1234", "10.0.0.2")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&mut app, request("10.0.0.9:1234", "10.0.0.3, 10.0.0.2")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    // an untrusted peer can't pick its address.
+    let res = test::call_service(&mut app, request("10.0.0.1:1234", "10.0.0.4")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+  }
+}
@@ -0,0 +1,122 @@
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{ok, Either, Ready};
+
+use actix_web::Error;
+use actix_web::dev::{
+  Service, Transform,
+  ServiceRequest, ServiceResponse,
+};
+
+use crate::error::Result;
+use crate::app::AppConfig;
+use crate::db;
+
+/// Request header with the client's maximum wait (milliseconds), capped by
+/// `db.max_wait_ms`.
+pub const MAX_WAIT_HEADER: &str = "X-Max-Wait-Ms";
+
+/// Limit the time a request spends retrying DB connections/queries
+/// (`db.retry_budget_ms`), the request fails with a 503 instead.
+///
+/// Without a budget each layer (client, statement, query) retries up to
+/// `MAX_RETRIES` times, which stacks up while the DB is flapping.
+///
+/// Clients can lower the deadline with `X-Max-Wait-Ms`, then reads still
+/// running at the deadline are abandoned too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryBudget {
+  budget: Duration,
+  max_wait: Duration,
+}
+
+impl RetryBudget {
+  /// Load `db.retry_budget_ms` and `db.max_wait_ms` (0 = disabled).
+  pub fn from_config(config: &AppConfig) -> Result<Self> {
+    let budget_ms = config.get_int("db.retry_budget_ms")?.unwrap_or(0).max(0);
+    let max_wait_ms = config.get_int("db.max_wait_ms")?.unwrap_or(0).max(0);
+    Ok(Self {
+      budget: Duration::from_millis(budget_ms as u64),
+      max_wait: Duration::from_millis(max_wait_ms as u64),
+    })
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.budget > Duration::from_millis(0) || self.max_wait > Duration::from_millis(0)
+  }
+}
+
+impl<S, B> Transform<S> for RetryBudget
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = RetryBudgetMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(RetryBudgetMiddleware {
+      budget: self.budget,
+      max_wait: self.max_wait,
+      service
+    })
+  }
+}
+
+pub struct RetryBudgetMiddleware<S> {
+  budget: Duration,
+  max_wait: Duration,
+  service: S,
+}
+
+impl<S> RetryBudgetMiddleware<S> {
+  /// The client's `X-Max-Wait-Ms`, capped by `db.max_wait_ms`.
+  fn client_wait(&self, req: &ServiceRequest) -> Option<Duration> {
+    if self.max_wait == Duration::from_millis(0) {
+      return None;
+    }
+    req.headers().get(MAX_WAIT_HEADER)
+      .and_then(|val| val.to_str().ok())
+      .and_then(|val| val.trim().parse::<u64>().ok())
+      .map(|ms| Duration::from_millis(ms).min(self.max_wait))
+  }
+}
+
+impl<S, B> Service for RetryBudgetMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Either<S::Future, db::RetryBudget<S::Future>>;
+
+  fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let now = Instant::now();
+    let mut deadline = if self.budget > Duration::from_millis(0) {
+      Some(now + self.budget)
+    } else {
+      None
+    };
+    let wait_deadline = self.client_wait(&req).map(|wait| now + wait);
+    if let Some(wait_deadline) = wait_deadline {
+      deadline = Some(deadline.map_or(wait_deadline, |deadline| deadline.min(wait_deadline)));
+    }
+    match deadline {
+      Some(deadline) => {
+        Either::Right(db::RetryBudget::new(deadline, wait_deadline, self.service.call(req)))
+      },
+      None => Either::Left(self.service.call(req)),
+    }
+  }
+}
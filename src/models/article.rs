@@ -1,9 +1,36 @@
+use std::str::FromStr;
+
 use chrono::NaiveDateTime;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::*;
 use crate::models::*;
 
+/// Scope in which article slugs must be unique.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlugScope {
+  /// Slugs are unique across all articles.
+  #[default]
+  Global,
+  /// Slugs are unique per author, articles are addressed by `@author/slug`.
+  Author,
+}
+
+impl FromStr for SlugScope {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "global" => Ok(SlugScope::Global),
+      "author" => Ok(SlugScope::Author),
+      _ => Err(config::ConfigError::Message(
+        format!("Invalid slug scope '{}', expected 'global' or 'author'", s)
+      ).into()),
+    }
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Article {
   pub id: i32,
@@ -12,10 +39,25 @@ pub struct Article {
   pub title: String,
   pub description: String,
   pub body: String,
+  #[serde(with = "crate::models::timestamp")]
   pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
   pub updated_at: NaiveDateTime,
 }
 
+/// A previous version of an article.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArticleRevision {
+  pub slug: String,
+  pub title: String,
+  pub description: String,
+  pub body: String,
+  /// When this version was saved.
+  #[serde(with = "crate::models::timestamp")]
+  pub created_at: NaiveDateTime,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ArticleDetails {
@@ -26,10 +68,96 @@ pub struct ArticleDetails {
   pub description: String,
   pub body: String,
   pub tag_list: Vec<String>,
+  #[serde(with = "crate::models::timestamp")]
   pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
   pub updated_at: NaiveDateTime,
   pub favorited: bool,
   pub favorites_count: i64,
+  /// Absolute URL of the article page (`api.include_urls`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub url: Option<String>,
   pub author: user::Profile,
 }
 
+
+/// Rows removed along with an article when it is deleted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArticleDependents {
+  pub comments_count: i64,
+  pub favorites_count: i64,
+}
+
+impl ArticleDependents {
+  pub fn total(&self) -> i64 {
+    self.comments_count + self.favorites_count
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde_json::json;
+
+  #[test]
+  fn parse_slug_scope() {
+    assert_eq!(SlugScope::default(), SlugScope::Global);
+    assert_eq!("global".parse::<SlugScope>().unwrap(), SlugScope::Global);
+    assert_eq!("author".parse::<SlugScope>().unwrap(), SlugScope::Author);
+    assert!("user".parse::<SlugScope>().is_err());
+  }
+
+  #[test]
+  fn article_details_realworld_json() {
+    let at = NaiveDateTime::from_timestamp(1455765776, 637_000_000);
+    let article = ArticleDetails {
+      id: 7,
+      slug: "how-to-train-your-dragon".to_string(),
+      title: "How to train your dragon".to_string(),
+      description: "Ever wonder how?".to_string(),
+      body: "It takes a Jacobian".to_string(),
+      tag_list: vec!["dragons".to_string(), "training".to_string()],
+      created_at: at,
+      updated_at: at,
+      favorited: false,
+      favorites_count: 3,
+      url: None,
+      author: user::Profile {
+        user_id: 2,
+        username: "jake".to_string(),
+        bio: Some("I work at statefarm".to_string()),
+        image: None,
+        following: true,
+        ..Default::default()
+      },
+    };
+    let value = serde_json::to_value(&article).unwrap();
+    assert_eq!(value, json!({
+      "slug": "how-to-train-your-dragon",
+      "title": "How to train your dragon",
+      "description": "Ever wonder how?",
+      "body": "It takes a Jacobian",
+      "tagList": ["dragons", "training"],
+      "createdAt": "2016-02-18T03:22:56.637Z",
+      "updatedAt": "2016-02-18T03:22:56.637Z",
+      "favorited": false,
+      "favoritesCount": 3,
+      "author": {
+        "username": "jake",
+        "bio": "I work at statefarm",
+        "image": null,
+        "following": true,
+      },
+    }));
+
+    // the ids aren't part of the JSON.
+    let parsed: ArticleDetails = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed, ArticleDetails {
+      id: 0,
+      author: user::Profile { user_id: 0, ..article.author.clone() },
+      ..article
+    });
+  }
+}
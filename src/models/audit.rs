@@ -0,0 +1,17 @@
+use chrono::NaiveDateTime;
+
+use serde::{Deserialize, Serialize};
+
+/// An admin action, `hash` chains each entry to the previous one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+  pub id: i32,
+  pub actor: String,
+  pub action: String,
+  pub target: String,
+  #[serde(with = "crate::models::timestamp")]
+  pub created_at: NaiveDateTime,
+  pub prev_hash: Option<String>,
+  pub hash: String,
+}
@@ -10,7 +10,9 @@ pub struct Comment {
   pub article_id: i32,
   pub user_id: i32,
   pub body: String,
+  #[serde(with = "crate::models::timestamp")]
   pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
   pub updated_at: NaiveDateTime,
 }
 
@@ -18,9 +20,26 @@ pub struct Comment {
 #[serde(rename_all = "camelCase")]
 pub struct CommentDetails {
   pub id: i32,
+  #[serde(with = "crate::models::timestamp")]
   pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
   pub updated_at: NaiveDateTime,
   pub body: String,
+  /// The comment was changed after it was created.
+  pub edited: bool,
   pub author: user::Profile,
 }
 
+impl CommentDetails {
+  pub fn from_comment(comment: Comment, author: user::Profile) -> Self {
+    Self {
+      id: comment.id,
+      created_at: comment.created_at,
+      updated_at: comment.updated_at,
+      body: comment.body,
+      edited: comment.updated_at > comment.created_at,
+      author,
+    }
+  }
+}
+
@@ -9,3 +9,9 @@ pub use user::*;
 
 pub mod tag;
 pub use tag::*;
+
+pub mod audit;
+pub use audit::*;
+
+pub mod timestamp;
+pub use timestamp::{TimestampFormat, set_timestamp_format, get_timestamp_format};
@@ -6,7 +6,9 @@ use serde::{Deserialize, Serialize};
 pub struct Tag {
   pub article_id: i32,
   pub tag_name: String,
+  #[serde(with = "crate::models::timestamp")]
   pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
   pub updated_at: NaiveDateTime,
 }
 
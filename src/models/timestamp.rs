@@ -0,0 +1,150 @@
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+
+use serde::{de, Deserializer, Serializer};
+
+use crate::error::*;
+
+/// JSON format used for `created_at`/`updated_at` timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+  /// RFC3339 string: "2016-02-18T03:22:56.637Z"
+  #[default]
+  Rfc3339,
+  /// Integer seconds since the unix epoch.
+  Unix,
+}
+
+impl FromStr for TimestampFormat {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "rfc3339" => Ok(TimestampFormat::Rfc3339),
+      "unix" => Ok(TimestampFormat::Unix),
+      _ => Err(config::ConfigError::Message(
+        format!("Invalid timestamp format '{}', expected 'rfc3339' or 'unix'", s)
+      ).into()),
+    }
+  }
+}
+
+thread_local! {
+  // Set by each server's workers, so servers can use different formats.
+  static FORMAT: Cell<TimestampFormat> = const { Cell::new(TimestampFormat::Rfc3339) };
+}
+
+/// Set the format for this thread (a server worker).
+pub fn set_timestamp_format(format: TimestampFormat) {
+  FORMAT.with(|f| f.set(format));
+}
+
+pub fn get_timestamp_format() -> TimestampFormat {
+  FORMAT.with(|f| f.get())
+}
+
+/// serde `with` module for `NaiveDateTime` fields.
+pub fn serialize<S: Serializer>(ts: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+  match get_timestamp_format() {
+    TimestampFormat::Rfc3339 => {
+      let ts = DateTime::<Utc>::from_utc(*ts, Utc);
+      serializer.serialize_str(&ts.to_rfc3339_opts(SecondsFormat::Millis, true))
+    },
+    TimestampFormat::Unix => {
+      serializer.serialize_i64(ts.timestamp())
+    },
+  }
+}
+
+/// Accepts either format, independent of the configured output format.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDateTime, D::Error> {
+  deserializer.deserialize_any(TimestampVisitor)
+}
+
+/// serde `with` module for `Option<NaiveDateTime>` fields.
+pub mod option {
+  use chrono::NaiveDateTime;
+  use serde::{Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(ts: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match ts {
+      Some(ts) => super::serialize(ts, serializer),
+      None => serializer.serialize_none(),
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error> {
+    super::deserialize(deserializer).map(Some)
+  }
+}
+
+struct TimestampVisitor;
+
+impl<'de> de::Visitor<'de> for TimestampVisitor {
+  type Value = NaiveDateTime;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("an RFC3339 timestamp string or unix timestamp integer")
+  }
+
+  fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Self::Value, E> {
+    NaiveDateTime::from_timestamp_opt(secs, 0)
+      .ok_or_else(|| E::custom(format!("timestamp out of range: {}", secs)))
+  }
+
+  fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Self::Value, E> {
+    self.visit_i64(secs as i64)
+  }
+
+  fn visit_str<E: de::Error>(self, val: &str) -> Result<Self::Value, E> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(val) {
+      return Ok(ts.naive_utc());
+    }
+    NaiveDateTime::from_str(val).map_err(E::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde::{Deserialize, Serialize};
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  struct Stamp {
+    #[serde(with = "super")]
+    at: NaiveDateTime,
+  }
+
+  #[test]
+  fn serialize_in_the_thread_format() {
+    let stamp = Stamp { at: NaiveDateTime::from_timestamp(1455765776, 637_000_000) };
+    assert_eq!(get_timestamp_format(), TimestampFormat::Rfc3339);
+    let rfc3339 = serde_json::to_value(&stamp).unwrap();
+    assert_eq!(rfc3339, json!({ "at": "2016-02-18T03:22:56.637Z" }));
+
+    set_timestamp_format(TimestampFormat::Unix);
+    let unix = serde_json::to_value(&stamp).unwrap();
+    assert_eq!(unix, json!({ "at": 1455765776 }));
+    // other threads (servers) keep their own format.
+    let other = std::thread::spawn(get_timestamp_format).join().unwrap();
+    assert_eq!(other, TimestampFormat::Rfc3339);
+    set_timestamp_format(TimestampFormat::Rfc3339);
+
+    // both formats are accepted on input.
+    let from_rfc3339: Stamp = serde_json::from_value(rfc3339).unwrap();
+    assert_eq!(from_rfc3339, stamp);
+    let from_unix: Stamp = serde_json::from_value(unix).unwrap();
+    assert_eq!(from_unix.at, NaiveDateTime::from_timestamp(1455765776, 0));
+  }
+
+  #[test]
+  fn parse_format() {
+    assert_eq!("unix".parse::<TimestampFormat>().unwrap(), TimestampFormat::Unix);
+    assert_eq!("rfc3339".parse::<TimestampFormat>().unwrap(), TimestampFormat::Rfc3339);
+    assert!("iso".parse::<TimestampFormat>().is_err());
+  }
+}
@@ -10,11 +10,15 @@ pub struct User {
   pub password: String,
   pub bio: Option<String>,
   pub image: Option<String>,
+  #[serde(with = "crate::models::timestamp")]
   pub created_at: NaiveDateTime,
+  #[serde(with = "crate::models::timestamp")]
   pub updated_at: NaiveDateTime,
+  /// Tokens issued with an older version are rejected.
+  pub token_version: i32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct Profile {
   #[serde(skip)]
   pub user_id: i32,
@@ -22,4 +26,15 @@ pub struct Profile {
   pub bio: Option<String>,
   pub image: Option<String>,
   pub following: bool,
+  /// Join date, only loaded by the profile queries (not for article authors).
+  #[serde(rename = "createdAt", default, skip_serializing_if = "Option::is_none",
+    with = "crate::models::timestamp::option")]
+  pub created_at: Option<NaiveDateTime>,
+  /// Number of articles by the user (`Profile.articles_count`), only loaded
+  /// by `GET /profiles/<username>`.
+  #[serde(rename = "articlesCount", default, skip_serializing_if = "Option::is_none")]
+  pub articles_count: Option<i64>,
+  /// Absolute URL of the profile page (`api.include_urls`).
+  #[serde(rename = "profileUrl", default, skip_serializing_if = "Option::is_none")]
+  pub profile_url: Option<String>,
 }
@@ -0,0 +1,202 @@
+use actix_web::{
+  get, post, web, HttpResponse,
+  Error
+};
+
+use crate::error::*;
+use crate::app::*;
+
+use crate::forms::*;
+
+use crate::db::DbService;
+
+use crate::auth::AuthData;
+use crate::middleware::Auth;
+
+/// Revoke all tokens issued to a user.
+#[post("/admin/users/{username}/logout-all", wrap="Auth::required()")]
+async fn logout_all(
+  auth: AuthData,
+  cfg: web::Data<AdminService>,
+  db: web::Data<DbService>,
+  username: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  if !cfg.is_admin(&auth) {
+    return Ok(HttpResponse::Forbidden().json(json!({
+      "error": "Admin access required.",
+    })));
+  }
+
+  // the audit entry is written by the same statement.
+  let found = if cfg.audit_log {
+    db.audit.bump_token_version(auth.user_id, "logout-all", &username).await?.is_some()
+  } else {
+    db.user.bump_token_version(&username).await? > 0
+  };
+  if !found {
+    return Ok(HttpResponse::NotFound().json(json!({
+      "error": "User not found",
+    })));
+  }
+  Ok(HttpResponse::Ok().finish())
+}
+
+/// List the audit log of admin actions, newest first.
+#[get("/admin/audit", wrap="Auth::required()")]
+async fn audit_log(
+  auth: AuthData,
+  cfg: web::Data<AdminService>,
+  db: web::Data<DbService>,
+  req: web::Query<AuditRequest>,
+) -> Result<HttpResponse, Error> {
+  if !cfg.is_admin(&auth) {
+    return Ok(HttpResponse::Forbidden().json(json!({
+      "error": "Admin access required.",
+    })));
+  }
+
+  let entries = db.audit.get_entries(req.into_inner()).await?;
+  Ok(HttpResponse::Ok().json(AuditList {
+    entries_count: entries.len(),
+    entries,
+  }))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AdminService {
+  /// Ids of the users allowed to use the admin endpoints.
+  pub user_ids: Vec<i32>,
+
+  /// Tokens are checked against the user's token version
+  /// (`auth.check_token_version`), needed by `logout-all`.
+  pub version_check: bool,
+
+  /// Record admin actions in the (hash-chained) audit log.
+  pub audit_log: bool,
+
+  /// Only register the read endpoints (`<prefix>.read_only`).
+  pub read_only: bool,
+}
+
+impl AdminService {
+  /// Check if the current user is an admin.
+  ///
+  /// Admins are matched by user id, users can pick (or change to) any free
+  /// username.
+  pub fn is_admin(&self, auth: &AuthData) -> bool {
+    auth.user_id > 0 && self.user_ids.contains(&auth.user_id)
+  }
+
+  /// Record an action by the current (admin) user on `target`.
+  ///
+  /// Nothing is recorded in read-only mode, a replica refuses the insert.
+  pub async fn audit(&self, db: &DbService, auth: &AuthData, action: &str, target: &str) -> Result<()> {
+    if self.audit_log && !self.read_only {
+      db.audit.append(auth.user_id, action, target).await?;
+    }
+    Ok(())
+  }
+}
+
+impl super::Service for AdminService {
+  fn load_app_config(&mut self, config: &AppConfig, prefix: &str) -> Result<()> {
+    self.read_only = super::read_only(config, prefix)?;
+    self.user_ids = config.get::<Vec<i32>>("Admin.user_ids")?.unwrap_or_default();
+    self.version_check = config.get_bool("auth.check_token_version")?.unwrap_or(false);
+    self.audit_log = config.get_bool("Admin.audit_log")?.unwrap_or(true);
+    Ok(())
+  }
+
+  fn api_config(&self, web: &mut web::ServiceConfig) {
+    web
+      .data(self.clone())
+      .service(audit_log);
+    // bumping the token version does nothing without the check.
+    if self.version_check && !self.read_only {
+      web.service(logout_all);
+    }
+  }
+
+  fn routes(&self) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut routes: Vec<(&'static str, &'static [&'static str])> = vec![
+      ("/admin/audit", &["GET"]),
+    ];
+    if self.version_check && !self.read_only {
+      routes.push(("/admin/users/{username}/logout-all", &["POST"]));
+    }
+    routes
+  }
+}
+
+pub fn new_factory() -> AdminService {
+  Default::default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn auth(user_id: i32) -> AuthData {
+    AuthData {
+      user_id,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn is_admin_by_user_id() {
+    let admin = AdminService {
+      user_ids: vec![1],
+      ..Default::default()
+    };
+    assert!(admin.is_admin(&auth(1)));
+    // another user with the admin's username (or any other) is not an admin.
+    assert!(!admin.is_admin(&auth(2)));
+    // anonymous requests.
+    assert!(!AdminService { user_ids: vec![0], ..Default::default() }.is_admin(&auth(0)));
+    assert!(!AdminService::default().is_admin(&auth(1)));
+  }
+
+  #[actix_rt::test]
+  async fn logout_all_needs_the_version_check() {
+    use actix_web::{test, App, dev::Service, http::Method};
+    use crate::db::{test_db, test_suffix};
+    use crate::services::{test_services, test_login, test_request};
+
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let (admin, admin_token) = test_login(&db, &format!("admin{}", suffix)).await;
+    let (_, token) = test_login(&db, &format!("target{}", suffix)).await;
+    let uri = format!("/admin/users/target{}/logout-all", suffix);
+    for version_check in &[false, true] {
+      let services = test_services(&[
+        ("test.services", vec!["User", "Admin"].into()),
+        ("Admin.user_ids", vec![admin.user_id as i64].into()),
+        ("auth.check_token_version", (*version_check).into()),
+      ]).unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+
+      // only admins.
+      let req = test_request(Method::POST, &uri, &token).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), if *version_check { 403 } else { 404 });
+
+      let req = test_request(Method::POST, &uri, &admin_token).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), if *version_check { 200 } else { 404 });
+    }
+
+    // the user's old token is rejected.
+    let services = test_services(&[("auth.check_token_version", true.into())]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let req = test_request(Method::GET, "/user", &token).to_request();
+    let status = match app.call(req).await {
+      Ok(res) => res.status(),
+      Err(err) => err.as_response_error().error_response().status(),
+    };
+    assert_eq!(status, 401);
+  }
+}
@@ -1,88 +1,506 @@
+use log::*;
+
 use actix_web::{
-  get, post, put, delete, web, HttpResponse,
+  get, post, put, patch, delete, route, web, HttpRequest, HttpResponse,
   Error
 };
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use futures::stream;
+use futures::future::{Future, FutureExt, LocalBoxFuture, Shared};
+
 use crate::error::*;
 use crate::app::*;
 
 use crate::models::*;
 use crate::forms::*;
 
-use crate::db::DbService;
+use crate::db::{DbService, StoreArticle, StoreComment, NewbieLimit, ArticleLimits, title_used_error, clean_tag_list, title_slug, is_valid_slug};
+
+use super::admin::AdminService;
 
 use crate::auth::AuthData;
 use crate::middleware::Auth;
 
+use super::{NO_CHANGE_HEADER, FEED_FALLBACK_HEADER, ApiUrls};
+use super::profile::cached_profiles;
+
 /// Get list of articles
 #[get("/articles", wrap="Auth::optional()")]
 async fn list(
+  http_req: HttpRequest,
   auth: Option<AuthData>,
+  cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   req: web::Query<ArticleRequest>
 ) -> Result<HttpResponse, Error> {
   let auth = auth.unwrap_or_default();
 
-  let articles = db.article.get_articles(&auth, req.into_inner()).await?;
+  if cfg.list_requires_filter && !req.has_filter() {
+    return Ok(HttpResponse::BadRequest().json(json!({
+      "error": "A 'tag', 'author' or 'favorited' filter is required.",
+    })));
+  }
+  if req.sort_order().is_none() {
+    return Ok(HttpResponse::UnprocessableEntity().json(json!({
+      "error": format!("Invalid 'sort', expected one of: {}", ArticleSort::NAMES.join(", ")),
+    })));
+  }
+  if !req.tag_within(cfg.max_filter_tag_len) {
+    return Ok(HttpResponse::UnprocessableEntity().json(json!({
+      "error": format!("The 'tag' filter is limited to {} characters.", cfg.max_filter_tag_len),
+    })));
+  }
+
+  let (limit, offset) = (req.limit, req.offset);
+  // only the rich `meta` has the total, don't count for the RealWorld shape.
+  let total = if rich_pagination() {
+    Some(db.article.count_articles(&req).await?)
+  } else {
+    None
+  };
+  let mut articles = db.article.get_articles(&auth, req.into_inner()).await?;
+  urls.set_articles(&http_req, &mut articles);
 
-  Ok(HttpResponse::Ok().json(ArticleList::<ArticleDetails> {
-    articles_count: articles.len(),
-    articles,
-  }))
+  Ok(HttpResponse::Ok().json(list_out(articles, limit, offset, total)))
+}
+
+/// A page of articles, with the total when it was counted.
+fn list_out(
+  articles: Vec<ArticleDetails>, limit: Option<i64>, offset: Option<i64>, total: Option<i64>,
+) -> ListOut<ArticleList<ArticleDetails>, ArticleDetails> {
+  let mut meta = PageMeta::page(articles.len(), limit, offset);
+  if let Some(total) = total {
+    meta = meta.with_total(total);
+  }
+  ListOut::new(articles, meta, |articles| {
+    ArticleList::<ArticleDetails> {
+      articles_count: articles.len(),
+      articles,
+    }
+  })
 }
 
 /// Get current user's feed
 #[get("/articles/feed", wrap="Auth::required()")]
 async fn feed(
+  http_req: HttpRequest,
+  auth: AuthData,
+  cfg: web::Data<ArticleService>,
+  db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
+  req: web::Query<FeedRequest>
+) -> Result<HttpResponse, Error> {
+  if !cfg.allow_feed {
+    return Ok(HttpResponse::NotFound().json(json!({
+      "error": "Feed disabled.",
+    })));
+  }
+
+  let (limit, offset) = (req.limit, req.offset);
+  let mut total = if rich_pagination() {
+    Some(db.article.count_feed(&auth).await?)
+  } else {
+    None
+  };
+  let mut articles = db.article.get_feed(&auth, req.into_inner()).await?;
+  let mut res = HttpResponse::Ok();
+  if articles.is_empty() && cfg.feed_fallback_to_global {
+    // an empty page past the end isn't an empty feed.
+    let feed_total = match total {
+      Some(total) => total,
+      None => db.article.count_feed(&auth).await?,
+    };
+    if feed_total == 0 {
+      // Empty feed, show the newest articles instead.
+      let global = ArticleRequest { limit, offset, ..Default::default() };
+      if total.is_some() {
+        total = Some(db.article.count_articles(&global).await?);
+      }
+      articles = db.article.get_articles(&auth, global).await?;
+      res.header(FEED_FALLBACK_HEADER, "global");
+    }
+  }
+  urls.set_articles(&http_req, &mut articles);
+
+  Ok(res.json(list_out(articles, limit, offset, total)))
+}
+
+/// Count feed articles newer than `since` (a timestamp in the API's format,
+/// or an article id with `rfc3339`) or `since_id`.
+#[get("/articles/feed/unread-count", wrap="Auth::required()")]
+async fn feed_unread_count(
+  auth: AuthData,
+  cfg: web::Data<ArticleService>,
+  db: web::Data<DbService>,
+  req: web::Query<UnreadCountRequest>
+) -> Result<HttpResponse, Error> {
+  if !cfg.allow_feed {
+    return Ok(HttpResponse::NotFound().json(json!({
+      "error": "Feed disabled.",
+    })));
+  }
+  let since = match (req.since_id, req.since.as_deref()) {
+    (Some(id), _) => Some(FeedMarker::Id(id)),
+    (None, Some(since)) => FeedMarker::parse(since, get_timestamp_format()),
+    (None, None) => None,
+  };
+  let since = match since {
+    Some(since) => since,
+    None => {
+      return Ok(HttpResponse::BadRequest().json(json!({
+        "error": "'since' must be a timestamp in the API's format, or use 'since_id'.",
+      })));
+    },
+  };
+
+  let count = db.article.count_feed_since(&auth, &since).await?;
+  Ok(HttpResponse::Ok().json(json!({
+    "unreadCount": count,
+  })))
+}
+
+/// Get current user's favorited articles
+#[get("/user/favorites", wrap="Auth::required()")]
+async fn user_favorites(
+  http_req: HttpRequest,
   auth: AuthData,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   req: web::Query<FeedRequest>
 ) -> Result<HttpResponse, Error> {
+  let (limit, offset) = (req.limit, req.offset);
+  let (mut articles, total) = db.article.get_user_favorites(&auth, req.into_inner()).await?;
+  urls.set_articles(&http_req, &mut articles);
 
-  let articles = db.article.get_feed(&auth, req.into_inner()).await?;
+  let meta = PageMeta::page(articles.len(), limit, offset).with_total(total);
+  Ok(HttpResponse::Ok().json(ListOut::new(articles, meta, |articles| {
+    ArticleList::<ArticleDetails> {
+      articles_count: total as usize,
+      articles,
+    }
+  })))
+}
 
-  Ok(HttpResponse::Ok().json(ArticleList::<ArticleDetails> {
-    articles_count: articles.len(),
-    articles,
-  }))
+/// Find an article by slug, with author scoped slugs the current user's
+/// articles are checked first.
+async fn find_article(
+  cfg: &ArticleService,
+  db: &DbService,
+  auth: &AuthData,
+  slug: &str,
+) -> Result<Option<ArticleDetails>> {
+  match cfg.slug_scope {
+    SlugScope::Global => db.article.get_by_slug(auth, slug).await,
+    SlugScope::Author => db.article.get_by_scoped_slug(auth, slug).await,
+  }
 }
 
-/// get article by slug
-#[get("/articles/{slug}", wrap="Auth::optional()")]
+type SharedFetch = Shared<LocalBoxFuture<'static, Rc<Result<Option<ArticleDetails>>>>>;
+
+/// In-flight `get_article` fetches of a worker, keyed by `(slug, user_id)`.
+#[derive(Default)]
+struct InflightArticles {
+  fetches: RefCell<HashMap<(String, i32), SharedFetch>>,
+}
+
+/// Removes the fetch from `InflightArticles` when the request that
+/// started it finishes (or is dropped).
+struct InflightGuard<'a> {
+  inflight: &'a InflightArticles,
+  key: (String, i32),
+}
+
+impl<'a> Drop for InflightGuard<'a> {
+  fn drop(&mut self) {
+    self.inflight.fetches.borrow_mut().remove(&self.key);
+  }
+}
+
+/// Same as `find_article`, but concurrent fetches of the same slug by the
+/// same user share one query.
+async fn find_article_coalesced(
+  inflight: &InflightArticles,
+  cfg: web::Data<ArticleService>,
+  db: web::Data<DbService>,
+  auth: AuthData,
+  slug: String,
+) -> Result<Option<ArticleDetails>> {
+  let key = (slug.clone(), auth.user_id);
+  coalesce(inflight, key, async move {
+    find_article(&cfg, &db, &auth, &slug).await
+  }).await
+}
+
+/// Run `fetch`, unless a fetch with the same key is in-flight, then share
+/// its result.
+async fn coalesce<F>(
+  inflight: &InflightArticles,
+  key: (String, i32),
+  fetch: F,
+) -> Result<Option<ArticleDetails>>
+where
+  F: Future<Output = Result<Option<ArticleDetails>>> + 'static,
+{
+  let shared = inflight.fetches.borrow().get(&key).cloned();
+  let res = match shared {
+    Some(fetch) => fetch.await,
+    None => {
+      let fetch = fetch.map(Rc::new).boxed_local().shared();
+      inflight.fetches.borrow_mut().insert(key.clone(), fetch.clone());
+      let _guard = InflightGuard { inflight, key };
+      fetch.await
+    },
+  };
+  Rc::try_unwrap(res).unwrap_or_else(|res| match &*res {
+    Ok(article) => Ok(article.clone()),
+    Err(err) => Err(shared_error(err)),
+  })
+}
+
+/// Copy of the error of a shared fetch, with the same response (status and
+/// message) for every request that waited on it.
+fn shared_error(err: &crate::error::Error) -> crate::error::Error {
+  use crate::error::Error::*;
+  match err {
+    Unauthorized(msg) => Unauthorized(msg.clone()),
+    NotFound(msg) => NotFound(msg.clone()),
+    Gone(msg) => Gone(msg.clone()),
+    UnsupportedMediaType(msg) => UnsupportedMediaType(msg.clone()),
+    UnprocessableEntity(msg) => UnprocessableEntity(msg.clone()),
+    InternalServerError => InternalServerError,
+    BadRequest(msg) => BadRequest(msg.clone()),
+    PasswordError(msg) => PasswordError(msg.clone()),
+    DisconnectedError(msg) => DisconnectedError(msg.clone()),
+    RetryBudgetExhausted => RetryBudgetExhausted,
+    // the sources can't be cloned, these are all a 500.
+    JsonError { .. } | JwtError { .. } | PgError { .. } | RecvError { .. }
+    | FromUtf8Error { .. } | IOError { .. } | ConfigError { .. } | Other(_) => {
+      error!("Coalesced article fetch failed: {:?}", err);
+      InternalServerError
+    },
+  }
+}
+
+/// preview the slug `store_article` would use for a title
+///
+/// Uses `title_slug` like `store_article`.  Only authenticated users are told
+/// if the slug is `available`.
+///
+/// Registered before `/articles/{slug}`, like the feed.
+#[get("/articles/slug-preview", wrap="Auth::optional()")]
+async fn slug_preview(
+  auth: Option<AuthData>,
+  cfg: web::Data<ArticleService>,
+  db: web::Data<DbService>,
+  req: web::Query<SlugPreviewRequest>,
+) -> Result<HttpResponse, Error> {
+  let slug = title_slug(&req.title);
+  match auth {
+    Some(auth) => {
+      let available = match find_article(&cfg, &db, &auth, &slug).await? {
+        // other authors can use the same slug.
+        Some(article) => cfg.slug_scope == SlugScope::Author && article.author.user_id != auth.user_id,
+        None => true,
+      };
+      Ok(HttpResponse::Ok().json(json!({
+        "slug": slug,
+        "available": available,
+      })))
+    },
+    None => {
+      Ok(HttpResponse::Ok().json(json!({
+        "slug": slug,
+      })))
+    },
+  }
+}
+
+/// 410 Gone if an article with the slug (by `author`) was deleted,
+/// otherwise 404.
+async fn article_not_found(db: &DbService, author: Option<&str>, slug: &str) -> Result<HttpResponse, Error> {
+  let deleted = match author {
+    Some(author) => db.article.is_deleted_author_slug(author, slug).await?,
+    None => db.article.is_deleted_slug(slug).await?,
+  };
+  if deleted {
+    return Err(crate::error::Error::Gone(json!({
+      "error": "Article was deleted",
+    })).into());
+  }
+  Ok(HttpResponse::NotFound().json(json!({
+    "error": "Article not found",
+  })))
+}
+
+/// get article by author and slug
+#[route("/articles/@{author}/{slug}", method="GET", method="HEAD", wrap="Auth::optional()")]
+async fn get_author_article(
+  http_req: HttpRequest,
+  auth: Option<AuthData>,
+  db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
+  path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+  let auth = auth.unwrap_or_default();
+  let (author, slug) = path.into_inner();
+
+  match db.article.get_by_author_slug(&auth, &author, &slug).await? {
+    Some(mut article) => {
+      urls.set_article(&http_req, &mut article);
+      Ok(HttpResponse::Ok().json(ArticleOut::<ArticleDetails> {
+        article,
+      }))
+    },
+    None => article_not_found(&db, Some(&author), &slug).await,
+  }
+}
+
+/// get article by slug (HEAD responses have the body stripped by actix)
+#[route("/articles/{slug}", method="GET", method="HEAD", wrap="Auth::optional()")]
 async fn get_article(
+  http_req: HttpRequest,
   auth: Option<AuthData>,
+  cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
+  inflight: web::Data<InflightArticles>,
   slug: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
   let auth = auth.unwrap_or_default();
 
-  match db.article.get_by_slug(&auth, &slug).await? {
-    Some(article) => {
+  if cfg.check_read_slugs && !is_valid_slug(&slug, cfg.max_slug_len) {
+    // can't be a slug, skip the DB.
+    return Ok(HttpResponse::NotFound().json(json!({
+      "error": "Article not found",
+    })));
+  }
+
+  let article = if cfg.coalesce_reads {
+    find_article_coalesced(&inflight, cfg.clone(), db.clone(), auth, slug.to_string()).await?
+  } else {
+    find_article(&cfg, &db, &auth, &slug).await?
+  };
+  match article {
+    Some(mut article) => {
+      urls.set_article(&http_req, &mut article);
       Ok(HttpResponse::Ok().json(ArticleOut::<ArticleDetails> {
         article,
       }))
     },
-    None => {
-      Ok(HttpResponse::NotFound().json(json!({
-        "error": "Article not found",
-      })))
-    }
+    None => article_not_found(&db, None, &slug).await,
+  }
+}
+
+/// Check the `tagList` of a stored/updated article.
+fn check_tags(cfg: &ArticleService, tag_list: &[String]) -> Option<HttpResponse> {
+  let tags = clean_tag_list(tag_list);
+  if cfg.require_tags && tags.is_empty() {
+    return Some(HttpResponse::UnprocessableEntity().json(json!({
+      "error": "At least one tag is required.",
+    })));
+  }
+  if cfg.max_tags > 0 && tags.len() > cfg.max_tags {
+    return Some(HttpResponse::UnprocessableEntity().json(json!({
+      "error": format!("An article is limited to {} tags.", cfg.max_tags),
+    })));
+  }
+  None
+}
+
+/// Trim the description and check it against `Article.require_description`
+/// and `Article.max_description_len`.
+fn check_description(cfg: &ArticleService, description: &mut String) -> Result<()> {
+  let trimmed = description.trim();
+  if trimmed.len() != description.len() {
+    *description = trimmed.to_string();
+  }
+  if cfg.require_description && description.is_empty() {
+    return Err(crate::error::Error::UnprocessableEntity(json!({
+      "errors": {
+        "description": ["can't be blank"],
+      },
+    })));
+  }
+  check_max_len(&[("description", Some(description.as_str()), cfg.max_description_len)])
+}
+
+/// Check that the slug of a title fits `Article.max_slug_len`, so the article
+/// can be read with `Article.check_read_slugs`.
+fn check_slug_len(cfg: &ArticleService, title: &str) -> Result<()> {
+  if cfg.max_slug_len > 0 && title_slug(title).len() > cfg.max_slug_len {
+    return Err(crate::error::Error::UnprocessableEntity(json!({
+      "errors": {
+        "title": [format!("makes a slug longer than {} characters", cfg.max_slug_len)],
+      },
+    })));
+  }
+  Ok(())
+}
+
+/// Non-fatal checks of a saved article (`Article.emit_warnings`).
+fn content_warnings(cfg: &ArticleService, article: &ArticleDetails) -> Vec<String> {
+  let mut warnings = Vec::new();
+  if !cfg.emit_warnings {
+    return warnings;
+  }
+  if article.tag_list.is_empty() {
+    warnings.push("No tags, the article will be harder to discover.".to_string());
+  }
+  if article.description.is_empty() {
+    warnings.push("No description.".to_string());
+  }
+  if article.body.chars().count() < cfg.warn_body_len {
+    warnings.push(format!("Body is shorter than {} characters.", cfg.warn_body_len));
   }
+  warnings
+}
+
+/// Default of `Article.max_filter_tag_len`.
+const DEFAULT_MAX_FILTER_TAG_LEN: i64 = 128;
+/// Default of `User.newbie_articles_per_hour`.
+const DEFAULT_NEWBIE_ARTICLES_PER_HOUR: i64 = 1;
+/// Default of `User.newbie_comments_per_hour`.
+const DEFAULT_NEWBIE_COMMENTS_PER_HOUR: i64 = 10;
+
+fn newbie_limit_reached() -> HttpResponse {
+  HttpResponse::TooManyRequests()
+    .header("Retry-After", "3600")
+    .json(json!({
+      "error": "New accounts have a lower hourly limit, please try again later.",
+    }))
 }
 
 /// post new article
 #[post("/articles", wrap="Auth::required()")]
 async fn store_article(
+  http_req: HttpRequest,
   auth: AuthData,
+  cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
-  req: web::Json<ArticleOut<CreateArticle>>,
+  urls: web::Data<ApiUrls>,
+  mut req: web::Json<ArticleOut<CreateArticle>>,
 ) -> Result<HttpResponse, Error> {
-  match db.article.store(&auth, &req.article).await? {
-    Some(article_id) => {
+  validate_form(&req.article)?;
+  check_description(&cfg, &mut req.article.description)?;
+  check_slug_len(&cfg, &req.article.title)?;
+  if let Some(res) = check_tags(&cfg, &req.article.tag_list) {
+    return Ok(res);
+  }
+  match db.article.store(&auth, &req.article, cfg.slug_scope, cfg.limits()).await? {
+    StoreArticle::Stored(article_id) => {
       match db.article.get_by_id(&auth, article_id).await? {
-        Some(article) => {
-          Ok(HttpResponse::Ok().json(ArticleOut::<ArticleDetails> {
+        Some(mut article) => {
+          urls.set_article(&http_req, &mut article);
+          let warnings = content_warnings(&cfg, &article);
+          Ok(HttpResponse::Ok().json(ArticleSavedOut::<ArticleDetails> {
             article,
+            warnings,
           }))
         },
         None => {
@@ -92,36 +510,55 @@ async fn store_article(
         }
       }
     },
-    None => {
-      Ok(HttpResponse::InternalServerError().json(json!({
-        "error": "Failed to store article",
+    StoreArticle::SlugUsed => {
+      Ok(HttpResponse::UnprocessableEntity().json(json!({
+        "error": "Article slug already used.",
       })))
-    }
+    },
+    StoreArticle::MaxPerAuthor => {
+      Ok(HttpResponse::UnprocessableEntity().json(json!({
+        "error": "Maximum number of articles reached.",
+      })))
+    },
+    StoreArticle::NewbieLimited => Ok(newbie_limit_reached()),
+    StoreArticle::TitleUsed => Err(title_used_error().into()),
   }
 }
 
 /// post update to existing article
 #[put("/articles/{slug}", wrap="Auth::required()")]
 async fn update_article(
+  http_req: HttpRequest,
   auth: AuthData,
   cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   slug: web::Path<String>,
-  req: web::Json<ArticleOut<UpdateArticle>>,
+  mut req: web::Json<ArticleOut<UpdateArticle>>,
 ) -> Result<HttpResponse, Error> {
-  match db.article.get_by_slug(&auth, &slug).await? {
+  validate_form(&req.article)?;
+  if let Some(description) = &mut req.article.description {
+    check_description(&cfg, description)?;
+  }
+  if let Some(title) = &req.article.title {
+    check_slug_len(&cfg, title)?;
+  }
+  match find_article(&cfg, &db, &auth, &slug).await? {
     Some(mut article) => {
       if cfg.allow_update && article.author.user_id == auth.user_id {
-        let old_article = article.clone();
-        let article = if db.article.update(&mut article, &req.article).await? > 0 {
-          // article updated return updated article.
-          article
-        } else {
-          // Failed to update article, return old article.
-          old_article
-        };
-        Ok(HttpResponse::Ok().json(ArticleOut::<ArticleDetails> {
+        if let Some(res) = req.article.tag_list.as_ref().and_then(|tags| check_tags(&cfg, tags)) {
+          return Ok(res);
+        }
+        if db.article.update(&mut article, &req.article, cfg.slug_scope, cfg.max_revisions, cfg.unique_title_per_author).await? == 0 {
+          return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Article slug already used.",
+          })));
+        }
+        urls.set_article(&http_req, &mut article);
+        let warnings = content_warnings(&cfg, &article);
+        Ok(HttpResponse::Ok().json(ArticleSavedOut::<ArticleDetails> {
           article,
+          warnings,
         }))
       } else {
         Ok(HttpResponse::Forbidden().json(json!({
@@ -129,11 +566,52 @@ async fn update_article(
         })))
       }
     },
-    None => {
-      Ok(HttpResponse::NotFound().json(json!({
-        "error": "Article not found",
-      })))
-    }
+    None => article_not_found(&db, None, &slug).await,
+  }
+}
+
+/// partial update of an existing article, only the changed fields are returned.
+#[patch("/articles/{slug}", wrap="Auth::required()")]
+async fn patch_article(
+  auth: AuthData,
+  cfg: web::Data<ArticleService>,
+  db: web::Data<DbService>,
+  slug: web::Path<String>,
+  mut req: web::Json<ArticleOut<UpdateArticle>>,
+) -> Result<HttpResponse, Error> {
+  validate_form(&req.article)?;
+  if let Some(description) = &mut req.article.description {
+    check_description(&cfg, description)?;
+  }
+  if let Some(title) = &req.article.title {
+    check_slug_len(&cfg, title)?;
+  }
+  match find_article(&cfg, &db, &auth, &slug).await? {
+    Some(mut article) => {
+      if cfg.allow_update && article.author.user_id == auth.user_id {
+        if let Some(res) = req.article.tag_list.as_ref().and_then(|tags| check_tags(&cfg, tags)) {
+          return Ok(res);
+        }
+        let old_article = article.clone();
+        if db.article.update(&mut article, &req.article, cfg.slug_scope, cfg.max_revisions, cfg.unique_title_per_author).await? == 0 {
+          return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Article slug already used.",
+          })));
+        }
+        // reload to get the new `updated_at`.
+        let article = db.article.get_by_id(&auth, article.id).await?
+          .unwrap_or(article);
+        Ok(HttpResponse::Ok().json(ArticleSavedOut::<ArticleChanges> {
+          article: ArticleChanges::diff(&old_article, &article),
+          warnings: content_warnings(&cfg, &article),
+        }))
+      } else {
+        Ok(HttpResponse::Forbidden().json(json!({
+          "error": "Update article disabled.",
+        })))
+      }
+    },
+    None => article_not_found(&db, None, &slug).await,
   }
 }
 
@@ -144,11 +622,25 @@ async fn delete_article(
   cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
   slug: web::Path<String>,
+  req: web::Query<DeleteArticleRequest>,
 ) -> Result<HttpResponse, Error> {
-  match db.article.get_by_slug(&auth, &slug).await? {
+  match find_article(&cfg, &db, &auth, &slug).await? {
     Some(article) => {
       if cfg.allow_delete && article.author.user_id == auth.user_id {
-        db.article.delete(article.id).await?;
+        if req.dry_run.unwrap_or(false) {
+          let dependents = db.article.count_dependents(article.id).await?;
+          return Ok(HttpResponse::Ok().json(json!({
+            "dependents": dependents,
+          })));
+        }
+        // the confirm count is checked by the delete itself.
+        if db.article.delete(article.id, req.confirm).await? == 0 && req.confirm.is_some() {
+          let dependents = db.article.count_dependents(article.id).await?;
+          return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Confirm count doesn't match the comments/favorites to delete.",
+            "dependents": dependents,
+          })));
+        }
         Ok(HttpResponse::Ok().finish())
       } else {
         Ok(HttpResponse::Forbidden().json(json!({
@@ -156,29 +648,145 @@ async fn delete_article(
         })))
       }
     },
-    None => {
-      Ok(HttpResponse::NotFound().json(json!({
-        "error": "Article not found",
-      })))
-    }
+    None => article_not_found(&db, None, &slug).await,
+  }
+}
+
+/// get previous versions of an article, only for the author and admins.
+#[get("/articles/{slug}/history", wrap="Auth::required()")]
+async fn article_history(
+  auth: AuthData,
+  cfg: web::Data<ArticleService>,
+  admin: Option<web::Data<AdminService>>,
+  db: web::Data<DbService>,
+  slug: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+  if cfg.max_revisions == 0 {
+    return Ok(HttpResponse::NotFound().json(json!({
+      "error": "Article history disabled.",
+    })));
+  }
+  match find_article(&cfg, &db, &auth, &slug).await? {
+    Some(article) => {
+      if article.author.user_id != auth.user_id && !admin.is_some_and(|admin| admin.is_admin(&auth)) {
+        return Ok(HttpResponse::Forbidden().json(json!({
+          "error": "Only the author or an admin can view the article history.",
+        })));
+      }
+      let revisions = db.article.get_revisions(article.id).await?;
+      Ok(HttpResponse::Ok().json(ArticleRevisionList {
+        revisions,
+      }))
+    },
+    None => article_not_found(&db, None, &slug).await,
   }
 }
 
+const EXPORT_PAGE_SIZE: i64 = 100;
+
+/// export all articles as newline-delimited JSON.
+///
+/// Articles are fetched a page at a time while streaming the response, only
+/// for admins.
+#[get("/admin/articles/export", wrap="Auth::required()")]
+async fn export_articles(
+  auth: AuthData,
+  cfg: web::Data<ArticleService>,
+  admin: Option<web::Data<AdminService>>,
+  db: web::Data<DbService>,
+) -> Result<HttpResponse, Error> {
+  if !cfg.allow_export {
+    return Ok(HttpResponse::Forbidden().json(json!({
+      "error": "Export articles disabled.",
+    })));
+  }
+  let admin = match admin {
+    Some(admin) if admin.is_admin(&auth) => admin,
+    _ => {
+      return Ok(HttpResponse::Forbidden().json(json!({
+        "error": "Admin access required.",
+      })));
+    },
+  };
+  admin.audit(&db, &auth, "export-articles", "articles").await?;
+
+  let pages = stream::unfold(Some(i32::MAX), move |before_id| {
+    let auth = auth.clone();
+    let db = db.clone();
+    async move {
+      let before_id = before_id?;
+      let articles = match db.article.get_articles_before(&auth, before_id, EXPORT_PAGE_SIZE).await {
+        Ok(articles) => articles,
+        Err(err) => return Some((Err(err), None)),
+      };
+      // Stop after the last (partial) page.
+      let next = if (articles.len() as i64) < EXPORT_PAGE_SIZE {
+        None
+      } else {
+        articles.last().map(|a| a.id)
+      };
+      let mut buf = Vec::new();
+      for article in articles.iter() {
+        if let Err(err) = serde_json::to_writer(&mut buf, article) {
+          return Some((Err(err.into()), None));
+        }
+        buf.push(b'\n');
+      }
+      Some((Ok(web::Bytes::from(buf)), next))
+    }
+  });
+
+  Ok(HttpResponse::Ok()
+    .content_type("application/x-ndjson")
+    .streaming::<_, crate::error::Error>(Box::pin(pages)))
+}
+
 /////////////////////////////// Article Comments
 
 /// get article comments by slug
 #[get("/articles/{slug}/comments", wrap="Auth::optional()")]
 async fn get_comments(
+  http_req: HttpRequest,
   auth: Option<AuthData>,
+  cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   slug: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
   let auth = auth.unwrap_or_default();
 
-  let comments = db.comment.get_comments_by_slug(&auth, &slug).await?;
-  Ok(HttpResponse::Ok().json(CommentList {
-    comments,
-  }))
+  let rows = match cfg.slug_scope {
+    SlugScope::Global => db.comment.get_comments_by_slug(&slug).await?,
+    SlugScope::Author => {
+      // the slug can be used by multiple authors.
+      match find_article(&cfg, &db, &auth, &slug).await? {
+        Some(article) => db.comment.get_comments_by_article(article.id).await?,
+        None => Vec::new(),
+      }
+    },
+  };
+  // an article without comments, or a deleted one (410).
+  if rows.is_empty() && db.article.is_deleted_slug(&slug).await?
+      && find_article(&cfg, &db, &auth, &slug).await?.is_none() {
+    return article_not_found(&db, None, &slug).await;
+  }
+  // All authors are loaded with one query.
+  let author_ids = rows.iter().map(|comment| comment.user_id).collect::<Vec<_>>();
+  let authors = cached_profiles(&http_req, &db, &auth, &author_ids).await?;
+  let mut comments = Vec::with_capacity(rows.len());
+  for comment in rows {
+    if let Some(author) = authors.get(&comment.user_id) {
+      let mut author = author.clone();
+      urls.set_profile(&http_req, &mut author);
+      comments.push(CommentDetails::from_comment(comment, author));
+    }
+  }
+  let meta = PageMeta::complete(comments.len());
+  Ok(HttpResponse::Ok().json(ListOut::new(comments, meta, |comments| {
+    CommentList {
+      comments,
+    }
+  })))
 }
 
 /// Add comment to article
@@ -190,11 +798,12 @@ async fn store_comment(
   slug: web::Path<String>,
   req: web::Json<CommentOut<CreateComment>>,
 ) -> Result<HttpResponse, Error> {
-  match db.article.get_by_slug(&auth, &slug).await? {
+  validate_form(&req.comment)?;
+  match find_article(&cfg, &db, &auth, &slug).await? {
     Some(article) => {
       if cfg.allow_comments {
-        match db.comment.store(&auth, article.id, &req.comment).await? {
-          Some(comment_id) => {
+        match db.comment.store(&auth, article.id, &req.comment, cfg.comment_min_interval_secs, cfg.newbie_comments).await? {
+          StoreComment::Stored(comment_id) => {
             match db.comment.get_comment_by_id(&auth, comment_id).await? {
               Some(comment) => {
                 Ok(HttpResponse::Ok().json(CommentOut {
@@ -208,11 +817,14 @@ async fn store_comment(
               }
             }
           },
-          None => {
-            Ok(HttpResponse::InternalServerError().json(json!({
-              "error": "Failed to add comment.",
-            })))
-          }
+          StoreComment::TooSoon(wait) => {
+            Ok(HttpResponse::TooManyRequests()
+              .header("Retry-After", wait.to_string())
+              .json(json!({
+                "error": "Commenting too fast, please wait before commenting again.",
+              })))
+          },
+          StoreComment::NewbieLimited => Ok(newbie_limit_reached()),
         }
       } else {
         Ok(HttpResponse::Forbidden().json(json!({
@@ -220,11 +832,7 @@ async fn store_comment(
         })))
       }
     },
-    None => {
-      Ok(HttpResponse::NotFound().json(json!({
-        "error": "Comment not found",
-      })))
-    }
+    None => article_not_found(&db, None, &slug).await,
   }
 }
 
@@ -261,99 +869,1497 @@ async fn delete_comment(
 /// favorite article
 #[post("/articles/{slug}/favorite", wrap="Auth::required()")]
 async fn favorite(
+  http_req: HttpRequest,
   auth: AuthData,
+  cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   slug: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-  match db.article.get_by_slug(&auth, &slug).await? {
+  match find_article(&cfg, &db, &auth, &slug).await? {
     Some(mut article) => {
-      // Check if the current user has already favorited the article
-      if !article.favorited {
-        // mark article as favorited by the current user
-        db.article.favorite(&auth, article.id).await?;
-        article.favorited = true;
+      // mark article as favorited by the current user
+      let changed = if cfg.favorite_auto_follows {
+        if article.author.user_id != auth.user_id {
+          article.author.following = true;
+        }
+        db.article.favorite_and_follow(&auth, article.id).await?
+      } else {
+        db.article.favorite(&auth, article.id).await?
+      } > 0;
+      let mut res = HttpResponse::Ok();
+      if changed {
         article.favorites_count += 1;
+      } else {
+        // Already favorited by the current user.
+        res.header(NO_CHANGE_HEADER, "true");
       }
-      Ok(HttpResponse::Ok().json(ArticleOut::<ArticleDetails> {
+      article.favorited = true;
+      urls.set_article(&http_req, &mut article);
+      Ok(res.json(ArticleOut::<ArticleDetails> {
         article,
       }))
     },
-    None => {
-      Ok(HttpResponse::NotFound().json(json!({
-        "error": "Article not found",
-      })))
-    }
+    None => article_not_found(&db, None, &slug).await,
   }
 }
 
 /// unfavorite article
 #[delete("/articles/{slug}/favorite", wrap="Auth::required()")]
 async fn unfavorite(
+  http_req: HttpRequest,
   auth: AuthData,
+  cfg: web::Data<ArticleService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   slug: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-  match db.article.get_by_slug(&auth, &slug).await? {
+  match find_article(&cfg, &db, &auth, &slug).await? {
     Some(mut article) => {
-      // Check if the current user has already favorited the article
-      if article.favorited {
-        // mark article as unfavorited by the current user
-        db.article.unfavorite(&auth, article.id).await?;
-        article.favorited = false;
+      // mark article as unfavorited by the current user
+      let mut res = HttpResponse::Ok();
+      if db.article.unfavorite(&auth, article.id).await? > 0 {
         article.favorites_count -= 1;
+      } else {
+        // The current user hadn't favorited the article.
+        res.header(NO_CHANGE_HEADER, "true");
       }
-      Ok(HttpResponse::Ok().json(ArticleOut::<ArticleDetails> {
+      article.favorited = false;
+      urls.set_article(&http_req, &mut article);
+      Ok(res.json(ArticleOut::<ArticleDetails> {
         article,
       }))
     },
-    None => {
-      Ok(HttpResponse::NotFound().json(json!({
-        "error": "Article not found",
-      })))
-    }
+    None => article_not_found(&db, None, &slug).await,
+  }
+}
+
+/// set the current user's favorite state of an article.
+///
+/// Idempotent, the article is reloaded so `favoritesCount` includes
+/// concurrent changes.
+#[put("/articles/{slug}/favorite", wrap="Auth::required()")]
+async fn set_favorite(
+  http_req: HttpRequest,
+  auth: AuthData,
+  cfg: web::Data<ArticleService>,
+  db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
+  slug: web::Path<String>,
+  req: web::Json<SetFavorite>,
+) -> Result<HttpResponse, Error> {
+  let article = match find_article(&cfg, &db, &auth, &slug).await? {
+    Some(article) => article,
+    None => return article_not_found(&db, None, &slug).await,
+  };
+  let changed = if !req.favorited {
+    db.article.unfavorite(&auth, article.id).await?
+  } else if cfg.favorite_auto_follows {
+    db.article.favorite_and_follow(&auth, article.id).await?
+  } else {
+    db.article.favorite(&auth, article.id).await?
+  } > 0;
+  let mut res = HttpResponse::Ok();
+  if !changed {
+    // Already in the requested state.
+    res.header(NO_CHANGE_HEADER, "true");
+  }
+  match db.article.get_by_id(&auth, article.id).await? {
+    Some(mut article) => {
+      urls.set_article(&http_req, &mut article);
+      Ok(res.json(ArticleOut::<ArticleDetails> {
+        article,
+      }))
+    },
+    None => article_not_found(&db, None, &slug).await,
   }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct ArticleService {
+  /// Only register the read endpoints (`<prefix>.read_only`).
+  pub read_only: bool,
+
   pub allow_update: bool,
   pub allow_delete: bool,
 
   pub allow_comments: bool,
+
+  pub allow_feed: bool,
+
+  /// Show the global list when the user's feed is empty.
+  pub feed_fallback_to_global: bool,
+
+  /// Reject unfiltered article lists.
+  pub list_requires_filter: bool,
+
+  /// Maximum length of the `tag` filter (0 = unlimited).
+  pub max_filter_tag_len: usize,
+
+  pub favorite_auto_follows: bool,
+
+  /// Maximum number of articles an author can store (0 = unlimited).
+  /// All of the author's stored articles count towards the limit.
+  pub max_per_author: i64,
+
+  pub allow_export: bool,
+
+  pub slug_scope: SlugScope,
+
+  /// 404 without a DB query for slugs `title_slug` can't make.
+  pub check_read_slugs: bool,
+
+  /// Longest slug of new titles, and accepted by `check_read_slugs` (0 = unlimited).
+  pub max_slug_len: usize,
+
+  /// Articles must have at least one (non-empty) tag.
+  pub require_tags: bool,
+
+  /// Maximum number of tags per article (0 = unlimited).
+  pub max_tags: usize,
+
+  /// Articles must have a (non-blank) description.
+  pub require_description: bool,
+
+  /// Maximum characters of the description (0 = unlimited).
+  pub max_description_len: usize,
+
+  /// An author can't have two articles with the same title.
+  pub unique_title_per_author: bool,
+
+  /// Add non-fatal content warnings to create/update responses.
+  pub emit_warnings: bool,
+
+  /// Bodies shorter than this many characters get a warning.
+  pub warn_body_len: usize,
+
+  /// Number of previous versions kept per article (0 = no history).
+  pub max_revisions: i64,
+
+  /// Share one query between concurrent identical `get_article` requests.
+  pub coalesce_reads: bool,
+
+  /// Minimum seconds between a user's comments on the same article (0 = no limit).
+  pub comment_min_interval_secs: i64,
+
+  /// Articles/comments per hour for new accounts (`User.newbie_*`).
+  pub newbie_articles: NewbieLimit,
+  pub newbie_comments: NewbieLimit,
+}
+
+impl ArticleService {
+  /// Limits checked when storing an article.
+  pub fn limits(&self) -> ArticleLimits {
+    ArticleLimits {
+      max_per_author: self.max_per_author,
+      newbie: self.newbie_articles,
+      unique_title: self.unique_title_per_author,
+    }
+  }
 }
 
 impl super::Service for ArticleService {
-  fn load_app_config(&mut self, config: &AppConfig, _prefix: &str) -> Result<()> {
+  fn load_app_config(&mut self, config: &AppConfig, prefix: &str) -> Result<()> {
+    self.read_only = super::read_only(config, prefix)?;
     self.allow_update = config.get_bool("Article.allow_update")?.unwrap_or(false);
     self.allow_delete = config.get_bool("Article.allow_delete")?.unwrap_or(false);
 
     self.allow_comments = config.get_bool("Article.allow_comments")?.unwrap_or(false);
+
+    // The feed is enabled unless explicitly disabled.
+    self.allow_feed = config.get_bool("Article.allow_feed")?.unwrap_or(true);
+    self.feed_fallback_to_global = config.get_bool("Article.feed_fallback_to_global")?.unwrap_or(false);
+
+    self.list_requires_filter = config.get_bool("Article.list_requires_filter")?.unwrap_or(false);
+    if self.feed_fallback_to_global && self.list_requires_filter {
+      // the fallback is an unfiltered list.
+      return Err(config::ConfigError::Message(
+        "Article.feed_fallback_to_global can't be used with Article.list_requires_filter".to_string()).into());
+    }
+
+    self.max_filter_tag_len = config.get_int("Article.max_filter_tag_len")?
+      .unwrap_or(DEFAULT_MAX_FILTER_TAG_LEN).max(0) as usize;
+
+    self.favorite_auto_follows = config.get_bool("Article.favorite_auto_follows")?.unwrap_or(false);
+
+    self.max_per_author = config.get_int("Article.max_per_author")?.unwrap_or(0);
+
+    self.allow_export = config.get_bool("Article.allow_export")?.unwrap_or(false);
+
+    self.max_revisions = config.get_int("Article.max_revisions")?.unwrap_or(0);
+
+    self.require_tags = config.get_bool("Article.require_tags")?.unwrap_or(false);
+    self.require_description = config.get_bool("Article.require_description")?.unwrap_or(true);
+    self.max_description_len = config.get_int("Article.max_description_len")?.unwrap_or(0).max(0) as usize;
+
+    self.max_tags = config.get_int("Article.max_tags")?.unwrap_or(0).max(0) as usize;
+    self.unique_title_per_author = config.get_bool("Article.unique_title_per_author")?.unwrap_or(false);
+    self.emit_warnings = config.get_bool("Article.emit_warnings")?.unwrap_or(false);
+    self.warn_body_len = config.get_int("Article.warn_body_len")?.unwrap_or(200).max(0) as usize;
+
+    self.coalesce_reads = config.get_bool("Article.coalesce_reads")?.unwrap_or(false);
+
+    self.comment_min_interval_secs = config.get_int("Article.comment_min_interval_secs")?.unwrap_or(0);
+
+    // The trust ramp is configured with the accounts.
+    let period_hours = config.get_int("User.newbie_period_hours")?.unwrap_or(0);
+    let period_hours = i32::try_from(period_hours).map_err(|_| config::ConfigError::Message(
+      format!("User.newbie_period_hours is out of range, got {}", period_hours)
+    ))?;
+    self.newbie_articles = NewbieLimit {
+      period_hours,
+      per_hour: config.get_int("User.newbie_articles_per_hour")?.unwrap_or(DEFAULT_NEWBIE_ARTICLES_PER_HOUR),
+    };
+    self.newbie_comments = NewbieLimit {
+      period_hours,
+      per_hour: config.get_int("User.newbie_comments_per_hour")?.unwrap_or(DEFAULT_NEWBIE_COMMENTS_PER_HOUR),
+    };
+
+    self.check_read_slugs = config.get_bool("Article.check_read_slugs")?.unwrap_or(false);
+    self.max_slug_len = config.get_int("Article.max_slug_len")?.unwrap_or(0).max(0) as usize;
+
+    if let Some(scope) = config.get_str("Article.slug_scope")? {
+      self.slug_scope = scope.parse()?;
+    }
     Ok(())
   }
 
   fn api_config(&self, web: &mut web::ServiceConfig) {
     web
       .data(self.clone())
+      // per-worker, the DB clients aren't shared between workers.
+      .data(InflightArticles::default())
       .service(list)
       .service(feed)
+      .service(feed_unread_count)
+      .service(user_favorites)
+      .service(export_articles)
+      .service(slug_preview)
 
       // Article get/create/update/delete
+      // (before the `/articles/{slug}/*` routes, slugs never start with '@')
+      .service(get_author_article)
       .service(get_article)
-      .service(store_article)
-      .service(update_article)
-      .service(delete_article)
+      .service(article_history)
 
       // Article comments
-      .service(get_comments)
-      .service(store_comment)
-      .service(delete_comment)
+      .service(get_comments);
+
+    if !self.read_only {
+      web
+        // Article create/update/delete
+        .service(store_article)
+        .service(update_article)
+        .service(patch_article)
+        .service(delete_article)
+
+        // Article comments
+        .service(store_comment)
+        .service(delete_comment)
+
+        // Article favorites
+        .service(favorite)
+        .service(set_favorite)
+        .service(unfavorite);
+    }
+  }
 
-      // Article favorites
-      .service(favorite)
-      .service(unfavorite);
+  fn routes(&self) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut routes: Vec<(&'static str, &'static [&'static str])> = vec![
+      ("/articles/feed", &["GET"]),
+      ("/articles/feed/unread-count", &["GET"]),
+      ("/user/favorites", &["GET"]),
+      ("/admin/articles/export", &["GET"]),
+      ("/articles/slug-preview", &["GET"]),
+      ("/articles/@{author}/{slug}", &["GET", "HEAD"]),
+      ("/articles/{slug}/history", &["GET"]),
+    ];
+    if self.read_only {
+      routes.push(("/articles", &["GET"]));
+      routes.push(("/articles/{slug}", &["GET", "HEAD"]));
+      routes.push(("/articles/{slug}/comments", &["GET"]));
+    } else {
+      routes.push(("/articles", &["GET", "POST"]));
+      routes.push(("/articles/{slug}", &["GET", "HEAD", "PUT", "PATCH", "DELETE"]));
+      routes.push(("/articles/{slug}/comments", &["GET", "POST"]));
+      routes.push(("/articles/{slug}/comments/{id}", &["DELETE"]));
+      routes.push(("/articles/{slug}/favorite", &["POST", "PUT", "DELETE"]));
+    }
+    routes
   }
 }
 
 pub fn new_factory() -> ArticleService {
   Default::default()
 }
+
+#[cfg(test)]
+mod tests {
+  use actix_web::{test, App, ResponseError, dev::Service, http::{Method, StatusCode}};
+
+  use crate::app::AppConfig;
+  use crate::db::{test_db, test_suffix, test_article, VersionedStatement};
+  use crate::error::Error;
+  use crate::services::{test_services, test_login, test_request, NO_CHANGE_HEADER};
+
+  #[actix_rt::test]
+  async fn favorite_auto_follows() {
+    for auto_follow in &[false, true] {
+      let services = match test_services(&[("Article.favorite_auto_follows", (*auto_follow).into())]) {
+        Some(services) => services,
+        None => return,
+      };
+      let db = test_db().await.unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let suffix = test_suffix();
+      let (reader, reader_token) = test_login(&db, &format!("reader{}", suffix)).await;
+      let author_name = format!("author{}", suffix);
+      let (author, author_token) = test_login(&db, &author_name).await;
+      let id = test_article(&db, &author, &format!("Favorite {}", suffix)).await;
+      let slug = db.article.get_by_id(&author, id).await.unwrap().unwrap().slug;
+      let uri = format!("/articles/{}/favorite", slug);
+
+      let req = test_request(Method::POST, &uri, &reader_token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["article"]["favorited"], true);
+      assert_eq!(res["article"]["author"]["following"], *auto_follow);
+      let profile = db.user.get_profile(&reader, &author_name, false).await.unwrap().unwrap();
+      assert_eq!(profile.following, *auto_follow);
+
+      // never follow yourself.
+      let req = test_request(Method::POST, &uri, &author_token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["article"]["favorited"], true);
+      assert_eq!(res["article"]["author"]["following"], false);
+      let profile = db.user.get_profile(&author, &author_name, false).await.unwrap().unwrap();
+      assert!(!profile.following);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn tag_filter_is_bounded() {
+    let services = match test_services(&[("Article.max_filter_tag_len", 8.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    // (query, expected status), the length is in characters.
+    let cases = [
+      ("tag=eighttag", StatusCode::OK),
+      ("tag=%C3%A9%C3%A9%C3%A9%C3%A9%C3%A9%C3%A9%C3%A9%C3%A9", StatusCode::OK),
+      ("tag=ninetags9", StatusCode::UNPROCESSABLE_ENTITY),
+      ("tag=a&tag=b", StatusCode::BAD_REQUEST),
+    ];
+    for &(query, status) in &cases {
+      let req = test_request(Method::GET, &format!("/articles?{}", query), "").to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), status, "{}", query);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn head_matches_get_without_a_body() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    // a real server, the body of HEAD responses is dropped by the HTTP layer.
+    let srv = test::start(move || App::new().configure(|web| services.web_config(web)));
+    let suffix = test_suffix();
+    let (author, _token) = test_login(&db, &format!("head{}", suffix)).await;
+    let id = test_article(&db, &author, &format!("Head {}", suffix)).await;
+    let slug = db.article.get_by_id(&author, id).await.unwrap().unwrap().slug;
+
+    let uris = vec![
+      format!("/api/articles/{}", slug),
+      format!("/api/articles/missing-{}", suffix),
+      format!("/api/profiles/head{}", suffix),
+      format!("/api/profiles/missing{}", suffix),
+    ];
+    for uri in uris {
+      let mut get = srv.request(Method::GET, srv.url(&uri)).send().await.unwrap();
+      let mut head = srv.request(Method::HEAD, srv.url(&uri)).send().await.unwrap();
+      assert_eq!(head.status(), get.status(), "{}", uri);
+      for name in &["content-type", "content-length"] {
+        assert_eq!(head.headers().get(*name), get.headers().get(*name), "{} {}", uri, name);
+      }
+      assert!(!get.body().await.unwrap().is_empty());
+      assert!(head.body().await.unwrap().is_empty(), "{}", uri);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn slug_preview_matches_the_stored_slug() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_auth, token) = test_login(&db, &format!("preview{}", suffix)).await;
+    // private use characters only (no usable characters), the slug is generated.
+    let generated = suffix.chars()
+      .filter_map(|c| c.to_digit(16))
+      .filter_map(|digit| std::char::from_u32(0xe000 + digit))
+      .collect::<String>();
+    for title in &[format!("Привет {}", suffix), generated] {
+      let encoded = percent_encoding::utf8_percent_encode(title, percent_encoding::NON_ALPHANUMERIC);
+      let uri = format!("/articles/slug-preview?title={}", encoded);
+      // anonymous users aren't told if the slug is used.
+      let req = test_request(Method::GET, &uri, "").to_request();
+      let anonymous: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert!(anonymous.get("available").is_none());
+      let req = test_request(Method::GET, &uri, &token).to_request();
+      let preview: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(preview["slug"], anonymous["slug"]);
+      assert_eq!(preview["available"], true);
+
+      let req = test_request(Method::POST, "/articles", &token)
+        .set_json(&json!({
+          "article": { "title": title, "description": "description", "body": "body", "tagList": [] },
+        }))
+        .to_request();
+      let stored: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(stored["article"]["slug"], preview["slug"]);
+
+      let req = test_request(Method::GET, &uri, &token).to_request();
+      let preview: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(preview["available"], false);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn reserved_slugs_round_trip() {
+    // per author slugs, so the fixed titles can be stored on every run.
+    let services = match test_services(&[("Article.slug_scope", "author".into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let (_auth, token) = test_login(&db, &format!("reserved{}", test_suffix())).await;
+    for (title, slug) in &[("Feed", "feed-article"), ("Slug preview", "slug-preview-article")] {
+      let req = test_request(Method::POST, "/articles", &token)
+        .set_json(&json!({
+          "article": { "title": title, "description": "description", "body": "body", "tagList": [] },
+        }))
+        .to_request();
+      let stored: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(stored["article"]["slug"], *slug);
+
+      let req = test_request(Method::GET, &format!("/articles/{}", slug), &token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["article"]["title"], *title);
+    }
+    // the fixed routes still answer.
+    let req = test_request(Method::GET, "/articles/feed", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert!(res["articles"].is_array());
+    let req = test_request(Method::GET, "/articles/slug-preview?title=Feed", "").to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["slug"], "feed-article");
+  }
+
+  #[actix_rt::test]
+  async fn history_for_the_author_and_admins() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let (author, author_token) = test_login(&db, &format!("historian{}", suffix)).await;
+    let (_, other_token) = test_login(&db, &format!("historianb{}", suffix)).await;
+    let (admin, admin_token) = test_login(&db, &format!("historianc{}", suffix)).await;
+    let services = test_services(&[
+      ("Article.allow_update", true.into()),
+      ("Article.max_revisions", 5.into()),
+      ("test.services", vec!["User", "Profile", "Article", "Tag", "Admin"].into()),
+      ("Admin.user_ids", vec![admin.user_id as i64].into()),
+    ]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let id = test_article(&db, &author, &format!("History {}", suffix)).await;
+    let slug = db.article.get_by_id(&author, id).await.unwrap().unwrap().slug;
+    let req = test_request(Method::PUT, &format!("/articles/{}", slug), &author_token)
+      .set_json(&json!({"article": {"body": "edited"}}))
+      .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+
+    for (token, status) in &[(&author_token, StatusCode::OK), (&other_token, StatusCode::FORBIDDEN), (&admin_token, StatusCode::OK)] {
+      let req = test_request(Method::GET, &format!("/articles/{}/history", slug), token).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), *status);
+      if *status == StatusCode::OK {
+        let res: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(res["revisions"][0]["body"], "body");
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn export_streams_all_articles_as_ndjson() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let author_name = format!("export{}", suffix);
+    let (author, token) = test_login(&db, &author_name).await;
+    test_article(&db, &author, &format!("Export {}", suffix)).await;
+
+    // disabled by default.
+    let req = test_request(Method::GET, "/admin/articles/export", &token).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), 403);
+
+    // only for admins.
+    let admin = vec!["User", "Profile", "Article", "Tag", "Admin"];
+    let services = test_services(&[
+      ("Article.allow_export", true.into()),
+      ("test.services", admin.clone().into()),
+    ]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let req = test_request(Method::GET, "/admin/articles/export", &token).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), 403);
+
+    let services = test_services(&[
+      ("Article.allow_export", true.into()),
+      ("test.services", admin.into()),
+      ("Admin.user_ids", vec![author.user_id as i64].into()),
+    ]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    // more than one page.
+    let mut titles = vec![format!("Export {}", suffix)];
+    for n in 0..super::EXPORT_PAGE_SIZE {
+      let title = format!("Export {} {}", suffix, n);
+      test_article(&db, &author, &title).await;
+      titles.push(title);
+    }
+    titles.reverse();
+
+    let req = test_request(Method::GET, "/admin/articles/export", &token).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get("content-type").unwrap(), "application/x-ndjson");
+    let body = test::read_body(res).await;
+    let articles: Vec<serde_json::Value> = std::str::from_utf8(&body).unwrap()
+      .lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect();
+    // newest first, each article once.
+    let exported: Vec<_> = articles.iter()
+      .filter(|a| a["author"]["username"] == author_name.as_str())
+      .map(|a| a["title"].as_str().unwrap())
+      .collect();
+    assert_eq!(exported, titles);
+
+    // the export is audited.
+    let req = crate::forms::AuditRequest { limit: Some(100), offset: None };
+    let entries = db.audit.get_entries(req).await.unwrap();
+    assert!(entries.iter().any(|e| e.actor == author_name && e.action == "export-articles"));
+  }
+
+  #[actix_rt::test]
+  async fn repeated_favorite_is_a_no_op() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_reader, token) = test_login(&db, &format!("fan{}", suffix)).await;
+    let (author, _token) = test_login(&db, &format!("star{}", suffix)).await;
+    let id = test_article(&db, &author, &format!("No change {}", suffix)).await;
+    let slug = db.article.get_by_id(&author, id).await.unwrap().unwrap().slug;
+    let uri = format!("/articles/{}/favorite", slug);
+
+    for (method, favorited, count) in &[(Method::POST, true, 1), (Method::DELETE, false, 0)] {
+      for no_change in &[false, true] {
+        let req = test_request(method.clone(), &uri, &token).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().contains_key(NO_CHANGE_HEADER), *no_change, "{} {}", method, no_change);
+        let res: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(res["article"]["favorited"], *favorited);
+        assert_eq!(res["article"]["favoritesCount"], *count);
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn patch_returns_only_the_changes() {
+    let services = match test_services(&[("Article.allow_update", true.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (author, token) = test_login(&db, &format!("patch{}", suffix)).await;
+    let id = test_article(&db, &author, &format!("Patch {}", suffix)).await;
+    let slug = db.article.get_by_id(&author, id).await.unwrap().unwrap().slug;
+    let uri = format!("/articles/{}", slug);
+
+    let req = test_request(Method::PATCH, &uri, &token)
+      .set_json(&json!({"article": {"body": "new body"}}))
+      .to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let changes = res["article"].as_object().unwrap();
+    let mut fields: Vec<_> = changes.keys().map(|key| key.as_str()).collect();
+    fields.sort();
+    assert_eq!(fields, vec!["body", "slug", "updatedAt"]);
+    assert_eq!(changes["body"], "new body");
+    assert_eq!(changes["slug"], slug.as_str());
+
+    // PUT still returns the whole article.
+    let req = test_request(Method::PUT, &uri, &token)
+      .set_json(&json!({"article": {"description": "new description"}}))
+      .to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["article"]["body"], "new body");
+    assert_eq!(res["article"]["description"], "new description");
+  }
+
+  #[actix_rt::test]
+  async fn feed_can_be_disabled() {
+    for allow_feed in &[true, false] {
+      let services = match test_services(&[("Article.allow_feed", (*allow_feed).into())]) {
+        Some(services) => services,
+        None => return,
+      };
+      let db = test_db().await.unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let (_reader, token) = test_login(&db, &format!("feed{}", test_suffix())).await;
+
+      let req = test_request(Method::GET, "/articles/feed", &token).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), if *allow_feed { 200 } else { 404 });
+    }
+  }
+
+  #[actix_rt::test]
+  async fn user_favorites_with_the_total_count() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (reader, token) = test_login(&db, &format!("favs{}", suffix)).await;
+    let (author, _token) = test_login(&db, &format!("faved{}", suffix)).await;
+    let mut ids = vec![];
+    for n in 0..3 {
+      let id = test_article(&db, &author, &format!("Fav {} {}", suffix, n)).await;
+      ids.push(id);
+    }
+    // favorite the first and last.
+    db.article.favorite(&reader, ids[0]).await.unwrap();
+    db.article.favorite(&reader, ids[2]).await.unwrap();
+
+    let req = test_request(Method::GET, "/user/favorites?limit=1", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["articlesCount"], 2);
+    assert_eq!(res["articles"].as_array().unwrap().len(), 1);
+    assert_eq!(res["articles"][0]["title"], format!("Fav {} 2", suffix));
+    assert_eq!(res["articles"][0]["favorited"], true);
+
+    let req = test_request(Method::GET, "/user/favorites?limit=1&offset=1", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["articlesCount"], 2);
+    assert_eq!(res["articles"][0]["title"], format!("Fav {} 0", suffix));
+
+    // the rich envelope has the exact `hasMore` from the total.
+    crate::forms::set_rich_pagination(true);
+    for (offset, has_more) in &[(0, true), (1, false)] {
+      let uri = format!("/user/favorites?limit=1&offset={}", offset);
+      let req = test_request(Method::GET, &uri, &token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["data"].as_array().unwrap().len(), 1);
+      assert_eq!(res["meta"], json!({"total": 2, "limit": 1, "offset": offset, "hasMore": has_more}));
+    }
+    crate::forms::set_rich_pagination(false);
+
+    let req = test_request(Method::GET, "/user/favorites", "").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), 401);
+  }
+
+  #[actix_rt::test]
+  async fn slug_scopes() {
+    for scope in &["global", "author"] {
+      let services = match test_services(&[("Article.slug_scope", (*scope).into())]) {
+        Some(services) => services,
+        None => return,
+      };
+      let db = test_db().await.unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let suffix = test_suffix();
+      let first_name = format!("first{}", suffix);
+      let (_first, first_token) = test_login(&db, &first_name).await;
+      let second_name = format!("second{}", suffix);
+      let (_second, second_token) = test_login(&db, &second_name).await;
+      let article = json!({"article": {
+        "title": format!("Same {} {}", scope, suffix),
+        "description": "description",
+        "body": "body",
+        "tagList": [],
+      }});
+
+      let req = test_request(Method::POST, "/articles", &first_token).set_json(&article).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      let slug = res["article"]["slug"].as_str().unwrap().to_string();
+      let req = test_request(Method::POST, "/articles", &second_token).set_json(&article).to_request();
+      let res = test::call_service(&mut app, req).await;
+      if *scope == "global" {
+        assert_eq!(res.status(), 422);
+        continue;
+      }
+      assert_eq!(res.status(), 200);
+      let res: serde_json::Value = test::read_body_json(res).await;
+      assert_eq!(res["article"]["slug"], slug.as_str());
+
+      for name in &[&first_name, &second_name] {
+        let req = test_request(Method::GET, &format!("/articles/@{}/{}", name, slug), "").to_request();
+        let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(res["article"]["author"]["username"], name.as_str());
+      }
+      // the slug alone is ambiguous, except for the authors themselves.
+      let req = test_request(Method::GET, &format!("/articles/{}", slug), "").to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), 404);
+      let req = test_request(Method::GET, &format!("/articles/{}", slug), &second_token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["article"]["author"]["username"], second_name.as_str());
+      // still unique per author.
+      let req = test_request(Method::POST, "/articles", &second_token).set_json(&article).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), 422);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn list_requires_a_filter() {
+    for requires_filter in &[false, true] {
+      let services = match test_services(&[("Article.list_requires_filter", (*requires_filter).into())]) {
+        Some(services) => services,
+        None => return,
+      };
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let get = |uri: &str| test_request(Method::GET, uri, "").to_request();
+
+      let res = test::call_service(&mut app, get("/articles?limit=1")).await;
+      assert_eq!(res.status(), if *requires_filter { 400 } else { 200 });
+      for uri in &["/articles?tag=rust", "/articles?author=nobody", "/articles?favorited=nobody"] {
+        let res = test::call_service(&mut app, get(uri)).await;
+        assert_eq!(res.status(), 200, "{}", uri);
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn required_tags() {
+    let services = match test_services(&[
+      ("Article.allow_update", true.into()),
+      ("Article.require_tags", true.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_, token) = test_login(&db, &format!("tagged{}", suffix)).await;
+    let article = |tags: serde_json::Value| json!({"article": {
+      "title": format!("Tagged {}", suffix), "description": "description", "body": "body", "tagList": tags,
+    }});
+
+    // blank tags don't count.
+    for tags in &[json!([]), json!(["", "  "])] {
+      let req = test_request(Method::POST, "/articles", &token).set_json(&article(tags.clone())).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), 422, "{}", tags);
+    }
+    let req = test_request(Method::POST, "/articles", &token).set_json(&article(json!([" rust "]))).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["article"]["tagList"], json!(["rust"]));
+
+    // updates can't remove all tags, but can leave them unchanged.
+    let uri = format!("/articles/{}", res["article"]["slug"].as_str().unwrap());
+    let req = test_request(Method::PUT, &uri, &token)
+      .set_json(&json!({"article": {"tagList": [" "]}}))
+      .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 422);
+    let req = test_request(Method::PUT, &uri, &token)
+      .set_json(&json!({"article": {"body": "new body"}}))
+      .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+  }
+
+  #[actix_rt::test]
+  async fn rich_pagination_counts_the_total() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let name = format!("paged{}", suffix);
+    let (author, _) = test_login(&db, &name).await;
+    let (reader, token) = test_login(&db, &format!("pagereader{}", suffix)).await;
+    db.user.follow(&reader, author.user_id).await.unwrap();
+    for n in 0..3 {
+      crate::db::test_article(&db, &author, &format!("Paged {} {}", n, suffix)).await;
+    }
+
+    for uri in &[format!("/articles?author={}&limit=2", name), "/articles/feed?limit=2".to_string()] {
+      // the RealWorld shape isn't counted.
+      let req = test_request(Method::GET, uri, &token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["articles"].as_array().unwrap().len(), 2, "{}", uri);
+      assert_eq!(res["articlesCount"], 2, "{}", uri);
+
+      crate::forms::set_rich_pagination(true);
+      let req = test_request(Method::GET, uri, &token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      crate::forms::set_rich_pagination(false);
+      assert_eq!(res["data"].as_array().unwrap().len(), 2, "{}", uri);
+      assert_eq!(res["meta"], json!({"total": 3, "limit": 2, "offset": 0, "hasMore": true}), "{}", uri);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn tag_count_is_bounded() {
+    let services = match test_services(&[
+      ("Article.allow_update", true.into()),
+      ("Article.max_tags", 2.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_, token) = test_login(&db, &format!("maxtags{}", suffix)).await;
+    let article = |tags: serde_json::Value| json!({"article": {
+      "title": format!("Max tags {}", suffix), "description": "description", "body": "body", "tagList": tags,
+    }});
+
+    let req = test_request(Method::POST, "/articles", &token)
+      .set_json(&article(json!(["a", "b", "c"]))).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 422);
+    // blank and duplicate tags don't count.
+    let req = test_request(Method::POST, "/articles", &token)
+      .set_json(&article(json!(["a", " ", "b", "a "]))).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["article"]["tagList"], json!(["a", "b"]));
+
+    let uri = format!("/articles/{}", res["article"]["slug"].as_str().unwrap());
+    for method in vec![Method::PUT, Method::PATCH] {
+      let req = test_request(method.clone(), &uri, &token)
+        .set_json(&json!({"article": {"tagList": ["a", "b", "c"]}}))
+        .to_request();
+      assert_eq!(test::call_service(&mut app, req).await.status(), 422, "{}", method);
+    }
+    let req = test_request(Method::PUT, &uri, &token)
+      .set_json(&json!({"article": {"tagList": ["c", "d"]}}))
+      .to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["article"]["tagList"], json!(["c", "d"]));
+  }
+
+  #[actix_rt::test]
+  async fn empty_feed_fallback() {
+    for fallback in &[false, true] {
+      let services = match test_services(&[("Article.feed_fallback_to_global", (*fallback).into())]) {
+        Some(services) => services,
+        None => return,
+      };
+      let db = test_db().await.unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let name = format!("lonely{}", test_suffix());
+      let (auth, token) = test_login(&db, &name).await;
+      test_article(&db, &auth, &format!("Global {}", name)).await;
+
+      let req = test_request(Method::GET, "/articles/feed?limit=5", &token).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), StatusCode::OK);
+      let header = res.headers().get(super::FEED_FALLBACK_HEADER).cloned();
+      let body: serde_json::Value = test::read_body_json(res).await;
+      let articles = body["articles"].as_array().unwrap();
+      if *fallback {
+        assert_eq!(header.unwrap(), "global");
+        assert!(!articles.is_empty() && articles.len() <= 5);
+        assert!(body["articlesCount"].as_u64().unwrap() >= articles.len() as u64);
+      } else {
+        assert!(header.is_none());
+        assert!(articles.is_empty());
+        assert_eq!(body["articlesCount"], 0);
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn impossible_slugs_skip_the_db() {
+    // the DB is down, only requests that don't query it get a 404.
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("db.url", "host=/nonexistent-rwtest port=1 user=test").unwrap();
+    config.conf.set("test.services", vec!["Article"]).unwrap();
+    config.conf.set("auth.jwt_secret", crate::services::TEST_JWT_SECRET).unwrap();
+    config.conf.set("Article.check_read_slugs", true).unwrap();
+    config.conf.set("Article.max_slug_len", 16).unwrap();
+    let services = crate::services::config_services(&config, "test").unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+
+    for slug in &["Hello-World", "hello--world", "hello_world", "a-slug-longer-than-16"] {
+      let req = test_request(Method::GET, &format!("/articles/{}", slug), "").to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", slug);
+    }
+    // a possible slug is looked up.
+    let req = test_request(Method::GET, "/articles/hello-world", "").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+  }
+
+  #[actix_rt::test]
+  async fn titles_fit_the_max_slug_len() {
+    let services = match test_services(&[
+      ("Article.check_read_slugs", true.into()),
+      ("Article.max_slug_len", 40.into()),
+      ("Article.allow_update", true.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_auth, token) = test_login(&db, &format!("slugged{}", suffix)).await;
+    let long_title = format!("Long {} {}", suffix, "word ".repeat(10));
+    let article = |title: &str| json!({"article": {
+      "title": title, "description": "description", "body": "body", "tagList": [],
+    }});
+
+    let req = test_request(Method::POST, "/articles", &token).set_json(&article(&long_title)).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), 422);
+    let res: serde_json::Value = test::read_body_json(res).await;
+    assert!(res["errors"]["title"].is_array());
+
+    // the stored slug can be read with the check on.
+    let title = format!("Short {}", suffix);
+    let req = test_request(Method::POST, "/articles", &token).set_json(&article(&title)).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let uri = format!("/articles/{}", res["article"]["slug"].as_str().unwrap());
+    let req = test_request(Method::GET, &uri, "").to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["article"]["title"], title.as_str());
+
+    for method in vec![Method::PUT, Method::PATCH] {
+      let req = test_request(method.clone(), &uri, &token)
+        .set_json(&json!({"article": {"title": long_title}}))
+        .to_request();
+      assert_eq!(test::call_service(&mut app, req).await.status(), 422, "{}", method);
+    }
+    let req = test_request(Method::GET, &uri, "").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+  }
+
+  #[actix_rt::test]
+  async fn tagless_articles_are_saved_with_warnings() {
+    let services = match test_services(&[
+      ("Article.emit_warnings", true.into()),
+      ("Article.warn_body_len", 10.into()),
+      ("Article.allow_update", true.into()),
+      // blank descriptions only get a warning.
+      ("Article.require_description", false.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("warned{}", test_suffix());
+    let (_auth, token) = test_login(&db, &name).await;
+    let store = |title: String, tags: Vec<&str>| {
+      test_request(Method::POST, "/articles", &token)
+        .set_json(&serde_json::json!({
+          "article": { "title": title, "description": "description", "body": "a long enough body", "tagList": tags },
+        }))
+        .to_request()
+    };
+
+    let req = store(format!("Tagless {}", name), vec![]);
+    let saved: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(saved["warnings"], serde_json::json!(["No tags, the article will be harder to discover."]));
+    let slug = saved["article"]["slug"].as_str().unwrap().to_string();
+    assert!(db.article.get_by_slug(&Default::default(), &slug).await.unwrap().is_some());
+
+    // updates are checked too, the article is still saved.
+    for method in &[Method::PUT, Method::PATCH] {
+      let req = test_request(method.clone(), &format!("/articles/{}", slug), &token)
+        .set_json(&serde_json::json!({
+          "article": { "description": "", "body": "short" },
+        }))
+        .to_request();
+      let saved: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(saved["warnings"], serde_json::json!([
+        "No tags, the article will be harder to discover.",
+        "No description.",
+        "Body is shorter than 10 characters.",
+      ]), "{}", method);
+    }
+    let article = db.article.get_by_slug(&Default::default(), &slug).await.unwrap().unwrap();
+    assert_eq!(article.body, "short");
+
+    // no warnings, no field.
+    let req = store(format!("Tagged {}", name), vec!["tagged"]);
+    let saved: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert!(saved.get("warnings").is_none(), "{}", saved);
+    assert_eq!(saved["article"]["tagList"], serde_json::json!(["tagged"]));
+
+    // off by default.
+    let services = test_services(&[]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let req = store(format!("Quiet {}", name), vec![]);
+    let saved: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert!(saved.get("warnings").is_none(), "{}", saved);
+  }
+
+  #[test]
+  fn descriptions_are_trimmed_and_checked() {
+    let mut cfg = super::ArticleService {
+      require_description: true,
+      max_description_len: 5,
+      ..Default::default()
+    };
+    let check = |cfg: &super::ArticleService, description: &str| {
+      let mut description = description.to_string();
+      match super::check_description(cfg, &mut description) {
+        Ok(()) => Ok(description),
+        Err(Error::UnprocessableEntity(body)) => Err(body["errors"]["description"][0].as_str().unwrap().to_string()),
+        Err(err) => panic!("unexpected error: {:?}", err),
+      }
+    };
+    assert_eq!(check(&cfg, "  short\n").unwrap(), "short");
+    assert_eq!(check(&cfg, "ééééé").unwrap(), "ééééé");
+    assert_eq!(check(&cfg, "").unwrap_err(), "can't be blank");
+    assert_eq!(check(&cfg, " \t ").unwrap_err(), "can't be blank");
+    assert_eq!(check(&cfg, "longer").unwrap_err(), "is too long (maximum is 5 characters)");
+
+    cfg.require_description = false;
+    cfg.max_description_len = 0;
+    assert_eq!(check(&cfg, "  ").unwrap(), "");
+    assert_eq!(check(&cfg, &"x".repeat(10_000)).unwrap().len(), 10_000);
+  }
+
+  #[actix_rt::test]
+  async fn description_is_required_by_default() {
+    // (config, blank descriptions are accepted)
+    let cases: Vec<(Vec<(&str, ::config::Value)>, bool)> = vec![
+      (vec![], false),
+      (vec![("Article.require_description", true.into())], false),
+      (vec![("Article.require_description", false.into())], true),
+    ];
+    for (mut config, blank_ok) in cases {
+      config.push(("Article.max_description_len", 10.into()));
+      let services = match test_services(&config) {
+        Some(services) => services,
+        None => return,
+      };
+      let db = test_db().await.unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let name = format!("described{}", test_suffix());
+      let (_auth, token) = test_login(&db, &name).await;
+      let mut store = |n: usize, description: Option<&str>| {
+        let mut article = json!({"title": format!("Described {} {}", name, n), "body": "body", "tagList": []});
+        if let Some(description) = description {
+          article["description"] = description.into();
+        }
+        let req = test_request(Method::POST, "/articles", &token)
+          .set_json(&json!({"article": article}))
+          .to_request();
+        let res = app.call(req);
+        async move {
+          let res = res.await.unwrap();
+          let status = res.status();
+          (status, test::read_body(res).await)
+        }
+      };
+
+      // the field is always required.
+      let (status, _) = store(0, None).await;
+      assert_eq!(status, StatusCode::BAD_REQUEST);
+      for (n, description) in vec!["", "  "].into_iter().enumerate() {
+        let (status, body) = store(n + 1, Some(description)).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        if blank_ok {
+          assert_eq!(status, StatusCode::OK, "{:?}", description);
+          assert_eq!(body["article"]["description"], "");
+        } else {
+          assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{:?}", description);
+          assert_eq!(body["errors"]["description"], json!(["can't be blank"]));
+        }
+      }
+      // trimmed, then at most `max_description_len` characters.
+      let (status, body) = store(3, Some(" 1234567890 ")).await;
+      let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+      assert_eq!(status, StatusCode::OK);
+      assert_eq!(body["article"]["description"], "1234567890");
+      let (status, body) = store(4, Some("12345678901")).await;
+      let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+      assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+      assert_eq!(body["errors"]["description"], json!(["is too long (maximum is 10 characters)"]));
+    }
+  }
+
+  #[test]
+  fn feed_fallback_needs_unfiltered_lists() {
+    use crate::services::Service;
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("Article.feed_fallback_to_global", true).unwrap();
+    assert!(super::ArticleService::default().load_app_config(&config, "test").is_ok());
+    config.conf.set("Article.list_requires_filter", true).unwrap();
+    assert!(super::ArticleService::default().load_app_config(&config, "test").is_err());
+  }
+
+  #[actix_rt::test]
+  async fn duplicate_titles_are_rejected() {
+    let services = match test_services(&[("Article.unique_title_per_author", true.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("retitled{}", test_suffix());
+    let (_auth, token) = test_login(&db, &name).await;
+    let store = || {
+      test_request(Method::POST, "/articles", &token)
+        .set_json(&serde_json::json!({
+          "article": { "title": format!("Twice {}", name), "description": "description", "body": "body", "tagList": [] },
+        }))
+        .to_request()
+    };
+
+    assert_eq!(test::call_service(&mut app, store()).await.status(), StatusCode::OK);
+    let res = test::call_service(&mut app, store()).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(res).await).unwrap();
+    assert_eq!(body, serde_json::json!({
+      "errors": {"title": ["already used by another of your articles"]},
+    }));
+  }
+
+  #[actix_rt::test]
+  async fn unknown_sort_is_rejected() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let req = test_request(Method::GET, "/articles?sort=popular", "").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = serde_json::from_slice(&test::read_body(res).await).unwrap();
+    assert_eq!(body["error"], "Invalid 'sort', expected one of: newest, oldest, most_favorited");
+    let req = test_request(Method::GET, "/articles?sort=oldest&limit=1", "").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+  }
+
+  #[actix_rt::test]
+  async fn deleted_articles_are_gone() {
+    let services = match test_services(&[("Article.allow_delete", true.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("gone{}", test_suffix());
+    let (auth, token) = test_login(&db, &name).await;
+    let id = test_article(&db, &auth, &format!("Gone {}", name)).await;
+    let slug = db.article.get_by_id(&auth, id).await.unwrap().unwrap().slug;
+    let never = format!("never-{}", name);
+
+    let req = test_request(Method::DELETE, &format!("/articles/{}", slug), &token).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+
+    let requests = |slug: &str| vec![
+      (Method::GET, format!("/articles/{}", slug)),
+      (Method::GET, format!("/articles/@{}/{}", name, slug)),
+      (Method::GET, format!("/articles/{}/comments", slug)),
+      (Method::POST, format!("/articles/{}/favorite", slug)),
+      (Method::DELETE, format!("/articles/{}/favorite", slug)),
+    ];
+    for (method, uri) in requests(&slug) {
+      let req = test_request(method, &uri, &token).to_request();
+      assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::GONE, "{}", uri);
+    }
+    for (method, uri) in requests(&never) {
+      let req = test_request(method, &uri, &token).to_request();
+      let status = test::call_service(&mut app, req).await.status();
+      if uri.ends_with("/comments") {
+        // an article without comments.
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+      } else {
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn delete_dry_run_and_confirm() {
+    let services = match test_services(&[("Article.allow_delete", true.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("confirm{}", test_suffix());
+    let (auth, token) = test_login(&db, &name).await;
+    let (reader, _) = test_login(&db, &format!("{}r", name)).await;
+    let id = test_article(&db, &auth, &format!("Confirm {}", name)).await;
+    let slug = db.article.get_by_id(&auth, id).await.unwrap().unwrap().slug;
+    db.article.favorite(&reader, id).await.unwrap();
+    let dependents = json!({"commentsCount": 0, "favoritesCount": 1});
+    let delete = |query: &str| test_request(Method::DELETE, &format!("/articles/{}{}", slug, query), &token).to_request();
+
+    // a dry run only reports the dependents.
+    let res = test::call_service(&mut app, delete("?dry_run=true")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(res["dependents"], dependents);
+    assert!(db.article.get_by_id(&auth, id).await.unwrap().is_some());
+
+    // a wrong confirm count is rejected.
+    let res = test::call_service(&mut app, delete("?confirm=0")).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let res: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(res["dependents"], dependents);
+    assert!(db.article.get_by_id(&auth, id).await.unwrap().is_some());
+
+    let res = test::call_service(&mut app, delete("?confirm=1")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(db.article.get_by_id(&auth, id).await.unwrap().is_none());
+  }
+
+  #[test]
+  fn shared_error_keeps_status() {
+    let errors = vec![
+      Error::Unauthorized(json!({"error": "Token revoked"})),
+      Error::NotFound(json!({"error": "Article not found"})),
+      Error::Gone(json!({"error": "Article was deleted"})),
+      Error::UnsupportedMediaType(json!({"error": "Content-Type must be application/json"})),
+      Error::UnprocessableEntity(json!({"errors": {"slug": ["invalid"]}})),
+      Error::InternalServerError,
+      Error::BadRequest("bad".to_string()),
+      Error::DisconnectedError("Failed to connect to database".to_string()),
+      Error::RetryBudgetExhausted,
+      Error::PasswordError("code=1".to_string()),
+      Error::from(std::io::Error::new(std::io::ErrorKind::Other, "io")),
+      Error::from(anyhow::anyhow!("other")),
+    ];
+    for err in errors {
+      let res = err.error_response();
+      let shared = super::shared_error(&err).error_response();
+      assert_eq!(shared.status(), res.status(), "{:?}", err);
+      assert_eq!(format!("{:?}", shared.body().as_ref()), format!("{:?}", res.body().as_ref()));
+    }
+    let res = super::shared_error(&Error::DisconnectedError("down".to_string())).error_response();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    let res = super::shared_error(&Error::RetryBudgetExhausted).error_response();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let res = super::shared_error(&Error::Gone(json!({"error": "Article was deleted"}))).error_response();
+    assert_eq!(res.status(), StatusCode::GONE);
+  }
+
+  #[actix_rt::test]
+  async fn concurrent_reads_share_one_fetch() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    let inflight = super::InflightArticles::default();
+    let fetches = Rc::new(Cell::new(0));
+    let read = |slug: &str, user_id: i32| {
+      let fetches = fetches.clone();
+      super::coalesce(&inflight, (slug.to_string(), user_id), async move {
+        fetches.set(fetches.get() + 1);
+        actix_rt::time::delay_for(Duration::from_millis(20)).await;
+        Ok(None)
+      })
+    };
+
+    let results = futures::future::join_all((0..10).map(|_| read("hot", 1))).await;
+    assert_eq!(results.len(), 10);
+    assert!(results.iter().all(|res| matches!(res, Ok(None))));
+    assert_eq!(fetches.get(), 1);
+    assert!(inflight.fetches.borrow().is_empty());
+
+    // other users (and later reads) get their own fetch.
+    let (one, two) = futures::future::join(read("hot", 1), read("hot", 2)).await;
+    assert!(one.is_ok() && two.is_ok());
+    assert_eq!(fetches.get(), 3);
+  }
+
+  #[actix_rt::test]
+  async fn articles_include_urls() {
+    let services = match test_services(&[
+      ("api.include_urls", true.into()),
+      ("api.base_url", "https://conduit.example.com/".into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let name = format!("linked{}", suffix);
+    let (author, _) = test_login(&db, &name).await;
+    let id = test_article(&db, &author, &format!("Linked {}", suffix)).await;
+    let slug = db.article.get_by_id(&author, id).await.unwrap().unwrap().slug;
+
+    let req = test_request(Method::GET, &format!("/articles/{}", slug), "").to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["article"]["url"], format!("https://conduit.example.com/article/{}", slug));
+    assert_eq!(res["article"]["author"]["profileUrl"], format!("https://conduit.example.com/profile/{}", name));
+
+    let req = test_request(Method::GET, &format!("/articles?author={}", name), "").to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["articles"][0]["url"], format!("https://conduit.example.com/article/{}", slug));
+
+    // not included by default.
+    let services = test_services(&[]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let req = test_request(Method::GET, &format!("/articles/{}", slug), "").to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert!(res["article"].get("url").is_none());
+    assert!(res["article"]["author"].get("profileUrl").is_none());
+  }
+
+  #[actix_rt::test]
+  async fn quick_comments_are_throttled() {
+    let services = match test_services(&[
+      ("Article.allow_comments", true.into()),
+      ("Article.comment_min_interval_secs", 60.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (author, token) = test_login(&db, &format!("slowmode{}", suffix)).await;
+    let first = test_article(&db, &author, &format!("Slow mode {}", suffix)).await;
+    let first = db.article.get_by_id(&author, first).await.unwrap().unwrap().slug;
+    let other = test_article(&db, &author, &format!("Slow mode other {}", suffix)).await;
+    let other = db.article.get_by_id(&author, other).await.unwrap().unwrap().slug;
+    let comment = |slug: &str| {
+      test_request(Method::POST, &format!("/articles/{}/comments", slug), &token)
+        .set_json(&json!({"comment": {"body": "comment"}}))
+        .to_request()
+    };
+
+    assert_eq!(test::call_service(&mut app, comment(&first)).await.status(), 200);
+    let res = test::call_service(&mut app, comment(&first)).await;
+    assert_eq!(res.status(), 429);
+    let retry: i64 = res.headers().get("Retry-After").unwrap().to_str().unwrap().parse().unwrap();
+    assert!(retry > 0 && retry <= 60, "Retry-After: {}", retry);
+    // per article.
+    assert_eq!(test::call_service(&mut app, comment(&other)).await.status(), 200);
+  }
+
+  #[actix_rt::test]
+  async fn put_favorite_sets_the_state() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("putfav{}", test_suffix());
+    let (auth, token) = test_login(&db, &name).await;
+    let id = test_article(&db, &auth, &format!("Put favorite {}", name)).await;
+    let slug = db.article.get_by_id(&auth, id).await.unwrap().unwrap().slug;
+
+    // (favorited, expected count, changed)
+    for &(favorited, count, changed) in &[(true, 1, true), (true, 1, false), (false, 0, true), (false, 0, false)] {
+      let req = test_request(Method::PUT, &format!("/articles/{}/favorite", slug), &token)
+        .set_json(&json!({ "favorited": favorited }))
+        .to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), StatusCode::OK);
+      assert_eq!(res.headers().contains_key(NO_CHANGE_HEADER), !changed);
+      let body: serde_json::Value = test::read_body_json(res).await;
+      assert_eq!(body["article"]["favorited"], favorited);
+      assert_eq!(body["article"]["favoritesCount"], count);
+    }
+
+    let req = test_request(Method::PUT, &format!("/articles/missing-{}/favorite", name), &token)
+      .set_json(&json!({ "favorited": true }))
+      .to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[test]
+  fn newbie_period_must_fit() {
+    use crate::services::Service;
+    let mut config = crate::app::AppConfig { conf: ::config::Config::default() };
+    config.conf.set("User.newbie_period_hours", 24).unwrap();
+    let mut service = super::ArticleService::default();
+    service.load_app_config(&config, "test").unwrap();
+    assert_eq!(service.newbie_articles.period_hours, 24);
+    assert_eq!(service.newbie_articles.per_hour, 1);
+    assert_eq!(service.newbie_comments.per_hour, 10);
+    config.conf.set("User.newbie_period_hours", i64::from(i32::MAX) + 1).unwrap();
+    assert!(super::ArticleService::default().load_app_config(&config, "test").is_err());
+  }
+
+  #[actix_rt::test]
+  async fn new_accounts_have_a_lower_limit() {
+    let services = match test_services(&[
+      ("User.newbie_period_hours", 24.into()),
+      ("User.newbie_articles_per_hour", 1.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_, newbie) = test_login(&db, &format!("newbie{}", suffix)).await;
+    let (old_auth, old) = test_login(&db, &format!("oldtimer{}", suffix)).await;
+    VersionedStatement::new(db.shared_cl.clone(),
+      "UPDATE users SET created_at = created_at - interval '2 days' WHERE id = $1").unwrap()
+      .execute(&[&old_auth.user_id]).await.unwrap();
+
+    let post = |token: &str, title: &str| {
+      test_request(Method::POST, "/articles", token)
+        .set_json(&json!({"article": {
+          "title": format!("{} {}", title, suffix),
+          "description": "description",
+          "body": "body",
+          "tagList": [],
+        }}))
+        .to_request()
+    };
+    let res = test::call_service(&mut app, post(&newbie, "Newbie one")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&mut app, post(&newbie, "Newbie two")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers().get("Retry-After").unwrap(), "3600");
+
+    // older accounts aren't limited.
+    for title in &["Old one", "Old two"] {
+      let res = test::call_service(&mut app, post(&old, title)).await;
+      assert_eq!(res.status(), StatusCode::OK, "{}", title);
+    }
+  }
+}
@@ -2,16 +2,31 @@ use log::*;
 
 use std::collections::HashMap;
 
-use actix_web::{web};
+use actix_web::{web, dev::ResourceDef, http::Method, HttpRequest, HttpResponse};
 
 use crate::error::*;
 use crate::app::*;
-use crate::db::DbService;
+use crate::db::{DbService, DbConfig};
+use crate::middleware::TokenVersionCheck;
+use crate::auth::jwt::JwtKeys;
 
 mod user;
 mod profile;
 mod article;
 mod tag;
+mod admin;
+mod urls;
+
+pub use urls::ApiUrls;
+
+/// Set on favorite/follow responses when the request didn't change anything.
+pub const NO_CHANGE_HEADER: &str = "X-No-Change";
+
+/// Set on feed responses that fell back to the global list.
+pub const FEED_FALLBACK_HEADER: &str = "X-Feed-Fallback";
+
+/// Names accepted in `<prefix>.services`.
+pub const SERVICE_NAMES: &[&str] = &["User", "Profile", "Article", "Tag", "Admin"];
 
 type BoxService = Box<dyn Service>;
 
@@ -25,6 +40,66 @@ pub trait Service: ServiceClone + Send {
 
   fn api_config(&self, _web: &mut web::ServiceConfig) {
   }
+
+  /// Paths (relative to `/api`) and methods of the `api_config` routes,
+  /// used to answer `OPTIONS` requests.
+  fn routes(&self) -> Vec<(&'static str, &'static [&'static str])> {
+    Vec::new()
+  }
+}
+
+/// Methods allowed for each API route.
+#[derive(Clone, Default)]
+struct AllowedMethods {
+  routes: Vec<(ResourceDef, &'static [&'static str])>,
+}
+
+impl AllowedMethods {
+  fn new(services: &[BoxService]) -> Self {
+    let routes = services.iter().flat_map(|service| service.routes())
+      .map(|(path, methods)| (ResourceDef::new(format!("/api{}", path)), methods))
+      .collect();
+    Self { routes }
+  }
+
+  /// `Allow` header value for `path`, `None` if no route matches.
+  fn allow(&self, path: &str) -> Option<String> {
+    // Like actix, a path can match multiple routes (`/articles/feed`
+    // also matches `/articles/{slug}`).
+    let mut methods: Vec<&str> = Vec::new();
+    for (def, route_methods) in self.routes.iter() {
+      if def.is_match(path) {
+        for method in route_methods.iter() {
+          if !methods.contains(method) {
+            methods.push(method);
+          }
+        }
+      }
+    }
+    if methods.is_empty() {
+      return None;
+    }
+    methods.push("OPTIONS");
+    Some(methods.join(", "))
+  }
+}
+
+/// Default `/api` service, answers `OPTIONS` with the route's allowed methods.
+async fn api_default(req: HttpRequest, allowed: web::Data<AllowedMethods>) -> HttpResponse {
+  if req.method() == Method::OPTIONS {
+    if let Some(allow) = allowed.allow(req.path()) {
+      return HttpResponse::NoContent()
+        .header("Allow", allow)
+        .finish();
+    }
+  }
+  HttpResponse::NotFound().finish()
+}
+
+/// `<prefix>.read_only`: only register the read endpoints, for a server
+/// running against a read replica.
+fn read_only(config: &AppConfig, prefix: &str) -> Result<bool> {
+  Ok(config.get_bool(&format!("{}.read_only", prefix))?.unwrap_or(false))
 }
 
 pub trait ServiceClone {
@@ -48,7 +123,12 @@ impl Clone for BoxService {
 
 #[derive(Clone, Default)]
 pub struct Services {
-  db_url: String,
+  db: DbConfig,
+  token_version_check: TokenVersionCheck,
+  urls: ApiUrls,
+  /// Loaded by `load_app_config`.
+  jwt: Option<JwtKeys>,
+  allowed: AllowedMethods,
   services: Vec<BoxService>,
 }
 
@@ -63,9 +143,8 @@ impl Services {
       "Profile" => Box::new(profile::new_factory()),
       "Article" => Box::new(article::new_factory()),
       "Tag" => Box::new(tag::new_factory()),
-      _ => {
-        panic!("Unknown Service: {}", name);
-      },
+      "Admin" => Box::new(admin::new_factory()),
+      _ => return Err(unknown_service(name, prefix)),
     };
 
     service.load_app_config(&config, prefix)?;
@@ -75,31 +154,37 @@ impl Services {
   /// Load Service config from AppConfig.
   pub fn load_app_config(&mut self, config: &AppConfig, prefix: &str) -> Result<()> {
     // DB config
-    self.db_url = config.get_str("db.url")?.expect("db.url must be set");
+    self.db = DbConfig::from_config(config, prefix)?;
+    self.token_version_check = TokenVersionCheck(config.get_bool("auth.check_token_version")?.unwrap_or(false));
+    self.urls = ApiUrls::from_config(config)?;
+    self.jwt = Some(JwtKeys::from_config(config)?);
 
-    let mut loaded: HashMap<String, bool> = HashMap::new();
-    let list = config.get_array(&format!("{}.services", prefix))?
-      .expect("missing list of services.");
-    for name in list.iter() {
-      let name = name.clone().into_str()?;
+    for name in service_names(config, prefix)? {
       info!("Loading {}Service config", name);
-      // check if it is loaded already.
-      if let Some(_) = loaded.get(&name) {
-        panic!("can't load service multiple times.")
-      }
-      loaded.insert(name.clone(), true);
-      // load service
       let service = self.load_service(&name, config, prefix)?;
       self.services.push(service);
     }
+    self.allowed = AllowedMethods::new(&self.services);
     Ok(())
   }
 
   /// Setup Service endpoints.
   pub fn web_config(&self, web: &mut web::ServiceConfig) {
     // Create DbService for worker.
-    let db = DbService::new(&self.db_url).expect("Failed to init db.");
+    let db = DbService::new(&self.db).expect("Failed to init db.");
+    if !self.db.prewarm_statements.is_empty() {
+      let db = db.clone();
+      actix_rt::spawn(async move {
+        if let Err(err) = db.prewarm().await {
+          error!("Failed to prewarm statements: {:?}", err);
+        }
+      });
+    }
     web.data(db);
+    web.data(self.token_version_check);
+    web.data(self.urls.clone());
+    web.data(self.jwt.clone().expect("JWT keys not loaded."));
+    web.data(self.allowed.clone());
 
     for service in self.services.iter() {
       service.web_config(web);
@@ -111,12 +196,264 @@ impl Services {
             service.api_config(web);
           }
         })
+        .default_service(web::route().to(api_default))
     );
   }
 }
 
+fn unknown_service(name: &str, prefix: &str) -> Error {
+  config::ConfigError::Message(format!(
+    "Unknown service \"{}\" in {}.services, valid services: {}",
+    name, prefix, SERVICE_NAMES.join(", ")
+  )).into()
+}
+
+/// Read and check `<prefix>.services`: only known services, each listed once.
+pub fn service_names(config: &AppConfig, prefix: &str) -> Result<Vec<String>> {
+  let list = config.get_array(&format!("{}.services", prefix))?
+    .ok_or_else(|| config::ConfigError::NotFound(format!("{}.services", prefix)))?;
+  let mut loaded: HashMap<String, bool> = HashMap::new();
+  let mut names = Vec::with_capacity(list.len());
+  for name in list.into_iter() {
+    let name = name.into_str()?;
+    if !SERVICE_NAMES.contains(&name.as_str()) {
+      return Err(unknown_service(&name, prefix));
+    }
+    // check if it is listed already.
+    if loaded.insert(name.clone(), true).is_some() {
+      return Err(config::ConfigError::Message(format!(
+        "Service \"{}\" is listed more than once in {}.services", name, prefix
+      )).into());
+    }
+    names.push(name);
+  }
+  Ok(names)
+}
+
 pub fn config_services(config: &AppConfig, prefix: &str) -> Result<Services> {
   let mut services = Services::new();
   services.load_app_config(config, prefix)?;
   Ok(services)
 }
+
+/// All services of a "test" server, with `settings` on top of the test
+/// database.  `None` without `TEST_DATABASE_URL`.
+#[cfg(test)]
+pub(crate) fn test_services(settings: &[(&str, ::config::Value)]) -> Option<Services> {
+  let url = std::env::var("TEST_DATABASE_URL").ok()?;
+  let mut config = AppConfig { conf: ::config::Config::default() };
+  config.conf.set("db.url", url).unwrap();
+  config.conf.set("test.services", vec!["User", "Profile", "Article", "Tag"]).unwrap();
+  config.conf.set("auth.jwt_secret", TEST_JWT_SECRET).unwrap();
+  for (key, value) in settings {
+    config.conf.set(key, value.clone()).unwrap();
+  }
+  Some(config_services(&config, "test").expect("test services"))
+}
+
+/// JWT secret of the test services.
+#[cfg(test)]
+const TEST_JWT_SECRET: &str = "test-secret-test-secret-test-secret";
+
+/// Keys of the test services.
+#[cfg(test)]
+pub(crate) fn test_jwt_keys() -> JwtKeys {
+  JwtKeys::from_secret(TEST_JWT_SECRET)
+}
+
+/// Register a test user, returns a token signed with the test JWT secret.
+#[cfg(test)]
+pub(crate) async fn test_login(db: &DbService, name: &str) -> (crate::auth::AuthData, String) {
+  use crate::auth::jwt::GenerateJwt;
+  let auth = crate::db::test_user(db, name).await;
+  let user = db.user.get_by_id(auth.user_id).await.unwrap().expect("test user");
+  let token = user.generate_jwt(&test_jwt_keys()).unwrap();
+  (auth, token)
+}
+
+/// API request, authorized with `token` (unless empty).
+#[cfg(test)]
+pub(crate) fn test_request(method: actix_web::http::Method, uri: &str, token: &str) -> actix_web::test::TestRequest {
+  let req = actix_web::test::TestRequest::default()
+    .method(method)
+    .uri(&format!("/api{}", uri));
+  if token.is_empty() {
+    req
+  } else {
+    req.header("Authorization", format!("Token {}", token))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::{test, App};
+
+  #[actix_rt::test]
+  async fn options_lists_the_allowed_methods() {
+    let services: Vec<BoxService> = vec![Box::new(article::new_factory())];
+    let allowed = AllowedMethods::new(&services);
+    let mut app = test::init_service(App::new()
+      .data(allowed)
+      .service(web::scope("/api").default_service(web::route().to(api_default)))).await;
+
+    let cases = vec![
+      ("/api/articles/some-slug", Some("GET, HEAD, PUT, PATCH, DELETE, OPTIONS")),
+      ("/api/articles/some-slug/comments/1", Some("DELETE, OPTIONS")),
+      // also matches `/articles/{slug}`.
+      ("/api/articles/feed", Some("GET, HEAD, PUT, PATCH, DELETE, OPTIONS")),
+      ("/api/unknown", None),
+    ];
+    for (uri, allow) in cases {
+      let req = test::TestRequest::default().method(Method::OPTIONS).uri(uri).to_request();
+      let res = test::call_service(&mut app, req).await;
+      match allow {
+        Some(allow) => {
+          assert_eq!(res.status(), 204, "{}", uri);
+          assert_eq!(res.headers().get("Allow").unwrap(), allow, "{}", uri);
+        },
+        None => assert_eq!(res.status(), 404, "{}", uri),
+      }
+    }
+
+    // other methods of unknown routes are still not found.
+    let req = test::TestRequest::get().uri("/api/unknown").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 404);
+  }
+
+  #[actix_rt::test]
+  async fn read_only_has_no_write_routes() {
+    use crate::db::{test_db, test_suffix, test_article};
+
+    let services = match test_services(&[
+      ("test.read_only", true.into()),
+      ("test.services", vec!["User", "Profile", "Article", "Tag", "Admin"].into()),
+      ("auth.check_token_version", true.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("readonly{}", test_suffix());
+    let (auth, token) = test_login(&db, &name).await;
+    let id = test_article(&db, &auth, &format!("Read only {}", name)).await;
+    let slug = db.article.get_by_id(&auth, id).await.unwrap().unwrap().slug;
+
+    let writes = vec![
+      (Method::POST, "/users".to_string()),
+      (Method::POST, "/users/logout".to_string()),
+      (Method::PUT, "/user".to_string()),
+      (Method::POST, format!("/profiles/{}/follow", name)),
+      (Method::DELETE, format!("/profiles/{}/follow", name)),
+      (Method::POST, "/profiles/follow-batch".to_string()),
+      (Method::POST, "/articles".to_string()),
+      (Method::PUT, format!("/articles/{}", slug)),
+      (Method::PATCH, format!("/articles/{}", slug)),
+      (Method::DELETE, format!("/articles/{}", slug)),
+      (Method::POST, format!("/articles/{}/comments", slug)),
+      (Method::DELETE, format!("/articles/{}/comments/1", slug)),
+      (Method::POST, format!("/articles/{}/favorite", slug)),
+      (Method::PUT, format!("/articles/{}/favorite", slug)),
+      (Method::DELETE, format!("/articles/{}/favorite", slug)),
+      (Method::DELETE, "/admin/tags/orphans".to_string()),
+      (Method::POST, format!("/admin/users/{}/logout-all", name)),
+    ];
+    for (method, uri) in writes {
+      let req = test_request(method.clone(), &uri, &token)
+        .set_json(&json!({}))
+        .to_request();
+      let status = test::call_service(&mut app, req).await.status();
+      assert_eq!(status, 404, "{} {}", method, uri);
+    }
+
+    // the reads are still served, and only they are allowed.
+    let req = test_request(Method::GET, &format!("/articles/{}", slug), &token).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+    let cases = vec![
+      (format!("/articles/{}", slug), "GET, HEAD, OPTIONS"),
+      ("/articles".to_string(), "GET, OPTIONS"),
+      ("/user".to_string(), "GET, OPTIONS"),
+      ("/admin/tags/orphans".to_string(), "GET, OPTIONS"),
+    ];
+    for (uri, allow) in cases {
+      let req = test_request(Method::OPTIONS, &uri, "").to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.headers().get("Allow").unwrap(), allow, "{}", uri);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn read_only_reads_work_on_a_read_only_db() {
+    use crate::db::{test_db, test_suffix, test_article};
+
+    // like a replica, the server's connection refuses all writes.
+    let url = match std::env::var("TEST_DATABASE_URL") {
+      Ok(url) => format!("{} options='-c default_transaction_read_only=on'", url),
+      Err(_) => return,
+    };
+    let db = test_db().await.unwrap();
+    let name = format!("replica{}", test_suffix());
+    let (auth, token) = test_login(&db, &name).await;
+    let id = test_article(&db, &auth, &format!("Replica {}", name)).await;
+    let slug = db.article.get_by_id(&auth, id).await.unwrap().unwrap().slug;
+
+    let services = test_services(&[
+      ("db.url", url.into()),
+      ("test.read_only", true.into()),
+      ("test.services", vec!["User", "Profile", "Article", "Tag", "Admin"].into()),
+      ("Admin.user_ids", vec![auth.user_id as i64].into()),
+      ("Article.allow_export", true.into()),
+    ]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+
+    let reads = vec![
+      "/user".to_string(),
+      "/user/favorites".to_string(),
+      format!("/profiles/{}", name),
+      "/articles".to_string(),
+      "/articles/feed".to_string(),
+      "/articles/feed/unread-count?since_id=0".to_string(),
+      format!("/articles/{}", slug),
+      format!("/articles/{}/comments", slug),
+      "/tags".to_string(),
+      "/admin/audit".to_string(),
+      "/admin/articles/export".to_string(),
+    ];
+    for uri in reads {
+      let req = test_request(Method::GET, &uri, &token).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), 200, "GET {}", uri);
+      // the export streams its body, errors show up while reading it.
+      test::read_body(res).await;
+    }
+
+    // the export isn't audited on a replica.
+    let req = crate::forms::AuditRequest { limit: Some(100), offset: None };
+    let entries = db.audit.get_entries(req).await.unwrap();
+    assert!(!entries.iter().any(|e| e.actor == name));
+  }
+
+  #[test]
+  fn service_names_are_checked() {
+    let names = |list: Vec<&str>| {
+      let mut config = AppConfig { conf: ::config::Config::default() };
+      config.conf.set("test.services", list).unwrap();
+      match service_names(&config, "test") {
+        Ok(names) => Ok(names),
+        Err(Error::ConfigError { source }) => Err(source.to_string()),
+        Err(err) => panic!("expected a config error: {:?}", err),
+      }
+    };
+    assert_eq!(names(vec!["User", "Article"]), Ok(vec!["User".to_string(), "Article".to_string()]));
+    let err = names(vec!["User", "Comment"]).unwrap_err();
+    assert!(err.contains("\"Comment\"") && err.contains("User, Profile, Article, Tag, Admin"), "{}", err);
+    let err = names(vec!["Tag", "Tag"]).unwrap_err();
+    assert!(err.contains("more than once"), "{}", err);
+
+    // a missing list is an error too.
+    let config = AppConfig { conf: ::config::Config::default() };
+    assert!(service_names(&config, "test").is_err());
+  }
+}
@@ -1,11 +1,19 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDateTime;
+
 use actix_web::{
-  get, post, delete, web, HttpResponse,
+  get, post, delete, route, web, HttpRequest, HttpResponse,
   Error
 };
 
 use crate::error::*;
 use crate::app::*;
 
+use crate::models::Profile;
 use crate::forms::*;
 
 use crate::db::DbService;
@@ -13,18 +21,69 @@ use crate::db::DbService;
 use crate::auth::AuthData;
 use crate::middleware::Auth;
 
+use super::{NO_CHANGE_HEADER, ApiUrls};
+
+/// Profiles loaded during the current request, stored in the request extensions.
+#[derive(Default)]
+struct ProfileCache(HashMap<i32, Profile>);
+
+/// Get the profiles of `user_ids`, each profile is only loaded once per request.
+///
+/// Profiles missing from the cache are loaded with one query.
+pub async fn cached_profiles(
+  req: &HttpRequest,
+  db: &DbService,
+  auth: &AuthData,
+  user_ids: &[i32],
+) -> Result<HashMap<i32, Profile>> {
+  let mut profiles = HashMap::new();
+  let mut missing = Vec::new();
+  if let Some(cache) = req.extensions().get::<ProfileCache>() {
+    for user_id in user_ids {
+      match cache.0.get(user_id) {
+        Some(profile) => {
+          profiles.insert(*user_id, profile.clone());
+        },
+        None => missing.push(*user_id),
+      }
+    }
+  } else {
+    missing.extend_from_slice(user_ids);
+  }
+  missing.sort_unstable();
+  missing.dedup();
+  if missing.is_empty() {
+    return Ok(profiles);
+  }
+
+  let loaded = db.user.get_profiles_by_ids(auth, &missing).await?;
+  let mut ext = req.extensions_mut();
+  if ext.get::<ProfileCache>().is_none() {
+    ext.insert(ProfileCache::default());
+  }
+  let cache = ext.get_mut::<ProfileCache>().unwrap();
+  for profile in loaded {
+    cache.0.insert(profile.user_id, profile.clone());
+    profiles.insert(profile.user_id, profile);
+  }
+  Ok(profiles)
+}
 
-/// get profile by username
-#[get("/profiles/{username}", wrap="Auth::optional()")]
+/// get profile by username (HEAD responses have the body stripped by actix)
+#[route("/profiles/{username}", method="GET", method="HEAD", wrap="Auth::optional()")]
 async fn get_profile(
+  http_req: HttpRequest,
   auth: Option<AuthData>,
+  cfg: web::Data<ProfileService>,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   username: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
   let auth = auth.unwrap_or_default();
 
-  match db.user.get_profile(&auth, &username).await? {
-    Some(profile) => {
+  match db.user.get_profile(&auth, &username, cfg.articles_count).await? {
+    Some(mut profile) => {
+      urls.set_profile(&http_req, &mut profile);
       Ok(HttpResponse::Ok().json(ProfileOut {
         profile,
       }))
@@ -37,22 +96,119 @@ async fn get_profile(
   }
 }
 
+/// Default for `?limit` of `GET /stats/top-commenters`.
+const DEFAULT_TOP_COMMENTERS: i64 = 10;
+
+/// Cached `?since` of `GET /stats/top-commenters` is rounded down to the
+/// hour.
+const TOP_COMMENTERS_SINCE_SECS: i64 = 3600;
+
+/// Maximum number of cached `since` buckets per worker.
+const TOP_COMMENTERS_CACHE_ENTRIES: usize = 24;
+
+/// Round `since` down to a whole bucket, so nearby timestamps share one
+/// cache entry.
+fn since_bucket(since: NaiveDateTime) -> NaiveDateTime {
+  let secs = since.timestamp();
+  NaiveDateTime::from_timestamp(secs - secs.rem_euclid(TOP_COMMENTERS_SINCE_SECS), 0)
+}
+
+type TopCommentersEntry = (Instant, Rc<Vec<TopCommenter>>);
+
+/// Per-worker cache of `GET /stats/top-commenters`, by `since` bucket.
+///
+/// Entries hold the configured maximum of commenters, smaller `?limit`s are
+/// cut from them.
+#[derive(Default)]
+struct TopCommentersCache {
+  entries: RefCell<HashMap<Option<NaiveDateTime>, TopCommentersEntry>>,
+}
+
+impl TopCommentersCache {
+  async fn get(&self, db: &DbService, since: Option<NaiveDateTime>, max: i64, ttl: Duration) -> Result<Rc<Vec<TopCommenter>>> {
+    if let Some((loaded, commenters)) = self.entries.borrow().get(&since) {
+      if loaded.elapsed() < ttl {
+        return Ok(commenters.clone());
+      }
+    }
+    let commenters = Rc::new(db.comment.get_top_commenters(max, since).await?);
+    let mut entries = self.entries.borrow_mut();
+    // clients can pick any `since` bucket, drop expired entries and then the
+    // oldest one when full.
+    entries.retain(|_, (loaded, _)| loaded.elapsed() < ttl);
+    if entries.len() >= TOP_COMMENTERS_CACHE_ENTRIES && !entries.contains_key(&since) {
+      let oldest = entries.iter().min_by_key(|(_, (loaded, _))| *loaded).map(|(key, _)| *key);
+      if let Some(oldest) = oldest {
+        entries.remove(&oldest);
+      }
+    }
+    entries.insert(since, (Instant::now(), commenters.clone()));
+    Ok(commenters)
+  }
+}
+
+/// Get the users with the most comments, since `?since` (rounded down to the
+/// hour when cached).
+#[get("/stats/top-commenters")]
+async fn top_commenters(
+  cfg: web::Data<ProfileService>,
+  db: web::Data<DbService>,
+  cache: web::Data<TopCommentersCache>,
+  req: web::Query<TopCommentersRequest>,
+) -> Result<HttpResponse, Error> {
+  if cfg.top_commenters_max == 0 {
+    return Ok(HttpResponse::NotFound().json(json!({
+      "error": "Not found",
+    })));
+  }
+  let since = match &req.since {
+    Some(since) => match chrono::DateTime::parse_from_rfc3339(since) {
+      Ok(ts) => Some(ts.naive_utc()),
+      Err(_) => {
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+          "error": "since must be an RFC3339 timestamp.",
+        })));
+      },
+    },
+    None => None,
+  };
+  // `?limit` can only lower the configured maximum.
+  let limit = req.limit.unwrap_or(DEFAULT_TOP_COMMENTERS).max(0).min(cfg.top_commenters_max);
+
+  let commenters = if cfg.top_commenters_cache_secs > 0 {
+    let ttl = Duration::from_secs(cfg.top_commenters_cache_secs);
+    // nearby `since` values share an entry, so the results can include up to
+    // an hour of earlier comments.
+    cache.get(&db, since.map(since_bucket), cfg.top_commenters_max, ttl).await?
+  } else {
+    Rc::new(db.comment.get_top_commenters(limit, since).await?)
+  };
+  let commenters = &commenters[..commenters.len().min(limit as usize)];
+  Ok(HttpResponse::Ok().json(json!({
+    "commenters": commenters,
+  })))
+}
+
 /// follow a user
 #[post("/profiles/{username}/follow", wrap="Auth::required()")]
 async fn follow(
+  http_req: HttpRequest,
   auth: AuthData,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   username: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-  match db.user.get_profile(&auth, &username).await? {
+  match db.user.get_profile(&auth, &username, false).await? {
     Some(mut profile) => {
-      // Check if the current user is already following them.
-      if !profile.following {
-        // update DB to mark the current user as following them.
-        db.user.follow(&auth, profile.user_id).await?;
-        profile.following = true;
+      // update DB to mark the current user as following them.
+      let mut res = HttpResponse::Ok();
+      if db.user.follow(&auth, profile.user_id).await? == 0 {
+        // The current user is already following them.
+        res.header(NO_CHANGE_HEADER, "true");
       }
-      Ok(HttpResponse::Ok().json(ProfileOut {
+      profile.following = true;
+      urls.set_profile(&http_req, &mut profile);
+      Ok(res.json(ProfileOut {
         profile,
       }))
     },
@@ -64,22 +220,48 @@ async fn follow(
   }
 }
 
+/// Maximum number of usernames in one follow batch.
+const MAX_FOLLOW_BATCH: usize = 100;
+
+/// follow multiple users
+#[post("/profiles/follow-batch", wrap="Auth::required()")]
+async fn follow_batch(
+  auth: AuthData,
+  db: web::Data<DbService>,
+  req: web::Json<FollowBatchRequest>,
+) -> Result<HttpResponse, Error> {
+  if req.usernames.len() > MAX_FOLLOW_BATCH {
+    return Ok(HttpResponse::UnprocessableEntity().json(json!({
+      "error": format!("At most {} usernames can be followed at once.", MAX_FOLLOW_BATCH),
+    })));
+  }
+
+  let results = db.user.follow_many(&auth, &req.usernames).await?;
+  Ok(HttpResponse::Ok().json(FollowBatchResponse {
+    results,
+  }))
+}
+
 /// unfollow a user
 #[delete("/profiles/{username}/follow", wrap="Auth::required()")]
 async fn unfollow(
+  http_req: HttpRequest,
   auth: AuthData,
   db: web::Data<DbService>,
+  urls: web::Data<ApiUrls>,
   username: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-  match db.user.get_profile(&auth, &username).await? {
+  match db.user.get_profile(&auth, &username, false).await? {
     Some(mut profile) => {
-      // Check if the current user is already following them.
-      if profile.following {
-        // update DB to mark the current user as not following them.
-        db.user.unfollow(&auth, profile.user_id).await?;
-        profile.following = false;
+      // update DB to mark the current user as not following them.
+      let mut res = HttpResponse::Ok();
+      if db.user.unfollow(&auth, profile.user_id).await? == 0 {
+        // The current user wasn't following them.
+        res.header(NO_CHANGE_HEADER, "true");
       }
-      Ok(HttpResponse::Ok().json(ProfileOut {
+      profile.following = false;
+      urls.set_profile(&http_req, &mut profile);
+      Ok(res.json(ProfileOut {
         profile,
       }))
     },
@@ -93,22 +275,216 @@ async fn unfollow(
 
 #[derive(Debug, Clone, Default)]
 pub struct ProfileService {
+  /// Include the user's `articlesCount` in `GET /profiles/<username>`.
+  pub articles_count: bool,
+
+  /// Only register the read endpoints (`<prefix>.read_only`).
+  pub read_only: bool,
+
+  /// Maximum `?limit` of `GET /stats/top-commenters` (0 = disabled).
+  pub top_commenters_max: i64,
+
+  /// How long top commenters are cached (0 = not cached).
+  pub top_commenters_cache_secs: u64,
 }
 
 impl super::Service for ProfileService {
-  fn load_app_config(&mut self, _config: &AppConfig, _prefix: &str) -> Result<()> {
+  fn load_app_config(&mut self, config: &AppConfig, prefix: &str) -> Result<()> {
+    self.read_only = super::read_only(config, prefix)?;
+    self.articles_count = config.get_bool("Profile.articles_count")?.unwrap_or(false);
+    self.top_commenters_max = config.get_int("Profile.top_commenters_max")?
+      .unwrap_or(0).max(0);
+    self.top_commenters_cache_secs = config.get_int("Profile.top_commenters_cache_secs")?
+      .unwrap_or(60).max(0) as u64;
     Ok(())
   }
 
   fn api_config(&self, web: &mut web::ServiceConfig) {
     web
       .data(self.clone())
+      .data(TopCommentersCache::default())
       .service(get_profile)
-      .service(follow)
-      .service(unfollow);
+      .service(top_commenters);
+    if !self.read_only {
+      web
+        .service(follow)
+        .service(follow_batch)
+        .service(unfollow);
+    }
+  }
+
+  fn routes(&self) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut routes: Vec<(&'static str, &'static [&'static str])> = vec![
+      ("/profiles/{username}", &["GET", "HEAD"]),
+      ("/stats/top-commenters", &["GET"]),
+    ];
+    if !self.read_only {
+      routes.push(("/profiles/{username}/follow", &["POST", "DELETE"]));
+      routes.push(("/profiles/follow-batch", &["POST"]));
+    }
+    routes
   }
 }
 
 pub fn new_factory() -> ProfileService {
   Default::default()
 }
+
+#[cfg(test)]
+mod tests {
+  use actix_web::{test, App, http::Method};
+
+  use crate::db::{test_db, test_suffix, VersionedStatement};
+  use crate::services::{test_services, test_login, test_request, NO_CHANGE_HEADER};
+
+  use super::{cached_profiles, since_bucket};
+
+  #[actix_rt::test]
+  async fn repeated_follow_is_a_no_op() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (_reader, token) = test_login(&db, &format!("follower{}", suffix)).await;
+    test_login(&db, &format!("followed{}", suffix)).await;
+    let uri = format!("/profiles/followed{}/follow", suffix);
+
+    for (method, following) in &[(Method::POST, true), (Method::DELETE, false)] {
+      for no_change in &[false, true] {
+        let req = test_request(method.clone(), &uri, &token).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().contains_key(NO_CHANGE_HEADER), *no_change, "{} {}", method, no_change);
+        let res: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(res["profile"]["following"], *following);
+      }
+    }
+  }
+
+  #[actix_rt::test]
+  async fn profiles_carry_the_join_date() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("joined{}", test_suffix());
+    let (author, _) = test_login(&db, &name).await;
+
+    let req = test_request(Method::GET, &format!("/profiles/{}", name), "").to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let joined = chrono::DateTime::parse_from_rfc3339(res["profile"]["createdAt"].as_str().unwrap()).unwrap();
+    let age = chrono::Utc::now().signed_duration_since(joined);
+    assert!(age < chrono::Duration::days(1) && age > chrono::Duration::days(-1), "joined {}", joined);
+
+    // article authors don't have it.
+    let id = crate::db::test_article(&db, &author, &format!("Joined {}", name)).await;
+    let article = db.article.get_by_id(&author, id).await.unwrap().unwrap();
+    let article = serde_json::to_value(&article).unwrap();
+    assert!(article["author"].get("createdAt").is_none());
+  }
+
+  #[actix_rt::test]
+  async fn articles_count_is_optional() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let name = format!("prolific{}", test_suffix());
+    let (author, token) = test_login(&db, &name).await;
+    crate::db::test_article(&db, &author, &format!("Prolific {}", name)).await;
+    crate::db::test_article(&db, &author, &format!("Prolific 2 {}", name)).await;
+
+    for &(enabled, count) in &[(false, None), (true, Some(2))] {
+      let services = test_services(&[("Profile.articles_count", enabled.into())]).unwrap();
+      let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+      let req = test_request(Method::GET, &format!("/profiles/{}", name), &token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["profile"]["articlesCount"].as_i64(), count, "{}", enabled);
+      // only `GET /profiles/<username>` has it.
+      let req = test_request(Method::GET, "/user", &token).to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert!(res["user"].get("articlesCount").is_none());
+    }
+  }
+
+  #[actix_rt::test]
+  async fn profiles_are_loaded_once_per_request() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let suffix = test_suffix();
+    let (reader, _token) = test_login(&db, &format!("cacher{}", suffix)).await;
+    let (first, _token) = test_login(&db, &format!("cached{}", suffix)).await;
+    let (second, _token) = test_login(&db, &format!("uncached{}", suffix)).await;
+    let req = test::TestRequest::default().to_http_request();
+
+    let ids = [first.user_id, first.user_id, second.user_id];
+    let profiles = cached_profiles(&req, &db, &reader, &ids[..1]).await.unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(profiles[&first.user_id].bio, None);
+
+    // a cached profile isn't loaded again during the same request.
+    let bio = VersionedStatement::new(db.shared_cl.clone(), "UPDATE users SET bio = 'new' WHERE id = ANY($1)")
+      .unwrap();
+    bio.execute(&[&vec![first.user_id, second.user_id]]).await.unwrap();
+    let profiles = cached_profiles(&req, &db, &reader, &ids).await.unwrap();
+    assert_eq!(profiles.len(), 2);
+    assert_eq!(profiles[&first.user_id].bio, None);
+    assert_eq!(profiles[&second.user_id].bio.as_deref(), Some("new"));
+
+    // other requests load it again.
+    let req = test::TestRequest::default().to_http_request();
+    let profiles = cached_profiles(&req, &db, &reader, &ids).await.unwrap();
+    assert_eq!(profiles[&first.user_id].bio.as_deref(), Some("new"));
+  }
+
+  #[actix_rt::test]
+  async fn follow_batch_results() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let me = format!("batcher{}", suffix);
+    let (auth, token) = test_login(&db, &me).await;
+    let first = format!("batched{}", suffix);
+    let (first_auth, _token) = test_login(&db, &first).await;
+    let second = format!("followed{}", suffix);
+    let (second_auth, _token) = test_login(&db, &second).await;
+    db.user.follow(&auth, second_auth.user_id).await.unwrap();
+    let missing = format!("missing{}", suffix);
+
+    let req = test_request(Method::POST, "/profiles/follow-batch", &token)
+      .set_json(&json!({"usernames": [&missing, &first, &me, &second, &first]}))
+      .to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res, json!({"results": [
+      {"username": missing, "status": "notFound"},
+      {"username": first, "status": "followed"},
+      {"username": me, "status": "self"},
+      {"username": second, "status": "alreadyFollowing"},
+    ]}));
+    let profile = db.user.get_profile(&auth, &first, false).await.unwrap().unwrap();
+    assert!(profile.following);
+    assert_eq!(profile.user_id, first_auth.user_id);
+  }
+
+  #[test]
+  fn since_bucket_rounds_down_to_the_hour() {
+    let ts = |s: &str| chrono::DateTime::parse_from_rfc3339(s).unwrap().naive_utc();
+    let hour = ts("2026-10-16T10:00:00Z");
+    assert_eq!(since_bucket(hour), hour);
+    assert_eq!(since_bucket(ts("2026-10-16T10:00:01Z")), hour);
+    assert_eq!(since_bucket(ts("2026-10-16T10:59:59.999Z")), hour);
+    assert_eq!(since_bucket(ts("2026-10-16T12:30:00+02:00")), hour);
+    assert_eq!(since_bucket(ts("1969-12-31T23:30:00Z")), ts("1969-12-31T23:00:00Z"));
+  }
+}
@@ -1,38 +1,354 @@
+use log::*;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
 use actix_web::{
-  get, web, HttpResponse,
+  get, delete, web, HttpResponse,
   Error
 };
 
 use crate::error::*;
 use crate::app::*;
 
+use crate::forms::*;
+
 use crate::db::DbService;
 
+use crate::auth::AuthData;
+use crate::middleware::Auth;
+
+use super::admin::AdminService;
+
+/// Default for `Tag.limit`.
+const DEFAULT_TAG_LIMIT: i64 = 500;
+
+/// Default for `Tag.count_cache_ttl_secs`.
+const DEFAULT_COUNT_CACHE_TTL: u64 = 60;
+
+type TagCounts = Rc<BTreeMap<String, i64>>;
+
+#[derive(Default)]
+struct TagCountState {
+  counts: Option<TagCounts>,
+  loaded: Option<Instant>,
+  refreshing: bool,
+}
+
+/// Per-worker cache of the article count for each tag.
+///
+/// Expired counts are still returned while they are refreshed in the
+/// background, so counts can lag by up to the TTL (plus one query).
+#[derive(Default)]
+struct TagCountCache {
+  state: RefCell<TagCountState>,
+}
+
+impl TagCountCache {
+  fn store(&self, counts: BTreeMap<String, i64>) -> TagCounts {
+    let counts = Rc::new(counts);
+    let mut state = self.state.borrow_mut();
+    state.counts = Some(counts.clone());
+    state.loaded = Some(Instant::now());
+    state.refreshing = false;
+    counts
+  }
+
+  async fn get(cache: web::Data<Self>, db: web::Data<DbService>, ttl: Duration) -> Result<TagCounts> {
+    let (counts, expired) = {
+      let state = cache.state.borrow();
+      let expired = state.loaded.is_none_or(|loaded| loaded.elapsed() >= ttl);
+      (state.counts.clone(), expired && !state.refreshing)
+    };
+    match counts {
+      Some(counts) => {
+        if expired {
+          // serve the stale counts, only one refresh at a time.
+          cache.state.borrow_mut().refreshing = true;
+          actix_rt::spawn(async move {
+            match db.tag.get_tag_counts().await {
+              Ok(counts) => {
+                cache.store(counts);
+              },
+              Err(err) => {
+                error!("Failed to refresh tag counts: {:?}", err);
+                cache.state.borrow_mut().refreshing = false;
+              },
+            }
+          });
+        }
+        Ok(counts)
+      },
+      None => {
+        let counts = db.tag.get_tag_counts().await?;
+        Ok(cache.store(counts))
+      },
+    }
+  }
+}
+
 /// Get list of tags
 #[get("/tags")]
 async fn list(
+  cfg: web::Data<TagService>,
   db: web::Data<DbService>,
+  req: web::Query<TagRequest>,
 ) -> Result<HttpResponse, Error> {
+  // `?limit` can only lower the configured limit.
+  let limit = match (req.limit, cfg.limit) {
+    (Some(req), 0) => Some(req.max(0)),
+    (Some(req), max) => Some(req.max(0).min(max)),
+    (None, 0) => None,
+    (None, max) => Some(max),
+  };
   // Get list of tags
-  let tags = db.tag.get_tags().await?;
-  Ok(HttpResponse::Ok().json(tags))
+  let tags = db.tag.get_tags(limit, cfg.popular).await?;
+  let meta = match limit {
+    Some(limit) => PageMeta::page(tags.tags.len(), Some(limit), None),
+    None => PageMeta::complete(tags.tags.len()),
+  };
+  Ok(HttpResponse::Ok().json(ListOut::new(tags.tags, meta, |tags| {
+    TagList {
+      tags,
+    }
+  })))
+}
+
+/// Get the number of articles for each tag (cached, see `TagCountCache`)
+#[get("/tags/counts")]
+async fn tag_counts(
+  cfg: web::Data<TagService>,
+  db: web::Data<DbService>,
+  cache: web::Data<TagCountCache>,
+) -> Result<HttpResponse, Error> {
+  let counts = if cfg.count_cache_ttl_secs > 0 {
+    TagCountCache::get(cache, db, Duration::from_secs(cfg.count_cache_ttl_secs)).await?
+  } else {
+    Rc::new(db.tag.get_tag_counts().await?)
+  };
+  Ok(HttpResponse::Ok().json(json!({
+    "tagCounts": &*counts,
+  })))
+}
+fn admin_required() -> HttpResponse {
+  HttpResponse::Forbidden().json(json!({
+    "error": "Admin access required.",
+  }))
+}
+
+/// List the counted tags that no article uses (the `tags` counts drifted
+/// from `article_tags`), only for admins.
+#[get("/admin/tags/orphans", wrap="Auth::required()")]
+async fn list_orphans(
+  auth: AuthData,
+  admin: Option<web::Data<AdminService>>,
+  db: web::Data<DbService>,
+) -> Result<HttpResponse, Error> {
+  // the Admin service must be enabled on the same server.
+  if !admin.is_some_and(|admin| admin.is_admin(&auth)) {
+    return Ok(admin_required());
+  }
+  let orphans = db.tag.get_orphan_tags().await?;
+  Ok(HttpResponse::Ok().json(json!({
+    "orphans": orphans,
+  })))
+}
+
+/// Remove the counts of the orphaned tags, only for admins.
+#[delete("/admin/tags/orphans", wrap="Auth::required()")]
+async fn cleanup_orphans(
+  auth: AuthData,
+  admin: Option<web::Data<AdminService>>,
+  db: web::Data<DbService>,
+) -> Result<HttpResponse, Error> {
+  let admin = match admin {
+    Some(admin) if admin.is_admin(&auth) => admin,
+    _ => return Ok(admin_required()),
+  };
+  let removed = db.tag.delete_orphan_tags().await?;
+  if !removed.is_empty() {
+    admin.audit(&db, &auth, "cleanup-tag-orphans", &removed.join(",")).await?;
+  }
+  Ok(HttpResponse::Ok().json(json!({
+    "removed": removed,
+  })))
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct TagService {
+  /// Maximum number of tags returned (0 = unlimited).
+  pub limit: i64,
+
+  /// Return the most used tags first, instead of by name.
+  pub popular: bool,
+
+  /// How long tag counts are cached (0 = not cached).
+  pub count_cache_ttl_secs: u64,
+
+  /// Only register the read endpoints (`<prefix>.read_only`).
+  pub read_only: bool,
 }
 
 impl super::Service for TagService {
-  fn load_app_config(&mut self, _config: &AppConfig, _prefix: &str) -> Result<()> {
+  fn load_app_config(&mut self, config: &AppConfig, prefix: &str) -> Result<()> {
+    self.read_only = super::read_only(config, prefix)?;
+    self.limit = config.get_int("Tag.limit")?.unwrap_or(DEFAULT_TAG_LIMIT);
+    self.popular = match config.get_str("Tag.order")?.as_deref() {
+      None | Some("name") => false,
+      Some("popular") => true,
+      Some(order) => {
+        return Err(config::ConfigError::Message(
+          format!("Invalid Tag.order '{}', expected 'name' or 'popular'", order)
+        ).into());
+      },
+    };
+    self.count_cache_ttl_secs = config.get_int("Tag.count_cache_ttl_secs")?
+      .map(|ttl| ttl.max(0) as u64).unwrap_or(DEFAULT_COUNT_CACHE_TTL);
     Ok(())
   }
 
   fn api_config(&self, web: &mut web::ServiceConfig) {
     web
-      .service(list);
+      .data(self.clone())
+      // per-worker
+      .data(TagCountCache::default())
+      .service(list)
+      .service(tag_counts)
+      .service(list_orphans);
+    if !self.read_only {
+      web.service(cleanup_orphans);
+    }
+  }
+
+  fn routes(&self) -> Vec<(&'static str, &'static [&'static str])> {
+    let orphans: &'static [&'static str] = if self.read_only {
+      &["GET"]
+    } else {
+      &["GET", "DELETE"]
+    };
+    vec![
+      ("/tags", &["GET"]),
+      ("/tags/counts", &["GET"]),
+      ("/admin/tags/orphans", orphans),
+    ]
   }
 }
 
 pub fn new_factory() -> TagService {
   Default::default()
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use actix_web::{test, web, App, http::{Method, StatusCode}};
+
+  use crate::db::{test_db, test_suffix, test_user, ArticleLimits, StoreArticle, VersionedStatement};
+  use crate::forms::article::CreateArticle;
+  use crate::models::SlugScope;
+  use crate::services::{test_services, test_login, test_request};
+
+  use super::TagCountCache;
+
+  #[actix_rt::test]
+  async fn tag_list_is_capped() {
+    let services = match test_services(&[("Tag.limit", 2.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("tagger{}", suffix)).await;
+    let req = CreateArticle {
+      title: format!("Tagged {}", suffix),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: (0..3).map(|n| format!("tag{}-{}", n, suffix)).collect(),
+    };
+    assert!(matches!(db.article.store(&author, &req, SlugScope::Global, ArticleLimits::default()).await.unwrap(), StoreArticle::Stored(_)));
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+
+    // `?limit` can only lower the configured cap.
+    for (uri, count) in &[("/tags", 2), ("/tags?limit=1", 1), ("/tags?limit=10", 2), ("/tags?limit=0", 0)] {
+      let req = test_request(Method::GET, uri, "").to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["tags"].as_array().unwrap().len(), *count, "{}", uri);
+    }
+  }
+
+  #[actix_rt::test]
+  async fn tag_counts_are_cached_for_the_ttl() {
+    let db = match test_db().await {
+      Some(db) => web::Data::new(db),
+      None => return,
+    };
+    let suffix = test_suffix();
+    let author = test_user(&db, &format!("counter{}", suffix)).await;
+    let tag = format!("counted-{}", suffix);
+    let store = |n| CreateArticle {
+      title: format!("Counted {} {}", n, suffix),
+      description: "description".to_string(),
+      body: "body".to_string(),
+      tag_list: vec![tag.clone()],
+    };
+    let cache = web::Data::new(TagCountCache::default());
+    let ttl = Duration::from_secs(60);
+
+    db.article.store(&author, &store(1), SlugScope::Global, ArticleLimits::default()).await.unwrap();
+    let counts = TagCountCache::get(cache.clone(), db.clone(), ttl).await.unwrap();
+    assert_eq!(counts.get(&tag), Some(&1));
+
+    // Within the TTL the cached counts are served without a query.
+    db.article.store(&author, &store(2), SlugScope::Global, ArticleLimits::default()).await.unwrap();
+    let counts = TagCountCache::get(cache.clone(), db.clone(), ttl).await.unwrap();
+    assert_eq!(counts.get(&tag), Some(&1));
+
+    // Once expired the stale counts are served while they refresh.
+    let counts = TagCountCache::get(cache.clone(), db.clone(), Duration::from_secs(0)).await.unwrap();
+    assert_eq!(counts.get(&tag), Some(&1));
+    for _ in 0..100 {
+      if !cache.state.borrow().refreshing {
+        break;
+      }
+      actix_rt::time::delay_for(Duration::from_millis(10)).await;
+    }
+    let counts = TagCountCache::get(cache.clone(), db.clone(), ttl).await.unwrap();
+    assert_eq!(counts.get(&tag), Some(&2));
+  }
+
+  #[actix_rt::test]
+  async fn orphaned_tags_are_reported_and_removed() {
+    let db = match test_db().await {
+      Some(db) => db,
+      None => return,
+    };
+    let name = format!("orphans{}", test_suffix());
+    let (admin, token) = test_login(&db, &name).await;
+    let (_user, user_token) = test_login(&db, &format!("not{}", name)).await;
+    let services = test_services(&[
+      ("test.services", vec!["User", "Profile", "Article", "Tag", "Admin"].into()),
+      ("Admin.user_ids", vec![i64::from(admin.user_id)].into()),
+    ]).unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    VersionedStatement::new(db.shared_cl.clone(),
+      r#"INSERT INTO tags(name, article_count) VALUES ($1, 1)"#).unwrap()
+      .execute(&[&name]).await.unwrap();
+
+    let get = |token: &str| test_request(Method::GET, "/admin/tags/orphans", token).to_request();
+    let res = test::call_service(&mut app, get(&user_token)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res: serde_json::Value = test::read_response_json(&mut app, get(&token)).await;
+    assert!(res["orphans"].as_array().unwrap().contains(&name.clone().into()), "{}", res);
+
+    let req = test_request(Method::DELETE, "/admin/tags/orphans", &user_token).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = test_request(Method::DELETE, "/admin/tags/orphans", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert!(res["removed"].is_array(), "{}", res);
+    let res: serde_json::Value = test::read_response_json(&mut app, get(&token)).await;
+    assert!(!res["orphans"].as_array().unwrap().contains(&name.into()), "{}", res);
+  }
+}
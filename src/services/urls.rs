@@ -0,0 +1,139 @@
+use actix_web::HttpRequest;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::error::*;
+use crate::app::*;
+
+use crate::models::*;
+
+/// Characters escaped in a URL path segment (all but unreserved ones).
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Absolute frontend URLs added to API responses (`api.include_urls`).
+#[derive(Debug, Clone, Default)]
+pub struct ApiUrls {
+  pub enabled: bool,
+  /// Frontend base URL.
+  pub base_url: Option<String>,
+  /// Origins (`scheme://host`) used as the base URL of requests made to
+  /// them (`api.url_origins`), the `Host` header isn't trusted otherwise.
+  pub origins: Vec<String>,
+}
+
+impl ApiUrls {
+  pub fn from_config(config: &AppConfig) -> Result<Self> {
+    let urls = Self {
+      enabled: config.get_bool("api.include_urls")?.unwrap_or(false),
+      base_url: config.get_str("api.base_url")?
+        .map(|url| url.trim_end_matches('/').to_string()),
+      origins: config.get_str_array("api.url_origins")?.unwrap_or_default().into_iter()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .collect(),
+    };
+    if urls.enabled && urls.base_url.is_none() && urls.origins.is_empty() {
+      return Err(config::ConfigError::Message(
+        "api.include_urls requires api.base_url or api.url_origins".to_string()).into());
+    }
+    Ok(urls)
+  }
+
+  fn base(&self, req: &HttpRequest) -> Option<String> {
+    if !self.origins.is_empty() {
+      let info = req.connection_info();
+      let origin = format!("{}://{}", info.scheme(), info.host());
+      if let Some(origin) = self.origins.iter().find(|o| o.eq_ignore_ascii_case(&origin)) {
+        return Some(origin.clone());
+      }
+    }
+    self.base_url.clone()
+  }
+
+  fn url(base: &str, path: &str, segment: &str) -> String {
+    format!("{}/{}/{}", base, path, utf8_percent_encode(segment, PATH_SEGMENT))
+  }
+
+  pub fn set_profile(&self, req: &HttpRequest, profile: &mut Profile) {
+    if !self.enabled {
+      return;
+    }
+    if let Some(base) = self.base(req) {
+      profile.profile_url = Some(Self::url(&base, "profile", &profile.username));
+    }
+  }
+
+  pub fn set_article(&self, req: &HttpRequest, article: &mut ArticleDetails) {
+    if !self.enabled {
+      return;
+    }
+    if let Some(base) = self.base(req) {
+      article.url = Some(Self::url(&base, "article", &article.slug));
+      article.author.profile_url = Some(Self::url(&base, "profile", &article.author.username));
+    }
+  }
+
+  pub fn set_articles(&self, req: &HttpRequest, articles: &mut [ArticleDetails]) {
+    if self.enabled {
+      for article in articles.iter_mut() {
+        self.set_article(req, article);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use actix_web::test::TestRequest;
+
+  fn profile(username: &str) -> Profile {
+    Profile {
+      username: username.to_string(),
+      ..Default::default()
+    }
+  }
+
+  fn profile_url(urls: &ApiUrls, host: &str, username: &str) -> Option<String> {
+    let req = TestRequest::default().header("host", host).to_http_request();
+    let mut profile = profile(username);
+    urls.set_profile(&req, &mut profile);
+    profile.profile_url
+  }
+
+  #[test]
+  fn urls_ignore_untrusted_hosts() {
+    let urls = ApiUrls {
+      enabled: true,
+      base_url: Some("https://conduit.example.com".to_string()),
+      origins: vec!["http://localhost:4100".to_string()],
+    };
+    assert_eq!(profile_url(&urls, "evil.example.com", "jake").as_deref(),
+      Some("https://conduit.example.com/profile/jake"));
+    assert_eq!(profile_url(&urls, "localhost:4100", "jake").as_deref(),
+      Some("http://localhost:4100/profile/jake"));
+    // usernames are encoded as one path segment.
+    assert_eq!(profile_url(&urls, "evil.example.com", "../a b?c#d").as_deref(),
+      Some("https://conduit.example.com/profile/..%2Fa%20b%3Fc%23d"));
+    assert_eq!(profile_url(&urls, "evil.example.com", "j\u{f6}rg").as_deref(),
+      Some("https://conduit.example.com/profile/j%C3%B6rg"));
+
+    // only allowed origins without a base URL.
+    let urls = ApiUrls { base_url: None, ..urls };
+    assert_eq!(profile_url(&urls, "evil.example.com", "jake"), None);
+    assert_eq!(profile_url(&urls, "localhost:4100", "jake").as_deref(),
+      Some("http://localhost:4100/profile/jake"));
+    let urls = ApiUrls { enabled: false, ..urls };
+    assert_eq!(profile_url(&urls, "localhost:4100", "jake"), None);
+  }
+
+  #[test]
+  fn include_urls_needs_a_trusted_base() {
+    let mut config = AppConfig { conf: ::config::Config::default() };
+    config.conf.set("api.include_urls", true).unwrap();
+    assert!(ApiUrls::from_config(&config).is_err());
+    config.conf.set("api.base_url", "https://conduit.example.com/").unwrap();
+    let urls = ApiUrls::from_config(&config).unwrap();
+    assert_eq!(urls.base_url.as_deref(), Some("https://conduit.example.com"));
+  }
+}
@@ -1,29 +1,33 @@
 use log::*;
 
-use std::convert::TryFrom;
-
 use actix_web::{
   get, post, put, web, HttpResponse,
   Error
 };
 
+use chrono::NaiveDateTime;
+
 use crate::error::*;
 use crate::app::*;
 use crate::forms::*;
 use crate::auth::AuthData;
+use crate::auth::jwt::JwtKeys;
 
-use crate::db::DbService;
+use crate::db::{DbService, Registration};
 use crate::auth::pass;
 
-use crate::middleware::Auth;
+use crate::middleware::{Auth, RateLimit};
 
 /// login user
-#[post("/users/login")]
+#[post("/users/login", wrap="RateLimit")]
 async fn login(
+  cfg: web::Data<UserService>,
   db: web::Data<DbService>,
+  keys: web::Data<JwtKeys>,
   login: web::Json<UserOut<LoginUser>>,
 ) -> Result<HttpResponse, Error> {
   let login = &login.user;
+  validate_form(login)?;
   // Get user from database
   let user = match db.user.get_by_email(&login.email).await? {
     Some(user) => user,
@@ -36,11 +40,11 @@ async fn login(
   let res = pass::check_password(&user.password, &login.password)?;
   info!("login: res={:?}", res);
   if res.is_valid {
-    if res.needs_update {
+    if res.needs_update && !cfg.read_only {
       // Rehash password.
       db.user.update_password(user.id, &login.password).await?;
     }
-    Ok(HttpResponse::Ok().json(UserResponse::try_from(user)?))
+    Ok(HttpResponse::Ok().json(UserResponse::new(user, &keys)?))
   } else {
     Ok(HttpResponse::Unauthorized().json(json!({
       "error": "Invalid user/password",
@@ -49,24 +53,81 @@ async fn login(
 }
 
 /// register new user
-#[post("/users")]
+#[post("/users", wrap="RateLimit")]
 async fn register(
   cfg: web::Data<UserService>,
   db: web::Data<DbService>,
+  keys: web::Data<JwtKeys>,
   register: web::Json<UserOut<RegisterUser>>,
 ) -> Result<HttpResponse, Error> {
   if !cfg.allow_register {
     return Ok(HttpResponse::Forbidden().finish());
   }
+  validate_form(&register.user)?;
+  cfg.limits.check_register(&register.user)?;
 
-  let user = match db.user.register_user(&register.user).await? {
-    Some(user) => user,
-    _ => {
-      return Ok(HttpResponse::InternalServerError().json("Failed to get user info."));
+  let invite = if cfg.require_invite {
+    match register.user.code.as_deref() {
+      Some(code) if !code.is_empty() => Some(code),
+      _ => {
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+          "error": "Invite code required.",
+        })));
+      },
+    }
+  } else {
+    None
+  };
+
+  let user = match db.user.register_user(&register.user, cfg.canonical_email, invite).await? {
+    Registration::Registered(user) => user,
+    Registration::EmailUsed => {
+      return Ok(HttpResponse::UnprocessableEntity().json(json!({
+        "error": "Email already registered.",
+      })));
+    },
+    Registration::UsernameUsed => {
+      return Ok(HttpResponse::UnprocessableEntity().json(json!({
+        "error": "Username already taken.",
+      })));
+    },
+    Registration::InvalidInvite => {
+      return Ok(HttpResponse::UnprocessableEntity().json(json!({
+        "error": "Invalid or expired invite code.",
+      })));
     },
   };
 
-  Ok(HttpResponse::Ok().json(UserResponse::try_from(user)?))
+  Ok(HttpResponse::Ok().json(UserResponse::new(user, &keys)?))
+}
+
+/// check if a username and/or email are available for registration.
+///
+/// Only a single flag is returned, so it doesn't reveal which value is taken.
+#[get("/users/available", wrap="RateLimit")]
+async fn available(
+  db: web::Data<DbService>,
+  req: web::Query<AvailableRequest>,
+) -> Result<HttpResponse, Error> {
+  if req.username.is_none() && req.email.is_none() {
+    return Ok(HttpResponse::BadRequest().json(json!({
+      "error": "username or email required",
+    })));
+  }
+
+  let mut available = true;
+  if let Some(username) = &req.username {
+    available &= db.user.get_by_username(username).await?.is_none();
+  }
+  if available {
+    if let Some(email) = &req.email {
+      available &= db.user.get_by_email(email).await?.is_none();
+    }
+  }
+
+  Ok(HttpResponse::Ok().json(json!({
+    "available": available,
+  })))
 }
 
 /// get current user
@@ -74,11 +135,31 @@ async fn register(
 async fn get_user(
   auth: AuthData,
   db: web::Data<DbService>,
+  keys: web::Data<JwtKeys>,
 ) -> Result<HttpResponse, Error> {
   // Get auth user from database
   match db.user.get_by_id(auth.user_id).await? {
     Some(user) => {
-      Ok(HttpResponse::Ok().json(UserResponse::try_from(user)?))
+      Ok(HttpResponse::Ok().json(UserResponse::new(user, &keys)?))
+    },
+    _ => {
+      // invalid user.
+      Ok(HttpResponse::NotFound().finish())
+    }
+  }
+}
+
+/// re-issue a token for the current user, to extend a session before the
+/// token expires.  Revoked tokens are rejected by `Auth`.
+#[post("/users/refresh", wrap="Auth::required()")]
+async fn refresh(
+  auth: AuthData,
+  db: web::Data<DbService>,
+  keys: web::Data<JwtKeys>,
+) -> Result<HttpResponse, Error> {
+  match db.user.get_by_id(auth.user_id).await? {
+    Some(user) => {
+      Ok(HttpResponse::Ok().json(UserResponse::new(user, &keys)?))
     },
     _ => {
       // invalid user.
@@ -87,18 +168,45 @@ async fn get_user(
   }
 }
 
+/// logout: revoke the current token until it expires.  Needs
+/// `auth.enable_revocation`.
+#[post("/users/logout", wrap="Auth::required()")]
+async fn logout(
+  auth: AuthData,
+  db: web::Data<DbService>,
+  keys: web::Data<JwtKeys>,
+) -> Result<HttpResponse, Error> {
+  if !keys.revocation_enabled() {
+    return Ok(HttpResponse::NotFound().finish());
+  }
+  let jti = match &auth.jti {
+    Some(jti) => jti,
+    None => {
+      // older token without an id, can't be revoked on its own.
+      return Err(crate::error::Error::UnprocessableEntity(json!({
+        "error": "Token can't be revoked, refresh it first.",
+      })).into());
+    }
+  };
+  let expires_at = NaiveDateTime::from_timestamp(auth.exp, 0);
+  db.user.revoke_token(jti, expires_at).await?;
+  Ok(HttpResponse::Ok().finish())
+}
+
 /// update user
 #[put("/user", wrap="Auth::required()")]
 async fn update(
   auth: AuthData,
+  cfg: web::Data<UserService>,
   db: web::Data<DbService>,
+  keys: web::Data<JwtKeys>,
   req: web::Json<UserOut<UpdateUser>>,
 ) -> Result<HttpResponse, Error> {
-  // Get current user from database
-  match db.user.get_by_id(auth.user_id).await? {
-    Some(mut user) => {
-      db.user.update(&mut user, &req.user).await?;
-      Ok(HttpResponse::Ok().json(UserResponse::try_from(user)?))
+  validate_form(&req.user)?;
+  cfg.limits.check_update(&req.user)?;
+  match db.user.update_user(auth.user_id, &req.user, cfg.canonical_email).await? {
+    Some(user) => {
+      Ok(HttpResponse::Ok().json(UserResponse::new(user, &keys)?))
     },
     _ => {
       // invalid user.
@@ -107,27 +215,448 @@ async fn update(
   }
 }
 
+/// Trim a list fetched with one extra item to `max` items, returns true if
+/// the list had more.
+fn truncate_export<T>(items: &mut Vec<T>, max: usize) -> bool {
+  if items.len() > max {
+    items.truncate(max);
+    true
+  } else {
+    false
+  }
+}
+
+/// export all of the current user's data as one JSON document.
+///
+/// Each list is limited to `User.export_max_items`, `truncated` is set when
+/// a list was cut.
+#[get("/user/export", wrap="Auth::required()")]
+async fn export(
+  auth: AuthData,
+  cfg: web::Data<UserService>,
+  db: web::Data<DbService>,
+) -> Result<HttpResponse, Error> {
+  let user = match db.user.get_by_id(auth.user_id).await? {
+    Some(user) => user,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+  let max = cfg.export_max_items;
+  let limit = max as i64 + 1;
+
+  // by author id, not by the (changeable) username.
+  let mut articles = db.article.get_user_articles(&auth, limit).await?;
+  let mut truncated = truncate_export(&mut articles, max);
+  let mut comments = db.comment.get_comments_by_user(user.id, limit).await?;
+  truncated |= truncate_export(&mut comments, max);
+  let mut following = db.user.get_following(user.id, limit).await?;
+  truncated |= truncate_export(&mut following, max);
+  let mut favorites = db.article.get_favorite_slugs(user.id, limit).await?;
+  truncated |= truncate_export(&mut favorites, max);
+
+  Ok(HttpResponse::Ok().json(UserExport {
+    user: user.into(),
+    articles,
+    comments,
+    following,
+    favorites,
+    truncated,
+  }))
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct UserService {
   pub allow_register: bool,
+
+  /// Check new emails for duplicates after removing `+tags` (and gmail dots).
+  pub canonical_email: bool,
+
+  /// Registration requires an invite code (see the `invite_codes` table).
+  pub require_invite: bool,
+
+  /// Maximum number of items in each list of `GET /user/export`.
+  pub export_max_items: usize,
+
+  /// Maximum lengths of username/email/bio.
+  pub limits: UserLimits,
+
+  /// Only register the read endpoints (`<prefix>.read_only`).
+  pub read_only: bool,
 }
 
 impl super::Service for UserService {
-  fn load_app_config(&mut self, config: &AppConfig, _prefix: &str) -> Result<()> {
+  fn load_app_config(&mut self, config: &AppConfig, prefix: &str) -> Result<()> {
+    self.read_only = super::read_only(config, prefix)?;
     self.allow_register = config.get_bool("User.allow_register")?.unwrap_or(false);
+    self.canonical_email = config.get_bool("User.canonical_email")?.unwrap_or(false);
+    self.require_invite = config.get_bool("User.require_invite")?.unwrap_or(false);
+    self.export_max_items = config.get_int("User.export_max_items")?.unwrap_or(1000).max(1) as usize;
+    let defaults = UserLimits::default();
+    self.limits = UserLimits {
+      username: config.get_int("User.max_username_len")?.map(|n| n.max(0) as usize).unwrap_or(defaults.username),
+      email: config.get_int("User.max_email_len")?.map(|n| n.max(0) as usize).unwrap_or(defaults.email),
+      bio: config.get_int("User.max_bio_len")?.map(|n| n.max(0) as usize).unwrap_or(defaults.bio),
+    };
     Ok(())
   }
 
   fn api_config(&self, web: &mut web::ServiceConfig) {
     web
       .data(self.clone())
-      .service(register)
       .service(login)
-      .service(update)
+      .service(refresh)
+      .service(available)
+      .service(export)
       .service(get_user);
+    if !self.read_only {
+      web
+        .service(register)
+        .service(logout)
+        .service(update);
+    }
+  }
+
+  fn routes(&self) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut routes: Vec<(&'static str, &'static [&'static str])> = vec![
+      ("/users/login", &["POST"]),
+      ("/users/refresh", &["POST"]),
+      ("/users/available", &["GET"]),
+      ("/user/export", &["GET"]),
+    ];
+    if self.read_only {
+      routes.push(("/user", &["GET"]));
+    } else {
+      routes.push(("/users", &["POST"]));
+      routes.push(("/users/logout", &["POST"]));
+      routes.push(("/user", &["GET", "PUT"]));
+    }
+    routes
   }
 }
 
 pub fn new_factory() -> UserService {
   Default::default()
 }
+
+#[cfg(test)]
+mod tests {
+  use actix_web::{test, App, dev::Service, http::{Method, StatusCode}};
+
+  use crate::auth::pass::BCRYPT_PASSWORD;
+  use crate::db::{test_db, test_suffix, test_user, test_article, DbService, NewbieLimit, VersionedStatement};
+  use crate::forms::CreateComment;
+  use crate::services::{test_services, test_login, test_request};
+
+  use super::truncate_export;
+
+  #[actix_rt::test]
+  async fn available_usernames_and_emails() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("taken{}", test_suffix());
+    test_user(&db, &name).await;
+    let free = format!("free{}", test_suffix());
+
+    let queries = vec![
+      (format!("username={}", name), false),
+      (format!("email={}@example.com", name), false),
+      (format!("username={}", free), true),
+      (format!("email={}@example.com", free), true),
+      (format!("username={}&email={}@example.com", free, name), false),
+    ];
+    for (query, available) in queries {
+      let req = test_request(Method::GET, &format!("/users/available?{}", query), "").to_request();
+      let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+      assert_eq!(res["available"], available, "{}", query);
+    }
+
+    let req = test_request(Method::GET, "/users/available", "").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+  }
+
+  #[actix_rt::test]
+  async fn login_upgrades_bcrypt_hashes() {
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("bcrypt{}", test_suffix());
+    let (auth, _token) = test_login(&db, &name).await;
+    // a user migrated from a legacy system.
+    VersionedStatement::new(db.shared_cl.clone(), "UPDATE users SET password = $2 WHERE id = $1")
+      .unwrap().execute(&[&auth.user_id, &BCRYPT_PASSWORD]).await.unwrap();
+    let mut login = |password: &str| {
+      let req = test_request(Method::POST, "/users/login", "")
+        .set_json(&serde_json::json!({
+          "user": { "email": format!("{}@example.com", name), "password": password },
+        }))
+        .to_request();
+      let res = app.call(req);
+      async move { res.await.unwrap().status() }
+    };
+    let stored = |db: &DbService| {
+      let id = auth.user_id;
+      let db = db.clone();
+      async move { db.user.get_by_id(id).await.unwrap().unwrap().password }
+    };
+
+    // a failed login keeps the old hash.
+    assert_eq!(login("wrong").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(stored(&db).await, BCRYPT_PASSWORD);
+
+    assert_eq!(login("password").await, StatusCode::OK);
+    let upgraded = stored(&db).await;
+    assert!(upgraded.starts_with("$argon2"), "{}", upgraded);
+    // and the upgraded hash still logs in.
+    assert_eq!(login("password").await, StatusCode::OK);
+    assert_eq!(stored(&db).await, upgraded);
+  }
+
+  #[actix_rt::test]
+  async fn field_lengths_are_limited() {
+    let username = format!("len{}", test_suffix());
+    let email = format!("{}@example.com", username);
+    let services = match test_services(&[
+      ("User.allow_register", true.into()),
+      ("User.max_username_len", (username.len() as i64).into()),
+      ("User.max_email_len", (email.len() as i64).into()),
+      ("User.max_bio_len", 10.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let mut call = |method: Method, uri: &str, token: &str, body: serde_json::Value| {
+      let req = test_request(method, uri, token).set_json(&body).to_request();
+      let res = app.call(req);
+      async move {
+        let res = res.await.unwrap();
+        let status = res.status();
+        (status, test::read_body_json::<serde_json::Value, _>(res).await)
+      }
+    };
+    let user = |username: &str, email: &str| json!({"user": {
+      "username": username, "email": email, "password": "password",
+    }});
+
+    // one character over.
+    let (status, body) = call(Method::POST, "/users", "", user(&format!("{}x", username), &email)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"].as_object().unwrap().keys().collect::<Vec<_>>(), vec!["username"]);
+    let (status, body) = call(Method::POST, "/users", "", user(&username, &format!("x{}", email))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"].as_object().unwrap().keys().collect::<Vec<_>>(), vec!["email"]);
+
+    // at the limits.
+    let (status, body) = call(Method::POST, "/users", "", user(&username, &email)).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = body["user"]["token"].as_str().unwrap().to_string();
+    let (status, _) = call(Method::PUT, "/user", &token, json!({"user": {"bio": "b".repeat(10)}})).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = call(Method::PUT, "/user", &token, json!({"user": {"bio": "b".repeat(11)}})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"]["bio"], json!(["is too long (maximum is 10 characters)"]));
+  }
+
+  #[actix_rt::test]
+  async fn register_requires_an_invite() {
+    let services = match test_services(&[
+      ("User.allow_register", true.into()),
+      ("User.require_invite", true.into()),
+    ]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let code = format!("invite{}", suffix);
+    VersionedStatement::new(db.shared_cl.clone(),
+      r#"INSERT INTO invite_codes(code, uses_remaining) VALUES ($1, 1)"#).unwrap()
+      .execute(&[&code]).await.unwrap();
+
+    let user = |code: Option<&str>| json!({"user": {
+      "username": format!("invitee{}", suffix),
+      "email": format!("invitee{}@example.com", suffix),
+      "password": "password",
+      "code": code,
+    }});
+    for (code, error) in &[(None, "Invite code required."), (Some(""), "Invite code required."),
+                           (Some("wrong"), "Invalid or expired invite code.")] {
+      let req = test_request(Method::POST, "/users", "").set_json(&user(*code)).to_request();
+      let res = test::call_service(&mut app, req).await;
+      assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY, "{:?}", code);
+      let body: serde_json::Value = test::read_body_json(res).await;
+      assert_eq!(body["error"], *error);
+    }
+    let req = test_request(Method::POST, "/users", "").set_json(&user(Some(&code))).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+  }
+
+  #[actix_rt::test]
+  async fn logout_revokes_the_token() {
+    let services = match test_services(&[("auth.enable_revocation", true.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("logout{}", test_suffix());
+    let (_auth, token) = test_login(&db, &name).await;
+    // a second session.
+    let req = test_request(Method::POST, "/users/login", "")
+      .set_json(&serde_json::json!({
+        "user": { "email": format!("{}@example.com", name), "password": "password" },
+      }))
+      .to_request();
+    let login: serde_json::Value = test::read_response_json(&mut app, req).await;
+    let other = login["user"]["token"].as_str().unwrap().to_string();
+    let mut status = |method: Method, uri: &str, token: &str| {
+      let req = test_request(method, uri, token).to_request();
+      let res = app.call(req);
+      // a rejected token is an `Err` from the middleware.
+      async move {
+        match res.await {
+          Ok(res) => res.status(),
+          Err(err) => err.as_response_error().error_response().status(),
+        }
+      }
+    };
+
+    assert_eq!(status(Method::GET, "/user", &token).await, StatusCode::OK);
+    assert_eq!(status(Method::POST, "/users/logout", &token).await, StatusCode::OK);
+    assert_eq!(status(Method::GET, "/user", &token).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(Method::POST, "/users/logout", &token).await, StatusCode::UNAUTHORIZED);
+    // only that token is revoked.
+    assert_eq!(status(Method::GET, "/user", &other).await, StatusCode::OK);
+  }
+
+  #[test]
+  fn truncate_export_cuts_extra_items() {
+    let mut items = vec![1, 2, 3];
+    assert!(truncate_export(&mut items, 2));
+    assert_eq!(items, vec![1, 2]);
+
+    let mut items = vec![1, 2];
+    assert!(!truncate_export(&mut items, 2));
+    assert_eq!(items, vec![1, 2]);
+
+    let mut items: Vec<i32> = vec![];
+    assert!(!truncate_export(&mut items, 2));
+  }
+
+  #[actix_rt::test]
+  async fn export_has_only_the_users_data() {
+    let services = match test_services(&[("User.export_max_items", 1.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let suffix = test_suffix();
+    let (alice, token) = test_login(&db, &format!("alice{}", suffix)).await;
+    let bob = test_user(&db, &format!("bob{}", suffix)).await;
+    let own = test_article(&db, &alice, &format!("Alice {}", suffix)).await;
+    let other = test_article(&db, &bob, &format!("Bob {}", suffix)).await;
+    let comment = CreateComment { body: format!("alice says {}", suffix) };
+    db.comment.store(&alice, other, &comment, 0, NewbieLimit::default()).await.unwrap();
+    let comment = CreateComment { body: format!("bob says {}", suffix) };
+    db.comment.store(&bob, own, &comment, 0, NewbieLimit::default()).await.unwrap();
+    db.user.follow(&alice, bob.user_id).await.unwrap();
+    db.article.favorite(&alice, other).await.unwrap();
+    db.article.favorite(&bob, own).await.unwrap();
+    let bob_slug = db.article.get_user_articles(&bob, 1).await.unwrap().remove(0).slug;
+
+    let req = test_request(Method::GET, "/user/export", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["user"]["username"], format!("alice{}", suffix));
+    assert!(res["user"].get("password").is_none());
+    assert_eq!(res["articles"].as_array().unwrap().len(), 1);
+    assert_eq!(res["articles"][0]["title"], format!("Alice {}", suffix));
+    assert_eq!(res["comments"].as_array().unwrap().len(), 1);
+    assert_eq!(res["comments"][0]["body"], format!("alice says {}", suffix));
+    assert_eq!(res["following"], serde_json::json!([format!("bob{}", suffix)]));
+    assert_eq!(res["favorites"], serde_json::json!([bob_slug]));
+    assert_eq!(res["truncated"], false);
+
+    // a second article doesn't fit in `export_max_items`.
+    test_article(&db, &alice, &format!("Alice again {}", suffix)).await;
+    let req = test_request(Method::GET, "/user/export", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["articles"].as_array().unwrap().len(), 1);
+    assert_eq!(res["truncated"], true);
+
+    let req = test_request(Method::GET, "/user/export", "").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+  }
+
+  #[actix_rt::test]
+  async fn invalid_forms_are_rejected() {
+    let services = match test_services(&[("User.allow_register", true.into())]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("valid{}", test_suffix());
+    let (_, token) = test_login(&db, &name).await;
+
+    let user = serde_json::json!({"user": {
+      "username": " ",
+      "email": "not-an-email",
+      "password": "short",
+    }});
+    let req = test_request(Method::POST, "/users", "").set_json(&user).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["errors"]["username"], serde_json::json!(["can't be blank"]));
+    assert_eq!(body["errors"]["email"], serde_json::json!(["is invalid"]));
+    assert_eq!(body["errors"]["password"], serde_json::json!(["is too short (minimum is 8 characters)"]));
+
+    let update = serde_json::json!({"user": {"email": "not-an-email"}});
+    let req = test_request(Method::PUT, "/user", &token).set_json(&update).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let user = db.user.get_by_username(&name).await.unwrap().unwrap();
+    assert_eq!(user.email, format!("{}@example.com", name));
+  }
+
+  #[actix_rt::test]
+  async fn refresh_reissues_the_token() {
+    use crate::auth::jwt::DecodeJwt;
+
+    let services = match test_services(&[]) {
+      Some(services) => services,
+      None => return,
+    };
+    let db = test_db().await.unwrap();
+    let mut app = test::init_service(App::new().configure(|web| services.web_config(web))).await;
+    let name = format!("refresh{}", test_suffix());
+    let (auth, token) = test_login(&db, &name).await;
+
+    let req = test_request(Method::POST, "/users/refresh", &token).to_request();
+    let res: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(res["user"]["username"], name);
+    let refreshed = res["user"]["token"].as_str().unwrap().to_string();
+    let claims = refreshed.decode_jwt(&crate::services::test_jwt_keys()).unwrap();
+    assert_eq!(claims.user_id, auth.user_id);
+
+    // the new token works.
+    let req = test_request(Method::GET, "/user", &refreshed).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let req = test_request(Method::POST, "/users/refresh", "").to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+  }
+}